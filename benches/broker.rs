@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use futures::StreamExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+use plm::{Frame, Modem};
+
+const SUBSCRIBERS: &[usize] = &[1, 8, 64, 256];
+
+/// Measures how long a received frame takes to reach every listener.
+fn fanout(c: &mut Criterion) {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+    let mut frame = BytesMut::new();
+    Frame::from_hex("025011223344556627130a")
+        .unwrap()
+        .to_bytes(&mut frame);
+
+    let mut group = c.benchmark_group("fanout");
+    for subscribers in SUBSCRIBERS {
+        // One modem for every sample, closed once they're all taken.
+        let (modem, mut port, mut streams) = rt.block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let (stream, accepted) = futures::join!(TcpStream::connect(address), listener.accept());
+            let (port, _) = accepted.unwrap();
+
+            let mut modem = Modem::new(stream.unwrap());
+            let mut streams = Vec::with_capacity(*subscribers);
+            for _ in 0..*subscribers {
+                streams.push(modem.listen().await.unwrap());
+            }
+
+            // Listeners are added asynchronously, so make sure they all
+            // are before sending anything.
+            while modem.health().listeners < *subscribers {
+                tokio::time::delay_for(Duration::from_millis(1)).await;
+            }
+            (modem, port, streams)
+        });
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(subscribers),
+            subscribers,
+            |b, _| {
+                b.iter_custom(|iterations| {
+                    rt.block_on(async {
+                        let mut elapsed = Duration::default();
+                        for _ in 0..iterations {
+                            let start = Instant::now();
+                            port.write_all(&frame).await.unwrap();
+                            for stream in &mut streams {
+                                stream.next().await.unwrap();
+                            }
+                            elapsed += start.elapsed();
+                        }
+                        elapsed
+                    })
+                })
+            },
+        );
+
+        drop(streams);
+        rt.block_on(modem.close(false)).unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fanout);
+criterion_main!(benches);
@@ -0,0 +1,109 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use plm::Frame;
+
+/// Counts allocations, so the encode benchmarks can report them.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Roughly what a busy network looks like: mostly standard messages from
+// devices, some extended ones, and the modem echoing our own sends.
+const TRAFFIC: &[(&str, &str, usize)] = &[
+    ("standard", "025011223344556627130a", 40),
+    ("status", "02501122334455662b1101", 20),
+    ("broadcast", "0250112233000001cb1101", 10),
+    (
+        "aldb record",
+        "02511122334455661f2f0000010fff00e201445566ff1c0100",
+        10,
+    ),
+    (
+        "extended get",
+        "02511122334455661f2e000101000000001c7f000000000000",
+        5,
+    ),
+    ("send", "0262112233000f0006", 10),
+    (
+        "extended send",
+        "02621122331f2f00000fff01000000000000000000c206",
+        3,
+    ),
+    ("modem info", "0260445566032a9c06", 1),
+    ("link record", "0257e201445566010000", 1),
+];
+
+/// The traffic as the modem sends it, including the ACKs echoed after our
+/// own frames.
+fn traffic() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (_, hex, count) in TRAFFIC {
+        let frame: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        for _ in 0..*count {
+            bytes.extend_from_slice(&frame);
+        }
+    }
+    bytes
+}
+
+fn decode(c: &mut Criterion) {
+    let traffic = traffic();
+
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Bytes(traffic.len() as u64));
+    group.bench_function("traffic mix", |b| {
+        b.iter(|| {
+            let mut bytes = BytesMut::from(&traffic[..]);
+            while let Some(frame) = Frame::from_bytes(&mut bytes).unwrap() {
+                criterion::black_box(frame);
+            }
+        })
+    });
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for (name, hex, _) in TRAFFIC {
+        let frame = Frame::from_hex(hex).unwrap();
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let mut bytes = BytesMut::new();
+        frame.to_bytes(&mut bytes);
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!("encode/{}: {} allocations", name, allocations);
+
+        group.bench_with_input(BenchmarkId::from_parameter(name), &frame, |b, frame| {
+            let mut bytes = BytesMut::with_capacity(64);
+            b.iter(|| {
+                bytes.clear();
+                frame.to_bytes(&mut bytes);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode, encode);
+criterion_main!(benches);
@@ -0,0 +1,167 @@
+#ifndef PLM_H
+#define PLM_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The result of every `plm_` function.
+ */
+typedef enum PlmStatus {
+  PLM_STATUS_OK = 0,
+  /**
+   * A pointer or string argument was null or invalid.
+   */
+  PLM_STATUS_INVALID_ARGUMENT,
+  /**
+   * The serial port could not be opened or used.
+   */
+  PLM_STATUS_IO,
+  /**
+   * The modem did not acknowledge the command.
+   */
+  PLM_STATUS_NOT_ACKNOWLEDGED,
+  /**
+   * The device refused the message.
+   */
+  PLM_STATUS_DEVICE_NOT_ACKNOWLEDGED,
+  /**
+   * The modem or device never responded.
+   */
+  PLM_STATUS_TIMEOUT,
+  /**
+   * The modem sent something that could not be decoded.
+   */
+  PLM_STATUS_PARSE,
+  /**
+   * An address string was not in the form `xx.xx.xx`.
+   */
+  PLM_STATUS_INVALID_ADDRESS,
+  /**
+   * The connection to the modem was lost.
+   */
+  PLM_STATUS_DISCONNECTED,
+  /**
+   * Any other error.
+   */
+  PLM_STATUS_OTHER,
+  /**
+   * The library panicked. The modem should be closed.
+   */
+  PLM_STATUS_PANIC,
+} PlmStatus;
+
+/**
+ * An opaque handle to an open modem.
+ */
+typedef struct PlmModem PlmModem;
+
+/**
+ * An INSTEON message, as passed to the event callback.
+ */
+typedef struct PlmMessage {
+  uint8_t from[3];
+  uint8_t to[3];
+  uint8_t flags;
+  uint8_t hops_remaining;
+  uint8_t max_hops;
+  uint8_t cmd1;
+  uint8_t cmd2;
+  /**
+   * Only meaningful when `flags` has the extended bit (0x10) set.
+   */
+  uint8_t data[14];
+} PlmMessage;
+
+/**
+ * Called with each received message and the `user_data` given when it
+ * was registered. The message is only valid during the call.
+ */
+typedef void (*PlmEventCallback)(const struct PlmMessage *message, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Opens the modem attached to the serial port at `path`, storing a handle
+ * in `modem`. Close it with [plm_modem_close].
+ *
+ * # Safety
+ * `path` must be a NUL terminated string and `modem` a valid pointer.
+ */
+enum PlmStatus plm_modem_open(const char *path, struct PlmModem **modem);
+
+/**
+ * Closes a modem opened with [plm_modem_open]. Passing null does nothing.
+ *
+ * # Safety
+ * `modem` must not be used after this.
+ */
+void plm_modem_close(struct PlmModem *modem);
+
+/**
+ * Parses an address such as `"11.22.33"` into the 3 bytes at `address`.
+ *
+ * # Safety
+ * `s` must be a NUL terminated string and `address` must point to 3 bytes.
+ */
+enum PlmStatus plm_address_parse(const char *s, uint8_t *address);
+
+/**
+ * Sends a message with `cmd1` and `cmd2` to the device at the 3 byte
+ * `address`. If `data` isn't null, its 14 bytes are sent in an extended
+ * message.
+ *
+ * # Safety
+ * `modem` must be open, `address` must point to 3 bytes and `data` must
+ * be null or point to 14 bytes.
+ */
+enum PlmStatus plm_modem_send(struct PlmModem *modem,
+                              const uint8_t *address,
+                              uint8_t cmd1,
+                              uint8_t cmd2,
+                              const uint8_t *data);
+
+/**
+ * Turns the device at the 3 byte `address` on to `level`, from 0 to 255.
+ *
+ * # Safety
+ * `modem` must be open and `address` must point to 3 bytes.
+ */
+enum PlmStatus plm_modem_on(struct PlmModem *modem, const uint8_t *address, uint8_t level);
+
+/**
+ * Turns the device at the 3 byte `address` off.
+ *
+ * # Safety
+ * `modem` must be open and `address` must point to 3 bytes.
+ */
+enum PlmStatus plm_modem_off(struct PlmModem *modem, const uint8_t *address);
+
+/**
+ * Calls `callback` on a background thread with every message received
+ * from now until the modem is closed.
+ *
+ * # Safety
+ * `modem` must be open, and `user_data` must be safe to use from another
+ * thread for as long as the modem is.
+ */
+enum PlmStatus plm_modem_set_event_callback(struct PlmModem *modem,
+                                            PlmEventCallback callback,
+                                            void *user_data);
+
+/**
+ * Returns a static description of `status`.
+ */
+const char *plm_status_string(enum PlmStatus status);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* PLM_H */
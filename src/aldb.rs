@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::frame::*;
+use crate::message::*;
+
+/// The memory offset of the first record in a device's link database.
+/// Subsequent records are found at lower offsets.
+pub const ALDB_START: u16 = 0x0fff;
+
+/// The size in bytes of a single device link record.
+pub const ALDB_RECORD_SIZE: u16 = 8;
+
+// How long a device takes to ramp at each rate from 0x00 to 0x1f, in
+// tenths of a second.
+const RAMP_RATE_TENTHS: [u64; 32] = [
+    5400, 4800, 4200, 3600, 3000, 2700, 2400, 2100, 1800, 1500, 1200, 900, 600, 470, 430, 385, 340,
+    320, 300, 280, 260, 235, 215, 190, 85, 65, 45, 20, 5, 3, 2, 1,
+];
+
+/// The ramp rate devices use unless told otherwise, half a second.
+pub const DEFAULT_RAMP_RATE: u8 = 0x1c;
+
+/// Returns the ramp rate that comes closest to `duration`, for use in the
+/// data of a responder [DeviceLinkRecord].
+pub fn ramp_rate(duration: Duration) -> u8 {
+    let tenths = duration.as_millis() as u64 / 100;
+    (0..RAMP_RATE_TENTHS.len())
+        .min_by_key(|rate| (RAMP_RATE_TENTHS[*rate] as i64 - tenths as i64).abs())
+        .unwrap() as u8
+}
+
+/// How a responder reacts when its controller triggers the link, e.g. a
+/// scene. This is kept in the data of the responder's [DeviceLinkRecord].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResponderData {
+    /// The level to turn on to, from 0 to 0xff.
+    pub level: u8,
+    /// How quickly to get there, see [ramp_rate].
+    pub ramp: u8,
+    /// The button or channel that reacts, 1 for single load devices.
+    pub button: u8,
+}
+
+impl ResponderData {
+    /// Returns the data for turning on to `level` at the default ramp rate.
+    pub fn new(level: u8) -> Self {
+        ResponderData {
+            level,
+            ramp: DEFAULT_RAMP_RATE,
+            button: 1,
+        }
+    }
+}
+
+impl From<[u8; 3]> for ResponderData {
+    fn from(data: [u8; 3]) -> Self {
+        ResponderData {
+            level: data[0],
+            ramp: data[1],
+            button: data[2],
+        }
+    }
+}
+
+impl From<ResponderData> for [u8; 3] {
+    fn from(data: ResponderData) -> Self {
+        [data.level, data.ramp, data.button]
+    }
+}
+
+impl fmt::Display for ResponderData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "level {}, ramp {:02x}, button {}",
+            self.level, self.ramp, self.button
+        )
+    }
+}
+
+pub(crate) const ALDB_READ: u8 = 0x00;
+pub(crate) const ALDB_RECORD: u8 = 0x01;
+pub(crate) const ALDB_WRITE: u8 = 0x02;
+
+/// A single record in a device's link database. These are laid out like the
+/// modem's [AllLinkRecord]s, but are addressed by their memory offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceLinkRecord {
+    /// The memory offset of the record in the device.
+    pub offset: u16,
+    pub flags: AllLinkFlags,
+    pub group: u8,
+    /// The other device in the link, usually the modem.
+    pub address: Address,
+    /// For responder records, this is usually the on-level, ramp rate and
+    /// button number.
+    pub data: [u8; 3],
+}
+
+impl DeviceLinkRecord {
+    /// Returns true if this record marks the end of the database. Every
+    /// record after it is unused.
+    pub fn is_last(&self) -> bool {
+        !self.flags.contains(AllLinkFlags::HAS_BEEN_USED)
+    }
+
+    /// Returns true if this record is in use.
+    pub fn is_in_use(&self) -> bool {
+        self.flags.contains(AllLinkFlags::IN_USE)
+    }
+
+    /// Returns true if the device is the controller in this link.
+    pub fn is_controller(&self) -> bool {
+        self.flags.contains(AllLinkFlags::IS_CONTROLLER)
+    }
+
+    /// Builds a [Message] asking the device at `to` for `count` records
+    /// starting at `offset`. A `count` of 0 requests every record.
+    pub(crate) fn read_request(to: Address, offset: u16, count: u8) -> Message {
+        let mut message: Message = (
+            to,
+            Command::ReadWriteAldb,
+            Command::None,
+            MessageFlags::EXTENDED,
+        )
+            .into();
+        message.data[1] = ALDB_READ;
+        message.data[2..4].copy_from_slice(&offset.to_be_bytes());
+        message.data[4] = count;
+        message
+    }
+
+    /// Builds a [Message] writing this record into the device at `to`.
+    pub(crate) fn write_request(&self, to: Address) -> Message {
+        let mut message: Message = (
+            to,
+            Command::ReadWriteAldb,
+            Command::None,
+            MessageFlags::EXTENDED,
+        )
+            .into();
+        message.data[1] = ALDB_WRITE;
+        message.data[2..4].copy_from_slice(&self.offset.to_be_bytes());
+        message.data[4] = ALDB_RECORD_SIZE as u8;
+        message.data[5] = self.flags.bits();
+        message.data[6] = self.group;
+        message.data[7..10].copy_from_slice(&<[u8; 3]>::from(self.address));
+        message.data[10..13].copy_from_slice(&self.data);
+        message
+    }
+
+    /// Extracts a record from a device's reply to a read request, if
+    /// `message` is one.
+    pub(crate) fn from_response(message: &Message) -> Option<DeviceLinkRecord> {
+        if !message.flags.contains(MessageFlags::EXTENDED)
+            || message.cmd1 != Command::ReadWriteAldb
+            || message.data[1] != ALDB_RECORD
+        {
+            return None;
+        }
+
+        Some(DeviceLinkRecord::from_data(&message.data))
+    }
+
+    /// Extracts a record from the data of a read response or write
+    /// request, which lay it out the same way.
+    pub(crate) fn from_data(data: &[u8; 14]) -> DeviceLinkRecord {
+        DeviceLinkRecord {
+            offset: u16::from_be_bytes([data[2], data[3]]),
+            flags: AllLinkFlags::from_bits_truncate(data[5]),
+            group: data[6],
+            address: Address::from(&data[7..10]),
+            data: [data[10], data[11], data[12]],
+        }
+    }
+}
+
+/// Puts the records of a device's link database back in order as they
+/// arrive. Devices answer a full read with one message per record, which
+/// may be duplicated or lost along the way.
+#[derive(Debug)]
+pub(crate) struct AldbReassembler {
+    next: Option<u16>,
+    pending: BTreeMap<u16, DeviceLinkRecord>,
+}
+
+impl AldbReassembler {
+    pub(crate) fn new() -> Self {
+        AldbReassembler {
+            next: Some(ALDB_START),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the offset of the next record to be returned by
+    /// [AldbReassembler::pop], or `None` once the end of the database has
+    /// been reached.
+    pub(crate) fn next_offset(&self) -> Option<u16> {
+        self.next
+    }
+
+    /// Adds a record received from the device. Duplicates and records that
+    /// were already returned are ignored.
+    pub(crate) fn push(&mut self, record: DeviceLinkRecord) {
+        match self.next {
+            Some(next) if record.offset <= next => {
+                self.pending.entry(record.offset).or_insert(record);
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the next record in order if it has been received. The
+    /// record marking the end of the database is never returned.
+    pub(crate) fn pop(&mut self) -> Option<DeviceLinkRecord> {
+        let next = self.next?;
+        let record = self.pending.remove(&next)?;
+
+        if record.is_last() {
+            self.next = None;
+            self.pending.clear();
+            return None;
+        }
+
+        self.next = next.checked_sub(ALDB_RECORD_SIZE);
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn ramp_rates() {
+        assert_eq!(ramp_rate(Duration::from_millis(500)), DEFAULT_RAMP_RATE);
+        assert_eq!(ramp_rate(Duration::from_secs(2)), 0x1b);
+        assert_eq!(ramp_rate(Duration::from_secs(3600)), 0x00);
+        assert_eq!(ramp_rate(Duration::from_secs(0)), 0x1f);
+    }
+
+    #[test]
+    fn write_then_read() {
+        let device = Address::from_str("11.22.33").unwrap();
+        let record = DeviceLinkRecord {
+            offset: 0x0ff7,
+            flags: AllLinkFlags::IN_USE | AllLinkFlags::HAS_BEEN_USED,
+            group: 0x01,
+            address: Address::from_str("44.55.66").unwrap(),
+            data: [0xff, 0x1f, 0x01],
+        };
+
+        let mut response = record.write_request(device);
+        assert_eq!(
+            &response.data[..13],
+            &[0x00, 0x02, 0x0f, 0xf7, 0x08, 0x82, 0x01, 0x44, 0x55, 0x66, 0xff, 0x1f, 0x01][..]
+        );
+
+        response.data[1] = ALDB_RECORD;
+        assert_eq!(DeviceLinkRecord::from_response(&response), Some(record));
+    }
+
+    #[test]
+    fn read_request() {
+        let message = DeviceLinkRecord::read_request(Address::default(), ALDB_START, 1);
+        assert_eq!(&message.data[..5], &[0x00, 0x00, 0x0f, 0xff, 0x01][..]);
+        assert_eq!(DeviceLinkRecord::from_response(&message), None);
+    }
+
+    fn record_at(offset: u16, last: bool) -> DeviceLinkRecord {
+        let flags = if last {
+            AllLinkFlags::empty()
+        } else {
+            AllLinkFlags::IN_USE | AllLinkFlags::HAS_BEEN_USED
+        };
+
+        DeviceLinkRecord {
+            offset,
+            flags,
+            group: 1,
+            address: Address::default(),
+            data: [0u8; 3],
+        }
+    }
+
+    #[test]
+    fn reassemble() {
+        let mut reassembler = AldbReassembler::new();
+        reassembler.push(record_at(0x0ff7, false));
+        assert_eq!(reassembler.pop(), None);
+        assert_eq!(reassembler.next_offset(), Some(ALDB_START));
+
+        reassembler.push(record_at(0x0fff, false));
+        reassembler.push(record_at(0x0fff, false));
+        reassembler.push(record_at(0x0fef, true));
+        assert_eq!(reassembler.pop(), Some(record_at(0x0fff, false)));
+        assert_eq!(reassembler.pop(), Some(record_at(0x0ff7, false)));
+
+        reassembler.push(record_at(0x0fff, false));
+        assert_eq!(reassembler.pop(), None);
+        assert_eq!(reassembler.next_offset(), None);
+    }
+}
@@ -1,4 +1,6 @@
+use std::collections::{hash_map::Entry, HashMap};
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 use futures::StreamExt;
@@ -9,7 +11,9 @@ use prettytable::{cell, format::FormatBuilder, row, table, Table};
 
 use log::debug;
 
-use tokio::net::TcpStream;
+use serde_json::json;
+
+use tokio::time::delay_for;
 
 use plm::*;
 
@@ -17,22 +21,106 @@ use plm::*;
 #[structopt(name = "plm")]
 struct App {
     /// A path to a serial device with an INSTEON modem connected, e.g. /dev/ttyUSB0
-    #[structopt(short, long, parse(from_os_str), conflicts_with = "host", required_unless = "host")]
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &["host", "emulate"],
+        required_unless_one = &["host", "emulate"]
+    )]
     device: Option<PathBuf>,
 
-    /// A host to connect over TCP
-    #[structopt(short, long, conflicts_with = "device", required_unless = "device")]
+    /// A host to connect over TCP, e.g. hub.local or hub.local:9761. The port defaults to 9761
+    #[structopt(
+        short,
+        long,
+        conflicts_with_all = &["device", "emulate"],
+        required_unless_one = &["device", "emulate"]
+    )]
     host: Option<String>,
 
+    /// Use a built-in emulated modem with a few fake devices instead of real hardware
+    #[structopt(long, conflicts_with_all = &["device", "host"])]
+    emulate: bool,
+
+    /// The most hops a message may take, from 0 to 3. Small networks often need only 1
+    #[structopt(long)]
+    max_hops: Option<u8>,
+
+    /// How long to wait for a device to respond, e.g. 30s
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    timeout: Option<Duration>,
+
+    /// How to write log output, "text" or "json" (one object per line)
+    #[structopt(long, default_value = "text")]
+    log_format: LogFormat,
+
     #[structopt(subcommand)]
     command: AppCommand,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => anyhow::bail!("Unknown log format '{}', expected text or json", s),
+        }
+    }
+}
+
 #[derive(StructOpt, Debug)]
 enum AppCommand {
     Modem(ModemCommand),
-    Listen,
+    /// Print every message received from the network
+    Listen {
+        /// Print each message using a template, e.g. "{time} {from}->{to} {cmd} {level}".
+        /// The fields are time, from, to, cmd, cmd1, cmd2, level, group, flags and hops.
+        #[structopt(short, long)]
+        format: Option<MessageTemplate>,
+    },
     Device(DeviceCommand),
+    /// Check the modem connection and print a report
+    Doctor,
+    /// Audit every linked device and summarize the health of the network
+    Report {
+        /// Seconds to listen for traffic before auditing
+        #[structopt(short, long, default_value = "10")]
+        listen: u64,
+
+        /// Print the report as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    Link(LinkCommand),
+    Scene(SceneCommand),
+    /// Send a command to an X10 device, e.g. `x10 A 1 on`
+    X10 {
+        /// The house code, from A to P
+        house: X10House,
+        /// The unit, from 1 to 16
+        unit: u8,
+        /// One of on, off, dim, bright, all-units-off, all-lights-on,
+        /// all-lights-off or status
+        command: X10Command,
+    },
+    #[cfg(feature = "plan")]
+    Plan(PlanCommand),
+    /// Serve remote control of the modem over the network
+    #[cfg(feature = "grpc")]
+    Serve {
+        /// The address to serve gRPC on, e.g. 0.0.0.0:50051
+        #[structopt(long)]
+        grpc: std::net::SocketAddr,
+    },
 }
 
 #[derive(StructOpt, Debug)]
@@ -50,6 +138,18 @@ enum DeviceCommand {
         /// Perform a "fast" operation, which avoids ramping on dimmers.
         #[structopt(short, long)]
         fast: bool,
+
+        /// Confirm the device reached the requested level, retrying if not.
+        #[structopt(long, conflicts_with = "fast")]
+        verify: bool,
+
+        /// Ramp to the level at this rate, from 0 (slowest) to 31 (fastest)
+        #[structopt(short, long, conflicts_with = "fast", conflicts_with = "verify")]
+        ramp: Option<u8>,
+
+        /// Wait this long, e.g. 10m or 1h30m, then turn the device off again
+        #[structopt(long = "for", parse(try_from_str = humantime::parse_duration))]
+        for_duration: Option<Duration>,
     },
     /// Turn a device off
     Off {
@@ -59,27 +159,82 @@ enum DeviceCommand {
         /// Perform a "fast" operation, which avoids ramping on dimmers.
         #[structopt(short, long)]
         fast: bool,
+
+        /// Confirm the device turned off, retrying if not.
+        #[structopt(long, conflicts_with = "fast")]
+        verify: bool,
     },
     /// Ping a device
     Ping {
         #[structopt(flatten)]
         common: DeviceFlags,
+
+        /// The number of pings to send
+        #[structopt(short, long, default_value = "1")]
+        count: u32,
+
+        /// Seconds to wait between pings
+        #[structopt(short, long, default_value = "1", parse(try_from_str = parse_interval))]
+        interval: f32,
     },
     /// Cause a device to emit a beep
     Beep {
         #[structopt(flatten)]
         common: DeviceFlags,
     },
+    /// Beep or blink a device so it can be found
+    Locate {
+        #[structopt(flatten)]
+        common: DeviceFlags,
+
+        /// How long to keep going, in seconds
+        #[structopt(short, long, default_value = "10")]
+        duration: u64,
+
+        /// Blink the load instead of beeping
+        #[structopt(short, long)]
+        blink: bool,
+    },
     /// Retrieve current device status
     Status {
         #[structopt(flatten)]
         common: DeviceFlags,
+
+        /// What to ask for: level, leds, config, or a number
+        #[structopt(short, long, default_value = "level")]
+        kind: StatusKind,
     },
     /// Retrieve current device status
     Version {
         #[structopt(flatten)]
         common: DeviceFlags,
     },
+    /// Show or change a device's operating flags
+    Flags {
+        #[structopt(flatten)]
+        common: DeviceFlags,
+
+        /// The kind of device: dimmer, keypad or sensor. Asks the device if not given.
+        #[structopt(long)]
+        family: Option<DeviceFamily>,
+
+        /// Flags to change before showing them, e.g. program-lock=on led-on-tx=off
+        #[structopt(short, long)]
+        set: Vec<String>,
+    },
+    /// Print state changes and heartbeats from a device as they happen
+    Watch {
+        #[structopt(flatten)]
+        common: DeviceFlags,
+
+        /// The group the device sends heartbeats on, 4 for most battery sensors
+        #[structopt(long, default_value = "4")]
+        heartbeat_group: u8,
+
+        /// Also append each event to this file as a line of JSON
+        #[structopt(long, parse(from_os_str))]
+        json: Option<PathBuf>,
+    },
 }
 
 #[derive(StructOpt, Debug)]
@@ -93,6 +248,70 @@ struct DeviceFlags {
 enum ModemCommand {
     Info,
     Links,
+    /// Show the modem's configuration, optionally changing it first
+    Config {
+        /// Settings to change, e.g. monitor-mode=on auto-linking=off. The
+        /// settings are auto-linking, monitor-mode, manual-led and deadman.
+        #[structopt(short, long)]
+        set: Vec<String>,
+    },
+    /// Turn the modem's LED on or off. Needs the manual-led setting on.
+    Led {
+        #[structopt(possible_values = &["on", "off"])]
+        state: String,
+    },
+    /// Put the modem's radio to sleep until the next command
+    Sleep,
+    /// Check that the modem is answering and show how long it took
+    Ping,
+    /// Erase the modem's links and settings
+    Reset {
+        /// Really erase everything
+        #[structopt(long)]
+        yes: bool,
+    },
+    /// List the groups the modem controls and the devices in each
+    Groups {
+        /// Read each member's link database for its on-level and ramp rate
+        #[structopt(long)]
+        aldb: bool,
+
+        /// Name devices using the aliases in this plan file
+        #[cfg(feature = "plan")]
+        #[structopt(long, parse(from_os_str))]
+        plan: Option<PathBuf>,
+    },
+    /// Keep the modem in linking mode, linking every device whose set button is pressed until Ctrl-C
+    Enroll {
+        /// Links the modem as a controller of each device
+        #[structopt(short, long, conflicts_with = "responder")]
+        controller: bool,
+
+        /// Links the modem as a responder to each device
+        #[structopt(short, long, conflicts_with = "controller")]
+        responder: bool,
+
+        /// The group number to link, defaults to 1
+        #[structopt(short, long, default_value = "1")]
+        group: u8,
+    },
+    /// Print the modem's memory in hex, or its link database if it can't be read
+    Dump {
+        /// The first address to print, in hex
+        #[structopt(long, default_value = "0", parse(try_from_str = parse_hex_u16))]
+        start: u16,
+
+        /// The address to stop before, in hex
+        #[structopt(long, default_value = "4000", parse(try_from_str = parse_hex_u16))]
+        end: u16,
+    },
+    /// Send a raw frame given in hex, e.g. 0260, and print the response
+    Send {
+        /// The frame to send
+        frame: String,
+    },
+    /// Print every byte read from and written to the modem, in hex
+    Tap,
     LinkDevice {
         /// The address of the device to link
         address: Option<Address>,
@@ -120,6 +339,79 @@ enum ModemCommand {
     },
 }
 
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Link commands")]
+enum LinkCommand {
+    /// Link two devices directly, so one controls the other without the modem
+    Cross {
+        /// Address of the controlling device, e.g. a switch
+        controller: Address,
+
+        /// Address of the responding device, e.g. a plug-in module
+        responder: Address,
+
+        /// The controller's group, usually its button number
+        #[structopt(short, long, default_value = "1")]
+        group: u8,
+
+        /// The level the responder turns on to, in percent
+        #[structopt(short, long, default_value = "100")]
+        level: u8,
+
+        /// How long the responder takes to ramp to the level, e.g. 2s
+        #[structopt(short, long, parse(try_from_str = humantime::parse_duration))]
+        ramp: Option<Duration>,
+    },
+    /// Remove a device's link from the modem's database, leaving the device alone
+    Delete {
+        /// Address of the linked device
+        address: Address,
+
+        /// The group of the link
+        #[structopt(short, long, default_value = "1")]
+        group: u8,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Scene commands")]
+enum SceneCommand {
+    /// Record the current level of some devices
+    Snapshot {
+        /// Write the snapshot to this file instead of printing it
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Addresses of the devices to record
+        #[structopt(required = true)]
+        addresses: Vec<Address>,
+    },
+    /// Put devices back to the levels recorded in a snapshot
+    Restore {
+        /// Path to a file written by snapshot
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
+#[cfg(feature = "plan")]
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Declarative network plans")]
+enum PlanCommand {
+    /// Show the changes a plan would make without touching the modem
+    Diff {
+        /// Path to the TOML plan file
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Make the changes needed to bring the modem in line with a plan
+    Apply {
+        /// Path to the TOML plan file
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
 fn create_table() -> Table {
     let mut table = Table::new();
     let format = FormatBuilder::new()
@@ -144,6 +436,43 @@ macro_rules! ptable {
     };
 }
 
+// Each modem setting, and whether it is on when its flag is set. The
+// "disable" flags read better inverted.
+const MODEM_SETTINGS: [(&str, ModemConfig, bool); 4] = [
+    ("auto-linking", ModemConfig::DISABLE_AUTO_LINKING, false),
+    ("monitor-mode", ModemConfig::MONITOR_MODE, true),
+    ("manual-led", ModemConfig::MANUAL_LED, true),
+    ("deadman", ModemConfig::DISABLE_DEADMAN, false),
+];
+
+async fn modem_config(modem: &mut Modem, changes: Vec<String>) -> Result<()> {
+    let mut config = modem.get_config().await?;
+
+    if !changes.is_empty() {
+        for change in &changes {
+            let (name, value) = match change.split('=').collect::<Vec<_>>()[..] {
+                [name, "on"] => (name, true),
+                [name, "off"] => (name, false),
+                _ => anyhow::bail!("Expected a setting like monitor-mode=on, not '{}'", change),
+            };
+            let (_, flag, set_when_on) = MODEM_SETTINGS
+                .iter()
+                .find(|(setting, _, _)| *setting == name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown modem setting '{}'", name))?;
+            config.set(*flag, value == *set_when_on);
+        }
+        modem.set_config(config).await?;
+    }
+
+    let mut table = create_table();
+    for (name, flag, set_when_on) in MODEM_SETTINGS.iter() {
+        let on = config.contains(*flag) == *set_when_on;
+        table.add_row(row![name, if on { "on" } else { "off" }]);
+    }
+    table.printstd();
+    Ok(())
+}
+
 async fn modem_info(modem: &mut Modem) -> Result<()> {
     let info = modem.get_info().await?;
 
@@ -179,6 +508,176 @@ async fn modem_links(modem: &mut Modem) -> Result<()> {
     Ok(())
 }
 
+fn parse_hex_u16(s: &str) -> Result<u16, std::num::ParseIntError> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16)
+}
+
+fn parse_interval(s: &str) -> Result<f32> {
+    let interval: f32 = s.parse()?;
+    anyhow::ensure!(
+        interval.is_finite() && interval >= 0.0,
+        "The interval must be a positive number of seconds, not '{}'",
+        s
+    );
+    Ok(interval)
+}
+
+async fn modem_dump(modem: &mut Modem, start: u16, end: u16) -> Result<()> {
+    let dump = modem.dump_memory(start..end).await?;
+    if dump.reconstructed {
+        eprintln!("The modem can't read its memory, showing its link database instead");
+    }
+
+    for record in dump.records {
+        let bytes: Vec<String> = record.data.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("{:04x}: {}", record.address, bytes.join(" "));
+    }
+
+    Ok(())
+}
+
+async fn modem_groups(
+    modem: &mut Modem,
+    aldb: bool,
+    names: HashMap<Address, String>,
+) -> Result<()> {
+    let info = modem.get_info().await?;
+    let links: Vec<AllLinkRecord> = modem.get_links().await?.collect();
+
+    let mut groups: Vec<u8> = links
+        .iter()
+        .filter(|link| link.flags.contains(AllLinkFlags::IS_CONTROLLER))
+        .map(|link| link.group)
+        .collect();
+    groups.sort_unstable();
+    groups.dedup();
+
+    let mut device_links = HashMap::new();
+    let mut table = create_table();
+    table.set_titles(row![b->"Group", b->"Device", b->"Name", b->"On-Level", b->"Ramp Rate"]);
+
+    for group in groups {
+        for link in links
+            .iter()
+            .filter(|link| link.group == group && link.flags.contains(AllLinkFlags::IS_CONTROLLER))
+        {
+            let name = names.get(&link.to).map(String::as_str).unwrap_or("");
+
+            // The device's own responder record holds what it does when
+            // the scene is triggered.
+            let mut on_level = String::new();
+            let mut ramp = String::new();
+            if aldb {
+                if let Entry::Vacant(entry) = device_links.entry(link.to) {
+                    // A device that can't be read is marked in its rows,
+                    // the rest of the groups are still shown.
+                    let records = match modem.read_device_links(link.to).await {
+                        Ok(records) => Some(records.collect::<Vec<_>>()),
+                        Err(e) => {
+                            eprintln!("Failed to read links from {}: {}", link.to, e);
+                            None
+                        }
+                    };
+                    entry.insert(records);
+                }
+
+                match &device_links[&link.to] {
+                    Some(records) => {
+                        if let Some(record) = records.iter().find(|record| {
+                            record.address == info.address
+                                && record.group == group
+                                && !record.flags.contains(AllLinkFlags::IS_CONTROLLER)
+                        }) {
+                            on_level = record.data[0].to_string();
+                            ramp = record.data[1].to_string();
+                        }
+                    }
+                    None => on_level = "unreadable".to_string(),
+                }
+            }
+
+            table.add_row(row![group, link.to, name, on_level, ramp]);
+        }
+    }
+
+    table.printstd();
+    Ok(())
+}
+
+async fn cross_link(
+    modem: &mut Modem,
+    controller: Address,
+    responder: Address,
+    group: u8,
+    data: [u8; 3],
+) -> Result<()> {
+    modem.cross_link(controller, responder, group, data).await?;
+
+    // Read both databases back to make sure the records really landed.
+    let mut table = create_table();
+    table.set_titles(row![b->"Device", b->"Record", b->"Result"]);
+    let mut missing = 0;
+    for (device, other, is_controller) in &[
+        (controller, responder, true),
+        (responder, controller, false),
+    ] {
+        let mut found = Ok(None);
+        match modem.stream_device_links(*device).await {
+            Ok(stream) => {
+                let mut stream = Box::pin(stream);
+                while let Some(record) = stream.next().await {
+                    match record {
+                        Ok(record)
+                            if record.is_in_use()
+                                && record.group == group
+                                && record.address == *other
+                                && record.is_controller() == *is_controller =>
+                        {
+                            found = Ok(Some(record));
+                            break;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            found = Err(e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => found = Err(e),
+        }
+
+        let mode = if *is_controller {
+            "Controller"
+        } else {
+            "Responder"
+        };
+        match found {
+            Ok(Some(record)) => table.add_row(row![
+                device,
+                format!("{} of {} at {:04x}", mode, other, record.offset),
+                Fg->"OK"
+            ]),
+            Ok(None) => {
+                missing += 1;
+                table.add_row(row![device, format!("{} of {}", mode, other), Fr->"MISSING"])
+            }
+            Err(e) => {
+                missing += 1;
+                table.add_row(row![
+                    device,
+                    format!("{} of {}", mode, other),
+                    Fr->format!("UNREADABLE: {}", e)
+                ])
+            }
+        };
+    }
+    table.printstd();
+
+    anyhow::ensure!(missing == 0, "Link records could not be verified");
+    Ok(())
+}
+
 async fn modem_link(
     modem: &mut Modem,
     address: Option<Address>,
@@ -199,11 +698,659 @@ async fn modem_link(
     Ok(())
 }
 
-async fn message_listen(modem: &mut Modem) -> Result<()> {
+async fn modem_enroll(modem: &mut Modem, mode: AllLinkMode, group: u8) -> Result<()> {
+    let started = Instant::now();
+    let mut linked = Vec::new();
+
+    println!("Press the set button on each device to link, then Ctrl-C to finish");
+    {
+        let mut session = Box::pin(modem.enroll_session(mode, group).await?);
+        let ctrl_c = tokio::signal::ctrl_c();
+        tokio::pin!(ctrl_c);
+
+        loop {
+            tokio::select! {
+                complete = session.next() => match complete {
+                    Some(complete) => {
+                        let complete = complete?;
+                        println!("Linked {} in group {}", complete.address, complete.group);
+                        linked.push(complete);
+                    }
+                    None => break,
+                },
+                _ = &mut ctrl_c => break,
+            }
+        }
+    }
+    modem.cancel_linking().await?;
+
+    println!();
+    println!(
+        "Linked {} devices in {}",
+        linked.len(),
+        humantime::format_duration(Duration::from_secs(started.elapsed().as_secs()))
+    );
+    if !linked.is_empty() {
+        let mut table = create_table();
+        table.set_titles(
+            row![b->"Address", b->"Mode", b->"Group", b->"Category", b->"Subcategory", b->"Firmware Version"],
+        );
+        for complete in linked {
+            table.add_row(row![
+                complete.address,
+                complete.mode,
+                complete.group,
+                complete.category,
+                complete.sub_category,
+                complete.firmware_version
+            ]);
+        }
+        table.printstd();
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "plan")]
+fn print_plan_changes(plan: &plm::plan::Plan, changes: &mut [plm::plan::PlanChange]) {
+    use plm::plan::PlanChange;
+
+    if changes.is_empty() {
+        println!("The modem already matches the plan.");
+        return;
+    }
+
+    let address = |change: &PlanChange| match change {
+        PlanChange::Add(link) => link.address,
+        PlanChange::Remove(record) => record.to,
+        PlanChange::Modify { link, .. } => link.address,
+    };
+    changes.sort_by_key(|change| <[u8; 3]>::from(address(change)));
+
+    let mut table = create_table();
+    table.set_titles(
+        row![b->"Address", b->"Alias", b->"Change", b->"Group", b->"Mode", b->"Responder", b->"Was"],
+    );
+
+    for change in changes.iter() {
+        let alias = plan
+            .devices
+            .iter()
+            .find(|device| device.address == address(change))
+            .and_then(|device| device.alias.clone())
+            .unwrap_or_default();
+
+        match change {
+            PlanChange::Add(link) => table.add_row(row![
+                link.address,
+                alias,
+                "add",
+                link.group,
+                link.mode,
+                link.responder
+                    .map(|responder| responder.to_string())
+                    .unwrap_or_default(),
+                ""
+            ]),
+            PlanChange::Modify { link, old } => table.add_row(row![
+                link.address,
+                alias,
+                "modify",
+                link.group,
+                link.mode,
+                link.responder
+                    .map(|responder| responder.to_string())
+                    .unwrap_or_default(),
+                old
+            ]),
+            PlanChange::Remove(record) => table.add_row(row![
+                record.to,
+                alias,
+                "remove",
+                record.group,
+                if record.flags.contains(AllLinkFlags::IS_CONTROLLER) {
+                    AllLinkMode::Controller
+                } else {
+                    AllLinkMode::Responder
+                },
+                "",
+                ""
+            ]),
+        };
+    }
+
+    table.printstd();
+}
+
+#[cfg(feature = "plan")]
+async fn handle_plan_command(modem: &mut Modem, command: PlanCommand) -> Result<()> {
+    use plm::plan::Plan;
+
+    match command {
+        PlanCommand::Diff { file } => {
+            let plan = Plan::from_path(&file)
+                .with_context(|| format!("Failed to load plan {}", file.display()))?;
+            let actual = plan.read_state(modem).await?;
+            print_plan_changes(&plan, &mut plan.diff(&actual));
+        }
+        PlanCommand::Apply { file } => {
+            let plan = Plan::from_path(&file)
+                .with_context(|| format!("Failed to load plan {}", file.display()))?;
+            print_plan_changes(&plan, &mut plan.apply(modem).await?);
+        }
+    }
+
+    Ok(())
+}
+
+struct Doctor {
+    table: Table,
+    failures: usize,
+}
+
+impl Doctor {
+    fn pass(&mut self, check: &str, details: impl ToString) {
+        self.table
+            .add_row(row![check, Fg->"PASS", details.to_string()]);
+    }
+
+    fn fail(&mut self, check: &str, error: impl std::fmt::Display, hint: &str) {
+        self.failures += 1;
+        self.table
+            .add_row(row![check, Fr->"FAIL", format!("{}\n{}", error, hint)]);
+    }
+}
+
+fn found_ports() -> String {
+    match available_ports() {
+        Ok(ports) if ports.is_empty() => "No serial ports found".to_string(),
+        Ok(ports) => {
+            let ports: Vec<String> = ports
+                .iter()
+                .map(|port| {
+                    if port.is_likely_modem() {
+                        format!("{} (likely a modem)", port.path.display())
+                    } else {
+                        port.path.display().to_string()
+                    }
+                })
+                .collect();
+            format!("Found {}", ports.join(", "))
+        }
+        Err(e) => format!("Failed to list serial ports: {}", e),
+    }
+}
+
+async fn doctor(device: Option<PathBuf>, host: Option<String>) -> Result<()> {
+    const NUM_ECHOES: u32 = 5;
+
+    let mut doctor = Doctor {
+        table: create_table(),
+        failures: 0,
+    };
+    doctor
+        .table
+        .set_titles(row![b->"Check", b->"Result", b->"Details"]);
+
+    let modem = if let Some(device) = device {
+        match std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&device)
+        {
+            Ok(_) => doctor.pass("Serial port", device.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => doctor.fail(
+                "Serial port",
+                e,
+                if cfg!(windows) {
+                    "Make sure no other program is using the port"
+                } else {
+                    "Add your user to the group that owns the port, e.g. dialout or uucp"
+                },
+            ),
+            Err(e) => doctor.fail(
+                "Serial port",
+                e,
+                &format!(
+                    "Check the path, and that the modem is plugged in\n{}",
+                    found_ports()
+                ),
+            ),
+        }
+
+        match Modem::from_path(device) {
+            Ok(modem) => Some(modem),
+            Err(e) => {
+                doctor.fail(
+                    "Open modem",
+                    e,
+                    "Make sure no other program is using the port",
+                );
+                None
+            }
+        }
+    } else {
+        let host = host.unwrap();
+        match Modem::from_tcp(&host) {
+            Ok(modem) => {
+                doctor.pass("Connect", &host);
+                Some(modem)
+            }
+            Err(e) => {
+                doctor.fail("Connect", e, "Check the host name and port of the hub");
+                None
+            }
+        }
+    };
+
+    if let Some(mut modem) = modem {
+        match modem.get_info().await {
+            Ok(info) => doctor.pass(
+                "Modem info",
+                format!(
+                    "Address {}, firmware {:02x}",
+                    info.address, info.firmware_version
+                ),
+            ),
+            Err(e) => doctor.fail(
+                "Modem info",
+                e,
+                "Make sure the device is an INSTEON modem, and try unplugging it for 10 seconds",
+            ),
+        }
+
+        let mut latencies = Vec::with_capacity(NUM_ECHOES as usize);
+        for _ in 0..NUM_ECHOES {
+            let start = Instant::now();
+            if modem.get_info().await.is_ok() {
+                latencies.push(start.elapsed());
+            }
+        }
+        match latencies.iter().max() {
+            Some(max) if latencies.len() as u32 == NUM_ECHOES => doctor.pass(
+                "Echo latency",
+                format!(
+                    "avg {}ms, max {}ms",
+                    (latencies.iter().sum::<Duration>() / NUM_ECHOES).as_millis(),
+                    max.as_millis()
+                ),
+            ),
+            _ => doctor.fail(
+                "Echo latency",
+                format!(
+                    "{} of {} echoes failed",
+                    NUM_ECHOES as usize - latencies.len(),
+                    NUM_ECHOES
+                ),
+                "Check the cable, and for other programs using the modem",
+            ),
+        }
+
+        match modem.get_links().await {
+            Ok(links) => doctor.pass("Link database", format!("{} records", links.count())),
+            Err(e) => doctor.fail(
+                "Link database",
+                e,
+                "The database may be corrupt. Try a factory reset of the modem",
+            ),
+        }
+    }
+
+    doctor.table.printstd();
+
+    if doctor.failures > 0 {
+        anyhow::bail!("{} checks failed", doctor.failures);
+    }
+    Ok(())
+}
+
+struct DeviceReport {
+    address: Address,
+    groups: Vec<u8>,
+    engine: Option<InsteonEngine>,
+    broken: Vec<String>,
+    last_seen: Option<Duration>,
+    messages: usize,
+}
+
+// Checks that each of the modem's records for a device has a matching
+// record in the device, and returns descriptions of those that don't.
+fn audit_links(
+    modem_address: Address,
+    modem_links: &[AllLinkRecord],
+    device_links: &[DeviceLinkRecord],
+) -> Vec<String> {
+    modem_links
+        .iter()
+        .filter(|link| {
+            let controller = link.flags.contains(AllLinkFlags::IS_CONTROLLER);
+            !device_links.iter().any(|record| {
+                record.is_in_use()
+                    && record.address == modem_address
+                    && record.group == link.group
+                    && record.is_controller() != controller
+            })
+        })
+        .map(|link| {
+            let mode = if link.flags.contains(AllLinkFlags::IS_CONTROLLER) {
+                AllLinkMode::Controller
+            } else {
+                AllLinkMode::Responder
+            };
+            format!("group {} ({}) missing from device", link.group, mode)
+        })
+        .collect()
+}
+
+async fn network_report(modem: &mut Modem, listen: u64, json: bool) -> Result<()> {
+    let info = modem.get_info().await?;
+    let links: Vec<AllLinkRecord> = modem.get_links().await?.collect();
+
+    if !json {
+        println!("Listening for {} seconds...", listen);
+    }
+    let mut traffic: HashMap<Address, usize> = HashMap::new();
     let mut stream = modem.listen().await?;
+    let deadline = Instant::now() + Duration::from_secs(listen);
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match tokio::time::timeout(remaining, stream.next()).await {
+            Ok(Some(message)) => *traffic.entry(message.from).or_default() += 1,
+            _ => break,
+        }
+    }
+    drop(stream);
 
-    while let Some(message) = stream.next().await {
-        println!("{:02x?}", message);
+    let mut addresses: Vec<Address> = links.iter().map(|link| link.to).collect();
+    addresses.sort_by_key(|address| <[u8; 3]>::from(*address));
+    addresses.dedup();
+
+    let mut devices = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let modem_links: Vec<AllLinkRecord> = links
+            .iter()
+            .filter(|link| link.to == address)
+            .cloned()
+            .collect();
+
+        let engine = modem.get_engine(address).await.ok();
+        let broken = match engine {
+            Some(InsteonEngine::I2) | Some(InsteonEngine::I2Cs) => {
+                let mut records = Vec::new();
+                let mut failed = None;
+                match modem.stream_device_links(address).await {
+                    Ok(stream) => {
+                        let mut stream = Box::pin(stream);
+                        while let Some(record) = stream.next().await {
+                            match record {
+                                Ok(record) => records.push(record),
+                                Err(e) => {
+                                    failed = Some(format!("link database unreadable: {}", e));
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => failed = Some(format!("link database unreadable: {}", e)),
+                }
+
+                match failed {
+                    Some(failed) => vec![failed],
+                    None => audit_links(info.address, &modem_links, &records),
+                }
+            }
+            _ => Vec::new(),
+        };
+
+        devices.push(DeviceReport {
+            address,
+            groups: modem_links.iter().map(|link| link.group).collect(),
+            engine,
+            broken,
+            last_seen: modem.last_seen(address).map(|seen| seen.elapsed()),
+            messages: traffic.get(&address).copied().unwrap_or(0),
+        });
+    }
+
+    let mut talkers: Vec<(Address, usize)> = traffic.into_iter().collect();
+    talkers.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    talkers.truncate(5);
+
+    let health = modem.health();
+    let stats = modem.stats();
+
+    if json {
+        let devices: Vec<serde_json::Value> = devices
+            .iter()
+            .map(|device| {
+                json!({
+                    "address": device.address.to_string(),
+                    "groups": device.groups,
+                    "reachable": device.engine.is_some(),
+                    "engine": device.engine.map(|engine| format!("{:?}", engine)),
+                    "broken_links": device.broken,
+                    "last_seen_secs": device.last_seen.map(|seen| seen.as_secs()),
+                    "messages": device.messages,
+                })
+            })
+            .collect();
+        let talkers: Vec<serde_json::Value> = talkers
+            .iter()
+            .map(|(address, count)| json!({ "address": address.to_string(), "messages": count }))
+            .collect();
+
+        let report = json!({
+            "modem": {
+                "address": info.address.to_string(),
+                "firmware_version": info.firmware_version,
+            },
+            "devices": devices,
+            "talkers": talkers,
+            "errors": {
+                "not_acknowledged": health.errors.not_acknowledged,
+                "timeouts": health.errors.timeouts,
+                "decode": health.errors.decode,
+                "io": health.errors.io,
+            },
+            "decoder": {
+                "decoded": stats.decoded,
+                "unknown": stats.unknown,
+                "discarded_bytes": stats.discarded_bytes,
+                "checksum_failures": stats.checksum_failures,
+                "overflows": stats.overflows,
+            },
+        });
+        println!("{}", report);
+        return Ok(());
+    }
+
+    ptable!(
+        ["Modem", info.address],
+        ["Firmware Version", info.firmware_version],
+        ["Devices", devices.len()],
+        [
+            "Unreachable",
+            devices
+                .iter()
+                .filter(|device| device.engine.is_none())
+                .count()
+        ],
+        [
+            "Broken Links",
+            devices
+                .iter()
+                .map(|device| device.broken.len())
+                .sum::<usize>()
+        ],
+        ["Frames Decoded", stats.decoded],
+        ["Unknown Frames", stats.unknown],
+        ["Bytes Discarded", stats.discarded_bytes],
+        ["Checksum Failures", stats.checksum_failures],
+        ["Buffer Overflows", stats.overflows]
+    );
+    println!();
+
+    let mut table = create_table();
+    table.set_titles(
+        row![b->"Address", b->"Engine", b->"Groups", b->"Last Seen", b->"Messages", b->"Problems"],
+    );
+    for device in &devices {
+        let engine = match device.engine {
+            Some(engine) => format!("{:?}", engine),
+            None => "unreachable".to_string(),
+        };
+        let groups: Vec<String> = device.groups.iter().map(u8::to_string).collect();
+        let last_seen = device
+            .last_seen
+            .map(|seen| format!("{}s ago", seen.as_secs()))
+            .unwrap_or_default();
+
+        table.add_row(row![
+            device.address,
+            engine,
+            groups.join(","),
+            last_seen,
+            device.messages,
+            device.broken.join("\n")
+        ]);
+    }
+    table.printstd();
+
+    if !talkers.is_empty() {
+        println!();
+        println!("Noisiest talkers:");
+        for (address, count) in talkers {
+            println!("  {} sent {} messages", address, count);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TemplatePiece {
+    Literal(String),
+    Field(String),
+}
+
+const TEMPLATE_FIELDS: &[&str] = &[
+    "time", "from", "to", "cmd", "cmd1", "cmd2", "level", "group", "flags", "hops",
+];
+
+/// A `listen --format` template, with `{field}` placeholders filled in from
+/// each message. Use `{{` and `}}` for literal braces.
+#[derive(Clone, Debug, PartialEq)]
+struct MessageTemplate(Vec<TemplatePiece>);
+
+impl std::str::FromStr for MessageTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => anyhow::bail!("Unterminated '{{{}' in template", field),
+                        }
+                    }
+                    if !TEMPLATE_FIELDS.contains(&field.as_str()) {
+                        anyhow::bail!(
+                            "Unknown field '{{{}}}', expected one of {}",
+                            field,
+                            TEMPLATE_FIELDS.join(", ")
+                        );
+                    }
+                    if !literal.is_empty() {
+                        pieces.push(TemplatePiece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(TemplatePiece::Field(field));
+                }
+                '}' => anyhow::bail!("Unmatched '}}' in template"),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            pieces.push(TemplatePiece::Literal(literal));
+        }
+
+        Ok(MessageTemplate(pieces))
+    }
+}
+
+impl MessageTemplate {
+    fn render(&self, received: Timestamp, message: &Message) -> String {
+        self.0
+            .iter()
+            .map(|piece| match piece {
+                TemplatePiece::Literal(literal) => literal.clone(),
+                TemplatePiece::Field(field) => match field.as_str() {
+                    "time" => humantime::format_rfc3339_millis(received.system).to_string(),
+                    "from" => message.from.to_string(),
+                    "to" => message.to.to_string(),
+                    "cmd" => message.cmd1.to_string(),
+                    "cmd1" => format!("{:02x}", u8::from(message.cmd1)),
+                    "cmd2" => format!("{:02x}", u8::from(message.cmd2)),
+                    "level" => (u8::from(message.cmd2) as u32 * 100 / 0xff).to_string(),
+                    "group" => message
+                        .group()
+                        .map(|group| group.to_string())
+                        .unwrap_or_default(),
+                    "flags" => format!("{:?}", message.flags),
+                    "hops" => format!("{}/{}", message.hops_remaining, message.max_hops),
+                    _ => unreachable!(),
+                },
+            })
+            .collect()
+    }
+}
+
+/// Builds the JSON record printed for each message by `listen` with
+/// `--log-format json`.
+fn message_record(received: Timestamp, message: &Message) -> serde_json::Value {
+    json!({
+        "time": humantime::format_rfc3339_millis(received.system).to_string(),
+        "address": message.from.to_string(),
+        "to": message.to.to_string(),
+        "flags": message.flags.bits(),
+        "cmd": message.cmd1.to_string(),
+        "cmd1": u8::from(message.cmd1),
+        "cmd2": u8::from(message.cmd2),
+        "group": message.group(),
+        "hops_remaining": message.hops_remaining,
+        "max_hops": message.max_hops,
+        "frame": message.to_hex(),
+    })
+}
+
+async fn message_listen(
+    modem: &mut Modem,
+    format: Option<MessageTemplate>,
+    log_format: LogFormat,
+) -> Result<()> {
+    let mut stream = modem.listen_timestamped(Replay::None).await?;
+
+    while let Some((received, message)) = stream.next().await {
+        match (&format, log_format) {
+            (Some(template), _) => println!("{}", template.render(received, &message)),
+            (None, LogFormat::Json) => println!("{}", message_record(received, &message)),
+            (None, LogFormat::Text) => println!("{:02x?}", message),
+        }
     }
 
     Ok(())
@@ -214,12 +1361,227 @@ fn remap_level(level: u8) -> u8 {
     ((level as f32 / 100f32) * 255f32) as u8
 }
 
+async fn device_ping(modem: &mut Modem, address: Address, count: u32, interval: f32) -> Result<()> {
+    let mut rtts = Vec::with_capacity(count as usize);
+
+    for seq in 1..=count {
+        let start = Instant::now();
+        match modem.send_message((address, Command::Ping).into()).await {
+            Ok(_) => {
+                let rtt = start.elapsed();
+                println!(
+                    "Reply from {}: seq={} time={}ms",
+                    address,
+                    seq,
+                    rtt.as_millis()
+                );
+                rtts.push(rtt);
+            }
+            Err(e) => println!("No reply from {}: seq={} {}", address, seq, e),
+        }
+
+        if seq < count {
+            delay_for(Duration::from_secs_f32(interval)).await;
+        }
+    }
+
+    let received = rtts.len() as u32;
+    println!("--- {} ping statistics ---", address);
+    println!(
+        "{} sent, {} received, {}% lost",
+        count,
+        received,
+        (count - received) * 100 / count.max(1)
+    );
+
+    if let (Some(min), Some(max)) = (rtts.iter().min(), rtts.iter().max()) {
+        let avg = rtts.iter().sum::<Duration>() / received;
+        println!(
+            "rtt min/avg/max = {}/{}/{} ms",
+            min.as_millis(),
+            avg.as_millis(),
+            max.as_millis()
+        );
+    }
+
+    anyhow::ensure!(received > 0, "{} did not answer", address);
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WatchState {
+    Off,
+    On,
+    Level(u8),
+}
+
+impl std::fmt::Display for WatchState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WatchState::Off => write!(f, "off"),
+            WatchState::On => write!(f, "on"),
+            WatchState::Level(level) => write!(f, "{}%", *level as u32 * 100 / 0xff),
+        }
+    }
+}
+
+async fn query_level(modem: &mut Modem, address: Address) -> Option<WatchState> {
+    let message = (address, Command::StatusRequest(StatusKind::OnLevel)).into();
+    match modem
+        .send_message_with_timeout(message, Duration::from_secs(2))
+        .await
+    {
+        Ok(response) => match u8::from(response.cmd2) {
+            0 => Some(WatchState::Off),
+            level => Some(WatchState::Level(level)),
+        },
+        Err(e) => {
+            debug!("Failed to get status of {}: {}", address, e);
+            None
+        }
+    }
+}
+
+async fn device_watch(
+    modem: &mut Modem,
+    address: Address,
+    heartbeat_group: u8,
+    json: Option<PathBuf>,
+) -> Result<()> {
+    use std::io::Write;
+
+    let mut json = match json {
+        Some(path) => Some(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    let mut states = HashMap::new();
+    if let Some(state) = query_level(modem, address).await {
+        println!("{} group 1: {}", address, state);
+        states.insert(1u8, state);
+    }
+
+    let mut stream = modem.listen().await?;
+    while let Some(message) = stream.next().await {
+        // Only group broadcasts carry state changes. The cleanups that
+        // follow them are duplicates.
+        if message.from != address || !message.flags.contains(MessageFlags::BROADCAST_OR_NAK) {
+            continue;
+        }
+
+        let group = match message.group() {
+            Some(group) => group,
+            None => continue,
+        };
+        let state = match message.cmd1 {
+            Command::On | Command::OnFast => WatchState::On,
+            Command::Off | Command::OffFast => WatchState::Off,
+            Command::StopManualChange if group == 1 => match query_level(modem, address).await {
+                Some(state) => state,
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        let time = humantime::format_rfc3339_millis(std::time::SystemTime::now());
+        let previous = states.insert(group, state);
+        let line = if group == heartbeat_group {
+            println!("{} {} heartbeat ({})", time, address, state);
+            format!(
+                r#"{{"time":"{}","address":"{}","group":{},"event":"heartbeat","state":"{}"}}"#,
+                time, address, group, state
+            )
+        } else {
+            let previous = previous
+                .map(|previous| previous.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            println!(
+                "{} {} group {}: {} -> {}",
+                time, address, group, previous, state
+            );
+            format!(
+                r#"{{"time":"{}","address":"{}","group":{},"event":"change","from":"{}","to":"{}"}}"#,
+                time, address, group, previous, state
+            )
+        };
+
+        if let Some(file) = json.as_mut() {
+            writeln!(file, "{}", line)?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn operating_flags<F: OperatingFlags>(
+    modem: &mut Modem,
+    address: Address,
+    set: &[String],
+) -> Result<()> {
+    for change in set {
+        let change: OperatingFlagChange<F> = change
+            .parse()
+            .with_context(|| format!("Unknown {} flag '{}'", F::FAMILY, change))?;
+        modem.set_operating_flag(address, change).await?;
+    }
+
+    let flags: F = modem.get_operating_flags(address).await?;
+    let mut table = create_table();
+    for (name, on) in flags.settings() {
+        table.add_row(row![name, if on { "on" } else { "off" }]);
+    }
+    table.printstd();
+    Ok(())
+}
+
 async fn handle_device_command(modem: &mut Modem, command: DeviceCommand) -> Result<()> {
+    let turn_off = match command {
+        DeviceCommand::On {
+            ref common,
+            fast,
+            for_duration: Some(duration),
+            ..
+        } => Some((common.address, fast, duration)),
+        _ => None,
+    };
+
     match command {
+        DeviceCommand::On {
+            common,
+            level,
+            verify: true,
+            ..
+        } => {
+            modem
+                .set_and_verify(common.address, remap_level(level))
+                .await?;
+        }
+        DeviceCommand::On {
+            common,
+            level,
+            ramp: Some(ramp),
+            ..
+        } => {
+            anyhow::ensure!(ramp <= 31, "Ramp rate must be between 0 and 31");
+            modem
+                .send_message(Message::on_with_ramp(
+                    common.address,
+                    remap_level(level),
+                    ramp,
+                ))
+                .await?;
+        }
         DeviceCommand::On {
             common,
             level,
             fast,
+            ..
         } => {
             modem
                 .send_message(
@@ -232,7 +1594,14 @@ async fn handle_device_command(modem: &mut Modem, command: DeviceCommand) -> Res
                 )
                 .await?;
         }
-        DeviceCommand::Off { common, fast } => {
+        DeviceCommand::Off {
+            common,
+            verify: true,
+            ..
+        } => {
+            modem.set_and_verify(common.address, 0).await?;
+        }
+        DeviceCommand::Off { common, fast, .. } => {
             modem
                 .send_message(
                     (
@@ -243,23 +1612,44 @@ async fn handle_device_command(modem: &mut Modem, command: DeviceCommand) -> Res
                 )
                 .await?;
         }
-        DeviceCommand::Ping { common } => {
+        DeviceCommand::Ping {
+            common,
+            count,
+            interval,
+        } => device_ping(modem, common.address, count, interval).await?,
+        DeviceCommand::Beep { common } => {
             modem
-                .send_message((common.address, Command::Ping).into())
+                .send_message((common.address, Command::Beep).into())
                 .await?;
         }
-        DeviceCommand::Beep { common } => {
+        DeviceCommand::Locate {
+            common,
+            duration,
+            blink,
+        } => {
             modem
-                .send_message((common.address, Command::Beep).into())
+                .locate_device(
+                    common.address,
+                    if blink {
+                        LocatePattern::Blink
+                    } else {
+                        LocatePattern::Beep
+                    },
+                    Duration::from_secs(duration),
+                )
                 .await?;
         }
-        DeviceCommand::Status { common } => {
+        DeviceCommand::Status { common, kind } => {
             let response = modem
-                .send_message((common.address, Command::StatusRequest).into())
+                .send_message((common.address, Command::StatusRequest(kind)).into())
                 .await?;
             ptable!(
                 ["CMD1", format!("{:02x?}", response.cmd1)],
-                ["CMD2", format!("{:02x?}", response.cmd2)]
+                ["CMD2", format!("{:02x?}", response.cmd2)],
+                [
+                    "Status",
+                    format!("{:02x?}", Status::from_response(kind, &response))
+                ]
             );
         },
         DeviceCommand::Version { common } => {
@@ -267,29 +1657,206 @@ async fn handle_device_command(modem: &mut Modem, command: DeviceCommand) -> Res
                 .send_message((common.address, Command::VersionQuery).into())
                 .await?.cmd2));
         }
+        DeviceCommand::Flags {
+            common,
+            family,
+            set,
+        } => {
+            let family = match family {
+                Some(family) => family,
+                None => {
+                    let id = modem.identify(common.address).await?;
+                    id.family().with_context(|| {
+                        format!(
+                            "Unknown device category {:02x}.{:02x}, use --family",
+                            id.category, id.sub_category
+                        )
+                    })?
+                }
+            };
+
+            match family {
+                DeviceFamily::Dimmer => {
+                    operating_flags::<DimmerFlags>(modem, common.address, &set).await?
+                }
+                DeviceFamily::Keypad => {
+                    operating_flags::<KeypadFlags>(modem, common.address, &set).await?
+                }
+                DeviceFamily::Sensor => {
+                    operating_flags::<SensorFlags>(modem, common.address, &set).await?
+                }
+            }
+        }
+        DeviceCommand::Watch {
+            common,
+            heartbeat_group,
+            json,
+        } => device_watch(modem, common.address, heartbeat_group, json).await?,
+    }
+
+    if let Some((address, fast, duration)) = turn_off {
+        println!(
+            "Turning {} off in {}, press Ctrl-C to do it now",
+            address,
+            humantime::format_duration(duration)
+        );
+
+        // Either way the device goes off, so it isn't left on if we're
+        // interrupted.
+        tokio::select! {
+            _ = delay_for(duration) => {},
+            _ = tokio::signal::ctrl_c() => {},
+        }
+
+        modem
+            .send_message((address, if fast { Command::OffFast } else { Command::Off }).into())
+            .await?;
     }
 
     Ok(())
 }
 
+fn init_logging(format: LogFormat) {
+    match format {
+        LogFormat::Text => pretty_env_logger::init(),
+        LogFormat::Json => {
+            use std::io::Write;
+
+            let mut builder = pretty_env_logger::formatted_builder();
+            builder.format(|buf, record| {
+                let time = humantime::format_rfc3339_millis(std::time::SystemTime::now());
+                let record = json!({
+                    "time": time.to_string(),
+                    "level": record.level().to_string(),
+                    "module": record.module_path().unwrap_or_else(|| record.target()),
+                    "message": record.args().to_string(),
+                });
+                writeln!(buf, "{}", record)
+            });
+            if let Ok(filters) = std::env::var("RUST_LOG") {
+                builder.parse_filters(&filters);
+            }
+            builder.init();
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    pretty_env_logger::init();
-
     let app = App::from_args();
 
+    init_logging(app.log_format);
+
     debug!("{:#?}", app);
 
-    let mut modem = if let Some(device) = app.device {
+    // The emulator runs for as long as this is alive.
+    let mut _emulator: Option<Box<dyn std::any::Any>> = None;
+    let device = if app.emulate {
+        #[cfg(all(unix, feature = "emulator"))]
+        {
+            use plm::emulator::*;
+            let emulator = Emulator::spawn(EmulatorConfig::demo())
+                .with_context(|| "Failed to start emulator")?;
+            let path = emulator.path().to_path_buf();
+            _emulator = Some(Box::new(emulator));
+            Some(path)
+        }
+        #[cfg(not(all(unix, feature = "emulator")))]
+        anyhow::bail!("--emulate needs plm to be built with the emulator feature on a unix system")
+    } else {
+        app.device
+    };
+
+    if let AppCommand::Doctor = app.command {
+        return doctor(device, app.host).await;
+    }
+
+    let mut modem = if let Some(device) = device {
         Modem::from_path(device).with_context(|| "Failed to open modem")?
     } else {
-        let stream = TcpStream::connect(app.host.unwrap()).await.with_context(|| "Failed to connect")?;
-        Modem::new(stream)
+        Modem::from_tcp(&app.host.unwrap()).with_context(|| "Failed to connect")?
     };
 
+    if let Some(hops) = app.max_hops {
+        modem
+            .set_max_hops(hops)
+            .with_context(|| "Invalid --max-hops")?;
+    }
+    if let Some(timeout) = app.timeout {
+        modem.set_timeout(timeout);
+    }
+
     match app.command {
         AppCommand::Modem(ModemCommand::Info) => modem_info(&mut modem).await?,
+        AppCommand::Modem(ModemCommand::Config { set }) => modem_config(&mut modem, set).await?,
+        AppCommand::Modem(ModemCommand::Led { state }) => modem.set_led(state == "on").await?,
+        AppCommand::Modem(ModemCommand::Sleep) => modem.sleep().await?,
+        AppCommand::Modem(ModemCommand::Ping) => {
+            let elapsed = modem.healthcheck().await?;
+            println!("Modem answered in {}", humantime::format_duration(elapsed));
+        }
+        AppCommand::Modem(ModemCommand::Reset { yes }) => {
+            if !yes {
+                anyhow::bail!("This erases every link on the modem, pass --yes to go ahead");
+            }
+            modem.factory_reset().await?;
+            println!("Modem reset");
+        }
         AppCommand::Modem(ModemCommand::Links) => modem_links(&mut modem).await?,
+        AppCommand::Modem(ModemCommand::Groups {
+            aldb,
+            #[cfg(feature = "plan")]
+            plan,
+        }) => {
+            #[allow(unused_mut)]
+            let mut names = HashMap::new();
+            #[cfg(feature = "plan")]
+            {
+                if let Some(plan) = plan {
+                    let plan = plm::plan::Plan::from_path(&plan)
+                        .with_context(|| format!("Failed to load plan {}", plan.display()))?;
+                    for device in plan.devices {
+                        if let Some(alias) = device.alias {
+                            names.insert(device.address, alias);
+                        }
+                    }
+                }
+            }
+            modem_groups(&mut modem, aldb, names).await?
+        }
+        AppCommand::Modem(ModemCommand::Enroll {
+            controller,
+            responder,
+            group,
+        }) => {
+            let mode = if controller {
+                AllLinkMode::Controller
+            } else if responder {
+                AllLinkMode::Responder
+            } else {
+                AllLinkMode::Auto
+            };
+
+            modem_enroll(&mut modem, mode, group).await?
+        }
+        AppCommand::Modem(ModemCommand::Dump { start, end }) => {
+            modem_dump(&mut modem, start, end).await?
+        }
+        AppCommand::Modem(ModemCommand::Send { frame }) => {
+            let frame = Frame::from_hex(&frame).with_context(|| "Invalid frame")?;
+            println!("{}", modem.send_frame(frame).await?.to_hex());
+        }
+        AppCommand::Modem(ModemCommand::Tap) => {
+            let mut tap = modem.tap();
+            while let Some(tapped) = tap.next().await {
+                let (direction, bytes) = match tapped {
+                    Tapped::Read(bytes) => ("<-", bytes),
+                    Tapped::Written(bytes) => ("->", bytes),
+                };
+                let hex: Vec<_> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                println!("{} {}", direction, hex.join(" "));
+            }
+        }
         AppCommand::Modem(ModemCommand::LinkDevice {
             address,
             controller,
@@ -309,9 +1876,67 @@ async fn main() -> anyhow::Result<()> {
 
             modem_link(&mut modem, address, mode, group).await?
         }
-        AppCommand::Listen => message_listen(&mut modem).await?,
+        AppCommand::Listen { format } => message_listen(&mut modem, format, app.log_format).await?,
         AppCommand::Device(command) => handle_device_command(&mut modem, command).await?,
+        AppCommand::Doctor => unreachable!(),
+        AppCommand::Report { listen, json } => network_report(&mut modem, listen, json).await?,
+        AppCommand::Link(LinkCommand::Cross {
+            controller,
+            responder,
+            group,
+            level,
+            ramp,
+        }) => {
+            let ramp = ramp.map(ramp_rate).unwrap_or(DEFAULT_RAMP_RATE);
+            cross_link(
+                &mut modem,
+                controller,
+                responder,
+                group,
+                [remap_level(level), ramp, 0x01],
+            )
+            .await?
+        }
+        AppCommand::Link(LinkCommand::Delete { address, group }) => {
+            if modem.delete_link(address, group).await?.is_none() {
+                anyhow::bail!("The modem has no link to {} in group {}", address, group);
+            }
+        }
+        AppCommand::Scene(SceneCommand::Snapshot { output, addresses }) => {
+            let snapshot = modem.snapshot(addresses).await?;
+            match output {
+                Some(output) => std::fs::write(&output, snapshot.to_string())
+                    .with_context(|| format!("Failed to write {}", output.display()))?,
+                None => print!("{}", snapshot),
+            }
+        }
+        AppCommand::Scene(SceneCommand::Restore { file }) => {
+            let snapshot: Snapshot = std::fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read {}", file.display()))?
+                .parse()
+                .with_context(|| format!("Invalid snapshot {}", file.display()))?;
+            modem.restore(&snapshot).await?;
+        }
+        AppCommand::X10 {
+            house,
+            unit,
+            command,
+        } => {
+            if !(1..=16).contains(&unit) {
+                anyhow::bail!("The X10 unit must be from 1 to 16");
+            }
+            modem.send_x10(house, unit, command).await?
+        }
+        #[cfg(feature = "plan")]
+        AppCommand::Plan(command) => handle_plan_command(&mut modem, command).await?,
+        #[cfg(feature = "grpc")]
+        AppCommand::Serve { grpc } => {
+            println!("Serving gRPC on {}", grpc);
+            return Ok(plm::grpc::serve(modem, grpc).await?);
+        }
     }
 
+    // Don't exit with anything still on its way to the modem.
+    modem.close(true).await?;
     Ok(())
 }
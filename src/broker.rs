@@ -1,154 +1,1363 @@
+use std::collections::{HashMap, VecDeque};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::{
-    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
-    future::FutureExt,
+    channel::mpsc::{self, unbounded, UnboundedSender},
+    future::{self, Fuse, Future, FutureExt},
     select,
     sink::SinkExt,
     stream::{Stream, StreamExt},
 };
 
-use log::debug;
+use futures_timer::Delay;
+
+use log::{debug, warn};
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Handle;
+use tokio::sync::watch;
 use tokio_serial::{DataBits, FlowControl, Parity, Serial, SerialPortSettings, StopBits};
 use tokio_util::codec::*;
 
 use crate::error::*;
 use crate::frame::*;
+use crate::queue::*;
+use crate::retry::*;
+use crate::tap::*;
+use crate::tcp::*;
+
+/// How long to wait for the modem to respond to a frame before giving up,
+/// unless set with
+/// [ModemBuilder::response_timeout](crate::ModemBuilder::response_timeout).
+pub const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sent to wake the modem from RF sleep. Any byte will do, and this one
+/// can't be mistaken for the start of a frame.
+const WAKE_BYTE: u8 = 0x00;
+
+/// How long the modem takes to wake up before it accepts commands.
+const WAKE_DURATION: Duration = Duration::from_millis(100);
+
+/// The number of received frames kept around for [Replay], unless set
+/// with [ModemBuilder::replay_capacity](crate::ModemBuilder::replay_capacity).
+pub const REPLAY_CAPACITY: usize = 64;
+
+/// The baud rate of a serial modem, unless set with
+/// [ModemBuilder::baud_rate](crate::ModemBuilder::baud_rate).
+pub const DEFAULT_BAUD_RATE: u32 = 19200;
+
+/// The number of frames and other requests that can be queued for the
+/// modem before senders wait, unless set with
+/// [ModemBuilder::command_capacity](crate::ModemBuilder::command_capacity).
+pub const DEFAULT_COMMAND_CAPACITY: usize = 64;
+
+/// The least time between the end of one exchange with the modem and the
+/// next frame sent, unless set with
+/// [ModemBuilder::send_gap](crate::ModemBuilder::send_gap). Frames sent
+/// any sooner are often refused.
+pub const DEFAULT_SEND_GAP: Duration = Duration::from_millis(20);
+
+/// How a [Broker] talks to the modem and how much it keeps around.
+#[derive(Debug, Clone)]
+pub(crate) struct BrokerConfig {
+    pub baud_rate: u32,
+    pub replay_capacity: usize,
+    pub max_buffered_bytes: usize,
+    /// Reopens a serial port whose connection is lost, trying as often as
+    /// the policy allows.
+    pub reconnect: Option<RetryPolicy>,
+    /// How many messages for the event loop can be queued before senders
+    /// wait.
+    pub command_capacity: usize,
+    /// How many frames can wait for each listener.
+    pub listener_capacity: usize,
+    pub backpressure: Backpressure,
+    /// The least time between the end of one exchange and the next send.
+    pub send_gap: Duration,
+    pub response_timeout: Duration,
+    /// Enables TCP keepalive on network connections, probing this often.
+    pub keepalive: Option<Duration>,
+    /// Runs the event loop as a task on this runtime rather than on a
+    /// thread with a runtime of its own.
+    pub runtime: Option<Handle>,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        BrokerConfig {
+            baud_rate: DEFAULT_BAUD_RATE,
+            replay_capacity: REPLAY_CAPACITY,
+            max_buffered_bytes: DEFAULT_MAX_BUFFERED_BYTES,
+            reconnect: None,
+            command_capacity: DEFAULT_COMMAND_CAPACITY,
+            listener_capacity: DEFAULT_LISTENER_CAPACITY,
+            backpressure: Backpressure::default(),
+            send_gap: DEFAULT_SEND_GAP,
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+            keepalive: Some(DEFAULT_TCP_KEEPALIVE),
+            runtime: None,
+        }
+    }
+}
+
+/// Recently received frames to deliver to a new listener ahead of live
+/// traffic, so a response that arrives before the listener is added
+/// isn't missed. At most [REPLAY_CAPACITY] frames are kept by default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Replay {
+    /// Only deliver frames received from now on.
+    None,
+    /// Replay up to this many of the most recent frames.
+    Frames(usize),
+    /// Replay the frames received within this long.
+    Within(Duration),
+}
+
+fn replayed(
+    recent: &VecDeque<(Timestamp, Frame)>,
+    replay: Replay,
+    now: Instant,
+) -> Vec<(Timestamp, Frame)> {
+    let skip = match replay {
+        Replay::None => recent.len(),
+        Replay::Frames(count) => recent.len().saturating_sub(count),
+        Replay::Within(duration) => recent
+            .iter()
+            .take_while(|(received, _)| now.saturating_duration_since(received.instant) > duration)
+            .count(),
+    };
+
+    recent.iter().skip(skip).cloned().collect()
+}
+
+/// Whether a [Modem](super::Modem) can currently talk to the modem.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionState {
+    Connected,
+    /// The connection was lost and the port is being reopened. Frames
+    /// sent meanwhile wait until it is back.
+    Reconnecting,
+    /// The connection was lost for good.
+    Disconnected,
+}
+
+/// Counts of errors seen by a [Modem](super::Modem) since it was opened.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ErrorCounts {
+    /// Frames the modem did not acknowledge.
+    pub not_acknowledged: u64,
+    /// Frames the modem never responded to.
+    pub timeouts: u64,
+    /// Received data that could not be decoded.
+    pub decode: u64,
+    /// Failures writing to the modem.
+    pub io: u64,
+}
+
+/// A snapshot of the state of a [Modem](super::Modem), as returned by
+/// [Modem::health](super::Modem::health).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Health {
+    /// False once the connection to the modem has been lost.
+    pub connected: bool,
+    /// When the modem last responded to a frame.
+    pub last_exchange: Option<Instant>,
+    /// The number of frames waiting to be sent.
+    pub queue_depth: usize,
+    /// The number of listeners receiving incoming frames.
+    pub listeners: usize,
+    /// The number of times the modem reported being factory reset.
+    pub resets: u64,
+    /// Frames discarded because a listener fell behind, under
+    /// [Backpressure::DropOldest].
+    pub dropped_frames: u64,
+    /// Listeners ended because they fell behind, under
+    /// [Backpressure::DropListener].
+    pub dropped_listeners: u64,
+    pub errors: ErrorCounts,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            connected: true,
+            last_exchange: None,
+            queue_depth: 0,
+            listeners: 0,
+            resets: 0,
+            dropped_frames: 0,
+            dropped_listeners: 0,
+            errors: ErrorCounts::default(),
+        }
+    }
+}
+
+impl Health {
+    fn record(&mut self, result: &Result<Frame, Error>) {
+        match result {
+            Ok(_) => self.last_exchange = Some(Instant::now()),
+            Err(Error::NotAcknowledged) => self.errors.not_acknowledged += 1,
+            Err(Error::Timeout) => self.errors.timeouts += 1,
+            Err(Error::Parse { .. })
+            | Err(Error::NomError(_))
+            | Err(Error::BufferOverflow { .. }) => self.errors.decode += 1,
+            Err(Error::IoError(_)) => self.errors.io += 1,
+            Err(_) => {}
+        }
+    }
+}
+
+/// The serial backend adds the `\\.\` device namespace prefix that
+/// Windows needs for COM10 and above, so strip it if the caller already
+/// did.
+#[cfg(windows)]
+fn port_path(path: &Path) -> &Path {
+    path.to_str()
+        .and_then(|path| path.strip_prefix(r"\\.\"))
+        .map(Path::new)
+        .unwrap_or(path)
+}
+
+#[cfg(not(windows))]
+fn port_path(path: &Path) -> &Path {
+    path
+}
 
 pub enum BrokerMessage {
     AddListener {
-        listener: UnboundedSender<Frame>,
+        listener: QueueSender<(Timestamp, Frame)>,
+        replay: Replay,
     },
+    /// Sends `frame`, answering with the modem's response or
+    /// [Error::Timeout] if there is none within `timeout`.
     SendFrame {
         frame: Frame,
+        timeout: Duration,
         responder: UnboundedSender<Result<Frame, Error>>,
     },
+    /// Answered once every frame queued before it has been sent and its
+    /// response handled.
+    Flush { responder: UnboundedSender<()> },
+    /// Wakes the modem from RF sleep.
+    Wake {
+        responder: UnboundedSender<Result<(), Error>>,
+    },
+    /// Stops the event loop and closes the port, answering once it's done.
+    /// Frames queued ahead of this are sent first unless
+    /// [Broker::close] was told not to drain.
+    Close { responder: UnboundedSender<()> },
+    /// Delivers the next acknowledgement from the device at `from`,
+    /// preferably the one for `cmd1`, or of the cleanup for `group`.
+    ExpectAck {
+        from: Address,
+        cmd1: Option<u8>,
+        group: Option<u8>,
+        responder: UnboundedSender<Frame>,
+    },
+}
+
+/// A sender waiting for a device to acknowledge a direct message. A `cmd1`
+/// of `None` takes any acknowledgement from the device. A `group` is set
+/// for a group cleanup, which is acknowledged with the group in cmd2.
+struct PendingAck {
+    from: Address,
+    cmd1: Option<u8>,
+    group: Option<u8>,
+    responder: UnboundedSender<Frame>,
+}
+
+/// Hands an acknowledgement to whoever is waiting for it. A device
+/// normally echoes the command it acknowledges, but some (e.g. answering a
+/// status request) don't, so those senders wait with no `cmd1` and take
+/// whatever the device sends that nobody else was waiting for. Anything
+/// else is left for the listeners.
+fn deliver_ack(pending: &mut Vec<PendingAck>, frame: &Frame) {
+    let (from, flags, cmd1, cmd2) = match frame {
+        Frame::StandardInsteonReceive {
+            from,
+            flags,
+            cmd1,
+            cmd2,
+            ..
+        }
+        | Frame::ExtendedInsteonReceive {
+            from,
+            flags,
+            cmd1,
+            cmd2,
+            ..
+        } => (*from, *flags, *cmd1, *cmd2),
+        _ => return,
+    };
+    if !flags.contains(MessageFlags::ACK) {
+        return;
+    }
+    // A cleanup's ACK carries the group in cmd2.
+    let group = if flags.contains(MessageFlags::GROUP) {
+        Some(cmd2)
+    } else {
+        None
+    };
+
+    pending.retain(|ack| !ack.responder.is_closed());
+    let index = pending
+        .iter()
+        .position(|ack| ack.from == from && ack.group == group && ack.cmd1 == Some(cmd1))
+        .or_else(|| {
+            pending
+                .iter()
+                .position(|ack| ack.from == from && ack.group == group && ack.cmd1.is_none())
+        });
+    if let Some(index) = index {
+        let _ = pending
+            .remove(index)
+            .responder
+            .unbounded_send(frame.clone());
+    }
 }
 
+/// State shared between a [Broker] and its event loop.
+#[derive(Default)]
+struct Shared {
+    health: Mutex<Health>,
+    stats: Mutex<DecoderStats>,
+    last_seen: Mutex<HashMap<Address, Instant>>,
+    /// Set when closing without draining, so queued frames are dropped.
+    closing: AtomicBool,
+    taps: Taps,
+}
+
+/// Counts a frame in [Health::queue_depth] while it's being queued, taking
+/// it back off if the send fails or is dropped before the frame gets to
+/// the event loop.
+struct Queued<'a>(Option<&'a Shared>);
+
+impl<'a> Queued<'a> {
+    fn new(shared: &'a Shared) -> Self {
+        shared.health.lock().unwrap().queue_depth += 1;
+        Queued(Some(shared))
+    }
+
+    /// The frame is queued, so the event loop takes it off when it's sent.
+    fn sent(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        if let Some(shared) = self.0 {
+            shared.health.lock().unwrap().queue_depth -= 1;
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Broker {
-    sender: UnboundedSender<BrokerMessage>,
+    sender: mpsc::Sender<BrokerMessage>,
+    shared: Arc<Shared>,
+    state: watch::Receiver<ConnectionState>,
+    listener_capacity: usize,
+    response_timeout: Duration,
 }
 
-async fn event_loop(
-    mut receiver: UnboundedReceiver<BrokerMessage>,
-    mut framed: Framed<impl AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static, FrameCodec>,
-) {
-    let mut listeners = Vec::<UnboundedSender<Frame>>::new();
+/// Why the event loop stopped.
+enum Stopped {
+    /// Every [Broker] was dropped, or one asked to close and is waiting to
+    /// hear that the port is closed.
+    Closed(Option<UnboundedSender<()>>),
+    /// The connection to the modem failed.
+    Lost,
+}
 
+/// Reopens the modem's port after the connection is lost, trying again as
+/// `policy` allows. Returns `None` once it gives up.
+async fn reopen<T, Fut>(open: &mut impl FnMut() -> Fut, policy: &RetryPolicy) -> Option<T>
+where
+    Fut: Future<Output = std::io::Result<T>>,
+{
+    let mut attempt = 1;
     loop {
-        select! {
-            maybe_frame = framed.next().fuse() => match(maybe_frame) {
-                Some(Ok(frame)) => {
-                    debug!("Received Frame: {:02x?}", frame);
+        match open().await {
+            Ok(io) => return Some(io),
+            Err(e) => {
+                let delay = policy.delay(attempt)?;
+                debug!(
+                    "Failed to reopen the modem, {}, retrying after {:?}",
+                    e, delay
+                );
+                Delay::new(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
 
-                    let mut new_listeners = Vec::with_capacity(listeners.len());
-                    while let Some(mut listener) = listeners.pop() {
-                        if listener.send(frame.clone()).await.is_ok() {
-                            new_listeners.push(listener);
-                        }
+async fn event_loop<T, F, Fut>(
+    mut receiver: mpsc::Receiver<BrokerMessage>,
+    mut framed: Framed<T, FrameCodec>,
+    shared: Arc<Shared>,
+    state: watch::Sender<ConnectionState>,
+    mut frames: Frames,
+    mut exchanges: Exchanges,
+    mut reconnect: Option<(F, RetryPolicy)>,
+) where
+    T: AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::io::Result<T>>,
+{
+    let mut closer = None;
+    loop {
+        if let Stopped::Closed(responder) = event_loop_inner(
+            &mut receiver,
+            &mut framed,
+            &shared,
+            &mut frames,
+            &mut exchanges,
+        )
+        .await
+        {
+            closer = responder;
+            break;
+        }
+
+        let (open, policy) = match reconnect.as_mut() {
+            Some(reconnect) => reconnect,
+            None => break,
+        };
+        shared.health.lock().unwrap().connected = false;
+        let _ = state.broadcast(ConnectionState::Reconnecting);
+        match reopen(open, policy).await {
+            Some(io) => {
+                warn!("Reconnected to the modem");
+                // Keep the codec, and with it the decoder's stats.
+                framed = Framed::new(io, framed.into_parts().codec);
+                shared.health.lock().unwrap().connected = true;
+                let _ = state.broadcast(ConnectionState::Connected);
+            }
+            None => {
+                warn!("Gave up reconnecting to the modem");
+                break;
+            }
+        }
+    }
+    // Close the port and end every listen before saying so.
+    drop(framed);
+    drop(frames);
+    drop(exchanges);
+    shared.health.lock().unwrap().connected = false;
+    let _ = state.broadcast(ConnectionState::Disconnected);
+    if let Some(mut closer) = closer {
+        let _ = closer.send(()).await;
+    }
+}
+
+/// Reads the next frame, publishing the decoder's updated stats.
+async fn next_frame(
+    framed: &mut Framed<impl AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static, FrameCodec>,
+    shared: &Shared,
+) -> Option<Result<Frame, Error>> {
+    let frame = framed.next().await;
+    *shared.stats.lock().unwrap() = framed.codec().stats();
+    frame
+}
+
+/// Spaces out the frames sent to the modem, which refuses a frame that
+/// follows too closely on the last exchange.
+struct Pacing {
+    gap: Duration,
+    last: Option<Instant>,
+}
+
+impl Pacing {
+    fn new(gap: Duration) -> Self {
+        Pacing { gap, last: None }
+    }
+
+    /// Waits until the next frame can be sent.
+    async fn wait(&self) {
+        if let Some(last) = self.last {
+            let elapsed = last.elapsed();
+            if elapsed < self.gap {
+                Delay::new(self.gap - elapsed).await;
+            }
+        }
+    }
+
+    /// Notes that an exchange just ended.
+    fn done(&mut self) {
+        self.last = Some(Instant::now());
+    }
+}
+
+/// Where received frames go: the listeners, the senders waiting for
+/// acknowledgements and the frames kept for [Replay].
+struct Frames {
+    listeners: Vec<QueueSender<(Timestamp, Frame)>>,
+    backpressure: Backpressure,
+    recent: VecDeque<(Timestamp, Frame)>,
+    replay_capacity: usize,
+    acks: Vec<PendingAck>,
+}
+
+impl Frames {
+    fn new(config: &BrokerConfig) -> Self {
+        Frames {
+            listeners: Vec::new(),
+            backpressure: config.backpressure,
+            recent: VecDeque::with_capacity(config.replay_capacity + 1),
+            replay_capacity: config.replay_capacity,
+            acks: Vec::new(),
+        }
+    }
+
+    /// Hands `frame` to `listener`, returning false if the listener is gone.
+    async fn deliver(
+        &self,
+        listener: &mut QueueSender<(Timestamp, Frame)>,
+        frame: (Timestamp, Frame),
+        shared: &Shared,
+    ) -> bool {
+        match listener.send(frame, self.backpressure).await {
+            Delivery::Queued => true,
+            Delivery::DroppedOldest => {
+                shared.health.lock().unwrap().dropped_frames += 1;
+                true
+            }
+            Delivery::DroppedListener => {
+                warn!("Dropped a listener that fell behind");
+                shared.health.lock().unwrap().dropped_listeners += 1;
+                false
+            }
+            Delivery::Closed => false,
+        }
+    }
+
+    fn add_listener(&mut self, listener: QueueSender<(Timestamp, Frame)>, shared: &Shared) {
+        self.listeners.push(listener);
+        shared.health.lock().unwrap().listeners = self.listeners.len();
+    }
+
+    /// Hands a frame that isn't a response to anything to the senders and
+    /// listeners waiting on it, along with when it was `received`.
+    async fn received(&mut self, received: Timestamp, frame: Frame, shared: &Shared) {
+        debug!("Received Frame: {:02x?}", frame);
+
+        if let Some(from) = frame.sender() {
+            shared
+                .last_seen
+                .lock()
+                .unwrap()
+                .insert(from, Instant::now());
+        }
+
+        if frame == Frame::UserResetDetected {
+            warn!("The modem was factory reset");
+            shared.health.lock().unwrap().resets += 1;
+        }
+
+        deliver_ack(&mut self.acks, &frame);
+
+        self.recent.push_back((received, frame.clone()));
+        if self.recent.len() > self.replay_capacity {
+            self.recent.pop_front();
+        }
+
+        let mut new_listeners = Vec::with_capacity(self.listeners.len());
+        while let Some(mut listener) = self.listeners.pop() {
+            if self
+                .deliver(&mut listener, (received, frame.clone()), shared)
+                .await
+            {
+                new_listeners.push(listener);
+            }
+        }
+
+        self.listeners = new_listeners;
+        shared.health.lock().unwrap().listeners = self.listeners.len();
+    }
+}
+
+/// A frame sent to the modem that is waiting for its response.
+struct InFlight {
+    frame: Frame,
+    timeout: Duration,
+    responder: UnboundedSender<Result<Frame, Error>>,
+}
+
+/// The exchange with the modem in progress, if any, and the requests held
+/// back until it's over. The modem only handles one frame at a time, and
+/// wants a moment between them.
+struct Exchanges {
+    in_flight: Option<InFlight>,
+    /// Fires when the frame in flight has waited too long.
+    expired: Fuse<Delay>,
+    held: VecDeque<BrokerMessage>,
+    /// How many requests can be held back before the loop stops taking
+    /// more, leaving senders to wait.
+    capacity: usize,
+    pacing: Pacing,
+}
+
+impl Exchanges {
+    fn new(config: &BrokerConfig) -> Self {
+        Exchanges {
+            in_flight: None,
+            expired: Fuse::terminated(),
+            held: VecDeque::new(),
+            capacity: config.command_capacity.max(1),
+            pacing: Pacing::new(config.send_gap),
+        }
+    }
+
+    /// Answers the frame in flight, returning false if there isn't one.
+    fn respond(&mut self, response: Result<Frame, Error>, shared: &Shared) -> bool {
+        let responder = match self.in_flight.take() {
+            Some(in_flight) => in_flight.responder,
+            None => return false,
+        };
+        self.expired = Fuse::terminated();
+        debug!("Received Response: {:02x?}", response);
+        shared.health.lock().unwrap().record(&response);
+        let _ = responder.unbounded_send(response);
+        self.pacing.done();
+        true
+    }
+
+    /// Starts on a request that needs the modem, returning why the loop
+    /// should stop if it should.
+    async fn start(
+        &mut self,
+        msg: BrokerMessage,
+        framed: &mut Framed<impl AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static, FrameCodec>,
+        shared: &Shared,
+    ) -> Option<Stopped> {
+        match msg {
+            BrokerMessage::SendFrame {
+                frame,
+                timeout,
+                mut responder,
+            } => {
+                debug!("Sending Frame: {:02x?}", frame);
+                shared.health.lock().unwrap().queue_depth -= 1;
+                if shared.closing.load(Ordering::SeqCst) {
+                    let _ = responder.send(Err(Error::Disconnected)).await;
+                    return None;
+                }
+                self.pacing.wait().await;
+                match framed.send(frame.clone()).await {
+                    Ok(()) => {
+                        // Don't let a modem that never echoes stall everything else.
+                        self.expired = Delay::new(timeout).fuse();
+                        self.in_flight = Some(InFlight {
+                            frame,
+                            timeout,
+                            responder,
+                        });
                     }
+                    Err(e) => {
+                        self.pacing.done();
+                        let e = Err(e);
+                        shared.health.lock().unwrap().record(&e);
+                        let _ = responder.send(e).await;
+                    }
+                }
+            }
+            BrokerMessage::Wake { mut responder } => {
+                debug!("Waking the modem");
+                let io = framed.get_mut();
+                let mut result = io.write_all(&[WAKE_BYTE]).await;
+                if result.is_ok() {
+                    result = io.flush().await;
+                }
+                if result.is_ok() {
+                    Delay::new(WAKE_DURATION).await;
+                }
+                let _ = responder.send(result.map_err(Error::from)).await;
+            }
+            BrokerMessage::Flush { mut responder } => {
+                // Sends are handled in order, so everything queued ahead of
+                // this is already done.
+                let _ = responder.send(()).await;
+            }
+            BrokerMessage::Close { responder } => {
+                debug!("Closing the connection to the modem");
+                return Some(Stopped::Closed(Some(responder)));
+            }
+            BrokerMessage::AddListener { .. } | BrokerMessage::ExpectAck { .. } => {
+                unreachable!("handled by the event loop")
+            }
+        }
+        None
+    }
+}
 
-                    listeners = new_listeners;
+async fn event_loop_inner(
+    receiver: &mut mpsc::Receiver<BrokerMessage>,
+    framed: &mut Framed<impl AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static, FrameCodec>,
+    shared: &Shared,
+    frames: &mut Frames,
+    exchanges: &mut Exchanges,
+) -> Stopped {
+    loop {
+        while exchanges.in_flight.is_none() {
+            let msg = match exchanges.held.pop_front() {
+                Some(msg) => msg,
+                None => break,
+            };
+            if let Some(stopped) = exchanges.start(msg, framed, shared).await {
+                return stopped;
+            }
+        }
+
+        let next_msg = if exchanges.held.len() < exchanges.capacity {
+            receiver.next().left_future()
+        } else {
+            future::pending().right_future()
+        };
+
+        select! {
+            _ = &mut exchanges.expired => {
+                if let Some(in_flight) = &exchanges.in_flight {
+                    warn!("No response within {:?}", in_flight.timeout);
+                }
+                exchanges.respond(Err(Error::Timeout), shared);
+            },
+            maybe_frame = next_frame(framed, shared).fuse() => match(maybe_frame) {
+                Some(Ok(frame)) => {
+                    let received = Timestamp::now();
+                    let answers = match &exchanges.in_flight {
+                        Some(in_flight) => in_flight.frame.is_response(&frame),
+                        None => false,
+                    };
+                    if answers {
+                        exchanges.respond(Ok(frame), shared);
+                    } else {
+                        // Anything else, e.g. a device answering an earlier
+                        // send, can arrive ahead of the response.
+                        frames.received(received, frame, shared).await;
+                    }
+                },
+                Some(Err(e @ Error::IoError(_))) => {
+                    warn!("Lost the connection to the modem, {}", e);
+                    shared.health.lock().unwrap().record(&Err(e));
+                    exchanges.respond(Err(Error::Disconnected), shared);
+                    return Stopped::Lost;
+                },
+                Some(Err(e)) if exchanges.in_flight.is_none() => {
+                    // The decoder has already skipped past the bad bytes.
+                    warn!("Failed to decode frame, {}", e);
+                    shared.health.lock().unwrap().record(&Err(e));
+                },
+                Some(Err(e)) => {
+                    // A refusal or garbled frame while waiting is most likely
+                    // the response.
+                    if let Error::Parse { .. } = &e {
+                        warn!("Failed to decode response, {}", e);
+                    }
+                    exchanges.respond(Err(e), shared);
+                },
+                None => {
+                    exchanges.respond(Err(Error::Disconnected), shared);
+                    return Stopped::Lost;
                 },
-                _ => break,
             },
-            msg = receiver.next() => {
-                match (msg) {
-                    Some(BrokerMessage::AddListener{ listener }) => {
-                        listeners.push(listener);
-                    },
-                    Some(BrokerMessage::SendFrame{ frame, mut responder }) => {
-                        debug!("Sending Frame: {:02x?}", frame);
-                        if let Err(e) = framed.send(frame).await {
-                            let _ = responder.send(Err(e)).await;
-                            continue;
-                        }
+            msg = next_msg.fuse() => match msg {
+                Some(BrokerMessage::AddListener{ mut listener, replay }) => {
+                    let mut open = true;
+                    for frame in replayed(&frames.recent, replay, Instant::now()) {
+                        open = open && frames.deliver(&mut listener, frame, shared).await;
+                    }
+                    if open {
+                        frames.add_listener(listener, shared);
+                    }
+                },
+                Some(BrokerMessage::ExpectAck{ from, cmd1, group, responder }) => {
+                    frames.acks.push(PendingAck { from, cmd1, group, responder });
+                },
+                Some(msg) => {
+                    if exchanges.in_flight.is_some() {
+                        exchanges.held.push_back(msg);
+                    } else if let Some(stopped) = exchanges.start(msg, framed, shared).await {
+                        return stopped;
+                    }
+                },
+                None => return Stopped::Closed(None), // No more messages coming, exit
+            }
+        }
+    }
+}
 
-                        match framed.next().await {
-                            None => {
-                                let _ = responder.send(Err(Error::Disconnected)).await;
-                                break;
-                            },
-                            Some(response) => {
-                                debug!("Received Response: {:02x?}", response);
-                                let _ = responder.send(response).await;
+impl Broker {
+    pub fn from_path(
+        path: impl AsRef<Path> + Send + 'static,
+        config: BrokerConfig,
+    ) -> Result<Broker, std::io::Error> {
+        let baud_rate = config.baud_rate;
+        let path = port_path(path.as_ref()).to_path_buf();
+        let open = move || {
+            let settings = SerialPortSettings {
+                baud_rate,
+                data_bits: DataBits::Eight,
+                flow_control: FlowControl::None,
+                parity: Parity::None,
+                stop_bits: StopBits::One,
+                timeout: Duration::from_millis(100),
+            };
+            Serial::from_path(&path, &settings)
+        };
+        // Opening a serial port doesn't block, so it's reopened the same way.
+        let reopen = open.clone();
+        Self::from_open(open, move || future::ready(reopen()), config)
+    }
+
+    /// Connects to a modem over TCP at `addr`, given as `host` or
+    /// `host:port`, e.g. a serial server or an Insteon Hub Pro.
+    pub fn from_tcp(addr: &str, config: BrokerConfig) -> Result<Broker, std::io::Error> {
+        let addr = with_default_port(addr, DEFAULT_TCP_PORT);
+        let reopen_addr = addr.clone();
+        let keepalive = config.keepalive;
+        Self::from_open(
+            move || connect(&addr, keepalive),
+            move || connect_async(reopen_addr.clone(), keepalive),
+            config,
+        )
+    }
+
+    /// Opens the modem's connection with `open`, blocking the caller since
+    /// this is done before the event loop starts. If the config allows,
+    /// `reopen` is used to reconnect from within the event loop, so it
+    /// mustn't block the runtime.
+    fn from_open<T, F, R, Fut>(
+        open: F,
+        mut reopen: R,
+        config: BrokerConfig,
+    ) -> Result<Broker, std::io::Error>
+    where
+        T: AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static,
+        F: FnOnce() -> std::io::Result<T> + Send + 'static,
+        R: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = std::io::Result<T>> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(config.command_capacity);
+        let shared = Arc::new(Shared::default());
+        let taps = shared.taps.clone();
+        let open = {
+            let taps = taps.clone();
+            move || open().map(|port| Tap::new(port, taps))
+        };
+        let reopen = move || {
+            let taps = taps.clone();
+            reopen().map(|port| port.map(|port| Tap::new(port, taps)))
+        };
+        let loop_shared = shared.clone();
+        let (state_sender, state) = watch::channel(ConnectionState::Connected);
+        let frames = Frames::new(&config);
+        let exchanges = Exchanges::new(&config);
+        let (max_buffered, reconnect) = (config.max_buffered_bytes, config.reconnect);
+        let start = move |port, reopen| {
+            event_loop(
+                receiver,
+                Framed::new(port, FrameCodec::with_max_buffered(max_buffered)),
+                loop_shared,
+                state_sender,
+                frames,
+                exchanges,
+                reconnect.map(|policy| (reopen, policy)),
+            )
+        };
+
+        match &config.runtime {
+            Some(runtime) => {
+                // The port has to be opened from within the runtime that
+                // will drive it.
+                let port = runtime.enter(open)?;
+                runtime.spawn(start(port, reopen));
+            }
+            None => {
+                let (init_sender, init_receiver) = channel();
+
+                thread::spawn(move || {
+                    let mut rt = tokio::runtime::Runtime::new().unwrap();
+                    rt.block_on(async move {
+                        match open() {
+                            Ok(port) => {
+                                init_sender.send(Ok(())).unwrap();
+                                start(port, reopen).await
                             }
+                            Err(e) => init_sender.send(Err(e)).unwrap(),
                         }
-                    },
-                    None => break, // No more messages coming, exit
-                }
+                    });
+                });
+
+                // Make sure we were able to create the port
+                init_receiver.recv().unwrap()?;
             }
         }
+
+        Ok(Broker {
+            sender,
+            shared,
+            state,
+            listener_capacity: config.listener_capacity,
+            response_timeout: config.response_timeout,
+        })
     }
-}
 
-impl Broker {
-    pub fn from_path(path: impl AsRef<Path> + Send + 'static) -> Result<Broker, std::io::Error> {
-        let (sender, receiver) = unbounded();
+    pub fn new(
+        handle: impl AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static,
+        config: BrokerConfig,
+    ) -> Broker {
+        let (sender, receiver) = mpsc::channel(config.command_capacity);
+        let shared = Arc::new(Shared::default());
+        let loop_shared = shared.clone();
+        let (state_sender, state) = watch::channel(ConnectionState::Connected);
 
-        let (init_sender, init_receiver) = channel();
-
-        thread::spawn(move || {
-            let mut rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(async move {
-                let settings = SerialPortSettings {
-                    baud_rate: 19200,
-                    data_bits: DataBits::Eight,
-                    flow_control: FlowControl::None,
-                    parity: Parity::None,
-                    stop_bits: StopBits::One,
-                    timeout: Duration::from_millis(100),
-                };
-
-                match Serial::from_path(path.as_ref(), &settings) {
-                    Ok(port) => {
-                        init_sender.send(Ok(())).unwrap();
-                        event_loop(receiver, Framed::new(port, FrameCodec())).await
-                    }
-                    Err(e) => init_sender.send(Err(e)).unwrap(),
-                }
-            });
-        });
+        let event_loop = event_loop(
+            receiver,
+            Framed::new(
+                Tap::new(handle, shared.taps.clone()),
+                FrameCodec::with_max_buffered(config.max_buffered_bytes),
+            ),
+            loop_shared,
+            state_sender,
+            Frames::new(&config),
+            Exchanges::new(&config),
+            None::<(fn() -> future::Ready<std::io::Result<_>>, RetryPolicy)>,
+        );
+        match &config.runtime {
+            Some(runtime) => {
+                runtime.spawn(event_loop);
+            }
+            None => {
+                thread::spawn(move || {
+                    let mut rt = tokio::runtime::Runtime::new().unwrap();
+                    rt.block_on(event_loop);
+                });
+            }
+        }
 
-        // Make sure we were able to create the port
-        init_receiver.recv().unwrap()?;
-        Ok(Broker { sender })
+        Broker {
+            sender,
+            shared,
+            state,
+            listener_capacity: config.listener_capacity,
+            response_timeout: config.response_timeout,
+        }
     }
 
-    pub fn new(handle: impl AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static) -> Broker {
-        let (sender, receiver) = unbounded();
+    /// Returns a receiver that sees each change in the [ConnectionState].
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
 
-        thread::spawn(move || {
-            let mut rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(
-                async move { event_loop(receiver, Framed::new(handle, FrameCodec())).await },
-            );
-        });
+    pub fn health(&self) -> Health {
+        self.shared.health.lock().unwrap().clone()
+    }
+
+    pub fn stats(&self) -> DecoderStats {
+        *self.shared.stats.lock().unwrap()
+    }
 
-        Broker { sender }
+    pub fn last_seen(&self) -> HashMap<Address, Instant> {
+        self.shared.last_seen.lock().unwrap().clone()
     }
 
     pub async fn send(&mut self, frame: Frame) -> Result<Frame, Error> {
+        self.send_with_timeout(frame, self.response_timeout).await
+    }
+
+    /// Sends `frame` like [Broker::send], waiting up to `timeout` for the
+    /// response, e.g. for a frame the modem takes a while to act on.
+    pub async fn send_with_timeout(
+        &mut self,
+        frame: Frame,
+        timeout: Duration,
+    ) -> Result<Frame, Error> {
+        let (sender, mut receiver) = unbounded();
+        // Counted first, since the loop may take the frame off the queue
+        // right away. `send` isn't used, since it can still be waiting after
+        // the frame is queued.
+        let queued = Queued::new(&self.shared);
+        let commands = &mut self.sender;
+        future::poll_fn(|cx| commands.poll_ready(cx)).await?;
+        commands.start_send(BrokerMessage::SendFrame {
+            frame,
+            timeout,
+            responder: sender,
+        })?;
+        queued.sent();
+        receiver.next().await.ok_or_else(|| Error::Disconnected)?
+    }
+
+    pub async fn flush(&mut self) -> Result<(), Error> {
         let (sender, mut receiver) = unbounded();
         self.sender
-            .send(BrokerMessage::SendFrame {
-                frame,
+            .send(BrokerMessage::Flush { responder: sender })
+            .await?;
+        receiver.next().await.ok_or(Error::Disconnected)
+    }
+
+    pub async fn wake(&mut self) -> Result<(), Error> {
+        let (sender, mut receiver) = unbounded();
+        self.sender
+            .send(BrokerMessage::Wake { responder: sender })
+            .await?;
+        receiver.next().await.ok_or(Error::Disconnected)?
+    }
+
+    /// Returns the next acknowledgement of `cmd1` from the device at
+    /// `from`, or of anything if `cmd1` is `None`. With a `group`, waits
+    /// for the acknowledgement of that group's cleanup instead. Register
+    /// before sending, since the device may answer right away.
+    pub async fn expect_ack(
+        &mut self,
+        from: Address,
+        cmd1: Option<u8>,
+        group: Option<u8>,
+    ) -> Result<impl Stream<Item = Frame> + Unpin, Error> {
+        let (sender, receiver) = unbounded();
+        self.sender
+            .send(BrokerMessage::ExpectAck {
+                from,
+                cmd1,
+                group,
                 responder: sender,
             })
             .await?;
-        receiver.next().await.ok_or_else(|| Error::Disconnected)?
+        Ok(receiver)
+    }
+
+    /// Stops the event loop, closing the port and ending every listen.
+    /// With `drain`, frames already queued are sent first; otherwise they
+    /// fail with [Error::Disconnected]. Returns once the port is closed.
+    pub async fn close(&mut self, drain: bool) -> Result<(), Error> {
+        if !drain {
+            self.shared.closing.store(true, Ordering::SeqCst);
+        }
+
+        let (sender, mut receiver) = unbounded();
+        self.sender
+            .send(BrokerMessage::Close { responder: sender })
+            .await?;
+        // The loop may have ended before getting to the message, which
+        // closes the port just the same.
+        receiver.next().await;
+        Ok(())
     }
 
-    pub async fn listen(&mut self) -> Result<impl Stream<Item = Frame>, Error> {
+    /// Returns a copy of every byte read from and written to the modem
+    /// from now on.
+    pub fn tap(&self) -> impl Stream<Item = Tapped> + Send + Unpin {
         let (sender, receiver) = unbounded();
+        self.shared.taps.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub async fn listen(&mut self, replay: Replay) -> Result<impl Stream<Item = Frame>, Error> {
+        Ok(self
+            .listen_timestamped(replay)
+            .await?
+            .map(|(_, frame)| frame))
+    }
+
+    /// Like [Broker::listen], but delivers each frame along with when it
+    /// was received.
+    pub async fn listen_timestamped(
+        &mut self,
+        replay: Replay,
+    ) -> Result<impl Stream<Item = (Timestamp, Frame)>, Error> {
+        let (sender, receiver) = queue(self.listener_capacity);
         self.sender
-            .send(BrokerMessage::AddListener { listener: sender })
+            .send(BrokerMessage::AddListener {
+                listener: sender,
+                replay,
+            })
             .await?;
         Ok(receiver)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use std::time::SystemTime;
+
+    #[test]
+    fn replay() {
+        let now = Instant::now();
+        let at = |ago| Timestamp {
+            instant: now - Duration::from_secs(ago),
+            system: SystemTime::UNIX_EPOCH,
+        };
+        let recent: VecDeque<(Timestamp, Frame)> = vec![
+            (at(5), Frame::Reset),
+            (at(1), Frame::CancelAllLink),
+            (at(0), Frame::GetModemInfo),
+        ]
+        .into_iter()
+        .collect();
+        let frames = |replay| -> Vec<Frame> {
+            replayed(&recent, replay, now)
+                .into_iter()
+                .map(|(_, frame)| frame)
+                .collect()
+        };
+
+        assert_eq!(frames(Replay::None), vec![]);
+        assert_eq!(frames(Replay::Frames(1)), vec![Frame::GetModemInfo]);
+        assert_eq!(frames(Replay::Frames(10)).len(), 3);
+        assert_eq!(
+            frames(Replay::Within(Duration::from_secs(2))),
+            vec![Frame::CancelAllLink, Frame::GetModemInfo]
+        );
+        assert_eq!(replayed(&recent, Replay::Frames(1), now)[0].0, at(0));
+    }
+
+    #[test]
+    fn ack_matching() {
+        let device = Address::from([0x11, 0x22, 0x33]);
+        let ack = |cmd1| Frame::StandardInsteonReceive {
+            from: device,
+            to: Address::from([0x44, 0x85, 0x11]),
+            flags: MessageFlags::ACK,
+            hops_remaining: 3,
+            max_hops: 3,
+            cmd1,
+            cmd2: 0x00,
+        };
+
+        let (on, mut on_acks) = unbounded();
+        let mut pending = vec![PendingAck {
+            from: device,
+            cmd1: Some(0x11),
+            group: None,
+            responder: on,
+        }];
+
+        // Another command's ACK isn't taken by the sender waiting for On.
+        deliver_ack(&mut pending, &ack(0x13));
+        assert_eq!(pending.len(), 1);
+        assert_eq!(on_acks.next().now_or_never(), None);
+
+        let (status, mut status_acks) = unbounded();
+        pending.push(PendingAck {
+            from: device,
+            cmd1: None,
+            group: None,
+            responder: status,
+        });
+        deliver_ack(&mut pending, &ack(0x01));
+        assert_eq!(status_acks.next().now_or_never(), Some(Some(ack(0x01))));
+
+        deliver_ack(&mut pending, &ack(0x11));
+        assert_eq!(on_acks.next().now_or_never(), Some(Some(ack(0x11))));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn health() {
+        let mut health = Health::default();
+        health.record(&Err(Error::Timeout));
+        health.record(&Err(Error::NotAcknowledged));
+        health.record(&Err(Error::parse("frame", &[])));
+        assert_eq!(health.last_exchange, None);
+
+        health.record(&Ok(Frame::Reset));
+        assert!(health.last_exchange.is_some());
+        assert_eq!(
+            health.errors,
+            ErrorCounts {
+                not_acknowledged: 1,
+                timeouts: 1,
+                decode: 1,
+                io: 0,
+            }
+        );
+    }
+
+    #[async_std::test]
+    async fn pacing() {
+        let mut pacing = Pacing::new(Duration::from_millis(50));
+        let start = Instant::now();
+        pacing.wait().await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        pacing.done();
+        pacing.wait().await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    /// A modem that answers each frame written to it with the next of
+    /// `replies`, and once they run out never answers at all.
+    struct Scripted {
+        replies: VecDeque<Vec<u8>>,
+        unread: Vec<u8>,
+        reader: Option<Waker>,
+    }
+
+    impl Scripted {
+        fn new(replies: &[&[u8]]) -> Self {
+            Scripted {
+                replies: replies.iter().map(|reply| reply.to_vec()).collect(),
+                unread: Vec::new(),
+                reader: None,
+            }
+        }
+    }
+
+    impl tokio::io::AsyncRead for Scripted {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            if self.unread.is_empty() {
+                self.reader = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            let len = buf.len().min(self.unread.len());
+            buf[..len].copy_from_slice(&self.unread[..len]);
+            self.unread.drain(..len);
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    impl tokio::io::AsyncWrite for Scripted {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            if let Some(reply) = self.replies.pop_front() {
+                self.unread.extend(reply);
+                if let Some(reader) = self.reader.take() {
+                    reader.wake();
+                }
+            }
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[async_std::test]
+    async fn response_timeout() {
+        let config = BrokerConfig {
+            response_timeout: Duration::from_millis(50),
+            ..BrokerConfig::default()
+        };
+        let mut broker = Broker::new(Scripted::new(&[]), config);
+
+        assert!(matches!(
+            broker.send(Frame::GetModemInfo).await,
+            Err(Error::Timeout)
+        ));
+        // The loop is still serving everything else.
+        broker.flush().await.unwrap();
+        assert!(matches!(
+            broker
+                .send_with_timeout(Frame::GetModemInfo, Duration::from_millis(10))
+                .await,
+            Err(Error::Timeout)
+        ));
+        assert_eq!(broker.health().errors.timeouts, 2);
+    }
+
+    #[async_std::test]
+    async fn unsolicited() {
+        // A button is pressed just before the modem echoes the frame.
+        let modem = Scripted::new(&[&[0x02, 0x54, 0x02, 0x02, 0x6d, 0x06]]);
+        let mut broker = Broker::new(modem, BrokerConfig::default());
+        let mut listener = broker.listen(Replay::None).await.unwrap();
+        broker.flush().await.unwrap();
+
+        assert_eq!(broker.send(Frame::LedOn).await, Ok(Frame::LedOn));
+        assert_eq!(
+            listener.next().await,
+            Some(Frame::ButtonEvent(ButtonEvent::Tapped(1)))
+        );
+    }
+
+    #[async_std::test]
+    async fn cleanup_ack() {
+        let device = Address::from([0x11, 0x22, 0x33]);
+        let cleanup = Frame::StandardInsteonSend {
+            to: device,
+            flags: MessageFlags::GROUP,
+            max_hops: 3,
+            cmd1: 0x11,
+            cmd2: 0x05,
+        };
+        let ack = Frame::StandardInsteonReceive {
+            from: device,
+            to: Address::from([0x44, 0x85, 0x11]),
+            flags: MessageFlags::ACK | MessageFlags::GROUP,
+            hops_remaining: 3,
+            max_hops: 3,
+            cmd1: 0x11,
+            cmd2: 0x05,
+        };
+        let mut reply = bytes::BytesMut::new();
+        cleanup.to_bytes(&mut reply);
+        reply.extend_from_slice(&[0x06]);
+        ack.to_bytes(&mut reply);
+
+        let mut broker = Broker::new(Scripted::new(&[&reply]), BrokerConfig::default());
+        let mut acks = broker
+            .expect_ack(device, Some(0x11), Some(0x05))
+            .await
+            .unwrap();
+        broker.send(cleanup).await.unwrap();
+        assert_eq!(acks.next().await, Some(ack));
+    }
+
+    #[async_std::test]
+    async fn listen_while_waiting() {
+        let mut broker = Broker::new(Scripted::new(&[]), BrokerConfig::default());
+        let mut sender = broker.clone();
+        let sending = sender.send(Frame::GetModemInfo);
+        futures::pin_mut!(sending);
+        assert!(futures::poll!(sending.as_mut()).is_pending());
+
+        // The listener is added long before the send times out.
+        let _listener = broker.listen(Replay::None).await.unwrap();
+        Delay::new(Duration::from_millis(100)).await;
+        assert_eq!(broker.health().listeners, 1);
+    }
+
+    #[async_std::test]
+    async fn queue_depth_after_cancel() {
+        let config = BrokerConfig {
+            command_capacity: 1,
+            response_timeout: Duration::from_millis(10),
+            ..BrokerConfig::default()
+        };
+        let mut broker = Broker::new(Scripted::new(&[]), config);
+
+        // Give up on sends while the queue is full, some of them before
+        // the loop has even taken the frame.
+        let mut sender = broker.clone();
+        for _ in 0..20 {
+            let sending = sender.send(Frame::LedOn);
+            futures::pin_mut!(sending);
+            let _ = futures::poll!(sending.as_mut());
+        }
+
+        broker.flush().await.unwrap();
+        assert_eq!(broker.health().queue_depth, 0);
+    }
+}
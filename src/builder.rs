@@ -0,0 +1,366 @@
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use log::debug;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Handle;
+
+use crate::broker::*;
+use crate::error::*;
+use crate::message::*;
+use crate::modem::*;
+use crate::queue::*;
+use crate::rate::*;
+use crate::retry::*;
+
+/// Opens a [Modem] with settings other than the defaults. Get one with
+/// [Modem::builder], change what's needed, then finish with
+/// [ModemBuilder::open] for a serial port or [ModemBuilder::build] for
+/// any other connection.
+///
+/// # Example
+/// ```no_run
+/// # use std::time::Duration;
+/// # use plm::{Modem, RetryPolicy};
+/// # fn main() -> std::io::Result<()> {
+/// let modem = Modem::builder()
+///     .baud_rate(19200)
+///     .timeout(Duration::from_secs(5))
+///     .retry_policy(RetryPolicy::never())
+///     .open("/dev/ttyUSB0")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ModemBuilder {
+    config: BrokerConfig,
+    timeout: Duration,
+    max_hops: u8,
+    retry_policy: RetryPolicy,
+    rate_limit: Option<RateLimit>,
+}
+
+impl Default for ModemBuilder {
+    fn default() -> Self {
+        ModemBuilder {
+            config: BrokerConfig::default(),
+            timeout: DEFAULT_TIMEOUT_DURATION,
+            max_hops: DEFAULT_MAX_HOPS,
+            retry_policy: RetryPolicy::default(),
+            rate_limit: None,
+        }
+    }
+}
+
+impl ModemBuilder {
+    /// Sets the baud rate of the serial port, [DEFAULT_BAUD_RATE] unless
+    /// changed. Only [ModemBuilder::open] uses it.
+    pub fn baud_rate(mut self, baud_rate: u32) -> Self {
+        self.config.baud_rate = baud_rate;
+        self
+    }
+
+    /// Sets the timeout, as with [Modem::set_timeout].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the hop count for messages, as with [Modem::set_max_hops]. It's
+    /// checked when the modem is opened.
+    pub fn max_hops(mut self, hops: u8) -> Self {
+        self.max_hops = hops;
+        self
+    }
+
+    /// Sets the [RetryPolicy], as with [Modem::set_retry_policy].
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Sets the [RateLimit], as with [Modem::set_rate_limit]. It's checked
+    /// when the modem is opened.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Reopens the serial port if the connection is lost, e.g. when a USB
+    /// modem is unplugged and plugged back in, trying again as `policy`
+    /// allows. Messages sent while it's down wait until the modem is back.
+    /// Without this, every call fails with [Error::Disconnected](crate::Error::Disconnected)
+    /// once the connection is lost. Only [ModemBuilder::open] and
+    /// [ModemBuilder::connect] use it.
+    pub fn reconnect(mut self, policy: RetryPolicy) -> Self {
+        self.config.reconnect = Some(policy);
+        self
+    }
+
+    /// Sets how often an idle TCP connection is probed to check the modem
+    /// is still there, [DEFAULT_TCP_KEEPALIVE](crate::DEFAULT_TCP_KEEPALIVE)
+    /// unless changed, or turns probing off with `None`. Only
+    /// [ModemBuilder::connect] uses it.
+    pub fn keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.config.keepalive = keepalive;
+        self
+    }
+
+    /// Sets the least time between the end of one exchange with the modem
+    /// and the next frame sent, [DEFAULT_SEND_GAP] unless changed. The
+    /// modem refuses frames that come too quickly, which then have to be
+    /// retried as the [RetryPolicy] allows.
+    pub fn send_gap(mut self, gap: Duration) -> Self {
+        self.config.send_gap = gap;
+        self
+    }
+
+    /// Sets how long to wait for the modem to respond to each frame,
+    /// [DEFAULT_RESPONSE_TIMEOUT] unless changed. A frame it doesn't answer
+    /// in time fails with [Error::Timeout](crate::Error::Timeout), and the
+    /// next frame is sent.
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.config.response_timeout = timeout;
+        self
+    }
+
+    /// Sets how many frames and other requests can be queued for the modem
+    /// before senders wait, [DEFAULT_COMMAND_CAPACITY] unless changed.
+    pub fn command_capacity(mut self, capacity: usize) -> Self {
+        self.config.command_capacity = capacity;
+        self
+    }
+
+    /// Sets how many received frames can wait for each listener, e.g. a
+    /// stream from [Modem::listen], [DEFAULT_LISTENER_CAPACITY] unless
+    /// changed.
+    pub fn listener_capacity(mut self, capacity: usize) -> Self {
+        self.config.listener_capacity = capacity;
+        self
+    }
+
+    /// Sets what happens when a listener's queue is full,
+    /// [Backpressure::DropOldest] unless changed. Drops are counted in
+    /// [Modem::health].
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.config.backpressure = backpressure;
+        self
+    }
+
+    /// Runs the connection to the modem as a task on `runtime`, e.g.
+    /// `Handle::current()` inside `#[tokio::main]`, rather than on a thread
+    /// with a Tokio runtime of its own. The task ends once the [Modem] and
+    /// every [ModemHandle](crate::ModemHandle) from it are dropped.
+    pub fn runtime(mut self, runtime: Handle) -> Self {
+        self.config.runtime = Some(runtime);
+        self
+    }
+
+    /// Sets how many received frames are kept for
+    /// [Replay](crate::Replay), [REPLAY_CAPACITY] unless changed.
+    pub fn replay_capacity(mut self, capacity: usize) -> Self {
+        self.config.replay_capacity = capacity;
+        self
+    }
+
+    /// Sets how many bytes may pile up without decoding a frame before
+    /// they are discarded, as with [FrameCodec::with_max_buffered](crate::FrameCodec::with_max_buffered).
+    pub fn max_buffered_bytes(mut self, max_buffered: usize) -> Self {
+        self.config.max_buffered_bytes = max_buffered;
+        self
+    }
+
+    /// Opens the modem attached to the serial port at `path`. Invalid
+    /// settings fail with [io::ErrorKind::InvalidInput], wrapping the
+    /// [Error].
+    pub fn open(self, path: impl AsRef<Path> + Send + 'static) -> io::Result<Modem> {
+        debug!("Creating Modem with path {}", path.as_ref().display());
+        self.check()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let broker = Broker::from_path(path, self.config.clone())?;
+        Ok(self.finish(broker))
+    }
+
+    /// Connects to a network-attached modem, e.g. through ser2net or an
+    /// Insteon Hub Pro, at `addr` given as `host` or `host:port`. The port
+    /// is [DEFAULT_TCP_PORT](crate::DEFAULT_TCP_PORT) unless given. Invalid
+    /// settings fail like they do for [ModemBuilder::open].
+    pub fn connect(self, addr: &str) -> io::Result<Modem> {
+        debug!("Creating Modem connected to {}", addr);
+        self.check()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let broker = Broker::from_tcp(addr, self.config.clone())?;
+        Ok(self.finish(broker))
+    }
+
+    /// Uses `handle` to talk to the modem, e.g. a TCP connection to a
+    /// serial server. Returns an [Error] if a setting is invalid.
+    pub fn build(
+        self,
+        handle: impl AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static,
+    ) -> Result<Modem, Error> {
+        self.check()?;
+        Ok(self.build_unchecked(handle))
+    }
+
+    /// Builds like [ModemBuilder::build] with settings known to be valid,
+    /// e.g. the defaults.
+    pub(crate) fn build_unchecked(
+        self,
+        handle: impl AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static,
+    ) -> Modem {
+        let broker = Broker::new(handle, self.config.clone());
+        self.finish(broker)
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        check_max_hops(self.max_hops)?;
+        if let Some(limit) = &self.rate_limit {
+            limit.check()?;
+        }
+        Ok(())
+    }
+
+    fn finish(self, broker: Broker) -> Modem {
+        let mut modem = Modem::from_broker(broker);
+        modem.set_timeout(self.timeout);
+        modem.max_hops = self.max_hops;
+        modem.set_retry_policy(self.retry_policy);
+        modem.rate_limit = self.rate_limit.map(TokenBucket::new);
+        modem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use super::*;
+    #[cfg(unix)]
+    use crate::broker::ConnectionState;
+    #[cfg(unix)]
+    use crate::emulator::*;
+    #[cfg(unix)]
+    use crate::frame::*;
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn builder() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::builder()
+            .timeout(Duration::from_secs(3))
+            .retry_policy(RetryPolicy::never())
+            .replay_capacity(4)
+            .open(emulator.path().to_path_buf())
+            .unwrap();
+
+        assert_eq!(modem.timeout(), Duration::from_secs(3));
+        assert_eq!(modem.retry_policy(), RetryPolicy::never());
+        assert!(modem.get_info().await.is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_max_hops() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let error = Modem::builder()
+            .max_hops(4)
+            .open(emulator.path().to_path_buf())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), Error::InvalidMaxHops(4).to_string());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_rate_limit() {
+        let limit = RateLimit {
+            commands_per_second: 0.0,
+            burst: 1,
+        };
+        let (handle, _) = tokio::io::duplex(64);
+        assert!(matches!(
+            Modem::builder().rate_limit(limit).build(handle),
+            Err(Error::InvalidRateLimit(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn reconnect() {
+        // Stands in for a device node that comes back when the modem is
+        // plugged in again.
+        let link = std::env::temp_dir().join(format!("plm-reconnect-{}", std::process::id()));
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(emulator.path(), &link).unwrap();
+
+        let mut modem = Modem::builder()
+            .reconnect(RetryPolicy::exponential(
+                20,
+                Duration::from_millis(10),
+                Duration::from_millis(100),
+            ))
+            .open(link.clone())
+            .unwrap();
+        let mut state = modem.connection_state();
+        assert_eq!(state.recv().await, Some(ConnectionState::Connected));
+
+        emulator.unplug().unwrap();
+        assert_eq!(state.recv().await, Some(ConnectionState::Reconnecting));
+        assert!(!modem.health().connected);
+
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        std::fs::remove_file(&link).unwrap();
+        std::os::unix::fs::symlink(emulator.path(), &link).unwrap();
+        assert_eq!(state.recv().await, Some(ConnectionState::Connected));
+
+        assert!(modem.send_frame(Frame::GetModemInfo).await.is_ok());
+        std::fs::remove_file(&link).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn runtime() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::builder()
+            .runtime(Handle::current())
+            .open(emulator.path().to_path_buf())
+            .unwrap();
+        let mut state = modem.connection_state();
+        assert_eq!(state.recv().await, Some(ConnectionState::Connected));
+        assert!(modem.get_info().await.is_ok());
+
+        // Dropping the modem ends the task.
+        drop(modem);
+        assert_eq!(state.recv().await, Some(ConnectionState::Disconnected));
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn tcp() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let path = emulator.path().to_path_buf();
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+
+        // Stands in for ser2net, passing bytes between the socket and the
+        // emulator's serial port.
+        std::thread::spawn(move || {
+            let (mut socket, _) = server.accept().unwrap();
+            let mut port = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .unwrap();
+            let (mut from_port, mut to_socket) =
+                (port.try_clone().unwrap(), socket.try_clone().unwrap());
+            std::thread::spawn(move || std::io::copy(&mut from_port, &mut to_socket));
+            let _ = std::io::copy(&mut socket, &mut port);
+        });
+
+        let mut modem = Modem::builder().connect(&addr).unwrap();
+        assert!(modem.get_info().await.is_ok());
+    }
+}
@@ -5,8 +5,14 @@ pub const NAK: u8 = 0x15u8;
 // PLM -> Host commands
 pub const STANDARD_INSTEON_RECV: u8 = 0x50u8;
 pub const EXTENDED_INSTEON_RECV: u8 = 0x51u8;
+pub const X10_RECV: u8 = 0x52u8;
 pub const ALL_LINK_COMPLETE: u8 = 0x53u8;
+pub const BUTTON_EVENT_REPORT: u8 = 0x54u8;
+pub const USER_RESET_DETECTED: u8 = 0x55u8;
+pub const ALL_LINK_CLEANUP_FAILURE: u8 = 0x56u8;
 pub const ALL_LINK_RECORD: u8 = 0x57u8;
+pub const ALL_LINK_CLEANUP_STATUS: u8 = 0x58u8;
+pub const DATABASE_RECORD_FOUND: u8 = 0x59u8;
 pub const GETIMINFO: u8 = 0x60u8;
 
 // Host -> PLM commands
@@ -14,12 +20,41 @@ pub const ALL_LINK_SEND: u8 = 0x61u8;
 pub const INSTEON_SEND: u8 = 0x62u8;
 pub const START_ALL_LINK: u8 = 0x64u8;
 pub const CANCEL_ALL_LINK: u8 = 0x65u8;
+pub const X10_SEND: u8 = 0x63u8;
 pub const RESET: u8 = 0x67u8;
 pub const GET_FIRST_ALL_LINK_RECORD: u8 = 0x69u8;
 pub const GET_NEXT_ALL_LINK_RECORD: u8 = 0x6au8;
+pub const SET_IM_CONFIGURATION: u8 = 0x6bu8;
+pub const GET_ALL_LINK_RECORD_FOR_SENDER: u8 = 0x6cu8;
+pub const LED_ON: u8 = 0x6du8;
+pub const LED_OFF: u8 = 0x6eu8;
+pub const MANAGE_ALL_LINK_RECORD: u8 = 0x6fu8;
+pub const RF_SLEEP: u8 = 0x72u8;
+pub const GET_IM_CONFIGURATION: u8 = 0x73u8;
+pub const READ_DATABASE: u8 = 0x75u8;
+
+// Button event report events, for the SET button. Buttons 2 and 3 are
+// 0x10 and 0x20 higher.
+pub const BUTTON_TAPPED: u8 = 0x02;
+pub const BUTTON_HELD: u8 = 0x03;
+pub const BUTTON_RELEASED: u8 = 0x04;
 
 // Linking modes
 pub const LINK_MODE_RESPONDER: u8 = 0x00;
 pub const LINK_MODE_CONTROLLER: u8 = 0x01;
 pub const LINK_MODE_AUTO: u8 = 0x03;
 pub const LINK_MODE_DELETE: u8 = 0xff;
+
+// Manage All-Link Record control codes
+pub const ALL_LINK_FIND_FIRST: u8 = 0x00;
+pub const ALL_LINK_FIND_NEXT: u8 = 0x01;
+pub const ALL_LINK_MODIFY: u8 = 0x20;
+pub const ALL_LINK_ADD_CONTROLLER: u8 = 0x40;
+pub const ALL_LINK_ADD_RESPONDER: u8 = 0x41;
+pub const ALL_LINK_DELETE: u8 = 0x80;
+
+// The oldest modem firmware versions that support each command
+pub const MIN_FIRMWARE_MANAGE_ALL_LINK_RECORD: u8 = 0x92;
+
+// Direct NAK reasons, found in cmd2
+pub const NAK_CHECKSUM: u8 = 0xfd;
@@ -0,0 +1,559 @@
+//! Typed wrappers for common kinds of devices, for application code that
+//! would rather call `dimmer.set_level(128)` than build [Message]s.
+//!
+//! ```no_run
+//! # use std::str::FromStr;
+//! # use plm::*;
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let modem = Modem::from_path("/dev/ttyUSB0")?.into_shared();
+//! let dimmer = Dimmer::new(modem.clone(), Address::from_str("11.22.33")?);
+//! dimmer.set_level(0x80).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{future, lock::Mutex, Stream, StreamExt};
+
+use crate::error::*;
+use crate::frame::*;
+use crate::keypad::*;
+use crate::message::*;
+use crate::modem::*;
+use crate::pending::*;
+use crate::remote::*;
+
+// I/OLinc operating flag commands, sent in cmd2 with SetOperatingFlags.
+// The following value turns each one off.
+const MOMENTARY_MODE_ON: u8 = 0x06;
+const MOMENTARY_TRIGGER_ON: u8 = 0x12;
+const MOMENTARY_FOLLOW_SENSE_ON: u8 = 0x14;
+
+// Extended set command for the I/OLinc's momentary time, in tenths of a
+// second.
+const SET_MOMENTARY_TIME: u8 = 0x06;
+
+/// A [Modem] that several devices can use in turn.
+pub type SharedModem = Arc<Mutex<Modem>>;
+
+impl Modem {
+    /// Wraps the modem so it can be shared between devices.
+    pub fn into_shared(self) -> SharedModem {
+        Arc::new(Mutex::new(self))
+    }
+}
+
+/// The parts every device type has.
+#[derive(Clone)]
+struct DeviceHandle {
+    modem: SharedModem,
+    address: Address,
+}
+
+impl DeviceHandle {
+    async fn send(&self, command: Command, cmd2: u8) -> Result<Message, Error> {
+        let message = (self.address, command, Command::from(cmd2)).into();
+        self.modem.lock().await.send_message(message).await
+    }
+
+    async fn level(&self) -> Result<u8, Error> {
+        self.modem.lock().await.get_level(self.address).await
+    }
+
+    async fn set_flag(&self, on_command: u8, on: bool) -> Result<(), Error> {
+        let command = if on { on_command } else { on_command + 1 };
+        self.send(Command::SetOperatingFlags, command).await?;
+        Ok(())
+    }
+}
+
+/// A dimmer, such as a SwitchLinc Dimmer or LampLinc.
+#[derive(Clone)]
+pub struct Dimmer(DeviceHandle);
+
+impl Dimmer {
+    pub fn new(modem: SharedModem, address: Address) -> Self {
+        Dimmer(DeviceHandle { modem, address })
+    }
+
+    pub fn address(&self) -> Address {
+        self.0.address
+    }
+
+    /// Ramps to `level`, from 0 (off) to 255 (fully on).
+    pub async fn set_level(&self, level: u8) -> Result<(), Error> {
+        match level {
+            0 => self.0.send(Command::Off, 0).await?,
+            level => self.0.send(Command::On, level).await?,
+        };
+        Ok(())
+    }
+
+    /// Turns fully on.
+    pub async fn on(&self) -> Result<(), Error> {
+        self.set_level(0xff).await
+    }
+
+    pub async fn off(&self) -> Result<(), Error> {
+        self.set_level(0).await
+    }
+
+    /// Returns the current level, from 0 (off) to 255 (fully on).
+    pub async fn level(&self) -> Result<u8, Error> {
+        self.0.level().await
+    }
+}
+
+/// An on/off switch, such as a SwitchLinc Relay or ApplianceLinc.
+#[derive(Clone)]
+pub struct Switch(DeviceHandle);
+
+impl Switch {
+    pub fn new(modem: SharedModem, address: Address) -> Self {
+        Switch(DeviceHandle { modem, address })
+    }
+
+    pub fn address(&self) -> Address {
+        self.0.address
+    }
+
+    pub async fn on(&self) -> Result<(), Error> {
+        self.0.send(Command::On, 0xff).await?;
+        Ok(())
+    }
+
+    pub async fn off(&self) -> Result<(), Error> {
+        self.0.send(Command::Off, 0).await?;
+        Ok(())
+    }
+
+    pub async fn is_on(&self) -> Result<bool, Error> {
+        Ok(self.0.level().await? != 0)
+    }
+}
+
+/// A sensor, such as a motion, door or leak sensor.
+#[derive(Clone)]
+pub struct Sensor(DeviceHandle);
+
+impl Sensor {
+    pub fn new(modem: SharedModem, address: Address) -> Self {
+        Sensor(DeviceHandle { modem, address })
+    }
+
+    pub fn address(&self) -> Address {
+        self.0.address
+    }
+
+    /// Returns true if the sensor is triggered, e.g. it sees motion or
+    /// the door is open. Battery powered sensors only answer for a few
+    /// seconds after they send something, so this usually times out.
+    pub async fn status(&self) -> Result<bool, Error> {
+        Ok(self.0.level().await? != 0)
+    }
+}
+
+/// A KeypadLinc. Button 1 controls the load, and each button broadcasts
+/// on the group of the same number when pressed. Six button keypads use
+/// buttons 1 and 3 through 6.
+#[derive(Clone)]
+pub struct Keypad(DeviceHandle);
+
+impl Keypad {
+    pub fn new(modem: SharedModem, address: Address) -> Self {
+        Keypad(DeviceHandle { modem, address })
+    }
+
+    pub fn address(&self) -> Address {
+        self.0.address
+    }
+
+    /// Returns the buttons whose LEDs are on.
+    pub async fn leds(&self) -> Result<KeypadButtons, Error> {
+        let mut modem = self.0.modem.lock().await;
+        Ok(modem.get_keypad_config(self.0.address, 1).await?.leds)
+    }
+
+    /// Turns on the LEDs of `leds` and turns off the rest.
+    pub async fn set_leds(&self, leds: KeypadButtons) -> Result<(), Error> {
+        let mut modem = self.0.modem.lock().await;
+        modem.set_keypad_leds(self.0.address, leds).await
+    }
+
+    /// Turns the LED of `button`, numbered from 1, on or off, leaving the
+    /// others as they are. Returns [Error::InvalidButton] unless `button`
+    /// is 1 to 8, without sending anything.
+    pub async fn set_button_led(&self, button: u8, on: bool) -> Result<(), Error> {
+        let button = KeypadButtons::button(button).ok_or(Error::InvalidButton(button))?;
+        let mut leds = self.leds().await?;
+        leds.set(button, on);
+        self.set_leds(leds).await
+    }
+
+    /// Decodes a button press from `message`, if it's a group broadcast
+    /// from this keypad.
+    pub fn decode(&self, message: &Message) -> Option<RemoteEvent> {
+        decode_button(self.0.address, 8, message)
+    }
+
+    /// Returns a stream of the keypad's button presses. The keypad's
+    /// buttons must be linked to the modem, e.g. with
+    /// [Modem::link_keypad_button], for the modem to hear them.
+    pub async fn button_events(&self) -> Result<impl Stream<Item = RemoteEvent>, Error> {
+        let keypad = self.clone();
+        let messages = self.0.modem.lock().await.listen().await?;
+        Ok(messages.filter_map(move |message| future::ready(keypad.decode(&message))))
+    }
+}
+
+/// How an [IoLinc]'s relay reacts to the commands it receives.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MomentaryMode {
+    /// The relay stays closed until turned off.
+    Latching,
+    /// The relay closes for a moment, on On or Off depending on how the
+    /// link that triggers it was made. The other command is ignored.
+    A,
+    /// The relay closes for a moment on both On and Off.
+    B,
+    /// The relay closes for a moment on On if the sensor is open, or on
+    /// Off if it is closed, e.g. to only ever close a garage door.
+    C,
+}
+
+/// An I/OLinc, with a relay output and a sensor input. The relay is
+/// switched with On and Off like a [Switch]; the sensor is read with its
+/// own status request.
+#[derive(Clone)]
+pub struct IoLinc(DeviceHandle);
+
+impl IoLinc {
+    pub fn new(modem: SharedModem, address: Address) -> Self {
+        IoLinc(DeviceHandle { modem, address })
+    }
+
+    pub fn address(&self) -> Address {
+        self.0.address
+    }
+
+    /// Closes the relay, or in a momentary mode, pulses it.
+    pub async fn relay_on(&self) -> Result<(), Error> {
+        self.0.send(Command::On, 0xff).await?;
+        Ok(())
+    }
+
+    pub async fn relay_off(&self) -> Result<(), Error> {
+        self.0.send(Command::Off, 0).await?;
+        Ok(())
+    }
+
+    /// Returns true if the relay is closed.
+    pub async fn relay_is_on(&self) -> Result<bool, Error> {
+        Ok(self.0.level().await? != 0)
+    }
+
+    /// Returns true if the sensor input is closed.
+    pub async fn sensor_is_on(&self) -> Result<bool, Error> {
+        // The I/OLinc answers the status request a KeypadLinc uses for its
+        // LEDs with the sensor state instead.
+        let status = self
+            .0
+            .modem
+            .lock()
+            .await
+            .get_status(self.0.address, StatusKind::Leds)
+            .await?;
+        match status {
+            Status::Leds(leds) => Ok(!leds.is_empty()),
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    /// Sets how the relay reacts to commands. In the momentary modes, the
+    /// relay stays closed for `duration`, up to 25.5 seconds, which is
+    /// rounded to the nearest tenth of a second.
+    pub async fn set_momentary_mode(
+        &self,
+        mode: MomentaryMode,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        self.0
+            .set_flag(MOMENTARY_MODE_ON, mode != MomentaryMode::Latching)
+            .await?;
+        self.0
+            .set_flag(MOMENTARY_TRIGGER_ON, mode == MomentaryMode::B)
+            .await?;
+        self.0
+            .set_flag(MOMENTARY_FOLLOW_SENSE_ON, mode == MomentaryMode::C)
+            .await?;
+
+        if mode != MomentaryMode::Latching {
+            let tenths = ((duration.as_millis() + 50) / 100).min(0xff) as u8;
+            let message = extended_set(self.0.address, 1, SET_MOMENTARY_TIME, tenths);
+            self.0.modem.lock().await.send_message(message).await?;
+        }
+        Ok(())
+    }
+}
+
+/// What a battery powered sensor reported with a group broadcast, as
+/// returned by [SensorEvent::decode].
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SensorEvent {
+    /// Motion was seen, or a door opened or a leak was found.
+    Triggered,
+    /// The sensor has been idle for its timeout.
+    Cleared,
+    /// It got dark.
+    Dusk,
+    /// It got light.
+    Dawn,
+    LowBattery,
+    /// The battery was replaced.
+    BatteryOk,
+}
+
+impl SensorEvent {
+    /// Decodes `message` if it is a group broadcast from a sensor. Motion
+    /// sensors report motion on group 1, light on group 2 and their
+    /// battery on group 3.
+    pub fn decode(message: &Message) -> Option<SensorEvent> {
+        if !message.is_group_broadcast() {
+            return None;
+        }
+        SensorEvent::from_group(message.group()?, message.cmd1)
+    }
+
+    /// Returns the event a sensor reports by sending `command` to `group`.
+    pub(crate) fn from_group(group: u8, command: Command) -> Option<SensorEvent> {
+        let on = match command {
+            Command::On | Command::OnFast => true,
+            Command::Off | Command::OffFast => false,
+            _ => return None,
+        };
+
+        match (group, on) {
+            (1, true) => Some(SensorEvent::Triggered),
+            (1, false) => Some(SensorEvent::Cleared),
+            (2, true) => Some(SensorEvent::Dusk),
+            (2, false) => Some(SensorEvent::Dawn),
+            (3, true) => Some(SensorEvent::LowBattery),
+            (3, false) => Some(SensorEvent::BatteryOk),
+            _ => None,
+        }
+    }
+}
+
+/// A battery powered device, such as a motion, door or leak sensor. These
+/// sleep most of the time and only listen right after they send
+/// something, so configuration messages are queued and delivered the
+/// next time one is heard from the device.
+#[derive(Clone)]
+pub struct BatteryDevice {
+    handle: DeviceHandle,
+    pending: Arc<Mutex<PendingCommands>>,
+}
+
+impl BatteryDevice {
+    pub fn new(modem: SharedModem, address: Address) -> Self {
+        BatteryDevice {
+            handle: DeviceHandle { modem, address },
+            pending: Arc::new(Mutex::new(PendingCommands::new())),
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        self.handle.address
+    }
+
+    /// Queues `message` for the device, to be sent when it next wakes up.
+    /// The message is addressed to the device whatever its `to`.
+    pub async fn queue(&self, mut message: Message) -> Result<(), Error> {
+        message.to = self.handle.address;
+        self.pending.lock().await.push(message)
+    }
+
+    /// Returns the number of messages waiting for the device to wake up.
+    pub async fn pending(&self) -> usize {
+        self.pending.lock().await.len()
+    }
+
+    /// Handles a [Message] heard on [Modem::listen]. If it's from this
+    /// device, the queued messages are delivered while the device is
+    /// awake, and the [SensorEvent] it carries is returned.
+    pub async fn handle(&self, message: &Message) -> Result<Option<SensorEvent>, Error> {
+        if message.from != self.handle.address {
+            return Ok(None);
+        }
+
+        let mut pending = self.pending.lock().await;
+        if pending.contains(self.handle.address) {
+            let mut modem = self.handle.modem.lock().await;
+            modem
+                .deliver_pending(&mut pending, self.handle.address)
+                .await?;
+        }
+
+        Ok(SensorEvent::decode(message))
+    }
+
+    /// Returns a stream of the device's [SensorEvent]s, delivering queued
+    /// messages with [BatteryDevice::handle] whenever the device is heard.
+    pub async fn events(&self) -> Result<impl Stream<Item = Result<SensorEvent, Error>>, Error> {
+        let device = self.clone();
+        let messages = self.handle.modem.lock().await.listen().await?;
+        Ok(messages
+            .then(move |message| {
+                let device = device.clone();
+                async move { device.handle(&message).await }
+            })
+            .filter_map(|event| future::ready(event.transpose())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::emulator::*;
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn devices() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let modem = Modem::from_path(emulator.path().to_path_buf())
+            .unwrap()
+            .into_shared();
+
+        let dimmer = Dimmer::new(modem.clone(), Address::from([0x11, 0x22, 0x33]));
+        dimmer.set_level(0x40).await.unwrap();
+        assert_eq!(dimmer.level().await.unwrap(), 0x40);
+        dimmer.off().await.unwrap();
+        assert_eq!(dimmer.level().await.unwrap(), 0);
+
+        let switch = Switch::new(modem.clone(), Address::from([0x22, 0x33, 0x44]));
+        switch.on().await.unwrap();
+        assert!(switch.is_on().await.unwrap());
+
+        let sensor = Sensor::new(modem, Address::from([0x44, 0x55, 0x66]));
+        assert!(!sensor.status().await.unwrap());
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn io_linc() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let modem = Modem::from_path(emulator.path().to_path_buf())
+            .unwrap()
+            .into_shared();
+        let io_linc = IoLinc::new(modem, Address::from([0x22, 0x33, 0x44]));
+
+        io_linc
+            .set_momentary_mode(MomentaryMode::B, Duration::from_secs(2))
+            .await
+            .unwrap();
+        io_linc.relay_on().await.unwrap();
+        assert!(io_linc.relay_is_on().await.unwrap());
+        io_linc.relay_off().await.unwrap();
+        assert!(!io_linc.relay_is_on().await.unwrap());
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn keypad_leds() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let modem = Modem::from_path(emulator.path().to_path_buf())
+            .unwrap()
+            .into_shared();
+        let keypad = Keypad::new(modem, Address::from([0x33, 0x44, 0x55]));
+
+        keypad.set_button_led(3, true).await.unwrap();
+        keypad.set_button_led(5, true).await.unwrap();
+        keypad.set_button_led(3, false).await.unwrap();
+        assert_eq!(keypad.leds().await.unwrap(), KeypadButtons::BUTTON_5);
+
+        assert_eq!(
+            keypad.set_button_led(9, true).await,
+            Err(Error::InvalidButton(9))
+        );
+        assert_eq!(keypad.leds().await.unwrap(), KeypadButtons::BUTTON_5);
+    }
+
+    fn broadcast(from: Address, group: u8, command: Command) -> Message {
+        let mut message: Message = (
+            Address::from([0x00, 0x00, group]),
+            command,
+            Command::None,
+            MessageFlags::GROUP | MessageFlags::BROADCAST_OR_NAK,
+        )
+            .into();
+        message.from = from;
+        message
+    }
+
+    #[test]
+    fn sensor_events() {
+        let from = Address::from([0x44, 0x55, 0x66]);
+        let decode = |group, command| SensorEvent::decode(&broadcast(from, group, command));
+        assert_eq!(decode(1, Command::On), Some(SensorEvent::Triggered));
+        assert_eq!(decode(1, Command::Off), Some(SensorEvent::Cleared));
+        assert_eq!(decode(2, Command::On), Some(SensorEvent::Dusk));
+        assert_eq!(decode(3, Command::On), Some(SensorEvent::LowBattery));
+        assert_eq!(decode(4, Command::On), None);
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn battery_device() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let modem = Modem::from_path(emulator.path().to_path_buf())
+            .unwrap()
+            .into_shared();
+        let address = Address::from([0x44, 0x55, 0x66]);
+        let sensor = BatteryDevice::new(modem, address);
+
+        sensor
+            .queue((Address::default(), Command::Beep).into())
+            .await
+            .unwrap();
+        assert_eq!(sensor.pending().await, 1);
+
+        let other = broadcast(Address::from([0x11, 0x22, 0x33]), 1, Command::On);
+        assert_eq!(sensor.handle(&other).await.unwrap(), None);
+        assert_eq!(sensor.pending().await, 1);
+
+        let motion = broadcast(address, 1, Command::On);
+        assert_eq!(
+            sensor.handle(&motion).await.unwrap(),
+            Some(SensorEvent::Triggered)
+        );
+        assert_eq!(sensor.pending().await, 0);
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn keypad_buttons() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let modem = Modem::from_path(emulator.path().to_path_buf())
+            .unwrap()
+            .into_shared();
+        let address = Address::from([0x33, 0x44, 0x55]);
+        let keypad = Keypad::new(modem, address);
+
+        let mut message = broadcast(address, 5, Command::Off);
+        assert_eq!(
+            keypad.decode(&message),
+            Some(RemoteEvent {
+                button: 5,
+                action: RemoteAction::Off
+            })
+        );
+
+        message.from = Address::from([0x11, 0x22, 0x33]);
+        assert_eq!(keypad.decode(&message), None);
+    }
+}
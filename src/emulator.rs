@@ -0,0 +1,891 @@
+//! A small stand-in for a PowerLinc Modem, so the modem tests and the
+//! command line app can run without hardware. The emulator answers on one
+//! end of a pseudo-terminal pair and
+//! [Modem::from_path](super::Modem::from_path) opens the other, so the
+//! whole serial path is exercised.
+//!
+//! The same emulated modem and devices answer for the in-memory modems in
+//! [testing](crate::testing), which work on every platform.
+
+#[cfg(all(unix, any(test, feature = "emulator")))]
+use std::{
+    ffi::{CStr, OsStr},
+    fs::File,
+    io::{self, Read, Write},
+    os::unix::ffi::OsStrExt,
+    os::unix::io::{AsRawFd, FromRawFd},
+    path::{Path, PathBuf},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use std::collections::HashMap;
+
+use bytes::BytesMut;
+
+use log::debug;
+
+use crate::aldb::*;
+use crate::constants::*;
+use crate::frame::*;
+use crate::keypad::{DATA_REQUEST, DATA_RESPONSE};
+use crate::message::InsteonEngine;
+use crate::modem::{MEMORY_RECORD_SIZE, MODEM_ALDB_START};
+
+const CANCEL_LINKING: u8 = 0x08;
+const START_LINKING: u8 = 0x09;
+const START_UNLINKING: u8 = 0x0a;
+const VERSION_QUERY: u8 = 0x0d;
+const ID_REQUEST: u8 = 0x10;
+const ON: u8 = 0x11;
+const ON_FAST: u8 = 0x12;
+const OFF: u8 = 0x13;
+const OFF_FAST: u8 = 0x14;
+const STATUS_REQUEST: u8 = 0x19;
+const SET_ADDRESS_MSB: u8 = 0x28;
+const PEEK: u8 = 0x2b;
+const EXTENDED_SET_GET: u8 = 0x2e;
+const READ_WRITE_ALDB: u8 = 0x2f;
+const SET_LED_MASK: u8 = 0x09;
+const SET_BUTTON_RESPONDER: u8 = 0x01;
+
+/// The category, sub-category and firmware of every emulated device: a
+/// SwitchLinc dimmer.
+const DEVICE_ID: [u8; 3] = [0x01, 0x20, 0x45];
+
+/// A device on the emulated network, whose level follows the commands
+/// sent to it.
+#[derive(Debug, Clone)]
+pub struct EmulatedDevice {
+    pub address: Address,
+    pub category: u8,
+    pub sub_category: u8,
+    pub firmware_version: u8,
+    pub level: u8,
+    /// The device's own link database, in memory order.
+    pub links: Vec<DeviceLinkRecord>,
+    /// True while the device is in linking mode, waiting for the modem.
+    pub linking: bool,
+    /// i1 devices ignore extended messages, and i2cs devices NAK those
+    /// with a bad checksum.
+    pub engine: InsteonEngine,
+    /// The keypad LEDs that are on, button `n` in bit `n - 1`.
+    pub leds: u8,
+}
+
+impl EmulatedDevice {
+    /// Returns a device of the given category at `address` that is off
+    /// and has no links.
+    pub fn new(address: Address, category: u8, sub_category: u8, firmware_version: u8) -> Self {
+        EmulatedDevice {
+            address,
+            category,
+            sub_category,
+            firmware_version,
+            level: 0,
+            links: Vec::new(),
+            linking: false,
+            engine: InsteonEngine::I2Cs,
+            leds: 0,
+        }
+    }
+
+    /// Returns the byte of the device's memory at `location`, where only
+    /// the link database is filled in.
+    fn peek(&self, location: u16) -> u8 {
+        let record = self
+            .links
+            .iter()
+            .find(|r| location <= r.offset && r.offset - location < ALDB_RECORD_SIZE);
+        match record {
+            Some(record) => {
+                let mut data = [record.flags.bits(), record.group, 0, 0, 0, 0, 0, 0];
+                data[2..5].copy_from_slice(&<[u8; 3]>::from(record.address));
+                data[5..].copy_from_slice(&record.data);
+                data[(ALDB_RECORD_SIZE - 1 - (record.offset - location)) as usize]
+            }
+            None => 0,
+        }
+    }
+
+    /// Returns a SwitchLinc dimmer at `address`.
+    pub fn dimmer(address: Address) -> Self {
+        EmulatedDevice::new(address, DEVICE_ID[0], DEVICE_ID[1], DEVICE_ID[2])
+    }
+
+    /// Returns a SwitchLinc relay at `address`.
+    pub fn switch(address: Address) -> Self {
+        EmulatedDevice::new(address, 0x02, 0x2a, 0x43)
+    }
+}
+
+/// How the emulator appears to the host, and the links and devices it
+/// starts with.
+#[derive(Debug, Clone)]
+pub struct EmulatorConfig {
+    pub info: ModemInfo,
+    pub links: Vec<AllLinkRecord>,
+    /// The devices that answer messages. If empty, every address answers
+    /// as a SwitchLinc dimmer that is always off.
+    pub devices: Vec<EmulatedDevice>,
+    /// Whether the modem answers memory reads. Older modems NAK them.
+    pub reads_memory: bool,
+}
+
+impl EmulatorConfig {
+    /// A small house: a dimmer, a relay switch, a keypad and a motion
+    /// sensor, all linked to the modem.
+    pub fn demo() -> Self {
+        let device = |address: [u8; 3], category, sub_category, firmware_version| {
+            EmulatedDevice::new(
+                Address::from(address),
+                category,
+                sub_category,
+                firmware_version,
+            )
+        };
+        let mut devices = vec![
+            device([0x11, 0x22, 0x33], 0x01, 0x20, 0x45),
+            device([0x22, 0x33, 0x44], 0x02, 0x2a, 0x43),
+            device([0x33, 0x44, 0x55], 0x01, 0x41, 0x45),
+            device([0x44, 0x55, 0x66], 0x10, 0x01, 0x3e),
+        ];
+
+        let used = AllLinkFlags::IN_USE | AllLinkFlags::HAS_BEEN_USED;
+        let modem = EmulatorConfig::default().info.address;
+        let mut links = Vec::new();
+        for device in &mut devices {
+            device.links = vec![
+                DeviceLinkRecord {
+                    offset: ALDB_START,
+                    flags: used,
+                    group: 1,
+                    address: modem,
+                    data: [0xff, DEFAULT_RAMP_RATE, 0x01],
+                },
+                DeviceLinkRecord {
+                    offset: ALDB_START - ALDB_RECORD_SIZE,
+                    flags: used | AllLinkFlags::IS_CONTROLLER,
+                    group: 1,
+                    address: modem,
+                    data: [0x00, 0x00, 0x01],
+                },
+            ];
+            links.push(AllLinkRecord {
+                flags: used | AllLinkFlags::IS_CONTROLLER,
+                group: 1,
+                to: device.address,
+                data: [
+                    device.category,
+                    device.sub_category,
+                    device.firmware_version,
+                ],
+            });
+            links.push(AllLinkRecord {
+                flags: used,
+                group: 1,
+                to: device.address,
+                data: [
+                    device.category,
+                    device.sub_category,
+                    device.firmware_version,
+                ],
+            });
+        }
+
+        EmulatorConfig {
+            links,
+            devices,
+            ..EmulatorConfig::default()
+        }
+    }
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        EmulatorConfig {
+            info: ModemInfo {
+                address: Address::from([0x44, 0x85, 0x11]),
+                category: 0x03,
+                sub_category: 0x15,
+                firmware_version: 0x9e,
+            },
+            links: vec![AllLinkRecord {
+                flags: AllLinkFlags::IN_USE
+                    | AllLinkFlags::IS_CONTROLLER
+                    | AllLinkFlags::HAS_BEEN_USED,
+                group: 1,
+                to: Address::from([0x11, 0x22, 0x33]),
+                data: [0x01, 0x20, 0x41],
+            }],
+            devices: Vec::new(),
+            reads_memory: true,
+        }
+    }
+}
+
+/// An emulated modem attached to a pseudo-terminal. It runs until the
+/// process exits or it is [unplugged](Emulator::unplug).
+#[cfg(all(unix, any(test, feature = "emulator")))]
+pub struct Emulator {
+    path: PathBuf,
+    // Holding the device side open keeps the pair alive while the modem
+    // reopens it.
+    device: File,
+    unplugged: Arc<AtomicBool>,
+}
+
+#[cfg(all(unix, any(test, feature = "emulator")))]
+impl Emulator {
+    /// Starts an emulated modem on a new pseudo-terminal.
+    pub fn spawn(config: EmulatorConfig) -> io::Result<Emulator> {
+        let (mut controller, device, path) = open_pty()?;
+        let unplugged = Arc::new(AtomicBool::new(false));
+        let thread_unplugged = unplugged.clone();
+
+        thread::spawn(move || {
+            let mut state = State::new(config);
+            let mut pending = Vec::new();
+            let mut buf = [0u8; 64];
+
+            while let Ok(len) = controller.read(&mut buf) {
+                if len == 0 || thread_unplugged.load(Ordering::SeqCst) {
+                    break;
+                }
+                pending.extend_from_slice(&buf[..len]);
+
+                while let Some(request_len) = command_len(&pending) {
+                    let request: Vec<u8> = pending.drain(..request_len).collect();
+                    let reply = state.respond(&request);
+                    if controller.write_all(&reply).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Emulator {
+            path,
+            device,
+            unplugged,
+        })
+    }
+
+    /// Stops the emulator as if the modem were unplugged, so reads from
+    /// the serial device fail.
+    pub fn unplug(&self) -> io::Result<()> {
+        self.unplugged.store(true, Ordering::SeqCst);
+        // Wake the emulator, which is waiting for a request.
+        (&self.device).write_all(&[0])
+    }
+
+    /// The path of the serial device to open.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Opens a pseudo-terminal pair, returning both ends and the path of the
+/// device end.
+#[cfg(all(unix, any(test, feature = "emulator")))]
+fn open_pty() -> io::Result<(File, File, PathBuf)> {
+    // ptsname returns a static buffer, so callers must take turns.
+    static PTSNAME: Mutex<()> = Mutex::new(());
+
+    let mut controller = 0;
+    let mut device = 0;
+    let result = unsafe {
+        libc::openpty(
+            &mut controller,
+            &mut device,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (controller, device) =
+        unsafe { (File::from_raw_fd(controller), File::from_raw_fd(device)) };
+
+    let path = {
+        let _guard = PTSNAME.lock().unwrap();
+        let name = unsafe { libc::ptsname(controller.as_raw_fd()) };
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let name = unsafe { CStr::from_ptr(name) };
+        PathBuf::from(OsStr::from_bytes(name.to_bytes()))
+    };
+
+    // The modem speaks binary, so the terminal must not touch the bytes.
+    unsafe {
+        let mut termios = std::mem::zeroed();
+        libc::tcgetattr(device.as_raw_fd(), &mut termios);
+        libc::cfmakeraw(&mut termios);
+        libc::tcsetattr(device.as_raw_fd(), libc::TCSANOW, &termios);
+    }
+
+    Ok((controller, device, path))
+}
+
+/// Reads or writes the link database of `device` as asked by the `data`
+/// of an extended [READ_WRITE_ALDB] message, returning the records to send
+/// back. A read ends with an unused record marking the end of the
+/// database.
+fn read_write_aldb(device: &mut EmulatedDevice, data: &[u8; 14]) -> Vec<DeviceLinkRecord> {
+    let offset = u16::from_be_bytes([data[2], data[3]]);
+    match data[1] {
+        ALDB_READ => {
+            let end = ALDB_START - ALDB_RECORD_SIZE * device.links.len() as u16;
+            let mut records: Vec<DeviceLinkRecord> = device
+                .links
+                .iter()
+                .copied()
+                .chain(std::iter::once(DeviceLinkRecord {
+                    offset: end,
+                    flags: AllLinkFlags::empty(),
+                    group: 0,
+                    address: Address::default(),
+                    data: [0u8; 3],
+                }))
+                .filter(|record| record.offset <= offset)
+                .collect();
+            if data[4] != 0 {
+                records.truncate(data[4] as usize);
+            }
+            records
+        }
+        ALDB_WRITE => {
+            let record = DeviceLinkRecord::from_data(data);
+            match device.links.iter_mut().find(|r| r.offset == record.offset) {
+                Some(existing) => *existing = record,
+                None => {
+                    device.links.push(record);
+                    device.links.sort_by_key(|r| std::cmp::Reverse(r.offset));
+                }
+            }
+            Vec::new()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// The emulated modem and the devices behind it.
+pub(crate) struct State {
+    pub(crate) config: EmulatorConfig,
+    next_link: usize,
+    last_target: Option<Address>,
+    modem_config: ModemConfig,
+    asleep: bool,
+    /// The mode and group the modem is linking in, if it is.
+    linking: Option<(AllLinkMode, u8)>,
+    /// The high byte of the address each device peeks at.
+    peek_msb: HashMap<Address, u8>,
+}
+
+impl State {
+    pub(crate) fn new(config: EmulatorConfig) -> Self {
+        State {
+            config,
+            next_link: 0,
+            last_target: None,
+            modem_config: ModemConfig::NONE,
+            asleep: false,
+            linking: None,
+            peek_msb: HashMap::new(),
+        }
+    }
+
+    /// Returns true if a device at `to` would answer a direct message.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn answers(&self, to: Address) -> bool {
+        !self.asleep
+            && (self.config.devices.is_empty()
+                || self.config.devices.iter().any(|d| d.address == to))
+    }
+
+    /// Presses the set button of the device at `address`, returning what
+    /// the modem sends the host as a result.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn press_set_button(&mut self, address: Address) -> Vec<u8> {
+        let mut reply = BytesMut::new();
+        let index = self
+            .config
+            .devices
+            .iter()
+            .position(|d| d.address == address);
+        if let Some(index) = index {
+            self.config.devices[index].linking = true;
+            self.link(index, &mut reply);
+        }
+        reply.to_vec()
+    }
+
+    /// Links the modem and the device at `index` if both are in linking
+    /// mode, or unlinks them if the modem is deleting.
+    fn link(&mut self, index: usize, reply: &mut BytesMut) {
+        let (mode, group) = match self.linking.take() {
+            Some(linking) => linking,
+            None => return,
+        };
+        let modem = self.config.info.address;
+        let device = &mut self.config.devices[index];
+        device.linking = false;
+
+        let address = device.address;
+        device
+            .links
+            .retain(|link| !(link.address == modem && link.group == group));
+        self.config
+            .links
+            .retain(|link| !(link.to == address && link.group == group));
+        if mode != AllLinkMode::Delete {
+            let used = AllLinkFlags::IN_USE | AllLinkFlags::HAS_BEEN_USED;
+            let controller = used | AllLinkFlags::IS_CONTROLLER;
+            let (modem_flags, device_flags) = if mode == AllLinkMode::Controller {
+                (controller, used)
+            } else {
+                (used, controller)
+            };
+            let offset = device
+                .links
+                .iter()
+                .map(|link| link.offset)
+                .min()
+                .map_or(ALDB_START, |last| last - ALDB_RECORD_SIZE);
+            device.links.push(DeviceLinkRecord {
+                offset,
+                flags: device_flags,
+                group,
+                address: modem,
+                data: [0xff, DEFAULT_RAMP_RATE, group],
+            });
+            self.config.links.push(AllLinkRecord {
+                flags: modem_flags,
+                group,
+                to: address,
+                data: [
+                    device.category,
+                    device.sub_category,
+                    device.firmware_version,
+                ],
+            });
+        }
+
+        Frame::AllLinkComplete(AllLinkComplete {
+            mode,
+            group,
+            address,
+            category: device.category,
+            sub_category: device.sub_category,
+            firmware_version: device.firmware_version,
+        })
+        .to_bytes(reply);
+    }
+
+    /// Returns the 8 bytes of memory at `address`, where only the link
+    /// database is filled in.
+    fn read_memory(&self, address: u16) -> MemoryRecord {
+        let mut data = [0u8; 8];
+        let index = MODEM_ALDB_START
+            .checked_sub(address)
+            .filter(|offset| offset % MEMORY_RECORD_SIZE == 0)
+            .map(|offset| (offset / MEMORY_RECORD_SIZE) as usize);
+        if let Some(link) = index.and_then(|index| self.config.links.get(index)) {
+            data[0] = link.flags.bits();
+            data[1] = link.group;
+            data[2..5].copy_from_slice(&<[u8; 3]>::from(link.to));
+            data[5..].copy_from_slice(&link.data);
+        }
+        MemoryRecord { address, data }
+    }
+
+    pub(crate) fn respond(&mut self, request: &[u8]) -> Vec<u8> {
+        debug!("Emulator received {:02x?}", request);
+        if request.len() < 2 {
+            // A stray byte, which wakes the radio if it's asleep.
+            self.asleep = false;
+            return Vec::new();
+        }
+        if self.asleep {
+            return Vec::new();
+        }
+
+        let mut reply = BytesMut::new();
+        let echo = |reply: &mut BytesMut, status: u8| {
+            reply.extend_from_slice(request);
+            reply.extend_from_slice(&[status]);
+        };
+
+        match request[1] {
+            GETIMINFO => Frame::ModemInfo(self.config.info.clone()).to_bytes(&mut reply),
+            INSTEON_SEND => {
+                echo(&mut reply, ACK);
+
+                let to = Address::from([request[2], request[3], request[4]]);
+                let flags = MessageFlags::from_bits_truncate(request[5]);
+                let (cmd1, mut cmd2) = (request[6], request[7]);
+                let cleanup = flags.contains(MessageFlags::GROUP)
+                    && !flags.contains(MessageFlags::BROADCAST_OR_NAK);
+                self.last_target = Some(to);
+
+                let device = if self.config.devices.is_empty() {
+                    None
+                } else {
+                    match self
+                        .config
+                        .devices
+                        .iter_mut()
+                        .find(|device| device.address == to)
+                    {
+                        Some(device) => Some(device),
+                        // Nobody is there to answer.
+                        None => return reply.to_vec(),
+                    }
+                };
+
+                let engine = device.as_ref().map_or(InsteonEngine::I2Cs, |d| d.engine);
+                if flags.contains(MessageFlags::EXTENDED) {
+                    let mut data = [0u8; 14];
+                    data.copy_from_slice(&request[8..22]);
+                    match engine {
+                        // i1 devices don't understand extended messages.
+                        InsteonEngine::I1 => return reply.to_vec(),
+                        InsteonEngine::I2Cs if data[13] != checksum(cmd1, cmd2, &data) => {
+                            Frame::StandardInsteonReceive {
+                                from: to,
+                                to: self.config.info.address,
+                                flags: MessageFlags::ACK | MessageFlags::BROADCAST_OR_NAK,
+                                hops_remaining: 3,
+                                max_hops: 3,
+                                cmd1,
+                                cmd2: NAK_CHECKSUM,
+                            }
+                            .to_bytes(&mut reply);
+                            return reply.to_vec();
+                        }
+                        _ => {}
+                    }
+                }
+
+                let mut aldb_records = Vec::new();
+                let mut keypad_data = None;
+                let id = match device {
+                    Some(device) => {
+                        match cmd1 {
+                            // A cleanup carries the group, and the device
+                            // goes to the level in its responder record.
+                            ON | ON_FAST if cleanup => {
+                                let modem = self.config.info.address;
+                                if let Some(link) = device.links.iter().find(|link| {
+                                    link.group == cmd2
+                                        && link.address == modem
+                                        && !link.flags.contains(AllLinkFlags::IS_CONTROLLER)
+                                }) {
+                                    device.level = link.data[0];
+                                }
+                            }
+                            ON | ON_FAST => device.level = cmd2,
+                            OFF | OFF_FAST => device.level = 0,
+                            STATUS_REQUEST => cmd2 = device.level,
+                            VERSION_QUERY => cmd2 = engine.into(),
+                            SET_ADDRESS_MSB => {
+                                self.peek_msb.insert(to, cmd2);
+                            }
+                            PEEK => {
+                                let msb = self.peek_msb.get(&to).copied().unwrap_or(0);
+                                cmd2 = device.peek(u16::from_be_bytes([msb, cmd2]));
+                            }
+                            START_LINKING | START_UNLINKING => device.linking = true,
+                            CANCEL_LINKING => device.linking = false,
+                            READ_WRITE_ALDB if flags.contains(MessageFlags::EXTENDED) => {
+                                let mut data = [0u8; 14];
+                                data.copy_from_slice(&request[8..22]);
+                                aldb_records = read_write_aldb(device, &data);
+                            }
+                            EXTENDED_SET_GET if flags.contains(MessageFlags::EXTENDED) => {
+                                match request[9] {
+                                    DATA_REQUEST => {
+                                        let mut data = [0u8; 14];
+                                        data[0] = request[8];
+                                        data[1] = DATA_RESPONSE;
+                                        data[10] = device.leds;
+                                        keypad_data = Some(data);
+                                    }
+                                    SET_LED_MASK => device.leds = request[10],
+                                    _ => {}
+                                }
+                            }
+                            _ => {}
+                        }
+                        [
+                            device.category,
+                            device.sub_category,
+                            device.firmware_version,
+                        ]
+                    }
+                    None => {
+                        if cmd1 == VERSION_QUERY {
+                            cmd2 = engine.into();
+                        }
+                        DEVICE_ID
+                    }
+                };
+
+                // Every device is awake and acknowledges direct messages,
+                // and a cleanup's ACK carries the group back.
+                if cleanup {
+                    Frame::StandardInsteonReceive {
+                        from: to,
+                        to: self.config.info.address,
+                        flags: MessageFlags::ACK | MessageFlags::GROUP,
+                        hops_remaining: 3,
+                        max_hops: 3,
+                        cmd1,
+                        cmd2: request[7],
+                    }
+                    .to_bytes(&mut reply);
+                } else if !flags.contains(MessageFlags::GROUP) {
+                    Frame::StandardInsteonReceive {
+                        from: to,
+                        to: self.config.info.address,
+                        flags: MessageFlags::ACK,
+                        hops_remaining: 3,
+                        max_hops: 3,
+                        cmd1,
+                        cmd2,
+                    }
+                    .to_bytes(&mut reply);
+                }
+
+                if let Some(data) = keypad_data {
+                    Frame::ExtendedInsteonReceive {
+                        from: to,
+                        to: self.config.info.address,
+                        flags: MessageFlags::EXTENDED,
+                        hops_remaining: 3,
+                        max_hops: 3,
+                        cmd1,
+                        cmd2: 0,
+                        data,
+                    }
+                    .to_bytes(&mut reply);
+                }
+
+                for record in aldb_records {
+                    let mut data = [0u8; 14];
+                    data[1] = ALDB_RECORD;
+                    data[2..4].copy_from_slice(&record.offset.to_be_bytes());
+                    data[5] = record.flags.bits();
+                    data[6] = record.group;
+                    data[7..10].copy_from_slice(&<[u8; 3]>::from(record.address));
+                    data[10..13].copy_from_slice(&record.data);
+                    Frame::ExtendedInsteonReceive {
+                        from: to,
+                        to: self.config.info.address,
+                        flags: MessageFlags::EXTENDED,
+                        hops_remaining: 3,
+                        max_hops: 3,
+                        cmd1,
+                        cmd2: 0,
+                        data,
+                    }
+                    .to_bytes(&mut reply);
+                }
+
+                // Devices answer an ID request with a set button broadcast
+                // carrying their category in place of the address.
+                if cmd1 == ID_REQUEST {
+                    Frame::StandardInsteonReceive {
+                        from: to,
+                        to: Address::from(id),
+                        flags: MessageFlags::BROADCAST_OR_NAK,
+                        hops_remaining: 3,
+                        max_hops: 3,
+                        cmd1: SET_BUTTON_RESPONDER,
+                        cmd2: 0,
+                    }
+                    .to_bytes(&mut reply);
+                }
+            }
+            GET_FIRST_ALL_LINK_RECORD | GET_NEXT_ALL_LINK_RECORD => {
+                if request[1] == GET_FIRST_ALL_LINK_RECORD {
+                    self.next_link = 0;
+                }
+
+                match self.config.links.get(self.next_link) {
+                    Some(link) => {
+                        echo(&mut reply, ACK);
+                        Frame::AllLinkRecord(link.clone()).to_bytes(&mut reply);
+                        self.next_link += 1;
+                    }
+                    None => echo(&mut reply, NAK),
+                }
+            }
+            ALL_LINK_SEND => {
+                echo(&mut reply, ACK);
+
+                // Clean up with every responder, which fails for devices
+                // that aren't emulated.
+                let group = request[2];
+                for link in self.config.links.iter().filter(|link| {
+                    link.group == group && link.flags.contains(AllLinkFlags::IS_CONTROLLER)
+                }) {
+                    let known = self.config.devices.is_empty()
+                        || self.config.devices.iter().any(|d| d.address == link.to);
+                    if known {
+                        Frame::StandardInsteonReceive {
+                            from: link.to,
+                            to: self.config.info.address,
+                            flags: MessageFlags::ACK | MessageFlags::GROUP,
+                            hops_remaining: 3,
+                            max_hops: 3,
+                            cmd1: request[3],
+                            cmd2: group,
+                        }
+                        .to_bytes(&mut reply);
+                    } else {
+                        Frame::AllLinkCleanupFailure {
+                            group,
+                            address: link.to,
+                        }
+                        .to_bytes(&mut reply);
+                    }
+                }
+                Frame::AllLinkCleanupStatus { completed: true }.to_bytes(&mut reply);
+            }
+            MANAGE_ALL_LINK_RECORD => {
+                let record = AllLinkRecord {
+                    flags: AllLinkFlags::from_bits_truncate(request[3]),
+                    group: request[4],
+                    to: Address::from([request[5], request[6], request[7]]),
+                    data: [request[8], request[9], request[10]],
+                };
+                let found = self
+                    .config
+                    .links
+                    .iter()
+                    .position(|link| link.group == record.group && link.to == record.to);
+                let same_role = self.config.links.iter().position(|link| {
+                    link.group == record.group
+                        && link.to == record.to
+                        && link.flags.contains(AllLinkFlags::IS_CONTROLLER)
+                            == record.flags.contains(AllLinkFlags::IS_CONTROLLER)
+                });
+
+                match (AllLinkAction::from(request[2]), found) {
+                    (AllLinkAction::FindFirst, Some(index)) => {
+                        echo(&mut reply, ACK);
+                        Frame::AllLinkRecord(self.config.links[index].clone()).to_bytes(&mut reply);
+                    }
+                    (AllLinkAction::Delete, Some(index)) => {
+                        self.config.links.remove(index);
+                        echo(&mut reply, ACK);
+                    }
+                    (AllLinkAction::Modify, _)
+                    | (AllLinkAction::AddController, _)
+                    | (AllLinkAction::AddResponder, _) => {
+                        match same_role {
+                            Some(index) => self.config.links[index] = record,
+                            None => self.config.links.push(record),
+                        }
+                        echo(&mut reply, ACK);
+                    }
+                    _ => echo(&mut reply, NAK),
+                }
+            }
+            START_ALL_LINK if !self.config.devices.is_empty() => {
+                echo(&mut reply, ACK);
+
+                // Link with whichever device is waiting, or wait for one.
+                self.linking = Some((AllLinkMode::from(request[2]), request[3]));
+                if let Some(index) = self.config.devices.iter().position(|d| d.linking) {
+                    self.link(index, &mut reply);
+                }
+            }
+            START_ALL_LINK => {
+                echo(&mut reply, ACK);
+
+                // Pretend the last device we talked to, or a new one,
+                // had its set button pressed.
+                let mode = match request[2] {
+                    LINK_MODE_RESPONDER => AllLinkMode::Responder,
+                    LINK_MODE_DELETE => AllLinkMode::Delete,
+                    _ => AllLinkMode::Controller,
+                };
+                let address = self
+                    .last_target
+                    .unwrap_or_else(|| Address::from([0x11, 0x22, 0x33]));
+                self.config.links.push(AllLinkRecord {
+                    flags: if mode == AllLinkMode::Controller {
+                        AllLinkFlags::IN_USE | AllLinkFlags::IS_CONTROLLER
+                    } else {
+                        AllLinkFlags::IN_USE
+                    },
+                    group: request[3],
+                    to: address,
+                    data: [0x01, 0x20, 0x41],
+                });
+
+                Frame::AllLinkComplete(AllLinkComplete {
+                    mode,
+                    group: request[3],
+                    address,
+                    category: DEVICE_ID[0],
+                    sub_category: DEVICE_ID[1],
+                    firmware_version: DEVICE_ID[2],
+                })
+                .to_bytes(&mut reply);
+            }
+            CANCEL_ALL_LINK => {
+                echo(&mut reply, ACK);
+                self.linking = None;
+            }
+            GET_IM_CONFIGURATION => Frame::ModemConfig(self.modem_config).to_bytes(&mut reply),
+            SET_IM_CONFIGURATION => {
+                self.modem_config = ModemConfig::from_bits_truncate(request[2]);
+                echo(&mut reply, ACK);
+            }
+            // The emulated devices only ever answer, so the last sender is
+            // the last device sent to.
+            GET_ALL_LINK_RECORD_FOR_SENDER => {
+                let target = self.last_target;
+                match self
+                    .config
+                    .links
+                    .iter()
+                    .find(|link| Some(link.to) == target)
+                {
+                    Some(link) => {
+                        echo(&mut reply, ACK);
+                        Frame::AllLinkRecord(link.clone()).to_bytes(&mut reply);
+                    }
+                    None => echo(&mut reply, NAK),
+                }
+            }
+            RF_SLEEP => {
+                echo(&mut reply, ACK);
+                self.asleep = true;
+            }
+            RESET => {
+                echo(&mut reply, ACK);
+                self.config.links.clear();
+                self.modem_config = ModemConfig::NONE;
+                Frame::UserResetDetected.to_bytes(&mut reply);
+            }
+            READ_DATABASE if self.config.reads_memory => {
+                echo(&mut reply, ACK);
+                let address = u16::from_be_bytes([request[2], request[3]]);
+                Frame::MemoryRecord(self.read_memory(address)).to_bytes(&mut reply);
+            }
+            READ_DATABASE => echo(&mut reply, NAK),
+            _ => echo(&mut reply, ACK),
+        }
+
+        reply.to_vec()
+    }
+}
@@ -1,5 +1,14 @@
+use crate::frame::{Address, AllLinkMode};
+
+/// The most input bytes kept in an [Error::Parse].
+pub const MAX_PARSE_ERROR_BYTES: usize = 32;
+
 /// Errors returned from various operations.
+///
+/// Variants are added as new failure modes are reported, so matches on
+/// `Error` need a `_` arm.
 #[derive(Clone, Debug, thiserror::Error, PartialEq)]
+#[non_exhaustive]
 pub enum Error {
     #[error("I/O error: {0:?}")]
     IoError(::std::io::ErrorKind),
@@ -11,14 +20,42 @@ pub enum Error {
     #[error("Command was not acknowledged")]
     NotAcknowledged,
 
-    /// Failure to parse a [Message](super::Message) or modem command.
-    #[error("Parse error")]
-    Parse,
+    /// A device refused a direct [Message](super::Message). The reason code
+    /// is included, e.g. `0xfd` for a bad checksum.
+    #[error("Device did not acknowledge message (reason {0:02x})")]
+    DeviceNotAcknowledged(u8),
+
+    /// The device does not support the requested operation, e.g. extended
+    /// messages sent to an i1 device.
+    #[error("Operation not supported by device")]
+    UnsupportedByDevice,
+
+    /// The modem's firmware is too old for the requested command.
+    #[error("Modem firmware version {actual:02x} is too old, {required:02x} is required")]
+    UnsupportedByModem { required: u8, actual: u8 },
+
+    /// Failure to parse a [Message](super::Message) or modem command. The
+    /// start of the unparsed input is included, up to [MAX_PARSE_ERROR_BYTES].
+    #[error("Parse error in {context}: {bytes:02x?}")]
+    Parse {
+        context: &'static str,
+        bytes: Vec<u8>,
+    },
+
+    /// Too much input arrived without a frame being decoded, so some of it
+    /// was thrown away. This usually means the serial port is at the wrong
+    /// baud rate.
+    #[error("Discarded {discarded} bytes of undecodable input")]
+    BufferOverflow { discarded: usize },
 
     /// An operation took too long to complete.
     #[error("Operation timed out")]
     Timeout,
 
+    /// The operation was cancelled with a [CancelHandle](super::CancelHandle).
+    #[error("Operation was cancelled")]
+    Cancelled,
+
     /// An unexpected response was received.
     #[error("Unexpected response received")]
     UnexpectedResponse,
@@ -30,6 +67,71 @@ pub enum Error {
     /// The modem was disconnected.
     #[error("Modem was disconnected.")]
     Disconnected,
+
+    /// The operation can't be performed with the given [AllLinkMode](super::AllLinkMode).
+    #[error("Unsupported link mode {0}")]
+    UnsupportedLinkMode(AllLinkMode),
+
+    /// No [Modem](super::Modem) is linked to the device with the given [Address](super::Address).
+    #[error("No modem is linked to {0}")]
+    NoRoute(Address),
+
+    /// Every group number is already in use by the modem.
+    #[error("No free group is available")]
+    NoFreeGroup,
+
+    /// A [Plan](super::plan::Plan) could not be loaded.
+    #[cfg(feature = "plan")]
+    #[error("Invalid plan: {0}")]
+    InvalidPlan(String),
+
+    /// A [PendingCommands](super::PendingCommands) file could not be read or written.
+    #[cfg(feature = "persist")]
+    #[error("Invalid pending command store: {0}")]
+    InvalidStore(String),
+
+    /// A device did not reach the requested state after being commanded to.
+    #[error("Device reported level {actual:02x}, expected {expected:02x}")]
+    VerificationFailed { expected: u8, actual: u8 },
+
+    /// A record written to a device's link database read back differently.
+    #[error("Device link at {offset:04x} in {address} did not read back as written")]
+    DeviceLinkMismatch { address: Address, offset: u16 },
+
+    /// An X10 unit outside of 1 to 16 was given.
+    #[error("Invalid X10 unit {0}, must be 1 to 16")]
+    InvalidX10Unit(u8),
+
+    /// An operating flag change was made for something other than a single
+    /// flag known to its family.
+    #[error("{0} is not a single known operating flag")]
+    UnknownOperatingFlag(String),
+
+    /// A hop count above [DEFAULT_MAX_HOPS](super::DEFAULT_MAX_HOPS) was given.
+    #[error("Invalid max hops {0}, must be 0 to 3")]
+    InvalidMaxHops(u8),
+
+    /// A [RateLimit](super::RateLimit) of zero, negative or NaN commands per
+    /// second was given.
+    #[error("Invalid rate limit of {0} commands per second")]
+    InvalidRateLimit(f64),
+
+    /// A button number the device doesn't have was given.
+    #[error("Invalid button {0}")]
+    InvalidButton(u8),
+
+    /// A remote was given a number of buttons other than 1, 4 or 8.
+    #[error("Invalid button count {0}")]
+    InvalidButtonCount(u8),
+}
+
+impl Error {
+    pub(crate) fn parse(context: &'static str, bytes: &[u8]) -> Error {
+        Error::Parse {
+            context,
+            bytes: bytes[..bytes.len().min(MAX_PARSE_ERROR_BYTES)].to_vec(),
+        }
+    }
 }
 
 impl From<::std::io::Error> for Error {
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use futures::{future, Stream, StreamExt};
+
+use crate::device::*;
+use crate::error::*;
+use crate::flags::*;
+use crate::frame::*;
+use crate::message::*;
+use crate::modem::*;
+use crate::remote::*;
+
+/// Something that happened on the network, as delivered by
+/// [Modem::events]. This saves every application from working out what
+/// the flags and commands of each [Message] mean.
+///
+/// More events may be added, so matches need a `_` arm.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Event {
+    /// A switch, dimmer or the load button of a keypad was turned on at the
+    /// device. `fast` is true for a double tap.
+    SwitchTurnedOn { address: Address, fast: bool },
+    /// A switch, dimmer or the load button of a keypad was turned off at
+    /// the device. `fast` is true for a double tap.
+    SwitchTurnedOff { address: Address, fast: bool },
+    /// A dimmer was brightened or dimmed by holding its paddle. Ask for
+    /// the new level with [Modem::get_level].
+    DimmerLevelChanged { address: Address },
+    /// A motion, door or leak sensor reported something.
+    Sensor {
+        address: Address,
+        event: SensorEvent,
+    },
+    /// A button other than the load button was pressed on a keypad or
+    /// remote.
+    ButtonPressed {
+        address: Address,
+        event: RemoteEvent,
+    },
+    /// A device was linked to the modem.
+    LinkCompleted(AllLinkComplete),
+    /// A device acknowledged a direct message with `command`.
+    Acknowledged {
+        address: Address,
+        #[cfg_attr(feature = "serde", serde(with = "crate::message::raw_command"))]
+        command: Command,
+    },
+}
+
+/// Turns received frames into [Event]s. The [DeviceFamily] of each device
+/// decides what its group broadcasts mean, e.g. group 1 On is motion from
+/// a sensor but a paddle press on a dimmer.
+#[derive(Debug, Default)]
+pub(crate) struct EventDecoder {
+    families: HashMap<Address, DeviceFamily>,
+    /// The last group broadcast, so the cleanup that repeats it is only
+    /// reported if the broadcast itself was missed.
+    last_broadcast: Option<(Address, u8, Command)>,
+}
+
+impl EventDecoder {
+    pub(crate) fn new(families: HashMap<Address, DeviceFamily>) -> Self {
+        EventDecoder {
+            families,
+            last_broadcast: None,
+        }
+    }
+
+    pub(crate) fn decode(&mut self, frame: Frame) -> Option<Event> {
+        if let Frame::AllLinkComplete(complete) = &frame {
+            if let Some(family) =
+                DeviceFamily::from_category(complete.category, complete.sub_category)
+            {
+                self.families.insert(complete.address, family);
+            }
+            return match complete.mode {
+                AllLinkMode::Delete => None,
+                _ => Some(Event::LinkCompleted(complete.clone())),
+            };
+        }
+
+        let message = Message::try_from(frame).ok()?;
+        let address = message.from;
+        if message.is_nak() || message.device_id().is_some() {
+            return None;
+        }
+
+        // Acknowledgements of the modem's own cleanups aren't news.
+        if message.is_cleanup_ack() {
+            return None;
+        }
+        if message.flags.contains(MessageFlags::ACK) {
+            return Some(Event::Acknowledged {
+                address,
+                command: message.cmd1,
+            });
+        }
+
+        let group = message.group()?;
+        let key = (address, group, message.cmd1);
+        if message.is_group_broadcast() {
+            self.last_broadcast = Some(key);
+        } else if message.is_cleanup() && self.last_broadcast == Some(key) {
+            return None;
+        }
+
+        match self.families.get(&address) {
+            Some(DeviceFamily::Sensor) => SensorEvent::from_group(group, message.cmd1)
+                .map(|event| Event::Sensor { address, event }),
+            _ if group == 1 => match message.cmd1 {
+                Command::On => Some(Event::SwitchTurnedOn {
+                    address,
+                    fast: false,
+                }),
+                Command::OnFast => Some(Event::SwitchTurnedOn {
+                    address,
+                    fast: true,
+                }),
+                Command::Off => Some(Event::SwitchTurnedOff {
+                    address,
+                    fast: false,
+                }),
+                Command::OffFast => Some(Event::SwitchTurnedOff {
+                    address,
+                    fast: true,
+                }),
+                Command::StopManualChange => Some(Event::DimmerLevelChanged { address }),
+                _ => None,
+            },
+            _ => RemoteAction::from_message(&message).map(|action| Event::ButtonPressed {
+                address,
+                event: RemoteEvent {
+                    button: group,
+                    action,
+                },
+            }),
+        }
+    }
+}
+
+impl Modem {
+    /// Delivers an [Event] for each thing that happens on the network,
+    /// along with when it was received. The kind of each device is taken
+    /// from the modem's link database, where linking records it, so link
+    /// devices before listening.
+    pub async fn events(
+        &mut self,
+    ) -> Result<impl Stream<Item = (Timestamp, Event)> + Send + Unpin, Error> {
+        let families = self
+            .get_links()
+            .await?
+            .filter_map(|record| {
+                DeviceFamily::from_category(record.data[0], record.data[1])
+                    .map(|family| (record.to, family))
+            })
+            .collect();
+
+        let mut decoder = EventDecoder::new(families);
+        Ok(self
+            .listen_raw_timestamped()
+            .await?
+            .filter_map(move |(received, frame)| {
+                future::ready(decoder.decode(frame).map(|event| (received, event)))
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn frame(from: &str, to: [u8; 3], flags: MessageFlags, cmd1: Command, cmd2: u8) -> Frame {
+        Frame::StandardInsteonReceive {
+            from: Address::from_str(from).unwrap(),
+            to: Address::from(to),
+            flags,
+            hops_remaining: 3,
+            max_hops: 3,
+            cmd1: cmd1.into(),
+            cmd2,
+        }
+    }
+
+    fn broadcast(from: &str, group: u8, cmd1: Command) -> Frame {
+        let flags = MessageFlags::GROUP | MessageFlags::BROADCAST_OR_NAK;
+        frame(from, [0, 0, group], flags, cmd1, 0)
+    }
+
+    fn cleanup(from: &str, group: u8, cmd1: Command) -> Frame {
+        frame(from, [0x44, 0x85, 0x11], MessageFlags::GROUP, cmd1, group)
+    }
+
+    #[test]
+    fn switch() {
+        let mut decoder = EventDecoder::default();
+        let address = Address::from_str("11.22.33").unwrap();
+
+        assert_eq!(
+            decoder.decode(broadcast("11.22.33", 1, Command::OnFast)),
+            Some(Event::SwitchTurnedOn {
+                address,
+                fast: true
+            })
+        );
+        assert_eq!(
+            decoder.decode(cleanup("11.22.33", 1, Command::OnFast)),
+            None
+        );
+
+        // The broadcast for this one was missed.
+        assert_eq!(
+            decoder.decode(cleanup("11.22.33", 1, Command::Off)),
+            Some(Event::SwitchTurnedOff {
+                address,
+                fast: false
+            })
+        );
+        assert_eq!(
+            decoder.decode(broadcast("11.22.33", 1, Command::StopManualChange)),
+            Some(Event::DimmerLevelChanged { address })
+        );
+    }
+
+    #[test]
+    fn families() {
+        let sensor = Address::from_str("44.55.66").unwrap();
+        let mut families = HashMap::new();
+        families.insert(sensor, DeviceFamily::Sensor);
+        let mut decoder = EventDecoder::new(families);
+
+        assert_eq!(
+            decoder.decode(broadcast("44.55.66", 1, Command::On)),
+            Some(Event::Sensor {
+                address: sensor,
+                event: SensorEvent::Triggered
+            })
+        );
+        assert_eq!(
+            decoder.decode(broadcast("33.44.55", 3, Command::Off)),
+            Some(Event::ButtonPressed {
+                address: Address::from_str("33.44.55").unwrap(),
+                event: RemoteEvent {
+                    button: 3,
+                    action: RemoteAction::Off
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn acknowledged() {
+        let mut decoder = EventDecoder::default();
+        assert_eq!(
+            decoder.decode(frame(
+                "11.22.33",
+                [0x44, 0x85, 0x11],
+                MessageFlags::ACK,
+                Command::On,
+                0xff
+            )),
+            Some(Event::Acknowledged {
+                address: Address::from_str("11.22.33").unwrap(),
+                command: Command::On
+            })
+        );
+        assert_eq!(decoder.decode(Frame::UserResetDetected), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let event = Event::SwitchTurnedOn {
+            address: Address::from([0x11, 0x22, 0x33]),
+            fast: false,
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"SwitchTurnedOn":{"address":"11.22.33","fast":false}}"#
+        );
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+    }
+
+    #[async_std::test]
+    async fn timestamped() {
+        let dimmer = Address::from_str("11.22.33").unwrap();
+        let mock = crate::testing::MockModem::new();
+        mock.set_links(vec![AllLinkRecord {
+            flags: AllLinkFlags::IN_USE,
+            group: 1,
+            to: dimmer,
+            data: [0x01, 0x20, 0x45],
+        }]);
+        let mut modem = Modem::new(mock.clone());
+        let mut events = modem.events().await.unwrap();
+        modem.flush().await.unwrap();
+
+        let before = std::time::Instant::now();
+        mock.receive(broadcast("11.22.33", 1, Command::On));
+        let (received, event) = events.next().await.unwrap();
+        assert!(received.instant >= before);
+        assert_eq!(
+            event,
+            Event::SwitchTurnedOn {
+                address: dimmer,
+                fast: false
+            }
+        );
+    }
+}
@@ -0,0 +1,361 @@
+//! A C API, enabled by the `ffi` feature. Build it as a shared library
+//! with `cargo rustc --lib --features ffi --crate-type cdylib`. The header
+//! in `include/plm.h` is generated from this file by cbindgen.
+//!
+//! Every function returns a [PlmStatus], and blocks until the modem
+//! responds. A panic is caught before it can unwind into C and returned as
+//! [PlmStatus::Panic]. Events are delivered on a background thread to the
+//! callback given to [plm_modem_set_event_callback].
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::str::FromStr;
+use std::thread;
+
+use futures::{executor::block_on, StreamExt};
+
+use crate::error::*;
+use crate::frame::*;
+use crate::message::*;
+use crate::modem::*;
+
+/// The result of every `plm_` function.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlmStatus {
+    Ok = 0,
+    /// A pointer or string argument was null or invalid.
+    InvalidArgument,
+    /// The serial port could not be opened or used.
+    Io,
+    /// The modem did not acknowledge the command.
+    NotAcknowledged,
+    /// The device refused the message.
+    DeviceNotAcknowledged,
+    /// The modem or device never responded.
+    Timeout,
+    /// The modem sent something that could not be decoded.
+    Parse,
+    /// An address string was not in the form `xx.xx.xx`.
+    InvalidAddress,
+    /// The connection to the modem was lost.
+    Disconnected,
+    /// Any other error.
+    Other,
+    /// The library panicked. The modem should be closed.
+    Panic,
+}
+
+impl From<Error> for PlmStatus {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::IoError(_) => PlmStatus::Io,
+            Error::NotAcknowledged => PlmStatus::NotAcknowledged,
+            Error::DeviceNotAcknowledged(_) => PlmStatus::DeviceNotAcknowledged,
+            Error::Timeout => PlmStatus::Timeout,
+            Error::Parse { .. } | Error::NomError(_) | Error::BufferOverflow { .. } => {
+                PlmStatus::Parse
+            }
+            Error::InvalidAddress => PlmStatus::InvalidAddress,
+            Error::Disconnected => PlmStatus::Disconnected,
+            _ => PlmStatus::Other,
+        }
+    }
+}
+
+impl<T> From<Result<T, Error>> for PlmStatus {
+    fn from(result: Result<T, Error>) -> Self {
+        match result {
+            Ok(_) => PlmStatus::Ok,
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// An INSTEON message, as passed to the event callback.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PlmMessage {
+    pub from: [u8; 3],
+    pub to: [u8; 3],
+    pub flags: u8,
+    pub hops_remaining: u8,
+    pub max_hops: u8,
+    pub cmd1: u8,
+    pub cmd2: u8,
+    /// Only meaningful when `flags` has the extended bit (0x10) set.
+    pub data: [u8; 14],
+}
+
+impl From<&Message> for PlmMessage {
+    fn from(message: &Message) -> Self {
+        PlmMessage {
+            from: message.from.into(),
+            to: message.to.into(),
+            flags: message.flags.bits(),
+            hops_remaining: message.hops_remaining,
+            max_hops: message.max_hops,
+            cmd1: message.cmd1.into(),
+            cmd2: message.cmd2.into(),
+            data: message.data,
+        }
+    }
+}
+
+/// Called with each received message and the `user_data` given when it
+/// was registered. The message is only valid during the call.
+pub type PlmEventCallback =
+    Option<extern "C" fn(message: *const PlmMessage, user_data: *mut c_void)>;
+
+/// An opaque handle to an open modem.
+pub struct PlmModem {
+    modem: Modem,
+}
+
+struct UserData(*mut c_void);
+
+// The caller promises `user_data` may be used from the event thread.
+unsafe impl Send for UserData {}
+
+/// Runs `f`, returning [PlmStatus::Panic] if it panics rather than letting
+/// the panic unwind across the FFI boundary.
+fn catch<F: FnOnce() -> PlmStatus>(f: F) -> PlmStatus {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(PlmStatus::Panic)
+}
+
+unsafe fn address(address: *const u8) -> Option<Address> {
+    if address.is_null() {
+        None
+    } else {
+        Some(Address::from(std::slice::from_raw_parts(address, 3)))
+    }
+}
+
+/// Opens the modem attached to the serial port at `path`, storing a handle
+/// in `modem`. Close it with [plm_modem_close].
+///
+/// # Safety
+/// `path` must be a NUL terminated string and `modem` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn plm_modem_open(
+    path: *const c_char,
+    modem: *mut *mut PlmModem,
+) -> PlmStatus {
+    catch(|| {
+        if path.is_null() || modem.is_null() {
+            return PlmStatus::InvalidArgument;
+        }
+
+        let path = match CStr::from_ptr(path).to_str() {
+            Ok(path) => path.to_owned(),
+            Err(_) => return PlmStatus::InvalidArgument,
+        };
+
+        match Modem::from_path(path) {
+            Ok(opened) => {
+                *modem = Box::into_raw(Box::new(PlmModem { modem: opened }));
+                PlmStatus::Ok
+            }
+            Err(e) => Error::from(e).into(),
+        }
+    })
+}
+
+/// Closes a modem opened with [plm_modem_open]. Passing null does nothing.
+///
+/// # Safety
+/// `modem` must not be used after this.
+#[no_mangle]
+pub unsafe extern "C" fn plm_modem_close(modem: *mut PlmModem) {
+    if !modem.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(modem))));
+    }
+}
+
+/// Parses an address such as `"11.22.33"` into the 3 bytes at `address`.
+///
+/// # Safety
+/// `s` must be a NUL terminated string and `address` must point to 3 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn plm_address_parse(s: *const c_char, address: *mut u8) -> PlmStatus {
+    catch(|| {
+        if s.is_null() || address.is_null() {
+            return PlmStatus::InvalidArgument;
+        }
+
+        let parsed = CStr::from_ptr(s)
+            .to_str()
+            .map_err(|_| Error::InvalidAddress)
+            .and_then(Address::from_str);
+        match parsed {
+            Ok(parsed) => {
+                ptr::copy_nonoverlapping(<[u8; 3]>::from(parsed).as_ptr(), address, 3);
+                PlmStatus::Ok
+            }
+            Err(e) => e.into(),
+        }
+    })
+}
+
+/// Sends a message with `cmd1` and `cmd2` to the device at the 3 byte
+/// `address`. If `data` isn't null, its 14 bytes are sent in an extended
+/// message.
+///
+/// # Safety
+/// `modem` must be open, `address` must point to 3 bytes and `data` must
+/// be null or point to 14 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn plm_modem_send(
+    modem: *mut PlmModem,
+    address: *const u8,
+    cmd1: u8,
+    cmd2: u8,
+    data: *const u8,
+) -> PlmStatus {
+    catch(|| {
+        let (modem, address) = match (modem.as_mut(), self::address(address)) {
+            (Some(modem), Some(address)) => (modem, address),
+            _ => return PlmStatus::InvalidArgument,
+        };
+
+        let mut message: Message = (address, Command::from(cmd1), Command::from(cmd2)).into();
+        if !data.is_null() {
+            message.flags |= MessageFlags::EXTENDED;
+            message
+                .data
+                .copy_from_slice(std::slice::from_raw_parts(data, 14));
+        }
+
+        block_on(modem.modem.send_message(message)).into()
+    })
+}
+
+/// Turns the device at the 3 byte `address` on to `level`, from 0 to 255.
+///
+/// # Safety
+/// `modem` must be open and `address` must point to 3 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn plm_modem_on(
+    modem: *mut PlmModem,
+    address: *const u8,
+    level: u8,
+) -> PlmStatus {
+    plm_modem_send(modem, address, Command::On.into(), level, ptr::null())
+}
+
+/// Turns the device at the 3 byte `address` off.
+///
+/// # Safety
+/// `modem` must be open and `address` must point to 3 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn plm_modem_off(modem: *mut PlmModem, address: *const u8) -> PlmStatus {
+    plm_modem_send(modem, address, Command::Off.into(), 0, ptr::null())
+}
+
+/// Calls `callback` on a background thread with every message received
+/// from now until the modem is closed.
+///
+/// # Safety
+/// `modem` must be open, and `user_data` must be safe to use from another
+/// thread for as long as the modem is.
+#[no_mangle]
+pub unsafe extern "C" fn plm_modem_set_event_callback(
+    modem: *mut PlmModem,
+    callback: PlmEventCallback,
+    user_data: *mut c_void,
+) -> PlmStatus {
+    catch(|| {
+        let (modem, callback) = match (modem.as_mut(), callback) {
+            (Some(modem), Some(callback)) => (modem, callback),
+            _ => return PlmStatus::InvalidArgument,
+        };
+
+        let mut stream = match block_on(modem.modem.listen()) {
+            Ok(stream) => stream,
+            Err(e) => return e.into(),
+        };
+
+        let user_data = UserData(user_data);
+        thread::spawn(move || {
+            let user_data = user_data;
+            while let Some(message) = block_on(stream.next()) {
+                callback(&PlmMessage::from(&message), user_data.0);
+            }
+        });
+
+        PlmStatus::Ok
+    })
+}
+
+/// Returns a static description of `status`.
+#[no_mangle]
+pub extern "C" fn plm_status_string(status: PlmStatus) -> *const c_char {
+    let description: &'static [u8] = match status {
+        PlmStatus::Ok => b"Success\0",
+        PlmStatus::InvalidArgument => b"Invalid argument\0",
+        PlmStatus::Io => b"I/O error\0",
+        PlmStatus::NotAcknowledged => b"Command was not acknowledged\0",
+        PlmStatus::DeviceNotAcknowledged => b"Device did not acknowledge message\0",
+        PlmStatus::Timeout => b"Operation timed out\0",
+        PlmStatus::Parse => b"Parse error\0",
+        PlmStatus::InvalidAddress => b"Invalid address format\0",
+        PlmStatus::Disconnected => b"Modem was disconnected\0",
+        PlmStatus::Other => b"Unknown error\0",
+        PlmStatus::Panic => b"Internal error\0",
+    };
+    description.as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_parse() {
+        let mut address = [0u8; 3];
+        let status =
+            unsafe { plm_address_parse(b"11.22.33\0".as_ptr() as _, address.as_mut_ptr()) };
+        assert_eq!(status, PlmStatus::Ok);
+        assert_eq!(address, [0x11, 0x22, 0x33]);
+
+        let status = unsafe { plm_address_parse(b"zz\0".as_ptr() as _, address.as_mut_ptr()) };
+        assert_eq!(status, PlmStatus::InvalidAddress);
+        assert_eq!(
+            unsafe { plm_address_parse(ptr::null(), address.as_mut_ptr()) },
+            PlmStatus::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn status() {
+        assert_eq!(
+            PlmStatus::from(Err::<(), _>(Error::Timeout)),
+            PlmStatus::Timeout
+        );
+        assert_eq!(PlmStatus::from(Ok::<_, Error>(())), PlmStatus::Ok);
+        let description = unsafe { CStr::from_ptr(plm_status_string(PlmStatus::Timeout)) };
+        assert_eq!(description.to_str(), Ok("Operation timed out"));
+        assert_eq!(catch(|| panic!("oops")), PlmStatus::Panic);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn on() {
+        use crate::emulator::*;
+        use std::ffi::CString;
+
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let path = CString::new(emulator.path().to_str().unwrap()).unwrap();
+        let mut modem = ptr::null_mut();
+        unsafe {
+            assert_eq!(plm_modem_open(path.as_ptr(), &mut modem), PlmStatus::Ok);
+            assert_eq!(
+                plm_modem_on(modem, [0x11, 0x22, 0x33].as_ptr(), 0xff),
+                PlmStatus::Ok
+            );
+            plm_modem_close(modem);
+        }
+    }
+}
@@ -0,0 +1,483 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bitflags::bitflags;
+
+use log::debug;
+
+use crate::error::*;
+use crate::frame::*;
+use crate::keypad::*;
+use crate::message::*;
+use crate::modem::*;
+
+// Extended set commands, sent in the second data byte
+const SET_RAMP_RATE: u8 = 0x05;
+const SET_ON_LEVEL: u8 = 0x06;
+const SET_LED_BRIGHTNESS: u8 = 0x07;
+
+/// The broad class of a device, which decides what its operating flags
+/// mean. The same bit can be resume-dim on a dimmer and something else
+/// entirely on a sensor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DeviceFamily {
+    /// Dimmers, switches and plug-in modules.
+    Dimmer,
+    /// KeypadLincs.
+    Keypad,
+    /// Battery powered sensors.
+    Sensor,
+}
+
+impl DeviceFamily {
+    /// Returns the family of a device with the given category and
+    /// sub-category, as reported when linking or by an ID request.
+    pub fn from_category(category: u8, sub_category: u8) -> Option<DeviceFamily> {
+        match (category, sub_category) {
+            (0x01, 0x0c) | (0x01, 0x1b) | (0x01, 0x1c) | (0x01, 0x41) | (0x01, 0x42) => {
+                Some(DeviceFamily::Keypad)
+            }
+            (0x02, 0x0f) | (0x02, 0x1e) | (0x02, 0x2c) => Some(DeviceFamily::Keypad),
+            (0x01, _) | (0x02, _) => Some(DeviceFamily::Dimmer),
+            (0x10, _) => Some(DeviceFamily::Sensor),
+            _ => None,
+        }
+    }
+}
+
+impl DeviceId {
+    /// Returns the [DeviceFamily] of the device, if it's a known one.
+    pub fn family(&self) -> Option<DeviceFamily> {
+        DeviceFamily::from_category(self.category, self.sub_category)
+    }
+}
+
+impl FromStr for DeviceFamily {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dimmer" => Ok(DeviceFamily::Dimmer),
+            "keypad" => Ok(DeviceFamily::Keypad),
+            "sensor" => Ok(DeviceFamily::Sensor),
+            _ => Err(Error::parse("device family", s.as_bytes())),
+        }
+    }
+}
+
+impl fmt::Display for DeviceFamily {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DeviceFamily::Dimmer => "dimmer",
+            DeviceFamily::Keypad => "keypad",
+            DeviceFamily::Sensor => "sensor",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The operating flags of one [DeviceFamily], as read with
+/// [Modem::get_operating_flags].
+pub trait OperatingFlags: Copy + fmt::Debug + PartialEq + 'static {
+    /// The family these flags belong to.
+    const FAMILY: DeviceFamily;
+
+    /// The name of each flag, and the cmd2 that turns it on. The following
+    /// value turns it off.
+    const COMMANDS: &'static [(&'static str, Self, u8)];
+
+    fn from_byte(byte: u8) -> Self;
+    fn to_byte(self) -> u8;
+
+    /// Returns the name of every flag along with whether it is set.
+    fn settings(self) -> Vec<(&'static str, bool)> {
+        Self::COMMANDS
+            .iter()
+            .map(|(name, flag, _)| (*name, self.to_byte() & flag.to_byte() == flag.to_byte()))
+            .collect()
+    }
+}
+
+bitflags! {
+    /// Operating flags of dimmers, switches and plug-in modules.
+    pub struct DimmerFlags: u8 {
+        /// Local linking with the set button is disabled.
+        const PROGRAM_LOCK = (1 << 0);
+        /// The LED blinks while the device transmits.
+        const LED_ON_TX    = (1 << 1);
+        /// Turning on returns to the previous level instead of the on-level.
+        const RESUME_DIM   = (1 << 2);
+        /// The status LED is turned off.
+        const LED_OFF      = (1 << 4);
+        /// Switching on the attached load turns on the device.
+        const LOAD_SENSE   = (1 << 5);
+        const NONE         = 0u8;
+    }
+}
+
+impl OperatingFlags for DimmerFlags {
+    const FAMILY: DeviceFamily = DeviceFamily::Dimmer;
+    const COMMANDS: &'static [(&'static str, Self, u8)] = &[
+        ("program-lock", DimmerFlags::PROGRAM_LOCK, 0x00),
+        ("led-on-tx", DimmerFlags::LED_ON_TX, 0x02),
+        ("resume-dim", DimmerFlags::RESUME_DIM, 0x04),
+        ("led-off", DimmerFlags::LED_OFF, 0x08),
+        ("load-sense", DimmerFlags::LOAD_SENSE, 0x0a),
+    ];
+
+    fn from_byte(byte: u8) -> Self {
+        DimmerFlags::from_bits_truncate(byte)
+    }
+
+    fn to_byte(self) -> u8 {
+        self.bits()
+    }
+}
+
+bitflags! {
+    /// Operating flags of KeypadLincs.
+    pub struct KeypadFlags: u8 {
+        /// Local linking with the set button is disabled.
+        const PROGRAM_LOCK = (1 << 0);
+        /// The LED blinks while the device transmits.
+        const LED_ON_TX    = (1 << 1);
+        /// Turning on returns to the previous level instead of the on-level.
+        const RESUME_DIM   = (1 << 2);
+        /// The keypad has eight buttons rather than six.
+        const EIGHT_BUTTON = (1 << 3);
+        /// The button backlights are turned off.
+        const LED_OFF      = (1 << 4);
+        /// The keypad beeps when a button is pressed.
+        const KEY_BEEP     = (1 << 5);
+        const NONE         = 0u8;
+    }
+}
+
+impl OperatingFlags for KeypadFlags {
+    const FAMILY: DeviceFamily = DeviceFamily::Keypad;
+    const COMMANDS: &'static [(&'static str, Self, u8)] = &[
+        ("program-lock", KeypadFlags::PROGRAM_LOCK, 0x00),
+        ("led-on-tx", KeypadFlags::LED_ON_TX, 0x02),
+        ("resume-dim", KeypadFlags::RESUME_DIM, 0x04),
+        ("eight-button", KeypadFlags::EIGHT_BUTTON, 0x06),
+        ("led-off", KeypadFlags::LED_OFF, 0x08),
+        ("key-beep", KeypadFlags::KEY_BEEP, 0x0a),
+    ];
+
+    fn from_byte(byte: u8) -> Self {
+        KeypadFlags::from_bits_truncate(byte)
+    }
+
+    fn to_byte(self) -> u8 {
+        self.bits()
+    }
+}
+
+bitflags! {
+    /// Operating flags of battery powered sensors.
+    pub struct SensorFlags: u8 {
+        /// Local linking with the set button is disabled.
+        const PROGRAM_LOCK = (1 << 0);
+        /// The LED blinks while the device transmits.
+        const LED_ON_TX    = (1 << 1);
+        const NONE         = 0u8;
+    }
+}
+
+impl OperatingFlags for SensorFlags {
+    const FAMILY: DeviceFamily = DeviceFamily::Sensor;
+    const COMMANDS: &'static [(&'static str, Self, u8)] = &[
+        ("program-lock", SensorFlags::PROGRAM_LOCK, 0x00),
+        ("led-on-tx", SensorFlags::LED_ON_TX, 0x02),
+    ];
+
+    fn from_byte(byte: u8) -> Self {
+        SensorFlags::from_bits_truncate(byte)
+    }
+
+    fn to_byte(self) -> u8 {
+        self.bits()
+    }
+}
+
+/// A change to a single operating flag, written as `name=on` or
+/// `name=off`, e.g. `program-lock=on`. Only the names known to the
+/// family `F` are accepted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OperatingFlagChange<F: OperatingFlags> {
+    entry: &'static (&'static str, F, u8),
+    on: bool,
+}
+
+impl<F: OperatingFlags> OperatingFlagChange<F> {
+    /// Returns a change turning `flag` on or off, or
+    /// [Error::UnknownOperatingFlag] if `flag` isn't a single flag known
+    /// to the family `F`.
+    pub fn new(flag: F, on: bool) -> Result<Self, Error> {
+        F::COMMANDS
+            .iter()
+            .find(|(_, known, _)| *known == flag)
+            .map(|entry| OperatingFlagChange { entry, on })
+            .ok_or_else(|| Error::UnknownOperatingFlag(format!("{:?}", flag)))
+    }
+
+    /// Returns the flag being changed.
+    pub fn flag(&self) -> F {
+        self.entry.1
+    }
+
+    /// Returns true if the flag is turned on, false if off.
+    pub fn on(&self) -> bool {
+        self.on
+    }
+
+    fn command(&self) -> u8 {
+        let (_, _, on) = self.entry;
+        if self.on {
+            *on
+        } else {
+            on + 1
+        }
+    }
+}
+
+impl<F: OperatingFlags> FromStr for OperatingFlagChange<F> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::parse("operating flag", s.as_bytes());
+        let mut parts = s.splitn(2, '=');
+        let name = parts.next().ok_or_else(invalid)?;
+        let on = match parts.next() {
+            Some("on") => true,
+            Some("off") => false,
+            _ => return Err(invalid()),
+        };
+
+        F::COMMANDS
+            .iter()
+            .find(|(known, _, _)| *known == name)
+            .map(|entry| OperatingFlagChange { entry, on })
+            .ok_or_else(invalid)
+    }
+}
+
+impl<F: OperatingFlags> fmt::Display for OperatingFlagChange<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, _, _) = self.entry;
+        write!(f, "{}={}", name, if self.on { "on" } else { "off" })
+    }
+}
+
+/// The settings a dimmer, switch or keypad keeps for its load, read with
+/// [Modem::get_device_properties]. `F` is the [OperatingFlags] of its
+/// family.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeviceProperties<F: OperatingFlags> {
+    pub flags: F,
+    /// How quickly the load ramps when turned on or off locally, see
+    /// [ramp_rate](super::ramp_rate).
+    pub ramp_rate: u8,
+    /// The level the load turns on to locally, from 0 to 0xff.
+    pub on_level: u8,
+    /// The brightness of the status LEDs, from 0x11 to 0x7f.
+    pub led_brightness: u8,
+}
+
+impl<F: OperatingFlags> DeviceProperties<F> {
+    /// Builds the properties from the operating flags and the data of the
+    /// device's reply to an extended get.
+    fn from_data(flags: F, data: &[u8; 14]) -> Self {
+        DeviceProperties {
+            flags,
+            ramp_rate: data[6],
+            on_level: data[7],
+            led_brightness: data[8],
+        }
+    }
+}
+
+impl Modem {
+    /// Reads the operating flags and load settings of the device at
+    /// `address`, which must belong to the family of `F`. The settings
+    /// come from an extended [Command::ExtendedSetGet] request for the
+    /// main load.
+    pub async fn get_device_properties<F: OperatingFlags>(
+        &mut self,
+        address: Address,
+    ) -> Result<DeviceProperties<F>, Error> {
+        let flags = self.get_operating_flags::<F>(address).await?;
+
+        let mut listener = self.listen().await?;
+        self.send_message(extended_set(address, 1, DATA_REQUEST, 0))
+            .await?;
+        let data = wait_for(&mut listener, self.timeout(), |message| {
+            if message.from == address
+                && message.cmd1 == Command::ExtendedSetGet
+                && message.data[1] == DATA_RESPONSE
+            {
+                Some(message.data)
+            } else {
+                None
+            }
+        })
+        .await?;
+
+        let properties = DeviceProperties::from_data(flags, &data);
+        debug!("Device {} has properties {:?}", address, properties);
+        Ok(properties)
+    }
+
+    /// Writes `properties` to the device at `address`. Only the operating
+    /// flags that differ from the device's current ones are changed.
+    pub async fn set_device_properties<F: OperatingFlags>(
+        &mut self,
+        address: Address,
+        properties: &DeviceProperties<F>,
+    ) -> Result<(), Error> {
+        let current = self.get_operating_flags::<F>(address).await?.to_byte();
+        let wanted = properties.flags.to_byte();
+        for entry in F::COMMANDS {
+            let bit = entry.1.to_byte();
+            if current & bit != wanted & bit {
+                let change = OperatingFlagChange {
+                    entry,
+                    on: wanted & bit == bit,
+                };
+                self.set_operating_flag(address, change).await?;
+            }
+        }
+
+        for &(command, value) in &[
+            (SET_RAMP_RATE, properties.ramp_rate),
+            (SET_ON_LEVEL, properties.on_level),
+            (SET_LED_BRIGHTNESS, properties.led_brightness),
+        ] {
+            self.send_message(extended_set(address, 1, command, value))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Reads the operating flags of the device at `address`, which must
+    /// belong to the family of `F`.
+    pub async fn get_operating_flags<F: OperatingFlags>(
+        &mut self,
+        address: Address,
+    ) -> Result<F, Error> {
+        let response = self
+            .send_message((address, Command::GetOperatingFlags).into())
+            .await?;
+        let flags = F::from_byte(response.cmd2.into());
+        debug!(
+            "Device {} ({}) has operating flags {:?}",
+            address,
+            F::FAMILY,
+            flags
+        );
+        Ok(flags)
+    }
+
+    /// Sets or clears a single operating flag on the device at `address`,
+    /// which must belong to the family of `F`.
+    pub async fn set_operating_flag<F: OperatingFlags>(
+        &mut self,
+        address: Address,
+        change: OperatingFlagChange<F>,
+    ) -> Result<(), Error> {
+        self.send_message(
+            (
+                address,
+                Command::SetOperatingFlags,
+                Command::Other(change.command()),
+            )
+                .into(),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_change() {
+        let change = OperatingFlagChange::<DimmerFlags>::from_str("led-on-tx=off").unwrap();
+        assert_eq!(change.flag(), DimmerFlags::LED_ON_TX);
+        assert!(!change.on());
+        assert_eq!(change.command(), 0x03);
+        assert_eq!(change.to_string(), "led-on-tx=off");
+
+        assert!(OperatingFlagChange::<DimmerFlags>::from_str("led-on-tx").is_err());
+        assert!(OperatingFlagChange::<DimmerFlags>::from_str("turbo=on").is_err());
+    }
+
+    #[test]
+    fn family_names() {
+        let change = OperatingFlagChange::<KeypadFlags>::from_str("key-beep=on").unwrap();
+        assert_eq!(change.command(), 0x0a);
+
+        // Only keypads know about key-beep
+        assert!(OperatingFlagChange::<DimmerFlags>::from_str("key-beep=on").is_err());
+        assert!(OperatingFlagChange::<SensorFlags>::from_str("resume-dim=on").is_err());
+    }
+
+    #[test]
+    fn new_change() {
+        let change = OperatingFlagChange::new(KeypadFlags::KEY_BEEP, false).unwrap();
+        assert_eq!(change.command(), 0x0b);
+        assert_eq!(change.to_string(), "key-beep=off");
+
+        // Only a single known flag can be changed
+        let both = KeypadFlags::PROGRAM_LOCK | KeypadFlags::KEY_BEEP;
+        assert!(matches!(
+            OperatingFlagChange::new(both, true),
+            Err(Error::UnknownOperatingFlag(_))
+        ));
+        assert!(OperatingFlagChange::new(KeypadFlags::NONE, true).is_err());
+    }
+
+    #[test]
+    fn family() {
+        assert_eq!(
+            DeviceFamily::from_category(0x01, 0x20),
+            Some(DeviceFamily::Dimmer)
+        );
+        assert_eq!(
+            DeviceFamily::from_category(0x01, 0x41),
+            Some(DeviceFamily::Keypad)
+        );
+        assert_eq!(DeviceFamily::from_category(0x05, 0x00), None);
+    }
+
+    #[test]
+    fn properties() {
+        let data = [
+            0x01, 0x01, 0x00, 0x00, 0x20, 0x00, 0x1c, 0xff, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let properties = DeviceProperties::from_data(DimmerFlags::RESUME_DIM, &data);
+        assert_eq!(
+            properties,
+            DeviceProperties {
+                flags: DimmerFlags::RESUME_DIM,
+                ramp_rate: 0x1c,
+                on_level: 0xff,
+                led_brightness: 0x7f,
+            }
+        );
+    }
+
+    #[test]
+    fn settings() {
+        let flags = KeypadFlags::PROGRAM_LOCK | KeypadFlags::KEY_BEEP;
+        let on: Vec<&str> = flags
+            .settings()
+            .into_iter()
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(on, vec!["program-lock", "key-beep"]);
+    }
+}
@@ -1,21 +1,47 @@
 use std::convert::From;
 use std::fmt;
 use std::str::FromStr;
+use std::time::{Instant, SystemTime};
 
 use bytes::{Buf, BufMut, BytesMut};
 
 use bitflags::bitflags;
 
-use nom::{self, alt, do_parse, named, number::streaming::be_u8, one_of, tag, take, take_until};
+use nom::{
+    self, alt, do_parse, named,
+    number::streaming::{be_u16, be_u8},
+    one_of, tag, take, take_until,
+};
 use tokio_util::codec::{Decoder, Encoder};
 
 use crate::constants::*;
 use crate::error::*;
+use crate::message::X10Message;
+
+/// When a [Frame] was received from the modem, captured as soon as it was
+/// decoded. See [Modem::listen_timestamped](crate::Modem::listen_timestamped).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timestamp {
+    /// For measuring intervals, such as latency.
+    pub instant: Instant,
+    /// For logging and correlating with other systems.
+    pub system: SystemTime,
+}
+
+impl Timestamp {
+    /// Returns a `Timestamp` for the current time.
+    pub fn now() -> Self {
+        Timestamp {
+            instant: Instant::now(),
+            system: SystemTime::now(),
+        }
+    }
+}
 
 /// An [Address] Represents an INSTEON device address. These are 3 bytes
 /// and are commonly represented as hex numbers separated
 /// by '.', e.g. '2b.a1.11'.
-#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Address([u8; 3]);
 
 impl From<[u8; 3]> for Address {
@@ -40,6 +66,9 @@ impl FromStr for Address {
         let mut buf = [0u8; 3];
 
         let pieces: Vec<&str> = s.split('.').collect();
+        if pieces.len() != 3 {
+            return Err(Error::InvalidAddress);
+        }
         for (idx, piece) in pieces.iter().enumerate() {
             let b = u8::from_str_radix(piece, 16);
             if b.is_err() {
@@ -65,8 +94,26 @@ impl fmt::Display for Address {
     }
 }
 
+/// Addresses are serialized in their usual form, e.g. "2b.a1.11".
+#[cfg(feature = "serde")]
+impl serde::Serialize for Address {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Address {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| serde::de::Error::custom(format!("invalid address {:?}", s)))
+    }
+}
+
 /// Represents the various link modes available.
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AllLinkMode {
     /// In this mode, the modem is linked as a responder or receiver of events.
     Responder,
@@ -111,6 +158,109 @@ impl From<AllLinkMode> for u8 {
     }
 }
 
+/// The operation performed by a [Frame::ManageAllLinkRecord].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AllLinkAction {
+    /// Finds the first record matching the group and address.
+    FindFirst,
+    /// Finds the next record matching the group and address.
+    FindNext,
+    /// Modifies the first matching record, or adds one if none match.
+    Modify,
+    /// Modifies the first matching controller record, or adds one.
+    AddController,
+    /// Modifies the first matching responder record, or adds one.
+    AddResponder,
+    /// Deletes the first matching record.
+    Delete,
+    /// An unrecognized control code.
+    Other(u8),
+}
+
+impl From<u8> for AllLinkAction {
+    fn from(code: u8) -> Self {
+        match code {
+            ALL_LINK_FIND_FIRST => AllLinkAction::FindFirst,
+            ALL_LINK_FIND_NEXT => AllLinkAction::FindNext,
+            ALL_LINK_MODIFY => AllLinkAction::Modify,
+            ALL_LINK_ADD_CONTROLLER => AllLinkAction::AddController,
+            ALL_LINK_ADD_RESPONDER => AllLinkAction::AddResponder,
+            ALL_LINK_DELETE => AllLinkAction::Delete,
+            _ => AllLinkAction::Other(code),
+        }
+    }
+}
+
+impl From<AllLinkAction> for u8 {
+    fn from(action: AllLinkAction) -> Self {
+        match action {
+            AllLinkAction::FindFirst => ALL_LINK_FIND_FIRST,
+            AllLinkAction::FindNext => ALL_LINK_FIND_NEXT,
+            AllLinkAction::Modify => ALL_LINK_MODIFY,
+            AllLinkAction::AddController => ALL_LINK_ADD_CONTROLLER,
+            AllLinkAction::AddResponder => ALL_LINK_ADD_RESPONDER,
+            AllLinkAction::Delete => ALL_LINK_DELETE,
+            AllLinkAction::Other(code) => code,
+        }
+    }
+}
+
+/// What happened to one of the modem's own buttons, as reported by a
+/// [Frame::ButtonEvent]. Button 1 is the SET button; some modems have
+/// buttons 2 and 3 too.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ButtonEvent {
+    /// The button was pressed and let go.
+    Tapped(u8),
+    /// The button has been held down for more than 3 seconds.
+    Held(u8),
+    /// The button was let go after being held.
+    Released(u8),
+    /// An unrecognized event.
+    Other(u8),
+}
+
+impl ButtonEvent {
+    /// Returns the number of the button, or `None` for
+    /// [ButtonEvent::Other].
+    pub fn button(&self) -> Option<u8> {
+        match *self {
+            ButtonEvent::Tapped(button)
+            | ButtonEvent::Held(button)
+            | ButtonEvent::Released(button) => Some(button),
+            ButtonEvent::Other(_) => None,
+        }
+    }
+}
+
+impl From<u8> for ButtonEvent {
+    fn from(event: u8) -> Self {
+        let button = (event >> 4) + 1;
+        if button > 3 {
+            return ButtonEvent::Other(event);
+        }
+
+        match event & 0x0f {
+            BUTTON_TAPPED => ButtonEvent::Tapped(button),
+            BUTTON_HELD => ButtonEvent::Held(button),
+            BUTTON_RELEASED => ButtonEvent::Released(button),
+            _ => ButtonEvent::Other(event),
+        }
+    }
+}
+
+impl From<ButtonEvent> for u8 {
+    fn from(event: ButtonEvent) -> Self {
+        let (button, code) = match event {
+            ButtonEvent::Tapped(button) => (button, BUTTON_TAPPED),
+            ButtonEvent::Held(button) => (button, BUTTON_HELD),
+            ButtonEvent::Released(button) => (button, BUTTON_RELEASED),
+            ButtonEvent::Other(event) => return event,
+        };
+        ((button - 1) << 4) | code
+    }
+}
+
 bitflags! {
     /// Represents the link flags.
     pub struct AllLinkFlags: u8 {
@@ -123,6 +273,24 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// The modem's configuration, as read with [Frame::GetModemConfig] and
+    /// written with [Frame::SetModemConfig].
+    pub struct ModemConfig: u8 {
+        /// Holding the SET button doesn't put the modem into linking mode.
+        const DISABLE_AUTO_LINKING = (1 << 7);
+        /// Messages between other devices are received too, not just
+        /// those sent to the modem.
+        const MONITOR_MODE         = (1 << 6);
+        /// The LED is left to the host instead of showing activity.
+        const MANUAL_LED           = (1 << 5);
+        /// The modem doesn't give up on a command from the host after
+        /// 240ms without the next byte.
+        const DISABLE_DEADMAN      = (1 << 4);
+        const NONE                 = 0u8;
+    }
+}
+
 bitflags! {
     /// Represents details about a [Message](super::Message).
     pub struct MessageFlags: u8 {
@@ -147,8 +315,33 @@ impl Default for MessageFlags {
     }
 }
 
+/// Serializes flags as their bits, as they appear in a [Frame]. Unknown
+/// bits are dropped when deserializing.
+#[cfg(feature = "serde")]
+macro_rules! impl_serde_bits {
+    ($t:ty) => {
+        impl serde::Serialize for $t {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_u8(self.bits())
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $t {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                Ok(<$t>::from_bits_truncate(u8::deserialize(deserializer)?))
+            }
+        }
+    };
+}
+
+#[cfg(feature = "serde")]
+impl_serde_bits!(AllLinkFlags);
+#[cfg(feature = "serde")]
+impl_serde_bits!(MessageFlags);
+
 /// Information about the attached modem.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModemInfo {
     /// The [Address] for the modem.
     pub address: Address,
@@ -162,6 +355,7 @@ pub struct ModemInfo {
 
 /// This represents a single link record in the modem's link database.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AllLinkRecord {
     pub flags: AllLinkFlags,
     pub group: u8,
@@ -169,8 +363,17 @@ pub struct AllLinkRecord {
     pub data: [u8; 3],
 }
 
+/// Eight bytes of the modem's memory, as read by [Frame::ReadMemory].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryRecord {
+    /// The memory address of the first byte.
+    pub address: u16,
+    pub data: [u8; 8],
+}
+
 /// This represents the result of a completed link.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AllLinkComplete {
     pub mode: AllLinkMode,
     pub group: u8,
@@ -181,7 +384,12 @@ pub struct AllLinkComplete {
 }
 
 /// This represents a single command or response to and from the modem.
+///
+/// New frames are added as more of the modem's commands are supported, so
+/// matches on `Frame` need a `_` arm. [Frame::code] and [Frame::sender]
+/// cover the common cases without matching.
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum Frame {
     /// Fetches the info for the current modem. The response will be in
     /// as `ModemInfo` frame.
@@ -256,13 +464,67 @@ pub enum Frame {
     AllLinkComplete(AllLinkComplete),
     GetFirstAllLinkRecord,
     GetNextAllLinkRecord,
+    /// Asks for the modem's link record for the device that sent the last
+    /// message it received, which arrives in a [Frame::AllLinkRecord].
+    GetAllLinkRecordForSender,
     AllLinkRecord(AllLinkRecord),
+    /// Finds, adds, modifies or deletes a record in the modem's link database.
+    ManageAllLinkRecord {
+        action: AllLinkAction,
+        record: AllLinkRecord,
+    },
     Reset,
     AllLinkCommand {
         group: u8,
         cmd1: u8,
         cmd2: u8,
     },
+    /// Reads the 8 bytes of modem memory at `address`, which must be a
+    /// multiple of 8. They arrive in a [Frame::MemoryRecord]. Only some
+    /// modems support this.
+    ReadMemory {
+        address: u16,
+    },
+    /// Produced in reply to [Frame::ReadMemory].
+    MemoryRecord(MemoryRecord),
+    /// Produced when the modem has been factory reset, which clears its
+    /// link database and configuration.
+    UserResetDetected,
+    /// Produced when one of the modem's own buttons, such as SET, is
+    /// pressed.
+    ButtonEvent(ButtonEvent),
+    /// Fetches the modem's configuration. The response is a
+    /// [Frame::ModemConfig].
+    GetModemConfig,
+    /// The response to [Frame::GetModemConfig].
+    ModemConfig(ModemConfig),
+    /// Changes the modem's configuration.
+    SetModemConfig(ModemConfig),
+    /// Turns the modem's LED on. Only works with [ModemConfig::MANUAL_LED]
+    /// set.
+    LedOn,
+    /// Turns the modem's LED off. Only works with [ModemConfig::MANUAL_LED]
+    /// set.
+    LedOff,
+    /// Puts the modem's radio to sleep to save power. Any byte sent to the
+    /// modem wakes it back up, and is otherwise ignored.
+    RfSleep,
+    /// Produced after a [Frame::AllLinkCommand] for each responder that
+    /// didn't acknowledge the modem's cleanup message.
+    AllLinkCleanupFailure {
+        group: u8,
+        address: Address,
+    },
+    /// Produced once the modem has finished the cleanups for a
+    /// [Frame::AllLinkCommand]. `completed` is false if it stopped early,
+    /// e.g. because of other traffic.
+    AllLinkCleanupStatus {
+        completed: bool,
+    },
+    /// Sends an X10 message over the powerline.
+    X10Send(X10Message),
+    /// Produced when an X10 message is received.
+    X10Receive(X10Message),
     Unknown {
         buf: Vec<u8>,
     },
@@ -278,15 +540,118 @@ where
     a
 }
 
+/// Calculates the checksum i2cs devices expect in the last data byte of an
+/// extended message. This is the two's complement of the sum of cmd1, cmd2
+/// and the first 13 data bytes. Older devices ignore it.
+pub(crate) fn checksum(cmd1: u8, cmd2: u8, data: &[u8; 14]) -> u8 {
+    let sum = data[..13]
+        .iter()
+        .fold(cmd1.wrapping_add(cmd2), |sum, x| sum.wrapping_add(*x));
+    (!sum).wrapping_add(1)
+}
+
+/// Returns the length of the host command at the start of `bytes`, or
+/// `None` if it hasn't all arrived yet. Stray bytes before a start byte
+/// are treated as a command of their own and ignored.
+#[cfg(any(test, feature = "emulator", feature = "testing"))]
+pub(crate) fn command_len(bytes: &[u8]) -> Option<usize> {
+    if bytes.is_empty() {
+        return None;
+    }
+    if bytes[0] != START {
+        return Some(1);
+    }
+
+    let len = match *bytes.get(1)? {
+        INSTEON_SEND => {
+            if *bytes.get(5)? & MessageFlags::EXTENDED.bits() != 0 {
+                22
+            } else {
+                8
+            }
+        }
+        ALL_LINK_SEND => 5,
+        START_ALL_LINK => 4,
+        MANAGE_ALL_LINK_RECORD => 11,
+        READ_DATABASE => 4,
+        X10_SEND => 4,
+        SET_IM_CONFIGURATION => 3,
+        RF_SLEEP => 4,
+        _ => 2,
+    };
+
+    if bytes.len() >= len {
+        Some(len)
+    } else {
+        None
+    }
+}
+
 impl Frame {
     /// Returns true if `other` is a response to self.
     pub fn is_response(&self, other: &Frame) -> bool {
         match (self, other) {
             (Frame::GetModemInfo, Frame::ModemInfo { .. }) => true,
+            (Frame::GetModemConfig, Frame::ModemConfig(_)) => true,
             _ => ::std::mem::discriminant(self) == ::std::mem::discriminant(other),
         }
     }
 
+    /// Returns the modem command code for this frame, e.g. `0x50` for a
+    /// [Frame::StandardInsteonReceive], or `None` for [Frame::Unknown].
+    pub fn code(&self) -> Option<u8> {
+        let code = match self {
+            Frame::GetModemInfo | Frame::ModemInfo(_) => GETIMINFO,
+            Frame::StandardInsteonSend { .. } | Frame::ExtendedInsteonSend { .. } => INSTEON_SEND,
+            Frame::StandardInsteonReceive { .. } => STANDARD_INSTEON_RECV,
+            Frame::ExtendedInsteonReceive { .. } => EXTENDED_INSTEON_RECV,
+            Frame::StartAllLink { .. } => START_ALL_LINK,
+            Frame::CancelAllLink => CANCEL_ALL_LINK,
+            Frame::AllLinkComplete(_) => ALL_LINK_COMPLETE,
+            Frame::GetFirstAllLinkRecord => GET_FIRST_ALL_LINK_RECORD,
+            Frame::GetNextAllLinkRecord => GET_NEXT_ALL_LINK_RECORD,
+            Frame::GetAllLinkRecordForSender => GET_ALL_LINK_RECORD_FOR_SENDER,
+            Frame::AllLinkRecord(_) => ALL_LINK_RECORD,
+            Frame::ManageAllLinkRecord { .. } => MANAGE_ALL_LINK_RECORD,
+            Frame::Reset => RESET,
+            Frame::AllLinkCommand { .. } => ALL_LINK_SEND,
+            Frame::ReadMemory { .. } => READ_DATABASE,
+            Frame::MemoryRecord(_) => DATABASE_RECORD_FOUND,
+            Frame::UserResetDetected => USER_RESET_DETECTED,
+            Frame::ButtonEvent(_) => BUTTON_EVENT_REPORT,
+            Frame::GetModemConfig | Frame::ModemConfig(_) => GET_IM_CONFIGURATION,
+            Frame::SetModemConfig(_) => SET_IM_CONFIGURATION,
+            Frame::LedOn => LED_ON,
+            Frame::LedOff => LED_OFF,
+            Frame::RfSleep => RF_SLEEP,
+            Frame::AllLinkCleanupFailure { .. } => ALL_LINK_CLEANUP_FAILURE,
+            Frame::AllLinkCleanupStatus { .. } => ALL_LINK_CLEANUP_STATUS,
+            Frame::X10Send(_) => X10_SEND,
+            Frame::X10Receive(_) => X10_RECV,
+            Frame::Unknown { .. } => return None,
+        };
+        Some(code)
+    }
+
+    /// Returns the [Address] of the device that sent a received INSTEON
+    /// message, or `None` for any other frame.
+    pub fn sender(&self) -> Option<Address> {
+        match self {
+            Frame::StandardInsteonReceive { from, .. }
+            | Frame::ExtendedInsteonReceive { from, .. } => Some(*from),
+            _ => None,
+        }
+    }
+
+    /// Returns the oldest modem firmware version that supports this frame,
+    /// or `None` if every version does.
+    pub fn min_firmware_version(&self) -> Option<u8> {
+        match self {
+            Frame::ManageAllLinkRecord { .. } => Some(MIN_FIRMWARE_MANAGE_ALL_LINK_RECORD),
+            _ => None,
+        }
+    }
+
     pub fn from_slice(src: &[u8]) -> Result<Option<Frame>, Error> {
         let mut bytes = BytesMut::new();
         bytes.extend_from_slice(src);
@@ -450,6 +815,25 @@ impl Frame {
                         data: [data[0], data[1], data[2]]
                     }))
                 ) |
+                // ManageAllLinkRecord
+                do_parse!(
+                    tag!(&[START, MANAGE_ALL_LINK_RECORD][..]) >>
+                    action: be_u8                               >>
+                    flags: be_u8                                >>
+                    group: be_u8                                >>
+                    to: take!(3)                                >>
+                    data: take!(3)                              >>
+                    ack: one_of!(TERMS)                         >>
+                    (ack as u8, Frame::ManageAllLinkRecord {
+                        action: action.into(),
+                        record: AllLinkRecord {
+                            flags: AllLinkFlags::from_bits_truncate(flags),
+                            group,
+                            to: to.into(),
+                            data: [data[0], data[1], data[2]]
+                        }
+                    })
+                ) |
                 // Reset
                 do_parse!(
                     tag!(&[START, RESET][..])  >>
@@ -466,6 +850,106 @@ impl Frame {
                     (ack as u8, Frame::AllLinkCommand {
                         group, cmd1, cmd2
                     })
+                ) |
+                // ReadMemory
+                do_parse!(
+                    tag!(&[START, READ_DATABASE][..]) >>
+                    address: be_u16                   >>
+                    ack: one_of!(TERMS)               >>
+                    (ack as u8, Frame::ReadMemory { address })
+                ) |
+                // MemoryRecord
+                do_parse!(
+                    tag!(&[START, DATABASE_RECORD_FOUND][..]) >>
+                    address: be_u16                           >>
+                    data: take!(8)                            >>
+                    (ACK, Frame::MemoryRecord(MemoryRecord {
+                        address, data: clone_from_slice(data)
+                    }))
+                ) |
+                // UserResetDetected
+                do_parse!(
+                    tag!(&[START, USER_RESET_DETECTED][..]) >>
+                    (ACK, Frame::UserResetDetected)
+                ) |
+                // ButtonEvent
+                do_parse!(
+                    tag!(&[START, BUTTON_EVENT_REPORT][..]) >>
+                    event: be_u8                            >>
+                    (ACK, Frame::ButtonEvent(event.into()))
+                ) |
+                // ModemConfig
+                do_parse!(
+                    tag!(&[START, GET_IM_CONFIGURATION][..]) >>
+                    flags: be_u8                             >>
+                    take!(2)                                 >>
+                    ack: one_of!(TERMS)                      >>
+                    (ack as u8, Frame::ModemConfig(ModemConfig::from_bits_truncate(flags)))
+                ) |
+                // SetModemConfig
+                do_parse!(
+                    tag!(&[START, SET_IM_CONFIGURATION][..]) >>
+                    flags: be_u8                             >>
+                    ack: one_of!(TERMS)                      >>
+                    (ack as u8, Frame::SetModemConfig(ModemConfig::from_bits_truncate(flags)))
+                ) |
+                // GetAllLinkRecordForSender
+                do_parse!(
+                    tag!(&[START, GET_ALL_LINK_RECORD_FOR_SENDER][..]) >>
+                    ack: one_of!(TERMS)                                >>
+                    (ack as u8, Frame::GetAllLinkRecordForSender)
+                ) |
+                // LedOn
+                do_parse!(
+                    tag!(&[START, LED_ON][..]) >>
+                    ack: one_of!(TERMS)        >>
+                    (ack as u8, Frame::LedOn)
+                ) |
+                // LedOff
+                do_parse!(
+                    tag!(&[START, LED_OFF][..]) >>
+                    ack: one_of!(TERMS)         >>
+                    (ack as u8, Frame::LedOff)
+                ) |
+                // RfSleep
+                do_parse!(
+                    tag!(&[START, RF_SLEEP][..]) >>
+                    take!(2)                     >>
+                    ack: one_of!(TERMS)          >>
+                    (ack as u8, Frame::RfSleep)
+                ) |
+                // AllLinkCleanupFailure
+                do_parse!(
+                    tag!(&[START, ALL_LINK_CLEANUP_FAILURE][..]) >>
+                    take!(1)                                     >>
+                    group: be_u8                                 >>
+                    address: take!(3)                            >>
+                    (ACK, Frame::AllLinkCleanupFailure {
+                        group, address: address.into()
+                    })
+                ) |
+                // AllLinkCleanupStatus, where a NAK isn't an error
+                do_parse!(
+                    tag!(&[START, ALL_LINK_CLEANUP_STATUS][..]) >>
+                    status: one_of!(TERMS)                      >>
+                    (ACK, Frame::AllLinkCleanupStatus {
+                        completed: status as u8 == ACK
+                    })
+                ) |
+                // X10Send
+                do_parse!(
+                    tag!(&[START, X10_SEND][..]) >>
+                    raw: be_u8                   >>
+                    flag: be_u8                  >>
+                    ack: one_of!(TERMS)          >>
+                    (ack as u8, Frame::X10Send(X10Message::from_raw(raw, flag)))
+                ) |
+                // X10Receive
+                do_parse!(
+                    tag!(&[START, X10_RECV][..]) >>
+                    raw: be_u8                   >>
+                    flag: be_u8                  >>
+                    (ACK, Frame::X10Receive(X10Message::from_raw(raw, flag)))
                 )
             )
         );
@@ -481,17 +965,96 @@ impl Frame {
                 }
             }
             Err(nom::Err::Incomplete(_)) => Ok(None),
-            Err(nom::Err::Error((_, nom::error::ErrorKind::Alt))) => Err(Error::Parse),
+            Err(nom::Err::Error((_, nom::error::ErrorKind::Alt))) => {
+                Err(Error::parse("frame", &src[..]))
+            }
             Err(nom::Err::Error((_, kind))) => Err(kind.into()),
             Err(nom::Err::Failure((_, kind))) => Err(kind.into()),
         }
     }
 
+    /// Parses a `Frame` from a hex string such as `"0262112233000f0006"`.
+    /// Whitespace is ignored. The trailing ACK may be left off of commands
+    /// sent to the modem.
+    pub fn from_hex(s: &str) -> Result<Frame, Error> {
+        let mut bytes = BytesMut::from(&decode_hex(s)?[..]);
+        let frame = match Self::from_bytes(&mut bytes)? {
+            Some(frame) => frame,
+            None => {
+                bytes.put_u8(ACK);
+                Self::from_bytes(&mut bytes)?.ok_or_else(|| Error::parse("hex frame", &bytes))?
+            }
+        };
+
+        if bytes.is_empty() {
+            Ok(frame)
+        } else {
+            Err(Error::parse("hex frame", &bytes))
+        }
+    }
+
+    /// Serializes the `Frame` into a lowercase hex string.
+    pub fn to_hex(&self) -> String {
+        let mut bytes = BytesMut::new();
+        self.to_bytes(&mut bytes);
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
     /// Serializes the `Frame` into the returned `Vec<u8>`.
     pub fn to_bytes(&self, bytes: &mut BytesMut) {
+        if let Frame::Unknown { ref buf } = *self {
+            bytes.put_u8(ACK);
+            bytes.put_slice(buf);
+            return;
+        }
+
         bytes.put_u8(START);
         match *self {
             Frame::GetModemInfo { .. } => bytes.put_u8(GETIMINFO),
+            Frame::ModemInfo(ref info) => {
+                bytes.put_u8(GETIMINFO);
+                bytes.put_slice(&info.address.0);
+                bytes.put_u8(info.category);
+                bytes.put_u8(info.sub_category);
+                bytes.put_u8(info.firmware_version);
+                bytes.put_u8(ACK);
+            }
+            Frame::StandardInsteonReceive {
+                ref from,
+                ref to,
+                ref flags,
+                ref hops_remaining,
+                ref max_hops,
+                ref cmd1,
+                ref cmd2,
+                ..
+            } => {
+                bytes.put_u8(STANDARD_INSTEON_RECV);
+                bytes.put_slice(&from.0);
+                bytes.put_slice(&to.0);
+                bytes.put_u8(flags.bits() | (hops_remaining & 0b11) << 2 | max_hops & 0b11);
+                bytes.put_u8(*cmd1);
+                bytes.put_u8(*cmd2);
+            }
+            Frame::ExtendedInsteonReceive {
+                ref from,
+                ref to,
+                ref flags,
+                ref hops_remaining,
+                ref max_hops,
+                ref cmd1,
+                ref cmd2,
+                ref data,
+                ..
+            } => {
+                bytes.put_u8(EXTENDED_INSTEON_RECV);
+                bytes.put_slice(&from.0);
+                bytes.put_slice(&to.0);
+                bytes.put_u8(flags.bits() | (hops_remaining & 0b11) << 2 | max_hops & 0b11);
+                bytes.put_u8(*cmd1);
+                bytes.put_u8(*cmd2);
+                bytes.put_slice(&data[..]);
+            }
             Frame::StandardInsteonSend {
                 ref to,
                 ref flags,
@@ -528,13 +1091,8 @@ impl Frame {
 
                 bytes.put_u8(*cmd1);
                 bytes.put_u8(*cmd2);
-                bytes.put_slice(&data[..]);
-
-                // We need to calculate a checksum and stick it in the last data slot.
-                // This is the two's complement of the sum of all bytes between
-                // cmd1 and the end of the buffer, inclusive.
-                let sum = bytes[6..].iter().fold(0u32, |sum, x| sum + u32::from(*x));
-                *(bytes.last_mut().unwrap()) = ((!sum + 1) & 255) as u8;
+                bytes.put_slice(&data[..13]);
+                bytes.put_u8(checksum(*cmd1, *cmd2, data));
             }
             Frame::StartAllLink {
                 ref mode,
@@ -545,8 +1103,35 @@ impl Frame {
                 bytes.put_u8(*group);
             }
             Frame::CancelAllLink => bytes.put_u8(CANCEL_ALL_LINK),
+            Frame::AllLinkComplete(ref complete) => {
+                bytes.put_u8(ALL_LINK_COMPLETE);
+                bytes.put_u8(complete.mode.into());
+                bytes.put_u8(complete.group);
+                bytes.put_slice(&complete.address.0);
+                bytes.put_u8(complete.category);
+                bytes.put_u8(complete.sub_category);
+                bytes.put_u8(complete.firmware_version);
+            }
+            Frame::AllLinkRecord(ref record) => {
+                bytes.put_u8(ALL_LINK_RECORD);
+                bytes.put_u8(record.flags.bits());
+                bytes.put_u8(record.group);
+                bytes.put_slice(&record.to.0);
+                bytes.put_slice(&record.data);
+            }
             Frame::GetFirstAllLinkRecord => bytes.put_u8(GET_FIRST_ALL_LINK_RECORD),
             Frame::GetNextAllLinkRecord => bytes.put_u8(GET_NEXT_ALL_LINK_RECORD),
+            Frame::ManageAllLinkRecord {
+                ref action,
+                ref record,
+            } => {
+                bytes.put_u8(MANAGE_ALL_LINK_RECORD);
+                bytes.put_u8((*action).into());
+                bytes.put_u8(record.flags.bits());
+                bytes.put_u8(record.group);
+                bytes.put_slice(&record.to.0);
+                bytes.put_slice(&record.data);
+            }
             Frame::Reset => bytes.put_u8(RESET),
             Frame::AllLinkCommand {
                 ref group,
@@ -558,22 +1143,173 @@ impl Frame {
                 bytes.put_u8(*cmd1);
                 bytes.put_u8(*cmd2);
             }
+            Frame::ReadMemory { address } => {
+                bytes.put_u8(READ_DATABASE);
+                bytes.put_u16(address);
+            }
+            Frame::MemoryRecord(ref record) => {
+                bytes.put_u8(DATABASE_RECORD_FOUND);
+                bytes.put_u16(record.address);
+                bytes.put_slice(&record.data);
+            }
+            Frame::UserResetDetected => bytes.put_u8(USER_RESET_DETECTED),
+            Frame::ButtonEvent(event) => {
+                bytes.put_u8(BUTTON_EVENT_REPORT);
+                bytes.put_u8(event.into());
+            }
+            Frame::GetModemConfig => bytes.put_u8(GET_IM_CONFIGURATION),
+            Frame::ModemConfig(config) => {
+                bytes.put_u8(GET_IM_CONFIGURATION);
+                bytes.put_u8(config.bits());
+                bytes.put_u16(0);
+                bytes.put_u8(ACK);
+            }
+            Frame::SetModemConfig(config) => {
+                bytes.put_u8(SET_IM_CONFIGURATION);
+                bytes.put_u8(config.bits());
+            }
+            Frame::GetAllLinkRecordForSender => bytes.put_u8(GET_ALL_LINK_RECORD_FOR_SENDER),
+            Frame::LedOn => bytes.put_u8(LED_ON),
+            Frame::LedOff => bytes.put_u8(LED_OFF),
+            Frame::RfSleep => {
+                bytes.put_u8(RF_SLEEP);
+                bytes.put_u16(0);
+            }
+            Frame::AllLinkCleanupFailure { group, address } => {
+                bytes.put_u8(ALL_LINK_CLEANUP_FAILURE);
+                bytes.put_u8(0x01);
+                bytes.put_u8(group);
+                bytes.put_slice(&address.0);
+            }
+            Frame::AllLinkCleanupStatus { completed } => {
+                bytes.put_u8(ALL_LINK_CLEANUP_STATUS);
+                bytes.put_u8(if completed { ACK } else { NAK });
+            }
+            Frame::X10Send(message) | Frame::X10Receive(message) => {
+                let (raw, flag) = message.to_raw();
+                bytes.put_u8(self.code().unwrap());
+                bytes.put_u8(raw);
+                bytes.put_u8(flag);
+            }
             _ => unimplemented!(),
         }
     }
 }
 
-pub struct FrameCodec();
+fn decode_hex(s: &str) -> Result<Vec<u8>, Error> {
+    let digits: Vec<u8> = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| {
+            c.to_digit(16)
+                .map(|d| d as u8)
+                .ok_or_else(|| Error::parse("hex", s.as_bytes()))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let pairs = digits.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(Error::parse("hex", s.as_bytes()));
+    }
+
+    Ok(pairs.map(|pair| pair[0] << 4 | pair[1]).collect())
+}
+
+/// Counts kept by the frame decoder, as returned by
+/// [Modem::stats](super::Modem::stats). These show how noisy the serial
+/// link and powerline are.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DecoderStats {
+    /// Frames decoded successfully, not including unknown ones.
+    pub decoded: u64,
+    /// Stray responses from the modem that aren't part of any known frame.
+    pub unknown: u64,
+    /// Bytes skipped while resynchronizing after a decode error.
+    pub discarded_bytes: u64,
+    /// Direct messages a device refused because of a bad checksum.
+    pub checksum_failures: u64,
+    /// Times the buffered input grew past the limit without a frame, see
+    /// [FrameCodec::with_max_buffered].
+    pub overflows: u64,
+}
+
+/// The default for [FrameCodec::with_max_buffered]. The longest frame is
+/// 25 bytes, so this only fills up with junk.
+pub const DEFAULT_MAX_BUFFERED_BYTES: usize = 1024;
+
+/// Decodes [Frame]s from the modem's serial stream and encodes them back,
+/// keeping [DecoderStats] as it goes.
+pub struct FrameCodec {
+    stats: DecoderStats,
+    max_buffered: usize,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::with_max_buffered(DEFAULT_MAX_BUFFERED_BYTES)
+    }
+}
+
+impl FrameCodec {
+    /// Creates a codec that discards its input up to the next start byte
+    /// whenever more than `max_buffered` bytes pile up without decoding
+    /// a frame, e.g. when the serial port is at the wrong baud rate.
+    pub fn with_max_buffered(max_buffered: usize) -> Self {
+        FrameCodec {
+            stats: DecoderStats::default(),
+            max_buffered,
+        }
+    }
+
+    pub fn stats(&self) -> DecoderStats {
+        self.stats
+    }
+
+    fn skip_to_next_start(&mut self, src: &mut BytesMut) -> usize {
+        let skip = src
+            .iter()
+            .skip(1)
+            .position(|b| *b == START)
+            .map(|position| position + 1)
+            .unwrap_or_else(|| src.len());
+        src.advance(skip);
+        self.stats.discarded_bytes += skip as u64;
+        skip
+    }
+}
 
 impl Decoder for FrameCodec {
     type Item = Frame;
     type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        match Frame::from_bytes(src) {
-            Ok(val) => Ok(val),
-            Err(e) => Err(e),
+        let mut frame = match Frame::from_bytes(src) {
+            Err(e @ Error::Parse { .. }) | Err(e @ Error::NomError(_)) => {
+                // Skip to the next start byte, so a frame mangled by line
+                // noise doesn't wedge every frame after it.
+                self.skip_to_next_start(src);
+                return Err(e);
+            }
+            Ok(None) if src.len() > self.max_buffered => {
+                let discarded = self.skip_to_next_start(src);
+                self.stats.overflows += 1;
+                return Err(Error::BufferOverflow { discarded });
+            }
+            result => result?,
+        };
+        let nak_flags = MessageFlags::BROADCAST_OR_NAK | MessageFlags::ACK;
+        match frame {
+            Some(Frame::Unknown { .. }) => self.stats.unknown += 1,
+            Some(Frame::StandardInsteonReceive { flags, cmd2, .. })
+                if flags - MessageFlags::EXTENDED == nak_flags && cmd2 == NAK_CHECKSUM =>
+            {
+                self.stats.decoded += 1;
+                self.stats.checksum_failures += 1;
+            }
+            Some(_) => self.stats.decoded += 1,
+            None => {}
         }
+        Ok(frame)
     }
 }
 
@@ -588,6 +1324,7 @@ impl Encoder<Frame> for FrameCodec {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::message::{X10House, X10Unit};
 
     #[test]
     fn address_parse() {
@@ -602,6 +1339,11 @@ mod tests {
         assert_eq!(Err(Error::InvalidAddress), Address::from_str("112233"));
     }
 
+    #[test]
+    fn address_parse_short() {
+        assert_eq!(Err(Error::InvalidAddress), Address::from_str("11.22"));
+    }
+
     #[test]
     fn no_command() {
         let buf = &[START][..];
@@ -617,13 +1359,22 @@ mod tests {
     #[test]
     fn unknown_command() {
         let buf = &[START, 0x95u8][..];
-        assert_eq!(Frame::from_slice(&buf), Err(Error::Parse));
+        assert_eq!(
+            Frame::from_slice(&buf),
+            Err(Error::Parse {
+                context: "frame",
+                bytes: vec![START, 0x95u8]
+            })
+        );
     }
 
     #[test]
     fn garbage() {
         let buf = &[0x1u8; 128][..];
-        assert_eq!(Frame::from_slice(&buf), Err(Error::Parse));
+        match Frame::from_slice(&buf) {
+            Err(Error::Parse { bytes, .. }) => assert_eq!(bytes.len(), MAX_PARSE_ERROR_BYTES),
+            other => panic!("unexpected result {:?}", other),
+        }
     }
 
     #[test]
@@ -631,4 +1382,266 @@ mod tests {
         let buf = &[START, CANCEL_ALL_LINK, ACK][..];
         assert_eq!(Frame::from_slice(&buf), Ok(Some(Frame::CancelAllLink)));
     }
+
+    #[test]
+    fn hex() {
+        let frame = Frame::StandardInsteonSend {
+            to: Address([0x11, 0x22, 0x33]),
+            flags: MessageFlags::NONE,
+            max_hops: 3,
+            cmd1: 0x11,
+            cmd2: 0xff,
+        };
+
+        assert_eq!(frame.to_hex(), "02621122330f11ff");
+        assert_eq!(
+            Frame::from_hex("02 62 11 22 33 0f 11 ff"),
+            Ok(frame.clone())
+        );
+        assert_eq!(Frame::from_hex("0262112233 0f11ff 06"), Ok(frame));
+    }
+
+    #[test]
+    fn extended_send_checksum() {
+        let mut data = [0u8; 14];
+        data[0] = 0x01;
+        let frame = Frame::ExtendedInsteonSend {
+            to: Address([0x11, 0x22, 0x33]),
+            flags: MessageFlags::EXTENDED,
+            max_hops: 3,
+            cmd1: 0x2e,
+            cmd2: 0x00,
+            data,
+        };
+
+        let mut bytes = BytesMut::new();
+        frame.to_bytes(&mut bytes);
+        assert_eq!(bytes.len(), 22);
+        assert_eq!(bytes[21], checksum(0x2e, 0x00, &data));
+        assert_eq!(bytes[21], 0xd1);
+    }
+
+    #[test]
+    fn hex_receive() {
+        let hex = "025011223344556627130a";
+        assert_eq!(Frame::from_hex(hex).unwrap().to_hex(), hex);
+    }
+
+    #[test]
+    fn decoder_resync() {
+        let mut bytes = BytesMut::from(&decode_hex("02950102650602").unwrap()[..]);
+
+        let mut codec = FrameCodec::default();
+        assert!(matches!(codec.decode(&mut bytes), Err(Error::Parse { .. })));
+        assert_eq!(codec.decode(&mut bytes), Ok(Some(Frame::CancelAllLink)));
+        assert_eq!(codec.decode(&mut bytes), Ok(None));
+        assert_eq!(&bytes[..], &[START]);
+        assert_eq!(
+            codec.stats(),
+            DecoderStats {
+                decoded: 1,
+                discarded_bytes: 3,
+                ..DecoderStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn decoder_overflow() {
+        // A stray ACK waits for the next start byte, which never comes.
+        let mut bytes = BytesMut::from(&[ACK; 20][..]);
+
+        let mut codec = FrameCodec::with_max_buffered(16);
+        assert_eq!(
+            codec.decode(&mut bytes),
+            Err(Error::BufferOverflow { discarded: 20 })
+        );
+        assert!(bytes.is_empty());
+        assert_eq!(codec.stats().overflows, 1);
+        assert_eq!(codec.stats().discarded_bytes, 20);
+    }
+
+    #[test]
+    fn decoder_stats() {
+        // A checksum NAK from a device, then a stray ACK.
+        let hex = "0250112233445566a72efd060260";
+        let mut bytes = BytesMut::from(&decode_hex(hex).unwrap()[..]);
+
+        let mut codec = FrameCodec::default();
+        while let Ok(Some(_)) = codec.decode(&mut bytes) {}
+        assert_eq!(
+            codec.stats(),
+            DecoderStats {
+                decoded: 1,
+                unknown: 1,
+                discarded_bytes: 0,
+                checksum_failures: 1,
+                overflows: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn accessors() {
+        let frame = Frame::from_hex("025011223344556627130a").unwrap();
+        assert_eq!(frame.code(), Some(STANDARD_INSTEON_RECV));
+        assert_eq!(frame.sender(), Some(Address([0x11, 0x22, 0x33])));
+
+        assert_eq!(Frame::CancelAllLink.code(), Some(CANCEL_ALL_LINK));
+        assert_eq!(Frame::CancelAllLink.sender(), None);
+        assert_eq!(Frame::Unknown { buf: vec![] }.code(), None);
+    }
+
+    #[test]
+    fn hex_invalid() {
+        assert!(matches!(Frame::from_hex("02600"), Err(Error::Parse { .. })));
+        assert!(matches!(
+            Frame::from_hex("0260zz"),
+            Err(Error::Parse { .. })
+        ));
+        assert_eq!(
+            Frame::from_hex("02650602"),
+            Err(Error::parse("hex frame", &[START]))
+        );
+    }
+
+    #[test]
+    fn manage_all_link_record() {
+        let frame = Frame::ManageAllLinkRecord {
+            action: AllLinkAction::AddController,
+            record: AllLinkRecord {
+                flags: AllLinkFlags::IN_USE | AllLinkFlags::IS_CONTROLLER,
+                group: 0x12,
+                to: Address([0x11, 0x22, 0x33]),
+                data: [0x01, 0x02, 0x03],
+            },
+        };
+
+        let mut bytes = BytesMut::new();
+        frame.to_bytes(&mut bytes);
+        assert_eq!(&bytes[..2], &[START, MANAGE_ALL_LINK_RECORD][..]);
+        assert_eq!(
+            &bytes[2..],
+            &[0x40, 0xc0, 0x12, 0x11, 0x22, 0x33, 0x01, 0x02, 0x03][..]
+        );
+
+        bytes.put_u8(ACK);
+        assert_eq!(Frame::from_bytes(&mut bytes), Ok(Some(frame.clone())));
+        assert_eq!(
+            frame.min_firmware_version(),
+            Some(MIN_FIRMWARE_MANAGE_ALL_LINK_RECORD)
+        );
+        assert_eq!(Frame::GetModemInfo.min_firmware_version(), None);
+    }
+
+    #[test]
+    fn memory_record() {
+        assert_eq!(
+            Frame::from_hex("02753ff806"),
+            Ok(Frame::ReadMemory { address: 0x3ff8 })
+        );
+        assert_eq!(
+            Frame::from_hex("02593ff0e201112233012041"),
+            Ok(Frame::MemoryRecord(MemoryRecord {
+                address: 0x3ff0,
+                data: [0xe2, 0x01, 0x11, 0x22, 0x33, 0x01, 0x20, 0x41],
+            }))
+        );
+    }
+
+    #[test]
+    fn user_reset_detected() {
+        assert_eq!(
+            Frame::from_slice(&[START, USER_RESET_DETECTED]),
+            Ok(Some(Frame::UserResetDetected))
+        );
+        assert_eq!(Frame::UserResetDetected.to_hex(), "0255");
+    }
+
+    #[test]
+    fn button_event() {
+        assert_eq!(
+            Frame::from_hex("025402"),
+            Ok(Frame::ButtonEvent(ButtonEvent::Tapped(1)))
+        );
+        assert_eq!(
+            Frame::from_hex("025423"),
+            Ok(Frame::ButtonEvent(ButtonEvent::Held(3)))
+        );
+        assert_eq!(ButtonEvent::from(0x14), ButtonEvent::Released(2));
+        assert_eq!(ButtonEvent::from(0x07), ButtonEvent::Other(0x07));
+        assert_eq!(ButtonEvent::Other(0x07).button(), None);
+        assert_eq!(
+            Frame::ButtonEvent(ButtonEvent::Released(2)).to_hex(),
+            "025414"
+        );
+    }
+
+    #[test]
+    fn get_all_link_record_for_sender() {
+        assert_eq!(
+            Frame::from_hex("026c06"),
+            Ok(Frame::GetAllLinkRecordForSender)
+        );
+        assert_eq!(Frame::from_hex("026c15"), Err(Error::NotAcknowledged));
+        assert_eq!(Frame::GetAllLinkRecordForSender.to_hex(), "026c");
+    }
+
+    #[test]
+    fn led() {
+        assert_eq!(Frame::from_hex("026d06"), Ok(Frame::LedOn));
+        assert_eq!(Frame::from_hex("026e"), Ok(Frame::LedOff));
+        assert_eq!(Frame::LedOff.to_hex(), "026e");
+    }
+
+    #[test]
+    fn rf_sleep() {
+        assert_eq!(Frame::from_hex("0272000006"), Ok(Frame::RfSleep));
+        assert_eq!(Frame::RfSleep.to_hex(), "02720000");
+    }
+
+    #[test]
+    fn all_link_cleanup() {
+        assert_eq!(
+            Frame::from_hex("02560101112233"),
+            Ok(Frame::AllLinkCleanupFailure {
+                group: 1,
+                address: Address([0x11, 0x22, 0x33]),
+            })
+        );
+        assert_eq!(
+            Frame::from_hex("025815"),
+            Ok(Frame::AllLinkCleanupStatus { completed: false })
+        );
+        assert_eq!(
+            Frame::AllLinkCleanupStatus { completed: true }.to_hex(),
+            "025806"
+        );
+    }
+
+    #[test]
+    fn modem_config() {
+        let config = ModemConfig::MONITOR_MODE | ModemConfig::DISABLE_AUTO_LINKING;
+        assert_eq!(
+            Frame::from_hex("0273c0000006"),
+            Ok(Frame::ModemConfig(config))
+        );
+        assert_eq!(
+            Frame::from_hex("026bc006"),
+            Ok(Frame::SetModemConfig(config))
+        );
+        assert_eq!(Frame::SetModemConfig(config).to_hex(), "026bc0");
+        assert!(Frame::GetModemConfig.is_response(&Frame::ModemConfig(config)));
+    }
+
+    #[test]
+    fn x10() {
+        let unit = X10Message::Unit {
+            house: X10House::new('A').unwrap(),
+            unit: X10Unit::new(1).unwrap(),
+        };
+        assert_eq!(Frame::from_hex("02526600"), Ok(Frame::X10Receive(unit)));
+        assert_eq!(Frame::from_hex("0263660006"), Ok(Frame::X10Send(unit)));
+        assert_eq!(Frame::X10Send(unit).to_hex(), "02636600");
+    }
 }
@@ -0,0 +1,307 @@
+//! A gRPC service for controlling a [Modem] over the network, defined in
+//! `proto/plm.proto`.
+//!
+//! ```no_run
+//! # use plm::Modem;
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let modem = Modem::from_path("/dev/ttyUSB0")?;
+//! plm::grpc::serve(modem, "0.0.0.0:50051".parse()?).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures::{lock::Mutex, Stream, StreamExt};
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::error::*;
+use crate::frame::*;
+use crate::message::*;
+use crate::modem::*;
+
+/// The types generated from `proto/plm.proto`.
+pub mod proto {
+    tonic::include_proto!("plm");
+}
+
+use proto::plm_server::{Plm, PlmServer};
+
+impl From<Error> for Status {
+    fn from(e: Error) -> Self {
+        let message = e.to_string();
+        match e {
+            Error::InvalidAddress => Status::invalid_argument(message),
+            Error::Timeout => Status::deadline_exceeded(message),
+            Error::NotAcknowledged | Error::DeviceNotAcknowledged(_) => Status::aborted(message),
+            Error::Disconnected | Error::IoError(_) => Status::unavailable(message),
+            _ => Status::internal(message),
+        }
+    }
+}
+
+fn address(address: &str) -> Result<Address, Status> {
+    Ok(Address::from_str(address)?)
+}
+
+fn byte(value: u32, name: &str) -> Result<u8, Status> {
+    u8::try_from(value).map_err(|_| Status::invalid_argument(format!("{} must be below 256", name)))
+}
+
+impl From<Message> for proto::Message {
+    fn from(message: Message) -> Self {
+        proto::Message {
+            from: message.from.to_string(),
+            to: message.to.to_string(),
+            flags: message.flags.bits().into(),
+            hops_remaining: message.hops_remaining.into(),
+            max_hops: message.max_hops.into(),
+            cmd1: u8::from(message.cmd1).into(),
+            cmd2: u8::from(message.cmd2).into(),
+            data: if message.flags.contains(MessageFlags::EXTENDED) {
+                message.data.to_vec()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+impl From<AllLinkMode> for proto::LinkMode {
+    fn from(mode: AllLinkMode) -> Self {
+        match mode {
+            AllLinkMode::Responder => proto::LinkMode::Responder,
+            AllLinkMode::Controller => proto::LinkMode::Controller,
+            AllLinkMode::Delete => proto::LinkMode::Delete,
+            AllLinkMode::Auto | AllLinkMode::None => proto::LinkMode::Auto,
+        }
+    }
+}
+
+impl From<proto::LinkMode> for AllLinkMode {
+    fn from(mode: proto::LinkMode) -> Self {
+        match mode {
+            proto::LinkMode::Responder => AllLinkMode::Responder,
+            proto::LinkMode::Controller => AllLinkMode::Controller,
+            proto::LinkMode::Auto => AllLinkMode::Auto,
+            proto::LinkMode::Delete => AllLinkMode::Delete,
+        }
+    }
+}
+
+/// Serves the `plm.Plm` gRPC service for a [Modem].
+pub struct PlmService {
+    modem: Arc<Mutex<Modem>>,
+}
+
+impl PlmService {
+    pub fn new(modem: Modem) -> Self {
+        PlmService {
+            modem: Arc::new(Mutex::new(modem)),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Plm for PlmService {
+    async fn get_modem_info(
+        &self,
+        _: Request<proto::Empty>,
+    ) -> Result<Response<proto::ModemInfo>, Status> {
+        let info = self.modem.lock().await.get_info().await?;
+        Ok(Response::new(proto::ModemInfo {
+            address: info.address.to_string(),
+            category: info.category.into(),
+            sub_category: info.sub_category.into(),
+            firmware_version: info.firmware_version.into(),
+        }))
+    }
+
+    async fn set_level(
+        &self,
+        request: Request<proto::SetLevelRequest>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        let request = request.into_inner();
+        let address = address(&request.address)?;
+        let message = match (byte(request.level, "level")?, request.fast) {
+            (0, false) => (address, Command::Off).into(),
+            (0, true) => (address, Command::OffFast).into(),
+            (level, false) => (address, Command::On, Command::from(level)).into(),
+            (level, true) => (address, Command::OnFast, Command::from(level)).into(),
+        };
+        self.modem.lock().await.send_message(message).await?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn get_level(
+        &self,
+        request: Request<proto::DeviceRequest>,
+    ) -> Result<Response<proto::Level>, Status> {
+        let address = address(&request.get_ref().address)?;
+        let level = self.modem.lock().await.get_level(address).await?;
+        Ok(Response::new(proto::Level {
+            level: level.into(),
+        }))
+    }
+
+    async fn send_message(
+        &self,
+        request: Request<proto::Message>,
+    ) -> Result<Response<proto::Message>, Status> {
+        let request = request.into_inner();
+        let mut message: Message = (
+            address(&request.to)?,
+            Command::from(byte(request.cmd1, "cmd1")?),
+            Command::from(byte(request.cmd2, "cmd2")?),
+        )
+            .into();
+        if !request.data.is_empty() {
+            if request.data.len() != message.data.len() {
+                return Err(Status::invalid_argument("data must be 14 bytes"));
+            }
+            message.flags |= MessageFlags::EXTENDED;
+            message.data.copy_from_slice(&request.data);
+        }
+
+        let reply = self.modem.lock().await.send_message(message).await?;
+        Ok(Response::new(reply.into()))
+    }
+
+    async fn list_links(
+        &self,
+        _: Request<proto::Empty>,
+    ) -> Result<Response<proto::LinkList>, Status> {
+        let links = self
+            .modem
+            .lock()
+            .await
+            .get_links()
+            .await?
+            .map(|link| proto::Link {
+                address: link.to.to_string(),
+                group: link.group.into(),
+                controller: link.flags.contains(AllLinkFlags::IS_CONTROLLER),
+                data: link.data.to_vec(),
+            })
+            .collect();
+        Ok(Response::new(proto::LinkList { links }))
+    }
+
+    async fn link_device(
+        &self,
+        request: Request<proto::LinkDeviceRequest>,
+    ) -> Result<Response<proto::LinkComplete>, Status> {
+        let request = request.into_inner();
+        let device = if request.address.is_empty() {
+            None
+        } else {
+            Some(address(&request.address)?)
+        };
+        let mode = proto::LinkMode::from_i32(request.mode)
+            .ok_or_else(|| Status::invalid_argument("Unknown link mode"))?;
+
+        let complete = self
+            .modem
+            .lock()
+            .await
+            .link_device(device, mode.into(), byte(request.group, "group")?)
+            .await?;
+        Ok(Response::new(proto::LinkComplete {
+            address: complete.address.to_string(),
+            group: complete.group.into(),
+            mode: proto::LinkMode::from(complete.mode) as i32,
+            category: complete.category.into(),
+            sub_category: complete.sub_category.into(),
+        }))
+    }
+
+    async fn cross_link(
+        &self,
+        request: Request<proto::CrossLinkRequest>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        let request = request.into_inner();
+        let data = [
+            byte(request.level, "level")?,
+            byte(request.ramp, "ramp")?,
+            byte(request.button, "button")?,
+        ];
+        self.modem
+            .lock()
+            .await
+            .cross_link(
+                address(&request.controller)?,
+                address(&request.responder)?,
+                byte(request.group, "group")?,
+                data,
+            )
+            .await?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    type EventsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::Message, Status>> + Send + Sync + 'static>>;
+
+    async fn events(
+        &self,
+        _: Request<proto::Empty>,
+    ) -> Result<Response<Self::EventsStream>, Status> {
+        let stream = self.modem.lock().await.listen().await?;
+        Ok(Response::new(Box::pin(
+            stream.map(|message| Ok(message.into())),
+        )))
+    }
+}
+
+/// Serves the gRPC service for `modem` on `address` until the server fails.
+pub async fn serve(modem: Modem, address: SocketAddr) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(PlmServer::new(PlmService::new(modem)))
+        .serve(address)
+        .await
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::emulator::*;
+    use proto::plm_client::PlmClient;
+
+    #[tokio::test]
+    async fn modem_info() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        tokio::spawn(serve(modem, address));
+
+        let mut client = loop {
+            match PlmClient::connect(format!("http://{}", address)).await {
+                Ok(client) => break client,
+                Err(_) => tokio::time::delay_for(std::time::Duration::from_millis(10)).await,
+            }
+        };
+
+        let info = client
+            .get_modem_info(proto::Empty {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(info.address, "44.85.11");
+
+        let status = client
+            .get_level(proto::DeviceRequest {
+                address: "nope".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
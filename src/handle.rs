@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use futures::{select_biased, FutureExt};
+use futures_timer::Delay;
+use log::debug;
+
+use crate::broker::*;
+use crate::error::*;
+use crate::frame::*;
+use crate::message::*;
+use crate::modem::*;
+use crate::retry::*;
+
+/// A cloneable way to send [Message]s, from [Modem::handle]. Every clone
+/// shares the modem's connection, and each can have a message in flight at
+/// the same time: the modem still sends one frame at a time, but waiting
+/// for a device to acknowledge doesn't hold up messages to other devices.
+///
+/// Acknowledgements are matched to senders by the device's address and the
+/// command sent. Messages to the same device are best sent one at a time,
+/// since most devices can't tell two senders apart.
+///
+/// Unlike [Modem::send_message], a `ModemHandle` doesn't rate limit, wake
+/// a sleeping modem or look up a device's engine; extended messages always
+/// carry a checksum.
+#[derive(Clone)]
+pub struct ModemHandle {
+    broker: Broker,
+    timeout: Duration,
+    retry_policy: RetryPolicy,
+    max_hops: u8,
+    device_max_hops: HashMap<Address, u8>,
+}
+
+impl ModemHandle {
+    pub(crate) fn new(
+        broker: Broker,
+        timeout: Duration,
+        retry_policy: RetryPolicy,
+        max_hops: u8,
+        device_max_hops: HashMap<Address, u8>,
+    ) -> Self {
+        ModemHandle {
+            broker,
+            timeout,
+            retry_policy,
+            max_hops,
+            device_max_hops,
+        }
+    }
+
+    /// Sets how long [ModemHandle::send_message] waits for a device to
+    /// respond. This starts out as the timeout of the [Modem] it came from.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets how often frames are sent again when the modem is too busy to
+    /// take them. This starts out as the policy of the [Modem] it came from.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Sends a [Message], returning the device's acknowledgement or an
+    /// error.
+    pub async fn send_message(&mut self, mut message: Message) -> Result<Message, Error> {
+        if !message.hops_explicit {
+            message.max_hops = self
+                .device_max_hops
+                .get(&message.to)
+                .copied()
+                .unwrap_or(self.max_hops);
+        }
+
+        debug!("Sending Message {:02x?}", message);
+
+        let mut delay = Delay::new(self.timeout).fuse();
+        let broker = &mut self.broker;
+        let policy = &self.retry_policy;
+        let mut sending = Box::pin(
+            async move {
+                let mut acks = broker
+                    .expect_ack(message.to, message.ack_cmd1(), message.group())
+                    .await?;
+                send_with_retries(broker, &message.send_frame(), policy).await?;
+                await_ack(&mut acks).await
+            }
+            .fuse(),
+        );
+
+        select_biased! {
+            _ = delay => Err(Error::Timeout),
+            r = sending => r
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use super::*;
+    #[cfg(unix)]
+    use crate::emulator::*;
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn concurrent() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+
+        let dimmer = Address::from([0x11, 0x22, 0x33]);
+        let switch = Address::from([0x22, 0x33, 0x44]);
+        let mut first = modem.handle();
+        let mut second = first.clone();
+
+        let (on, off) = futures::join!(
+            first.send_message((dimmer, Command::On, Command::Other(0xff)).into()),
+            second.send_message((switch, Command::Off).into())
+        );
+
+        let on = on.unwrap();
+        assert_eq!((on.from, on.cmd1), (dimmer, Command::On));
+        let off = off.unwrap();
+        assert_eq!((off.from, off.cmd1), (switch, Command::Off));
+    }
+}
@@ -0,0 +1,316 @@
+//! Talks to the modem inside an Insteon Hub (model 2245) through the Hub's
+//! HTTP interface, for when there's no serial port to open.
+//!
+//! ```no_run
+//! # use plm::{hub::Hub, Modem};
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), plm::Error> {
+//! let hub = Hub::new("192.168.1.20", "username", "password");
+//! let mut modem = Modem::new(hub);
+//! println!("{:?}", modem.get_info().await?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The username and password are printed on the bottom of the Hub. Frames
+//! are sent with `GET /3?<hex>=I=3`, and what the modem sends back is read
+//! by polling `/buffstatus.xml` and clearing it with `GET /1?XB=M=1`. Bytes
+//! that arrive between polling and clearing are lost, so this is slower and
+//! less reliable than a serial or TCP connection.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_timer::Delay;
+use log::debug;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::tcp::with_default_port;
+
+/// The port the Hub serves HTTP on unless another is given.
+pub const DEFAULT_HUB_PORT: u16 = 25105;
+
+/// How often the Hub's buffer is polled, unless set with
+/// [Hub::set_poll_interval].
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long to wait for the Hub to answer a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The number of hex digits of data in the Hub's buffer, which is followed
+/// by two more giving how many of them are filled.
+const BUFFER_DIGITS: usize = 200;
+
+type Request<T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send>>;
+
+/// Where the Hub is and how to log in.
+struct Client {
+    addr: String,
+    authorization: String,
+}
+
+impl Client {
+    /// Makes a GET request for `path`, returning the body.
+    async fn get(&self, path: &str) -> io::Result<String> {
+        debug!("Requesting {} from the hub", path);
+        let request = async {
+            let mut stream = TcpStream::connect(self.addr.as_str()).await?;
+            let request = format!(
+                "GET {} HTTP/1.0\r\nHost: {}\r\nAuthorization: Basic {}\r\n\r\n",
+                path, self.addr, self.authorization
+            );
+            stream.write_all(request.as_bytes()).await?;
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await?;
+            Ok::<_, io::Error>(response)
+        };
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, request)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "The hub didn't answer"))??;
+
+        let response = String::from_utf8_lossy(&response);
+        let (head, body) = match response.find("\r\n\r\n") {
+            Some(end) => (&response[..end], &response[end + 4..]),
+            None => (&response[..], ""),
+        };
+        let status = head.split_whitespace().nth(1).unwrap_or_default();
+        match status {
+            "200" => Ok(body.to_string()),
+            "401" => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "The hub refused the username or password",
+            )),
+            _ => Err(io::Error::other(format!(
+                "The hub answered {}",
+                head.lines().next().unwrap_or_default()
+            ))),
+        }
+    }
+
+    async fn send(self: Arc<Self>, bytes: Vec<u8>) -> io::Result<()> {
+        let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        self.get(&format!("/3?{}=I=3", hex)).await?;
+        Ok(())
+    }
+
+    /// Waits `interval`, then returns what the modem has sent since the
+    /// last poll.
+    async fn poll(self: Arc<Self>, interval: Duration) -> io::Result<Vec<u8>> {
+        Delay::new(interval).await;
+        let body = self.get("/buffstatus.xml").await?;
+        let bytes = parse_buffer(&body)?;
+        if !bytes.is_empty() {
+            self.get("/1?XB=M=1").await?;
+        }
+        Ok(bytes)
+    }
+}
+
+/// Returns the filled part of the buffer in the body of `/buffstatus.xml`,
+/// e.g. `<response><BS>0260...0000</BS></response>`.
+fn parse_buffer(body: &str) -> io::Result<Vec<u8>> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected buffer from the hub: {}", body),
+        )
+    };
+
+    let start = body.find("<BS>").ok_or_else(invalid)? + 4;
+    let end = body[start..].find("</BS>").ok_or_else(invalid)? + start;
+    let buffer = body[start..end].trim();
+    if buffer.len() != BUFFER_DIGITS + 2 || !buffer.is_ascii() {
+        return Err(invalid());
+    }
+
+    let filled = usize::from_str_radix(&buffer[BUFFER_DIGITS..], 16).map_err(|_| invalid())?;
+    let data = &buffer[..filled.min(BUFFER_DIGITS) & !1];
+    (0..data.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&data[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+/// The modem inside an Insteon Hub, to be passed to [Modem::new](crate::Modem::new)
+/// or [ModemBuilder::build](crate::ModemBuilder::build).
+pub struct Hub {
+    client: Arc<Client>,
+    poll_interval: Duration,
+    /// Bytes written but not yet sent.
+    written: Vec<u8>,
+    sending: Option<Request<()>>,
+    /// Bytes read from the Hub but not yet by the caller.
+    unread: Vec<u8>,
+    polling: Option<Request<Vec<u8>>>,
+}
+
+impl Hub {
+    /// Talks to the Hub at `host`, or `host:port` if it isn't on
+    /// [DEFAULT_HUB_PORT], logging in as `username`. An IPv6 address with a
+    /// port goes in brackets, e.g. `[fd00::5]:25105`.
+    pub fn new(host: &str, username: &str, password: &str) -> Self {
+        Hub {
+            client: Arc::new(Client {
+                addr: with_default_port(host, DEFAULT_HUB_PORT),
+                authorization: base64::encode(format!("{}:{}", username, password)),
+            }),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            written: Vec::new(),
+            sending: None,
+            unread: Vec::new(),
+            polling: None,
+        }
+    }
+
+    /// Sets how often the Hub is asked for what the modem has sent. Polling
+    /// more often answers sooner but keeps the Hub busier.
+    pub fn set_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+    }
+}
+
+impl AsyncRead for Hub {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        while self.unread.is_empty() {
+            if self.polling.is_none() {
+                let poll = self.client.clone().poll(self.poll_interval);
+                self.polling = Some(Box::pin(poll));
+            }
+            let bytes = match self.polling.as_mut().unwrap().as_mut().poll(cx) {
+                Poll::Ready(bytes) => bytes,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.polling = None;
+            self.unread = bytes?;
+        }
+
+        let len = buf.len().min(self.unread.len());
+        buf[..len].copy_from_slice(&self.unread[..len]);
+        self.unread.drain(..len);
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncWrite for Hub {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        // Everything written up to a flush goes in one request.
+        self.written.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            if let Some(sending) = self.sending.as_mut() {
+                let result = futures::ready!(sending.as_mut().poll(cx));
+                self.sending = None;
+                result?;
+            }
+            if self.written.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            let bytes = std::mem::take(&mut self.written);
+            self.sending = Some(Box::pin(self.client.clone().send(bytes)));
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modem::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::sync::Mutex;
+
+    #[test]
+    fn buffer() {
+        let body = format!("<response><BS>0260{}06</BS></response>", "0".repeat(196));
+        assert_eq!(parse_buffer(&body).unwrap(), vec![0x02, 0x60, 0x00]);
+        let body = format!("<response><BS>{}00</BS></response>", "0".repeat(200));
+        assert!(parse_buffer(&body).unwrap().is_empty());
+        assert!(parse_buffer("<response></response>").is_err());
+    }
+
+    /// Serves the Hub's HTTP interface, answering each frame sent with the
+    /// modem's info.
+    fn serve() -> String {
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        let buffer = Arc::new(Mutex::new(String::new()));
+
+        std::thread::spawn(move || {
+            for stream in server.incoming() {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+                let path = lines.next().unwrap().unwrap();
+                let authorized = lines
+                    .map(|line| line.unwrap())
+                    .take_while(|line| !line.is_empty())
+                    .any(|line| line == "Authorization: Basic dXNlcjpwYXNz");
+                if !authorized {
+                    write!(stream, "HTTP/1.0 401 Unauthorized\r\n\r\n").unwrap();
+                    continue;
+                }
+
+                let mut buffer = buffer.lock().unwrap();
+                let body = match path.split_whitespace().nth(1).unwrap() {
+                    "/3?0260=I=3" => {
+                        buffer.push_str("026044851103450b06");
+                        String::new()
+                    }
+                    "/buffstatus.xml" => format!(
+                        "<response><BS>{:0<200}{:02X}</BS></response>",
+                        buffer,
+                        buffer.len()
+                    ),
+                    "/1?XB=M=1" => {
+                        buffer.clear();
+                        String::new()
+                    }
+                    path => panic!("Unexpected request for {}", path),
+                };
+                write!(stream, "HTTP/1.0 200 OK\r\n\r\n{}", body).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn address() {
+        let addr = |host| Hub::new(host, "user", "pass").client.addr.clone();
+        assert_eq!(addr("hub.local"), "hub.local:25105");
+        assert_eq!(addr("10.0.0.5:80"), "10.0.0.5:80");
+        assert_eq!(addr("fd00::5"), "[fd00::5]:25105");
+        assert_eq!(addr("[fd00::5]:80"), "[fd00::5]:80");
+    }
+
+    #[async_std::test]
+    async fn hub() {
+        let mut hub = Hub::new(&serve(), "user", "pass");
+        hub.set_poll_interval(Duration::from_millis(10));
+        let mut modem = Modem::new(hub);
+
+        let info = modem.get_info().await.unwrap();
+        assert_eq!(
+            info.address,
+            crate::frame::Address::from([0x44, 0x85, 0x11])
+        );
+        assert_eq!(info.firmware_version, 0x0b);
+    }
+}
@@ -0,0 +1,330 @@
+use bitflags::bitflags;
+
+use log::debug;
+
+use crate::error::*;
+use crate::frame::*;
+use crate::message::*;
+use crate::modem::*;
+
+const SET_FOLLOW_MASK: u8 = 0x02;
+const SET_OFF_MASK: u8 = 0x03;
+const SET_NON_TOGGLE_MASK: u8 = 0x08;
+const SET_LED_MASK: u8 = 0x09;
+const SET_ON_OFF_MASK: u8 = 0x0b;
+
+pub(crate) const DATA_REQUEST: u8 = 0x00;
+pub(crate) const DATA_RESPONSE: u8 = 0x01;
+
+// Operating flag commands, sent in cmd2 with SetOperatingFlags
+const BLINK_ON_ERROR_ON: u8 = 0x14;
+const BLINK_ON_ERROR_OFF: u8 = 0x15;
+
+bitflags! {
+    /// A set of KeypadLinc buttons. Button `n` is bit `n - 1`.
+    pub struct KeypadButtons: u8 {
+        const BUTTON_1 = (1 << 0);
+        const BUTTON_2 = (1 << 1);
+        const BUTTON_3 = (1 << 2);
+        const BUTTON_4 = (1 << 3);
+        const BUTTON_5 = (1 << 4);
+        const BUTTON_6 = (1 << 5);
+        const BUTTON_7 = (1 << 6);
+        const BUTTON_8 = (1 << 7);
+        const NONE     = 0u8;
+    }
+}
+
+impl KeypadButtons {
+    /// Returns the set containing only `button`, numbered from 1 to 8, or
+    /// `None` if there's no such button.
+    pub fn button(button: u8) -> Option<KeypadButtons> {
+        if (1..=8).contains(&button) {
+            Some(KeypadButtons::from_bits_truncate(1 << (button - 1)))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of each button in the set, lowest first.
+    pub fn numbers(self) -> impl Iterator<Item = u8> {
+        (1..=8).filter(move |button| self.bits() & (1 << (button - 1)) != 0)
+    }
+}
+
+/// What pressing a KeypadLinc button sends, as set with
+/// [Modem::set_keypad_button_mode].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeypadButtonMode {
+    /// Alternates between On and Off, the default.
+    Toggle,
+    /// Always sends On.
+    AlwaysOn,
+    /// Always sends Off.
+    AlwaysOff,
+}
+
+impl KeypadConfig {
+    /// Returns the mode of `button`, from 1 to 8, or `None` if there's no
+    /// such button.
+    pub fn button_mode(&self, button: u8) -> Option<KeypadButtonMode> {
+        let buttons = KeypadButtons::button(button)?;
+        Some(if !self.non_toggle.contains(buttons) {
+            KeypadButtonMode::Toggle
+        } else if self.on_off.contains(buttons) {
+            KeypadButtonMode::AlwaysOn
+        } else {
+            KeypadButtonMode::AlwaysOff
+        })
+    }
+}
+
+/// The configuration of a single KeypadLinc button, as returned by
+/// [Modem::get_keypad_config].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeypadConfig {
+    /// The button this configuration was read for.
+    pub button: u8,
+    /// Buttons that turn on along with this one.
+    pub follow_mask: KeypadButtons,
+    /// Buttons that turn off when this one turns on.
+    pub off_mask: KeypadButtons,
+    /// Buttons that always send the same command instead of toggling.
+    pub non_toggle: KeypadButtons,
+    /// Buttons whose LED is currently lit.
+    pub leds: KeypadButtons,
+    /// For non-toggle buttons, those that always send On. The rest always send Off.
+    pub on_off: KeypadButtons,
+}
+
+/// Builds an extended [Command::ExtendedSetGet] message for `button`, with
+/// `command` and `value` in the second and third data bytes.
+pub(crate) fn extended_set(address: Address, button: u8, command: u8, value: u8) -> Message {
+    let mut message: Message = (
+        address,
+        Command::ExtendedSetGet,
+        Command::None,
+        MessageFlags::EXTENDED,
+    )
+        .into();
+    message.data[0] = button;
+    message.data[1] = command;
+    message.data[2] = value;
+    message
+}
+
+impl Modem {
+    /// Reads the configuration of `button` from the KeypadLinc at `address`.
+    pub async fn get_keypad_config(
+        &mut self,
+        address: Address,
+        button: u8,
+    ) -> Result<KeypadConfig, Error> {
+        let mut listener = self.listen().await?;
+        self.send_message(extended_set(address, button, DATA_REQUEST, 0))
+            .await?;
+
+        let data = wait_for(&mut listener, self.timeout(), |message| {
+            if message.from == address
+                && message.cmd1 == Command::ExtendedSetGet
+                && message.data[1] == DATA_RESPONSE
+            {
+                Some(message.data)
+            } else {
+                None
+            }
+        })
+        .await?;
+        debug!("Got keypad data {:02x?}", data);
+
+        Ok(KeypadConfig {
+            button,
+            follow_mask: KeypadButtons::from_bits_truncate(data[2]),
+            off_mask: KeypadButtons::from_bits_truncate(data[3]),
+            non_toggle: KeypadButtons::from_bits_truncate(data[9]),
+            leds: KeypadButtons::from_bits_truncate(data[10]),
+            on_off: KeypadButtons::from_bits_truncate(data[12]),
+        })
+    }
+
+    /// Sets the buttons on the KeypadLinc at `address` that turn on
+    /// whenever `button` does.
+    pub async fn set_keypad_follow_mask(
+        &mut self,
+        address: Address,
+        button: u8,
+        mask: KeypadButtons,
+    ) -> Result<(), Error> {
+        self.send_message(extended_set(address, button, SET_FOLLOW_MASK, mask.bits()))
+            .await?;
+        Ok(())
+    }
+
+    /// Sets the buttons on the KeypadLinc at `address` that turn off
+    /// whenever `button` turns on. Giving a set of buttons each other in
+    /// their off masks makes them mutually exclusive.
+    pub async fn set_keypad_off_mask(
+        &mut self,
+        address: Address,
+        button: u8,
+        mask: KeypadButtons,
+    ) -> Result<(), Error> {
+        self.send_message(extended_set(address, button, SET_OFF_MASK, mask.bits()))
+            .await?;
+        Ok(())
+    }
+
+    /// Configures which buttons on the KeypadLinc at `address` are
+    /// non-toggle. Of those, buttons in `on` always send On, and the rest
+    /// always send Off. Buttons not in `non_toggle` toggle as usual.
+    pub async fn set_keypad_toggle_mode(
+        &mut self,
+        address: Address,
+        non_toggle: KeypadButtons,
+        on: KeypadButtons,
+    ) -> Result<(), Error> {
+        self.send_message(extended_set(
+            address,
+            0,
+            SET_NON_TOGGLE_MASK,
+            non_toggle.bits(),
+        ))
+        .await?;
+        self.send_message(extended_set(address, 0, SET_ON_OFF_MASK, on.bits()))
+            .await?;
+        Ok(())
+    }
+
+    /// Changes what `button` on the KeypadLinc at `address` sends when
+    /// pressed, leaving the other buttons as they are. Returns
+    /// [Error::InvalidButton] unless `button` is 1 to 8.
+    pub async fn set_keypad_button_mode(
+        &mut self,
+        address: Address,
+        button: u8,
+        mode: KeypadButtonMode,
+    ) -> Result<(), Error> {
+        let buttons = KeypadButtons::button(button).ok_or(Error::InvalidButton(button))?;
+        let config = self.get_keypad_config(address, button).await?;
+
+        let mut non_toggle = config.non_toggle;
+        let mut on = config.on_off;
+        non_toggle.set(buttons, mode != KeypadButtonMode::Toggle);
+        on.set(buttons, mode == KeypadButtonMode::AlwaysOn);
+        self.set_keypad_toggle_mode(address, non_toggle, on).await
+    }
+
+    /// Turns on the LEDs of `leds` on the KeypadLinc at `address`, and
+    /// turns off the rest. The LEDs of buttons that control a load or
+    /// follow a scene change again when those do.
+    pub async fn set_keypad_leds(
+        &mut self,
+        address: Address,
+        leds: KeypadButtons,
+    ) -> Result<(), Error> {
+        self.send_message(extended_set(address, 1, SET_LED_MASK, leds.bits()))
+            .await?;
+        Ok(())
+    }
+
+    /// Makes `buttons` on the KeypadLinc at `address` mutually exclusive,
+    /// like the preset buttons on a radio: turning one on turns the rest
+    /// off. Each button's off mask is replaced.
+    pub async fn set_keypad_radio_group(
+        &mut self,
+        address: Address,
+        buttons: KeypadButtons,
+    ) -> Result<(), Error> {
+        for button in buttons.numbers() {
+            let others = buttons.bits() & !(1 << (button - 1));
+            self.set_keypad_off_mask(address, button, KeypadButtons::from_bits_truncate(others))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Sets whether the KeypadLinc at `address` blinks its LEDs when a
+    /// message it sends isn't acknowledged.
+    pub async fn set_keypad_blink_on_error(
+        &mut self,
+        address: Address,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        let command = if enabled {
+            BLINK_ON_ERROR_ON
+        } else {
+            BLINK_ON_ERROR_OFF
+        };
+        self.send_message((address, Command::SetOperatingFlags, Command::Other(command)).into())
+            .await?;
+        Ok(())
+    }
+
+    /// Links `button` on the KeypadLinc at `address` as a controller of
+    /// the modem, so presses are reported on the [Modem::listen] stream with
+    /// the button number as the group.
+    pub async fn link_keypad_button(&mut self, address: Address, button: u8) -> Result<(), Error> {
+        self.link_programmatically(address, button, AllLinkMode::Responder, [0, 0, button])
+            .await
+    }
+
+    /// Makes `button` on the KeypadLinc at `address` respond to the modem's
+    /// `group`, e.g. a scene created with [Modem::create_scene].
+    /// The button's LED follows the scene, and for the load button
+    /// `on_level` is the level it goes to.
+    pub async fn bind_keypad_button(
+        &mut self,
+        address: Address,
+        button: u8,
+        group: u8,
+        on_level: u8,
+    ) -> Result<(), Error> {
+        self.link_programmatically(
+            address,
+            group,
+            AllLinkMode::Controller,
+            [on_level, 0x1f, button],
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons() {
+        assert_eq!(KeypadButtons::button(1), Some(KeypadButtons::BUTTON_1));
+        assert_eq!(KeypadButtons::button(8).unwrap().bits(), 0x80);
+        assert_eq!(KeypadButtons::button(0), None);
+        assert_eq!(KeypadButtons::button(9), None);
+
+        let buttons = KeypadButtons::BUTTON_3 | KeypadButtons::BUTTON_5;
+        assert_eq!(buttons.numbers().collect::<Vec<u8>>(), vec![3, 5]);
+    }
+
+    #[test]
+    fn button_mode() {
+        let config = KeypadConfig {
+            button: 1,
+            follow_mask: KeypadButtons::NONE,
+            off_mask: KeypadButtons::NONE,
+            non_toggle: KeypadButtons::BUTTON_2 | KeypadButtons::BUTTON_3,
+            leds: KeypadButtons::NONE,
+            on_off: KeypadButtons::BUTTON_2,
+        };
+        assert_eq!(config.button_mode(1), Some(KeypadButtonMode::Toggle));
+        assert_eq!(config.button_mode(2), Some(KeypadButtonMode::AlwaysOn));
+        assert_eq!(config.button_mode(3), Some(KeypadButtonMode::AlwaysOff));
+        assert_eq!(config.button_mode(9), None);
+    }
+
+    #[test]
+    fn set_message() {
+        let message = extended_set(Address::default(), 3, SET_OFF_MASK, 0xf0);
+        assert!(message.flags.contains(MessageFlags::EXTENDED));
+        assert_eq!(message.cmd1, Command::ExtendedSetGet);
+        assert_eq!(&message.data[..3], &[3, SET_OFF_MASK, 0xf0][..]);
+    }
+}
@@ -18,15 +18,77 @@
 //! # }
 //! ```
 
+mod aldb;
 mod broker;
+mod builder;
 mod constants;
+mod device;
+#[cfg(any(test, feature = "emulator", feature = "testing"))]
+pub mod emulator;
 mod error;
+mod event;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod flags;
 mod frame;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod handle;
+#[cfg(feature = "hub")]
+pub mod hub;
+mod keypad;
+mod linking;
+mod manager;
 mod message;
 mod modem;
+mod payload;
+mod pending;
+#[cfg(feature = "plan")]
+pub mod plan;
+mod ports;
+#[cfg(feature = "python")]
+mod python;
+mod queue;
+mod rate;
+mod remote;
+mod retry;
+mod scene;
+mod snapshot;
+mod tap;
+mod tcp;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
+pub use aldb::*;
+pub use broker::{
+    ConnectionState, ErrorCounts, Health, Replay, DEFAULT_BAUD_RATE, DEFAULT_COMMAND_CAPACITY,
+    DEFAULT_RESPONSE_TIMEOUT, DEFAULT_SEND_GAP, REPLAY_CAPACITY,
+};
+pub use builder::*;
+pub use device::*;
 pub use error::*;
+pub use event::*;
+pub use flags::*;
+pub use handle::*;
+pub use keypad::*;
+pub use linking::*;
+pub use manager::*;
 pub use message::*;
 pub use modem::*;
+pub use payload::*;
+pub use pending::*;
+pub use ports::*;
+pub use queue::{Backpressure, DEFAULT_LISTENER_CAPACITY};
+pub use rate::*;
+pub use remote::*;
+pub use retry::*;
+pub use scene::*;
+pub use snapshot::*;
+pub use tap::Tapped;
+pub use tcp::{DEFAULT_TCP_KEEPALIVE, DEFAULT_TCP_PORT};
 
-pub use frame::{Address, AllLinkComplete, AllLinkFlags, AllLinkMode, MessageFlags, ModemInfo};
+pub use frame::{
+    Address, AllLinkAction, AllLinkComplete, AllLinkFlags, AllLinkMode, AllLinkRecord, ButtonEvent,
+    DecoderStats, Frame, FrameCodec, MemoryRecord, MessageFlags, ModemConfig, ModemInfo, Timestamp,
+    DEFAULT_MAX_BUFFERED_BYTES,
+};
@@ -0,0 +1,76 @@
+use std::fmt;
+
+use crate::frame::*;
+
+/// A change in the modem's linking state, as delivered by
+/// [Modem::subscribe_linking](super::Modem::subscribe_linking).
+///
+/// More events may be added, so matches need a `_` arm.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum LinkingEvent {
+    /// The modem entered linking mode for `group`.
+    Started { mode: AllLinkMode, group: u8 },
+    /// A device was linked.
+    Linked(AllLinkComplete),
+    /// A device's link was deleted.
+    Deleted(AllLinkComplete),
+    /// Linking mode was cancelled with
+    /// [Modem::cancel_linking](super::Modem::cancel_linking). The modem
+    /// leaving linking mode by itself when it times out, or when
+    /// [Modem::link_device](super::Modem::link_device) starts a new session,
+    /// isn't reported.
+    Cancelled,
+}
+
+impl LinkingEvent {
+    /// Returns the event for a [Frame::AllLinkComplete], or `None` for any
+    /// other frame.
+    pub(crate) fn from_frame(frame: Frame) -> Option<LinkingEvent> {
+        match frame {
+            Frame::AllLinkComplete(complete) if complete.mode == AllLinkMode::Delete => {
+                Some(LinkingEvent::Deleted(complete))
+            }
+            Frame::AllLinkComplete(complete) => Some(LinkingEvent::Linked(complete)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LinkingEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkingEvent::Started { mode, group } => {
+                write!(f, "linking started: {} in group {}", mode, group)
+            }
+            LinkingEvent::Linked(complete) => write!(
+                f,
+                "device linked: {:02x}.{:02x} at {}, group {}",
+                complete.category, complete.sub_category, complete.address, complete.group
+            ),
+            LinkingEvent::Deleted(complete) => write!(
+                f,
+                "device unlinked: {:02x}.{:02x} at {}, group {}",
+                complete.category, complete.sub_category, complete.address, complete.group
+            ),
+            LinkingEvent::Cancelled => write!(f, "linking cancelled"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_frame() {
+        let frame = Frame::from_hex("0253ff011a2b3c012045").unwrap();
+        let event = LinkingEvent::from_frame(frame).unwrap();
+        assert!(matches!(event, LinkingEvent::Deleted(_)));
+        assert_eq!(
+            event.to_string(),
+            "device unlinked: 01.20 at 1a.2b.3c, group 1"
+        );
+        assert_eq!(LinkingEvent::from_frame(Frame::CancelAllLink), None);
+    }
+}
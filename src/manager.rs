@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use futures::stream::{self, Stream, StreamExt};
+
+use log::debug;
+
+use crate::error::*;
+use crate::frame::*;
+use crate::message::*;
+use crate::modem::*;
+
+/// A [ModemManager] owns several [Modem]s, e.g. one per building wing,
+/// and routes each [Message] to the modem that is linked to its target.
+pub struct ModemManager {
+    modems: Vec<Modem>,
+    routes: HashMap<Address, usize>,
+}
+
+impl ModemManager {
+    /// Constructs an empty `ModemManager`.
+    pub fn new() -> Self {
+        ModemManager {
+            modems: Vec::new(),
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Adds a [Modem] and returns its index. Call [ModemManager::refresh_routes]
+    /// afterwards to route messages to it.
+    pub fn add(&mut self, modem: Modem) -> usize {
+        self.modems.push(modem);
+        self.modems.len() - 1
+    }
+
+    /// Returns the [Modem] at `index`.
+    pub fn modem(&mut self, index: usize) -> Option<&mut Modem> {
+        self.modems.get_mut(index)
+    }
+
+    /// Returns the [Modem] linked to the device with the given [Address].
+    pub fn modem_for(&mut self, address: Address) -> Option<&mut Modem> {
+        let index = *self.routes.get(&address)?;
+        self.modems.get_mut(index)
+    }
+
+    /// Rebuilds the routing table from the link database of every modem.
+    /// When more than one modem is linked to a device, the first one added wins.
+    pub async fn refresh_routes(&mut self) -> Result<(), Error> {
+        let mut routes = HashMap::new();
+        for (index, modem) in self.modems.iter_mut().enumerate() {
+            for record in modem.get_links().await? {
+                routes.entry(record.to).or_insert(index);
+            }
+        }
+
+        debug!(
+            "Routing {} devices over {} modems",
+            routes.len(),
+            self.modems.len()
+        );
+        self.routes = routes;
+        Ok(())
+    }
+
+    /// Sends a [Message] through the modem linked to its target.
+    ///
+    /// Returns an acknowledged [Message] or an error, including
+    /// [Error::NoRoute] if no modem is linked to the target.
+    pub async fn send_message(&mut self, message: Message) -> Result<Message, Error> {
+        self.modem_for(message.to)
+            .ok_or(Error::NoRoute(message.to))?
+            .send_message(message)
+            .await
+    }
+
+    /// Listens for incoming [Message]s on every modem and delivers them on a
+    /// single [Stream], along with the index of the modem that received them.
+    pub async fn listen(&mut self) -> Result<impl Stream<Item = (usize, Message)>, Error> {
+        let mut streams = Vec::with_capacity(self.modems.len());
+        for (index, modem) in self.modems.iter_mut().enumerate() {
+            streams.push(modem.listen().await?.map(move |message| (index, message)));
+        }
+
+        Ok(stream::select_all(streams))
+    }
+}
+
+impl Default for ModemManager {
+    fn default() -> Self {
+        ModemManager::new()
+    }
+}
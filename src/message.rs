@@ -1,12 +1,17 @@
-use std::{convert::TryFrom, fmt};
+use std::{convert::TryFrom, fmt, str::FromStr};
 
 use crate::error::*;
 use crate::frame::*;
+use crate::keypad::KeypadButtons;
 
 /// A [Command] (two, actually) is sent in a [Message].
 /// This type has some commonly used ones, but you can send
 /// arbitrary values via [Command::Other].
+///
+/// More commands will be added over time, so matches on `Command` need a
+/// `_` arm. Use `u8::from(command)` to compare raw values.
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Command {
     /// When sent to a device, turns the device on.
     /// When received, it indicates that the device was turned on by manipulation.
@@ -32,18 +37,56 @@ pub enum Command {
     /// Retrieves the protocol version information.
     VersionQuery,
 
+    /// Asks the device to identify itself. After acknowledging, it sends a
+    /// broadcast carrying its [DeviceId], see [Message::device_id].
+    IdRequest,
+
     /// Cancels linking mode for the device.
     CancelLinking,
 
     /// Starts linking mode for the device.
     StartLinking,
 
-    /// Queries the status of the device.
-    StatusRequest,
+    /// Starts unlinking mode for the device.
+    StartUnlinking,
+
+    /// Sets the high byte of the memory address for [Command::Peek], which
+    /// is sent in cmd2. Used to read the link database of i1 devices.
+    SetAddressMsb,
+
+    /// Reads the byte of the device's memory at the low address byte in
+    /// cmd2, which is returned in cmd2 of the acknowledgement.
+    Peek,
+
+    /// Queries the status of the device. The [StatusKind] selects what is
+    /// returned, and is sent in cmd2.
+    StatusRequest(StatusKind),
+
+    /// Starts brightening or dimming the device, as if its paddle were held.
+    /// cmd2 is the [DimDirection].
+    StartManualChange,
+
+    /// Stops a change started by [Command::StartManualChange].
+    StopManualChange,
 
     /// Causes the device to beep once.
     Beep,
 
+    /// Reads the device's [OperatingFlags](super::OperatingFlags). The
+    /// flags are returned in cmd2 of the acknowledgement.
+    GetOperatingFlags,
+
+    /// Sets or clears one of the device's operating flags, selected by cmd2.
+    SetOperatingFlags,
+
+    /// Reads or writes device properties such as ramp rate and LED
+    /// brightness. Only valid in an extended [Message].
+    ExtendedSetGet,
+
+    /// Reads or writes the device's link database. Only valid in an
+    /// extended [Message].
+    ReadWriteAldb,
+
     /// Arbitrary commands not covered by one of the cases above.
     Other(u8),
 
@@ -68,13 +111,23 @@ impl From<u8> for Command {
         match b {
             0x08u8 => CancelLinking,
             0x09u8 => StartLinking,
+            0x0au8 => StartUnlinking,
             0x0du8 => VersionQuery,
             0x0fu8 => Ping,
-            0x19u8 => StatusRequest,
+            0x10u8 => IdRequest,
+            0x17u8 => StartManualChange,
+            0x18u8 => StopManualChange,
+            0x19u8 => StatusRequest(StatusKind::OnLevel),
+            0x1fu8 => GetOperatingFlags,
+            0x20u8 => SetOperatingFlags,
+            0x28u8 => SetAddressMsb,
+            0x2bu8 => Peek,
             0x11u8 => On,
             0x12u8 => OnFast,
             0x13u8 => Off,
             0x14u8 => OffFast,
+            0x2eu8 => ExtendedSetGet,
+            0x2fu8 => ReadWriteAldb,
             0x30u8 => Beep,
             0 => None,
             _ => Other(b),
@@ -92,18 +145,208 @@ impl From<Command> for u8 {
             OffFast => 0x14u8,
             Ping => 0x0fu8,
             VersionQuery => 0x0du8,
+            IdRequest => 0x10u8,
             CancelLinking => 0x08u8,
             StartLinking => 0x09u8,
-            StatusRequest => 0x19u8,
+            StartUnlinking => 0x0au8,
+            SetAddressMsb => 0x28u8,
+            Peek => 0x2bu8,
+            StatusRequest(_) => 0x19u8,
+            GetOperatingFlags => 0x1fu8,
+            SetOperatingFlags => 0x20u8,
+            StartManualChange => 0x17u8,
+            StopManualChange => 0x18u8,
             Beep => 0x30u8,
+            ExtendedSetGet => 0x2eu8,
+            ReadWriteAldb => 0x2fu8,
             Other(cmd) => cmd,
             None => 0u8,
         }
     }
 }
 
+/// Serializes a [Command] field as its raw value, like the flags, so it
+/// reads back the same as a received [Message] would. `Command` doesn't
+/// implement serde on its own, since the raw value loses the [StatusKind]
+/// of a [Command::StatusRequest].
+#[cfg(feature = "serde")]
+pub(crate) mod raw_command {
+    use super::Command;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(command: &Command, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(u8::from(*command))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Command, D::Error> {
+        Ok(Command::from(u8::deserialize(deserializer)?))
+    }
+}
+
+/// What a [Command::StatusRequest] asks the device for.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StatusKind {
+    /// The current level of the load.
+    OnLevel,
+    /// Which LEDs are lit, e.g. on a KeypadLinc.
+    Leds,
+    /// The ramp rate and on-level configuration, on devices that support it.
+    Config,
+    /// A device specific value.
+    Other(u8),
+}
+
+impl From<u8> for StatusKind {
+    fn from(b: u8) -> Self {
+        match b {
+            0x00 => StatusKind::OnLevel,
+            0x01 => StatusKind::Leds,
+            0x03 => StatusKind::Config,
+            _ => StatusKind::Other(b),
+        }
+    }
+}
+
+impl From<StatusKind> for u8 {
+    fn from(kind: StatusKind) -> Self {
+        match kind {
+            StatusKind::OnLevel => 0x00,
+            StatusKind::Leds => 0x01,
+            StatusKind::Config => 0x03,
+            StatusKind::Other(b) => b,
+        }
+    }
+}
+
+impl FromStr for StatusKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "level" => Ok(StatusKind::OnLevel),
+            "leds" => Ok(StatusKind::Leds),
+            "config" => Ok(StatusKind::Config),
+            _ => u8::from_str(s)
+                .map(StatusKind::from)
+                .map_err(|_| Error::parse("status kind", s.as_bytes())),
+        }
+    }
+}
+
+/// A device's reply to a [Command::StatusRequest], decoded according to
+/// its [StatusKind].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Status {
+    /// The current level, from 0 (off) to 0xff.
+    Level(u8),
+    /// The LEDs that are lit.
+    Leds(KeypadButtons),
+    /// The undecoded value for [StatusKind::Config] and [StatusKind::Other].
+    Raw(u8),
+}
+
+impl Status {
+    /// Decodes the reply `response` to a status request of `kind`.
+    pub fn from_response(kind: StatusKind, response: &Message) -> Status {
+        let value = u8::from(response.cmd2);
+        match kind {
+            StatusKind::OnLevel => Status::Level(value),
+            StatusKind::Leds => Status::Leds(KeypadButtons::from_bits_truncate(value)),
+            StatusKind::Config | StatusKind::Other(_) => Status::Raw(value),
+        }
+    }
+}
+
+/// The direction of a [Command::StartManualChange].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DimDirection {
+    /// Brighten the device.
+    Up,
+    /// Dim the device.
+    Down,
+}
+
+impl From<DimDirection> for Command {
+    fn from(direction: DimDirection) -> Self {
+        match direction {
+            DimDirection::Up => Command::Other(0x01),
+            DimDirection::Down => Command::None,
+        }
+    }
+}
+
+/// How [Modem::locate_device](super::Modem::locate_device) makes a device
+/// noticeable.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LocatePattern {
+    /// Repeatedly beep. Not every device has a beeper.
+    Beep,
+    /// Repeatedly switch the load on and off, then restore its level.
+    Blink,
+}
+
+// cmd1 of the broadcast a device sends when its set button is pressed, or
+// in answer to an ID request.
+const SET_BUTTON_RESPONDER: u8 = 0x01;
+const SET_BUTTON_CONTROLLER: u8 = 0x02;
+
+/// What kind of device something is, as reported by
+/// [Command::IdRequest] or when linking.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DeviceId {
+    pub category: u8,
+    pub sub_category: u8,
+    pub firmware_version: u8,
+}
+
+/// The version of the INSTEON engine in a device, as reported by
+/// [Command::VersionQuery].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InsteonEngine {
+    /// The original engine. Extended messages are not supported.
+    I1,
+    /// Supports extended messages.
+    I2,
+    /// Supports extended messages, but requires a checksum in them.
+    I2Cs,
+    /// An unrecognized engine version.
+    Other(u8),
+}
+
+impl From<InsteonEngine> for u8 {
+    fn from(engine: InsteonEngine) -> Self {
+        match engine {
+            InsteonEngine::I1 => 0x00,
+            InsteonEngine::I2 => 0x01,
+            InsteonEngine::I2Cs => 0x02,
+            InsteonEngine::Other(b) => b,
+        }
+    }
+}
+
+impl From<u8> for InsteonEngine {
+    fn from(b: u8) -> Self {
+        match b {
+            0x00 => InsteonEngine::I1,
+            0x01 => InsteonEngine::I2,
+            0x02 => InsteonEngine::I2Cs,
+            _ => InsteonEngine::Other(b),
+        }
+    }
+}
+
+/// The number of hops a [Message] is allowed by default.
+pub const DEFAULT_MAX_HOPS: u8 = 3;
+
+// The cmd1 of standard messages turning a device on or off at a ramp rate.
+const ON_AT_RAMP: u8 = 0x2e;
+const OFF_AT_RAMP: u8 = 0x2f;
+
 /// A [Message] can be sent to a device with a given [Address].
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Message {
     /// The address of the device that sent the `Message`.
     pub from: Address,
@@ -117,14 +360,24 @@ pub struct Message {
     /// The number of hops remaining
     pub hops_remaining: u8,
 
-    /// The maximum number of hops allowed for this `Message`.
+    /// The maximum number of hops allowed for this `Message`. Unless
+    /// [hops_explicit](Message::hops_explicit) is set, the
+    /// [Modem](crate::Modem) sends it with its setting for the device
+    /// instead, see [Modem::max_hops](crate::Modem::max_hops).
     pub max_hops: u8,
 
+    /// Whether `max_hops` was chosen for this `Message`, so the modem's
+    /// setting doesn't apply. See [Message::with_max_hops].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub hops_explicit: bool,
+
     /// The first `Command` contained in the `Message`.
+    #[cfg_attr(feature = "serde", serde(with = "raw_command"))]
     pub cmd1: Command,
 
     /// The second `Command` contained in the `Message`. Often this is a group number or other
     /// details accompanying `cmd1`.
+    #[cfg_attr(feature = "serde", serde(with = "raw_command"))]
     pub cmd2: Command,
 
     /// Arbitrary user data, only available in an extended `Message`.
@@ -132,6 +385,14 @@ pub struct Message {
 }
 
 impl Message {
+    /// Returns the message sent with at most `hops` hops, whatever the
+    /// [Modem](crate::Modem) is set to.
+    pub fn with_max_hops(mut self, hops: u8) -> Self {
+        self.max_hops = hops;
+        self.hops_explicit = true;
+        self
+    }
+
     /// Returns true if `other` is an ACK of `self`.
     pub fn is_ack(&self, other: &Message) -> bool {
         match *other {
@@ -141,6 +402,193 @@ impl Message {
             _ => false,
         }
     }
+
+    /// Returns the [DeviceId] carried by a set button broadcast, which is
+    /// also how devices answer [Command::IdRequest]. The id is found in
+    /// place of the [to](Message::to) address.
+    pub fn device_id(&self) -> Option<DeviceId> {
+        let set_button = matches!(
+            self.cmd1,
+            Command::Other(SET_BUTTON_RESPONDER) | Command::Other(SET_BUTTON_CONTROLLER)
+        );
+
+        if !set_button
+            || !self.flags.contains(MessageFlags::BROADCAST_OR_NAK)
+            || self
+                .flags
+                .intersects(MessageFlags::GROUP | MessageFlags::ACK)
+        {
+            return None;
+        }
+
+        let [category, sub_category, firmware_version] = <[u8; 3]>::from(self.to);
+        Some(DeviceId {
+            category,
+            sub_category,
+            firmware_version,
+        })
+    }
+
+    /// Returns the cmd1 a device echoes when it acknowledges this message,
+    /// or `None` if its ACK carries something else there, e.g. the link
+    /// database delta answering a [Command::StatusRequest].
+    pub(crate) fn ack_cmd1(&self) -> Option<u8> {
+        match self.cmd1 {
+            Command::StatusRequest(_) => None,
+            cmd1 => Some(cmd1.into()),
+        }
+    }
+
+    /// Returns true if this is a NAK, i.e. the device refused a direct
+    /// message. The reason is found in [cmd2](Message::cmd2).
+    pub fn is_nak(&self) -> bool {
+        self.flags
+            .contains(MessageFlags::ACK | MessageFlags::BROADCAST_OR_NAK)
+    }
+
+    /// Returns true if this is a group broadcast, which a controller sends
+    /// to every responder in a group at once. The group is in the low byte
+    /// of [to](Message::to).
+    pub fn is_group_broadcast(&self) -> bool {
+        self.flags & (MessageFlags::GROUP | MessageFlags::BROADCAST_OR_NAK | MessageFlags::ACK)
+            == MessageFlags::GROUP | MessageFlags::BROADCAST_OR_NAK
+    }
+
+    /// Returns true if this is a group cleanup, which a controller sends
+    /// directly to each responder after a group broadcast, repeating its
+    /// command. The group is in [cmd2](Message::cmd2).
+    pub fn is_cleanup(&self) -> bool {
+        self.flags & (MessageFlags::GROUP | MessageFlags::BROADCAST_OR_NAK | MessageFlags::ACK)
+            == MessageFlags::GROUP
+    }
+
+    /// Returns true if this is a responder acknowledging a group cleanup.
+    /// The group is in [cmd2](Message::cmd2).
+    pub fn is_cleanup_ack(&self) -> bool {
+        self.flags & (MessageFlags::GROUP | MessageFlags::BROADCAST_OR_NAK | MessageFlags::ACK)
+            == MessageFlags::GROUP | MessageFlags::ACK
+    }
+
+    /// Returns the group this `Message` was sent to, or `None` if it was
+    /// sent directly to a single device.
+    ///
+    /// A group broadcast carries the group in the low byte of
+    /// [to](Message::to), while the direct cleanup that follows it (and the
+    /// cleanup's acknowledgement or NAK) carries it in [cmd2](Message::cmd2).
+    pub fn group(&self) -> Option<u8> {
+        if !self.flags.contains(MessageFlags::GROUP) {
+            None
+        } else if self.is_group_broadcast() {
+            Some(<[u8; 3]>::from(self.to)[2])
+        } else {
+            Some(self.cmd2.into())
+        }
+    }
+
+    /// Calculates the checksum i2cs devices expect in the last data byte of
+    /// an extended `Message`. This is the two's complement of the sum of
+    /// cmd1, cmd2 and the first 13 data bytes.
+    pub fn checksum(&self) -> u8 {
+        checksum(self.cmd1.into(), self.cmd2.into(), &self.data)
+    }
+
+    /// Builds a standard `Message` turning the device at `to` on to
+    /// `level` at the given ramp rate. Its cmd1 shares its value with
+    /// [Command::ExtendedSetGet], which it decodes to.
+    ///
+    /// The command only has 4 bits each for the level and ramp rate, so
+    /// `level` is rounded down to the nearest 16th of full brightness.
+    /// `ramp` goes from 0 (slowest, about 9 minutes) to 0x1f (fastest)
+    /// like the device's own ramp rate setting, and is rounded down to an
+    /// odd value. Anything faster is sent as 0x1f.
+    pub fn on_with_ramp(to: Address, level: u8, ramp: u8) -> Message {
+        (
+            to,
+            Command::from(ON_AT_RAMP),
+            Command::Other(pack_ramp(level, ramp)),
+        )
+            .into()
+    }
+
+    /// Builds a standard `Message` turning the device at `to` off at the
+    /// given ramp rate, as described in [Message::on_with_ramp]. Its cmd1
+    /// shares its value with [Command::ReadWriteAldb].
+    pub fn off_with_ramp(to: Address, ramp: u8) -> Message {
+        (
+            to,
+            Command::from(OFF_AT_RAMP),
+            Command::Other(pack_ramp(0, ramp)),
+        )
+            .into()
+    }
+
+    /// Parses a `Message` from the hex string of a standard or extended
+    /// INSTEON receive frame, such as `"025011223344556627130a"`.
+    pub fn from_hex(s: &str) -> Result<Message, Error> {
+        Message::try_from(Frame::from_hex(s)?)
+    }
+
+    /// Serializes the `Message` into the hex string of a standard or
+    /// extended INSTEON receive frame.
+    pub fn to_hex(&self) -> String {
+        Frame::from(*self).to_hex()
+    }
+
+    /// Returns the frame that asks the modem to send this message. The
+    /// checksum of an extended message is filled in as it's encoded.
+    pub(crate) fn send_frame(self) -> Frame {
+        if self.flags.contains(MessageFlags::EXTENDED) {
+            Frame::ExtendedInsteonSend {
+                to: self.to,
+                flags: self.flags,
+                max_hops: self.max_hops,
+                cmd1: self.cmd1.into(),
+                cmd2: self.cmd2.into(),
+                data: self.data,
+            }
+        } else {
+            Frame::StandardInsteonSend {
+                to: self.to,
+                flags: self.flags,
+                max_hops: self.max_hops,
+                cmd1: self.cmd1.into(),
+                cmd2: self.cmd2.into(),
+            }
+        }
+    }
+}
+
+// Packs a level and ramp rate into the nibbles of cmd2 for ON_AT_RAMP and
+// OFF_AT_RAMP, capping the ramp rate at the fastest.
+fn pack_ramp(level: u8, ramp: u8) -> u8 {
+    (level & 0xf0) | (ramp.min(0x1f) >> 1)
+}
+
+impl From<Message> for Frame {
+    fn from(message: Message) -> Self {
+        if message.flags.contains(MessageFlags::EXTENDED) {
+            Frame::ExtendedInsteonReceive {
+                from: message.from,
+                to: message.to,
+                flags: message.flags,
+                hops_remaining: message.hops_remaining,
+                max_hops: message.max_hops,
+                cmd1: message.cmd1.into(),
+                cmd2: message.cmd2.into(),
+                data: message.data,
+            }
+        } else {
+            Frame::StandardInsteonReceive {
+                from: message.from,
+                to: message.to,
+                flags: message.flags,
+                hops_remaining: message.hops_remaining,
+                max_hops: message.max_hops,
+                cmd1: message.cmd1.into(),
+                cmd2: message.cmd2.into(),
+            }
+        }
+    }
 }
 
 impl Default for Message {
@@ -149,8 +597,9 @@ impl Default for Message {
             from: Address::default(),
             to: Address::default(),
             flags: MessageFlags::default(),
-            hops_remaining: 3,
-            max_hops: 3,
+            hops_remaining: DEFAULT_MAX_HOPS,
+            max_hops: DEFAULT_MAX_HOPS,
+            hops_explicit: false,
             cmd1: Command::default(),
             cmd2: Command::default(),
             data: [0u8; 14],
@@ -164,6 +613,9 @@ impl From<(Address, Command)> for Message {
         let (to, cmd1) = c;
         msg.to = to;
         msg.cmd1 = cmd1;
+        if let Command::StatusRequest(kind) = cmd1 {
+            msg.cmd2 = u8::from(kind).into();
+        }
         msg
     }
 }
@@ -221,6 +673,7 @@ impl TryFrom<Frame> for Message {
                 flags,
                 hops_remaining,
                 max_hops,
+                hops_explicit: false,
                 cmd1: cmd1.into(),
                 cmd2: cmd2.into(),
                 data: [0u8; 14],
@@ -240,6 +693,7 @@ impl TryFrom<Frame> for Message {
                 flags,
                 hops_remaining,
                 max_hops,
+                hops_explicit: false,
                 cmd1: cmd1.into(),
                 cmd2: cmd2.into(),
                 data,
@@ -248,3 +702,383 @@ impl TryFrom<Frame> for Message {
         }
     }
 }
+
+// The house and unit codes, in order from A or 1 to P or 16.
+const X10_CODES: [u8; 16] = [
+    0x6, 0xe, 0x2, 0xa, 0x1, 0x9, 0x5, 0xd, 0x7, 0xf, 0x3, 0xb, 0x0, 0x8, 0x4, 0xc,
+];
+
+/// The house code of an X10 device, from A to P.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct X10House(u8);
+
+impl X10House {
+    /// Returns the house with `letter`, or `None` if it isn't A to P.
+    pub fn new(letter: char) -> Option<X10House> {
+        let letter = letter.to_ascii_uppercase();
+        if ('A'..='P').contains(&letter) {
+            Some(X10House(letter as u8 - b'A'))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the letter of the house, e.g. `'A'`.
+    pub fn letter(&self) -> char {
+        (b'A' + self.0) as char
+    }
+
+    fn from_code(code: u8) -> X10House {
+        X10House(position(code))
+    }
+
+    fn code(&self) -> u8 {
+        X10_CODES[self.0 as usize]
+    }
+}
+
+fn position(code: u8) -> u8 {
+    X10_CODES.iter().position(|c| *c == code & 0x0f).unwrap() as u8
+}
+
+impl fmt::Display for X10House {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.letter())
+    }
+}
+
+impl FromStr for X10House {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next().and_then(X10House::new), chars.next()) {
+            (Some(house), None) => Ok(house),
+            _ => Err(Error::parse("X10 house", s.as_bytes())),
+        }
+    }
+}
+
+/// The unit code of an X10 device, from 1 to 16.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct X10Unit(u8);
+
+impl X10Unit {
+    /// Returns the unit with `number`, or `None` if it isn't 1 to 16.
+    pub fn new(number: u8) -> Option<X10Unit> {
+        if (1..=16).contains(&number) {
+            Some(X10Unit(number - 1))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of the unit, e.g. `1`.
+    pub fn number(&self) -> u8 {
+        self.0 + 1
+    }
+
+    fn from_code(code: u8) -> X10Unit {
+        X10Unit(position(code))
+    }
+
+    fn code(&self) -> u8 {
+        X10_CODES[self.0 as usize]
+    }
+}
+
+impl fmt::Display for X10Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.number())
+    }
+}
+
+/// A command for the selected units of an X10 house.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum X10Command {
+    AllUnitsOff = 0x0,
+    AllLightsOn = 0x1,
+    On = 0x2,
+    Off = 0x3,
+    Dim = 0x4,
+    Bright = 0x5,
+    AllLightsOff = 0x6,
+    ExtendedCode = 0x7,
+    HailRequest = 0x8,
+    HailAcknowledge = 0x9,
+    PresetDim1 = 0xa,
+    PresetDim2 = 0xb,
+    ExtendedData = 0xc,
+    StatusOn = 0xd,
+    StatusOff = 0xe,
+    StatusRequest = 0xf,
+}
+
+impl From<u8> for X10Command {
+    fn from(code: u8) -> Self {
+        use X10Command::*;
+        match code & 0x0f {
+            0x0 => AllUnitsOff,
+            0x1 => AllLightsOn,
+            0x2 => On,
+            0x3 => Off,
+            0x4 => Dim,
+            0x5 => Bright,
+            0x6 => AllLightsOff,
+            0x7 => ExtendedCode,
+            0x8 => HailRequest,
+            0x9 => HailAcknowledge,
+            0xa => PresetDim1,
+            0xb => PresetDim2,
+            0xc => ExtendedData,
+            0xd => StatusOn,
+            0xe => StatusOff,
+            _ => StatusRequest,
+        }
+    }
+}
+
+impl From<X10Command> for u8 {
+    fn from(command: X10Command) -> Self {
+        command as u8
+    }
+}
+
+impl FromStr for X10Command {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "on" => Ok(X10Command::On),
+            "off" => Ok(X10Command::Off),
+            "dim" => Ok(X10Command::Dim),
+            "bright" => Ok(X10Command::Bright),
+            "all-units-off" => Ok(X10Command::AllUnitsOff),
+            "all-lights-on" => Ok(X10Command::AllLightsOn),
+            "all-lights-off" => Ok(X10Command::AllLightsOff),
+            "status" => Ok(X10Command::StatusRequest),
+            _ => Err(Error::parse("X10 command", s.as_bytes())),
+        }
+    }
+}
+
+// The flag byte of an X10 frame, saying what its raw byte holds.
+const X10_FLAG_UNIT: u8 = 0x00;
+const X10_FLAG_COMMAND: u8 = 0x80;
+
+/// An X10 message, as sent with [Frame::X10Send] or received in a
+/// [Frame::X10Receive]. Devices are controlled by first selecting one or
+/// more units of a house, then sending a command to that house.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum X10Message {
+    /// Selects `unit` of `house` for the next command.
+    Unit { house: X10House, unit: X10Unit },
+    /// Sends `command` to the selected units of `house`.
+    Command {
+        house: X10House,
+        command: X10Command,
+    },
+}
+
+impl X10Message {
+    /// Returns a message selecting `unit` of `house`, or
+    /// [Error::InvalidX10Unit] if `unit` isn't 1 to 16.
+    pub fn unit(house: X10House, unit: u8) -> Result<X10Message, Error> {
+        let unit = X10Unit::new(unit).ok_or(Error::InvalidX10Unit(unit))?;
+        Ok(X10Message::Unit { house, unit })
+    }
+
+    /// Decodes the raw byte and flag of an X10 frame.
+    pub fn from_raw(raw: u8, flag: u8) -> X10Message {
+        let house = X10House::from_code(raw >> 4);
+        if flag & X10_FLAG_COMMAND != 0 {
+            X10Message::Command {
+                house,
+                command: raw.into(),
+            }
+        } else {
+            X10Message::Unit {
+                house,
+                unit: X10Unit::from_code(raw),
+            }
+        }
+    }
+
+    /// Returns the raw byte and flag for an X10 frame.
+    pub fn to_raw(&self) -> (u8, u8) {
+        match *self {
+            X10Message::Unit { house, unit } => (house.code() << 4 | unit.code(), X10_FLAG_UNIT),
+            X10Message::Command { house, command } => {
+                (house.code() << 4 | u8::from(command), X10_FLAG_COMMAND)
+            }
+        }
+    }
+
+    /// Returns the house the message is for.
+    pub fn house(&self) -> X10House {
+        match *self {
+            X10Message::Unit { house, .. } | X10Message::Command { house, .. } => house,
+        }
+    }
+}
+
+impl fmt::Display for X10Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            X10Message::Unit { house, unit } => write!(f, "{}{}", house, unit),
+            X10Message::Command { house, command } => write!(f, "{} {:?}", house, command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum() {
+        let mut message: Message = (
+            Address::default(),
+            Command::ReadWriteAldb,
+            Command::None,
+            MessageFlags::EXTENDED,
+        )
+            .into();
+        message.data[2] = 0x0f;
+        message.data[3] = 0xff;
+        message.data[4] = 0x01;
+        assert_eq!(message.checksum(), 0xc2);
+    }
+
+    #[test]
+    fn nak() {
+        let mut message: Message = (Address::default(), Command::On).into();
+        message.flags = MessageFlags::ACK;
+        assert!(!message.is_nak());
+        message.flags |= MessageFlags::BROADCAST_OR_NAK;
+        assert!(message.is_nak());
+    }
+
+    #[test]
+    fn group() {
+        let to = Address::from_str("00.00.03").unwrap();
+        let mut message: Message = (to, Command::On, Command::Other(0x05)).into();
+        assert_eq!(message.group(), None);
+
+        assert!(!message.is_group_broadcast() && !message.is_cleanup());
+
+        message.flags = MessageFlags::GROUP | MessageFlags::BROADCAST_OR_NAK;
+        assert_eq!(message.group(), Some(3));
+        assert!(message.is_group_broadcast());
+
+        message.flags = MessageFlags::GROUP;
+        assert_eq!(message.group(), Some(5));
+        assert!(message.is_cleanup() && !message.is_group_broadcast());
+        message.flags |= MessageFlags::ACK;
+        assert_eq!(message.group(), Some(5));
+        assert!(message.is_cleanup_ack() && !message.is_cleanup());
+
+        // A responder refusing the cleanup
+        message.flags |= MessageFlags::BROADCAST_OR_NAK;
+        assert!(message.is_nak() && !message.is_cleanup_ack());
+        assert_eq!(message.group(), Some(5));
+    }
+
+    #[test]
+    fn device_id() {
+        let to = Address::from_str("01.20.45").unwrap();
+        let mut message: Message = (to, Command::Other(0x01)).into();
+        assert_eq!(message.device_id(), None);
+
+        message.flags = MessageFlags::BROADCAST_OR_NAK;
+        assert_eq!(
+            message.device_id(),
+            Some(DeviceId {
+                category: 0x01,
+                sub_category: 0x20,
+                firmware_version: 0x45
+            })
+        );
+    }
+
+    #[test]
+    fn status_request() {
+        let message: Message =
+            (Address::default(), Command::StatusRequest(StatusKind::Leds)).into();
+        assert_eq!(u8::from(message.cmd1), 0x19);
+        assert_eq!(u8::from(message.cmd2), 0x01);
+
+        let response: Message = (Address::default(), Command::None, Command::Other(0x05)).into();
+        assert_eq!(
+            Status::from_response(StatusKind::Leds, &response),
+            Status::Leds(KeypadButtons::BUTTON_1 | KeypadButtons::BUTTON_3)
+        );
+        assert_eq!(StatusKind::from_str("config"), Ok(StatusKind::Config));
+    }
+
+    #[test]
+    fn ramp() {
+        let message = Message::on_with_ramp(Address::default(), 0x80, 0x1f);
+        assert_eq!(u8::from(message.cmd1), 0x2e);
+        assert_eq!(u8::from(message.cmd2), 0x8f);
+        assert!(!message.flags.contains(MessageFlags::EXTENDED));
+
+        let message = Message::off_with_ramp(Address::default(), 0x04);
+        assert_eq!(u8::from(message.cmd1), 0x2f);
+        assert_eq!(u8::from(message.cmd2), 0x02);
+
+        // Faster than the fastest is capped rather than rejected.
+        let message = Message::on_with_ramp(Address::default(), 0xff, 0xff);
+        assert_eq!(u8::from(message.cmd2), 0xff);
+    }
+
+    #[test]
+    fn x10() {
+        let a = X10House::from_str("a").unwrap();
+        assert_eq!(a.to_string(), "A");
+        assert!(X10House::from_str("Q").is_err());
+
+        let unit = X10Message::Unit {
+            house: a,
+            unit: X10Unit::new(2).unwrap(),
+        };
+        assert_eq!(unit.to_raw(), (0x6e, 0x00));
+        assert_eq!(X10Message::from_raw(0x6e, 0x00), unit);
+        assert_eq!(X10Message::unit(a, 2), Ok(unit));
+        assert_eq!(X10Message::unit(a, 0), Err(Error::InvalidX10Unit(0)));
+        assert_eq!(X10Message::unit(a, 17), Err(Error::InvalidX10Unit(17)));
+        assert_eq!(X10Unit::new(16).map(|unit| unit.number()), Some(16));
+        assert_eq!(X10Unit::new(0), None);
+
+        let on = X10Message::Command {
+            house: X10House::new('M').unwrap(),
+            command: X10Command::On,
+        };
+        assert_eq!(on.to_raw(), (0x02, 0x80));
+        assert_eq!(X10Message::from_raw(0x02, 0x80), on);
+        assert_eq!(on.to_string(), "M On");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut message: Message = (
+            Address::from([0x2b, 0xa1, 0x11]),
+            Command::ExtendedSetGet,
+            Command::Other(0x42),
+            MessageFlags::EXTENDED,
+        )
+            .into();
+        message.data[0] = 0x01;
+
+        let json = serde_json::to_value(message).unwrap();
+        assert_eq!(json["to"], "2b.a1.11");
+        assert_eq!(json["flags"], 0x10);
+        assert_eq!(json["cmd1"], 0x2e);
+        assert_eq!(json["cmd2"], 0x42);
+        assert_eq!(serde_json::from_value::<Message>(json).unwrap(), message);
+
+        assert!(serde_json::from_str::<Address>("\"2b.a1\"").is_err());
+        assert!(serde_json::from_str::<Address>("\"2b.a1.11.00\"").is_err());
+    }
+}
@@ -1,36 +1,226 @@
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io;
+use std::ops::Range;
 use std::path::Path;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use log::{debug, error, warn};
 
 use futures::{
-    future::FutureExt,
+    channel::mpsc::{unbounded, UnboundedSender},
+    future::{self, AbortHandle, Future, FutureExt},
     select_biased,
-    stream::{Stream, StreamExt},
+    stream::{self, Stream, StreamExt},
 };
 
 use futures_timer::Delay;
 
+use crate::aldb::*;
 use crate::broker::*;
+use crate::builder::*;
+use crate::constants::*;
 use crate::error::*;
 use crate::frame::*;
+use crate::handle::*;
+use crate::linking::*;
 use crate::message::*;
+use crate::rate::*;
+use crate::retry::*;
+use crate::tap::*;
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::watch;
 
-const NUM_RETRIES: u8 = 20;
 const RETRY_DELAY: Duration = Duration::from_millis(250);
 
 /// The default duration to wait for [Message] replies. 10 seconds.
 pub const DEFAULT_TIMEOUT_DURATION: Duration = Duration::from_secs(10);
 
+/// The approximate time a dimmer takes to go from off to fully on during
+/// a manual change.
+pub const MANUAL_CHANGE_DURATION: Duration = Duration::from_millis(4500);
+
+/// The time between each beep or blink of [Modem::locate_device].
+const LOCATE_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// How long [Modem::stream_device_links] waits for the next record before
+/// requesting it again.
+const ALDB_GAP_DURATION: Duration = Duration::from_secs(2);
+
+/// The number of times [Modem::stream_device_links] requests a missing
+/// record before giving up.
+pub const NUM_ALDB_RETRIES: u8 = 3;
+
+/// The number of times [Modem::set_and_verify] sends a command before giving up.
+pub const NUM_VERIFY_ATTEMPTS: u8 = 3;
+
+/// Returns `hops`, or [Error::InvalidMaxHops] if it's more than
+/// [DEFAULT_MAX_HOPS].
+pub(crate) fn check_max_hops(hops: u8) -> Result<u8, Error> {
+    if hops <= DEFAULT_MAX_HOPS {
+        Ok(hops)
+    } else {
+        Err(Error::InvalidMaxHops(hops))
+    }
+}
+
+/// Waits up to `duration` for a [Message] or [Frame] on `stream` that
+/// `filter` maps to `Some`.
+pub(crate) async fn wait_for<I, T>(
+    stream: &mut (impl Stream<Item = I> + Unpin),
+    duration: Duration,
+    mut filter: impl FnMut(I) -> Option<T>,
+) -> Result<T, Error> {
+    let mut delay = Delay::new(duration).fuse();
+    loop {
+        select_biased! {
+            _ = delay => return Err(Error::Timeout),
+            message = stream.next().fuse() => match message {
+                Some(message) => {
+                    if let Some(value) = filter(message) {
+                        return Ok(value);
+                    }
+                }
+                None => return Err(Error::Disconnected),
+            }
+        }
+    }
+}
+
+/// Sends `frame`, retrying as `policy` allows while the modem is too busy
+/// to take it.
+pub(crate) async fn send_with_retries(
+    broker: &mut Broker,
+    frame: &Frame,
+    policy: &RetryPolicy,
+) -> Result<Frame, Error> {
+    let mut attempt = 1;
+    loop {
+        debug!("Sending Frame (attempt {}) {:02x?}", attempt, frame);
+
+        match broker.send(frame.clone()).await {
+            Ok(response) => {
+                debug!("Received Response: {:02x?}", response);
+                return Ok(response);
+            }
+            Err(Error::NotAcknowledged) if attempt < policy.max_attempts => {
+                let delay = policy.delay(attempt).unwrap_or_default();
+                warn!("Frame not acknowledged, retrying after {:?}", delay);
+                Delay::new(delay).await;
+                attempt += 1;
+            }
+            e => {
+                error!("Failed to send frame, {:02x?}", e);
+                return e;
+            }
+        }
+    }
+}
+
+/// Waits for the acknowledgement registered with [Broker::expect_ack],
+/// turning a NAK into [Error::DeviceNotAcknowledged].
+pub(crate) async fn await_ack(
+    acks: &mut (impl Stream<Item = Frame> + Unpin),
+) -> Result<Message, Error> {
+    let response = acks
+        .next()
+        .await
+        .ok_or(Error::Disconnected)
+        .and_then(Message::try_from)?;
+    debug!("Received Message: {:02x?}", response);
+    if response.is_nak() {
+        return Err(Error::DeviceNotAcknowledged(response.cmd2.into()));
+    }
+    Ok(response)
+}
+
+/// How long [Modem::dump_memory] waits for each [MemoryRecord] after the
+/// modem acknowledges the read.
+const MEMORY_READ_DURATION: Duration = Duration::from_secs(2);
+
+/// The address of the first record in the modem's link database, which
+/// grows downward from here.
+pub const MODEM_ALDB_START: u16 = 0x3ff8;
+
+/// The size of a [MemoryRecord], and of each record in the modem's link
+/// database.
+pub const MEMORY_RECORD_SIZE: u16 = 8;
+
+/// How often [Modem::enroll_session] puts the modem back into linking
+/// mode, well before the modem's own 4 minute timeout.
+const ENROLL_REARM_INTERVAL: Duration = Duration::from_secs(3 * 60);
+
+/// How long to wait between X10 messages. X10 is slow, and the modem NAKs
+/// messages sent before the previous one has gone out on the powerline.
+const X10_SEND_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the modem may take to erase itself in [Modem::factory_reset].
+const RESET_DURATION: Duration = Duration::from_secs(20);
+
+/// Something that happened to the modem itself, as delivered by
+/// [Modem::modem_events].
+///
+/// More events may be added, so matches need a `_` arm.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ModemEvent {
+    /// The modem was factory reset, so its link database is empty and its
+    /// configuration is back to the defaults.
+    ModemReset,
+    /// One of the modem's own buttons, such as SET, was pressed.
+    Button(ButtonEvent),
+}
+
+/// The modem memory read by [Modem::dump_memory].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryDump {
+    /// The records in the requested range, lowest address first.
+    pub records: Vec<MemoryRecord>,
+    /// True if the modem can't read its memory, so the records were rebuilt
+    /// from its link database instead.
+    pub reconstructed: bool,
+}
+
+/// How the responders of a group took a [Modem::send_all_link_command].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AllLinkCommandResult {
+    /// The responders that acknowledged the modem's cleanup message.
+    pub acknowledged: Vec<Address>,
+    /// The responders the modem reported as not acknowledging it.
+    pub failed: Vec<Address>,
+    /// False if the modem stopped sending cleanups early, e.g. because of
+    /// other traffic, so some responders may be in neither list.
+    pub completed: bool,
+}
+
+/// Cancels a send started with [Modem::send_message_cancellable]. It can
+/// be cloned and moved to another task.
+#[derive(Debug, Clone)]
+pub struct CancelHandle(AbortHandle);
+
+impl CancelHandle {
+    /// Stops the send. Does nothing if it has already finished.
+    pub fn cancel(&self) {
+        self.0.abort();
+    }
+}
 
 /// A [Modem] is a connection to an INSTEON Modem. It can be used to send
 /// [Message]s and manage device links (e.g. [Modem::link_device]).
 pub struct Modem {
     broker: Broker,
+    engines: HashMap<Address, InsteonEngine>,
+    info: Option<ModemInfo>,
+    pub(crate) max_hops: u8,
+    timeout: Duration,
+    device_max_hops: HashMap<Address, u8>,
+    pub(crate) rate_limit: Option<TokenBucket>,
+    retry_policy: RetryPolicy,
+    linking_listeners: Vec<UnboundedSender<LinkingEvent>>,
+    resets_seen: u64,
+    config: Option<ModemConfig>,
+    asleep: bool,
 }
 
 impl Modem {
@@ -39,11 +229,25 @@ impl Modem {
     /// # Arguments
     /// * `path` - The path to a serial port with an INSTEON modem attached.
     pub fn from_path(path: impl AsRef<Path> + Send + 'static) -> io::Result<Self> {
-        debug!("Creating Modem with path {}", path.as_ref().display());
-
-        let broker = Broker::from_path(path)?;
+        Self::builder().open(path)
+    }
 
-        Ok(Self { broker })
+    /// Connects to a network-attached modem, e.g. through ser2net or an
+    /// Insteon Hub Pro, with TCP keepalive on. The connection is reopened
+    /// if it drops, backing off for up to a few minutes before giving up.
+    /// Use [ModemBuilder::connect] for other settings.
+    ///
+    /// # Arguments
+    /// * `addr` - The modem's `host` or `host:port`. The port is
+    ///   [DEFAULT_TCP_PORT](crate::DEFAULT_TCP_PORT) unless given.
+    pub fn from_tcp(addr: &str) -> io::Result<Self> {
+        Self::builder()
+            .reconnect(RetryPolicy::exponential(
+                20,
+                Duration::from_millis(500),
+                Duration::from_secs(30),
+            ))
+            .connect(addr)
     }
 
     /// Constructs a new `Modem` from an arbitrary I/O modem
@@ -51,154 +255,1524 @@ impl Modem {
     /// # Arguments
     /// * `handle` - An async readable, writable modem
     pub fn new(handle: impl AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static) -> Modem {
+        Self::builder().build_unchecked(handle)
+    }
+
+    /// Returns a [ModemBuilder] for opening a modem with settings other
+    /// than the defaults, e.g. a different baud rate.
+    pub fn builder() -> ModemBuilder {
+        ModemBuilder::default()
+    }
+
+    pub(crate) fn from_broker(broker: Broker) -> Self {
         Self {
-            broker: Broker::new(handle),
+            broker,
+            engines: HashMap::new(),
+            info: None,
+            max_hops: DEFAULT_MAX_HOPS,
+            timeout: DEFAULT_TIMEOUT_DURATION,
+            device_max_hops: HashMap::new(),
+            rate_limit: None,
+            retry_policy: RetryPolicy::default(),
+            linking_listeners: Vec::new(),
+            resets_seen: 0,
+            config: None,
+            asleep: false,
         }
     }
 
-    async fn send_frame(&mut self, frame: Frame) -> Result<Frame, Error> {
-        let mut retries = NUM_RETRIES;
-        loop {
-            retries -= 1;
-            debug!(
-                "Sending Frame (attempt {}) {:02x?}",
-                NUM_RETRIES - retries,
-                frame
+    /// Returns a [ModemHandle] for sending messages from several tasks at
+    /// once. It starts out with this modem's timeout, retry policy and hop
+    /// counts.
+    pub fn handle(&self) -> ModemHandle {
+        ModemHandle::new(
+            self.broker.clone(),
+            self.timeout,
+            self.retry_policy,
+            self.max_hops,
+            self.device_max_hops.clone(),
+        )
+    }
+
+    /// Returns a receiver that sees each change in the [ConnectionState],
+    /// e.g. while [ModemBuilder::reconnect] brings a lost connection back.
+    /// Its first value is the current state, so a daemon can watch for the
+    /// link going down rather than waiting for a send to fail.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.broker.connection_state()
+    }
+
+    /// Returns a snapshot of the connection's state and error counts, e.g.
+    /// for a daemon's liveness probe.
+    pub fn health(&self) -> Health {
+        self.broker.health()
+    }
+
+    /// Returns counts of the frames decoded and bytes discarded since the
+    /// modem was opened, e.g. to judge how noisy the serial link is.
+    pub fn stats(&self) -> DecoderStats {
+        self.broker.stats()
+    }
+
+    /// Returns when a message was last received from the device at
+    /// `address`, or `None` if none has been since the modem was opened.
+    pub fn last_seen(&self, address: Address) -> Option<Instant> {
+        self.broker.last_seen().get(&address).copied()
+    }
+
+    /// Returns the devices that have been heard from, but not within
+    /// `max_age`, along with when each was last seen. The longest silent
+    /// devices come first.
+    pub fn stale_devices(&self, max_age: Duration) -> Vec<(Address, Instant)> {
+        let now = Instant::now();
+        let mut stale: Vec<(Address, Instant)> = self
+            .broker
+            .last_seen()
+            .into_iter()
+            .filter(|(_, seen)| now.saturating_duration_since(*seen) > max_age)
+            .collect();
+        stale.sort_by_key(|(_, seen)| *seen);
+        stale
+    }
+
+    /// Sets the number of hops allowed for [Message]s sent through this
+    /// `Modem`. One hop is often enough in a small home, while large ones
+    /// may need the maximum of 3. Messages given their own hop count with
+    /// [Message::with_max_hops] are sent as they are.
+    ///
+    /// Returns [Error::InvalidMaxHops] if `hops` is more than
+    /// [DEFAULT_MAX_HOPS].
+    pub fn set_max_hops(&mut self, hops: u8) -> Result<(), Error> {
+        self.max_hops = check_max_hops(hops)?;
+        Ok(())
+    }
+
+    /// Overrides the number of hops set with [Modem::set_max_hops] for
+    /// [Message]s sent to the device at `address`. Passing `None` removes
+    /// the override.
+    ///
+    /// Returns [Error::InvalidMaxHops] if `hops` is more than
+    /// [DEFAULT_MAX_HOPS].
+    pub fn set_device_max_hops(&mut self, address: Address, hops: Option<u8>) -> Result<(), Error> {
+        match hops {
+            Some(hops) => {
+                self.device_max_hops.insert(address, check_max_hops(hops)?);
+            }
+            None => {
+                self.device_max_hops.remove(&address);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of hops allowed for [Message]s sent to `address`.
+    pub fn max_hops(&self, address: Address) -> u8 {
+        self.device_max_hops
+            .get(&address)
+            .copied()
+            .unwrap_or(self.max_hops)
+    }
+
+    /// Sets how long [Modem::send_message] and the methods built on it
+    /// wait for a device to respond. The default is
+    /// [DEFAULT_TIMEOUT_DURATION].
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the timeout set with [Modem::set_timeout].
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Limits how quickly INSTEON messages are sent, so a chatty caller
+    /// can't saturate the powerline and drown out incoming traffic.
+    /// Frames handled by the modem itself are not limited. Passing `None`
+    /// removes the limit.
+    ///
+    /// Returns [Error::InvalidRateLimit] unless the limit allows more than
+    /// zero commands per second.
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) -> Result<(), Error> {
+        if let Some(limit) = &limit {
+            limit.check()?;
+        }
+        self.rate_limit = limit.map(TokenBucket::new);
+        Ok(())
+    }
+
+    /// Sets how often frames are sent again when the modem is too busy to
+    /// take them. The default is [RetryPolicy::default].
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Returns the [RetryPolicy] set with [Modem::set_retry_policy].
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    /// Waits until every frame queued so far has been sent and the modem
+    /// has responded to it, or it failed. Sends abandoned part way, e.g. by
+    /// a timeout, are still finished by the modem and are waited for too.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        self.broker.flush().await
+    }
+
+    /// Closes the connection to the modem, returning once the port is
+    /// closed and the background task has finished. If `drain` is true,
+    /// queued frames are sent first as with [Modem::flush]; otherwise they
+    /// fail with [Error::Disconnected].
+    ///
+    /// Every listen, e.g. [Modem::listen] or [Modem::events], ends, and
+    /// any [ModemHandle] from this modem fails with [Error::Disconnected]
+    /// from then on.
+    pub async fn close(mut self, drain: bool) -> Result<(), Error> {
+        self.broker.close(drain).await
+    }
+
+    /// Sends a raw [Frame] to the modem, retrying as the [RetryPolicy] set
+    /// with [Modem::set_retry_policy] allows if it is not acknowledged.
+    ///
+    /// Returns the modem's response to the frame, or
+    /// [Error::UnsupportedByModem] if the modem's firmware is too old for it.
+    pub async fn send_frame(&mut self, frame: Frame) -> Result<Frame, Error> {
+        let policy = self.retry_policy;
+        self.send_frame_with_retry_policy(frame, &policy).await
+    }
+
+    /// Sends a raw [Frame] like [Modem::send_frame], retrying as `policy`
+    /// allows instead, e.g. to give up right away on a frame whose answer
+    /// would be stale by the time a retry got through.
+    pub async fn send_frame_with_retry_policy(
+        &mut self,
+        frame: Frame,
+        policy: &RetryPolicy,
+    ) -> Result<Frame, Error> {
+        self.prepare().await?;
+        self.check_firmware(&frame).await?;
+
+        if let Some(bucket) = self.rate_limit.as_mut() {
+            match frame {
+                Frame::StandardInsteonSend { .. }
+                | Frame::ExtendedInsteonSend { .. }
+                | Frame::AllLinkCommand { .. } => {
+                    if let Some(wait) = bucket.take(Instant::now()) {
+                        debug!("Rate limited, waiting {:?}", wait);
+                        Delay::new(wait).await;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let response = send_with_retries(&mut self.broker, &frame, policy).await?;
+        if let Frame::StartAllLink { mode, group } = frame {
+            self.notify_linking(LinkingEvent::Started { mode, group });
+        }
+        Ok(response)
+    }
+
+    /// Wakes the modem if it's asleep and checks for a reset, before
+    /// sending anything.
+    async fn prepare(&mut self) -> Result<(), Error> {
+        if self.asleep {
+            self.wake().await?;
+        }
+        self.check_reset().await
+    }
+
+    /// Forgets everything cached about the modem and its devices if it has
+    /// been factory reset since the last check, and puts back the
+    /// configuration set with [Modem::set_config].
+    async fn check_reset(&mut self) -> Result<(), Error> {
+        let resets = self.broker.health().resets;
+        if resets != self.resets_seen {
+            debug!("The modem was reset, clearing cached state");
+            self.resets_seen = resets;
+            self.info = None;
+            self.engines.clear();
+
+            if let Some(config) = self.config {
+                debug!("Restoring modem config {:?}", config);
+                self.broker.send(Frame::SetModemConfig(config)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn notify_linking(&mut self, event: LinkingEvent) {
+        self.linking_listeners
+            .retain(|listener| listener.unbounded_send(event.clone()).is_ok());
+    }
+
+    async fn check_firmware(&mut self, frame: &Frame) -> Result<(), Error> {
+        let required = match frame.min_firmware_version() {
+            Some(required) => required,
+            None => return Ok(()),
+        };
+
+        let actual = match &self.info {
+            Some(info) => info.firmware_version,
+            None => match self.broker.send(Frame::GetModemInfo).await? {
+                Frame::ModemInfo(info) => {
+                    let version = info.firmware_version;
+                    self.info = Some(info);
+                    version
+                }
+                _ => return Err(Error::UnexpectedResponse),
+            },
+        };
+
+        if actual < required {
+            warn!("{:02x?} needs modem firmware {:02x}", frame, required);
+            Err(Error::UnsupportedByModem { required, actual })
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn send_message_direct(&mut self, mut message: Message) -> Result<Message, Error> {
+        if !message.hops_explicit {
+            message.max_hops = self.max_hops(message.to);
+        }
+
+        debug!("Sending Message {:02x?}", message);
+
+        let mut acks = self
+            .broker
+            .expect_ack(message.to, message.ack_cmd1(), message.group())
+            .await?;
+        self.send_frame(message.send_frame()).await?;
+        await_ack(&mut acks).await
+    }
+
+    /// Sends a [Message]. This uses the timeout set with
+    /// [Modem::set_timeout], [DEFAULT_TIMEOUT_DURATION] unless changed.
+    ///
+    /// Returns an acknowledged [Message] or an error.
+    pub async fn send_message(&mut self, message: Message) -> Result<Message, Error> {
+        self.send_message_with_timeout(message, self.timeout).await
+    }
+
+    /// Sends `messages` like [Modem::send_message], returning the outcome
+    /// of each in the same order. A failure doesn't stop the rest of the
+    /// batch, so a sweep over many devices reports every one that couldn't
+    /// be reached.
+    ///
+    /// Messages to different devices are queued at once, so each goes out
+    /// as soon as the modem has taken the one before it instead of waiting
+    /// for the device to answer. Messages to the same device are sent in
+    /// the order given, each after the device has answered the one before
+    /// it, so an ACK is never credited to the wrong message. Messages are
+    /// still paced and held to the [RateLimit] like any other. A failed extended message to a device whose
+    /// engine isn't known yet is tried again afterwards, as
+    /// [Modem::send_message_with_timeout] would.
+    pub async fn send_batch(&mut self, messages: Vec<Message>) -> Vec<Result<Message, Error>> {
+        if let Err(e) = self.prepare().await {
+            return messages.iter().map(|_| Err(e.clone())).collect();
+        }
+
+        let now = Instant::now();
+        let mut devices: HashMap<Address, Vec<_>> = HashMap::new();
+        for (i, &message) in messages.iter().enumerate() {
+            let mut message = message;
+            if !message.hops_explicit {
+                message.max_hops = self.max_hops(message.to);
+            }
+            let i1 = self.engines.get(&message.to) == Some(&InsteonEngine::I1);
+            let ready = self
+                .rate_limit
+                .as_mut()
+                .and_then(|bucket| bucket.take(now))
+                .map(|wait| now + wait);
+            let mut broker = self.broker.clone();
+            let policy = self.retry_policy;
+            let duration = self.timeout;
+
+            let send = async move {
+                if i1 && message.flags.contains(MessageFlags::EXTENDED) {
+                    return Err(Error::UnsupportedByDevice);
+                }
+                if let Some(ready) = ready {
+                    Delay::new(ready.saturating_duration_since(Instant::now())).await;
+                }
+
+                debug!("Sending Message {:02x?}", message);
+                let mut acks = broker
+                    .expect_ack(message.to, message.ack_cmd1(), message.group())
+                    .await?;
+                send_with_retries(&mut broker, &message.send_frame(), &policy).await?;
+
+                let mut delay = Delay::new(duration).fuse();
+                let mut acked = Box::pin(await_ack(&mut acks).fuse());
+                select_biased! {
+                    _ = delay => Err(Error::Timeout),
+                    r = acked => r,
+                }
+            };
+            devices.entry(message.to).or_default().push((i, send));
+        }
+
+        // Each device's messages go one after another; only the devices
+        // themselves run side by side.
+        let devices = devices.into_values().map(|sends| async move {
+            let mut results = Vec::with_capacity(sends.len());
+            for (i, send) in sends {
+                results.push((i, send.await));
+            }
+            results
+        });
+        let mut results: Vec<Result<Message, Error>> =
+            messages.iter().map(|_| Err(Error::Cancelled)).collect();
+        for (i, result) in future::join_all(devices).await.into_iter().flatten() {
+            results[i] = result;
+        }
+
+        for (message, result) in messages.iter().zip(results.iter_mut()) {
+            if let Err(e) = result {
+                if message.flags.contains(MessageFlags::EXTENDED)
+                    && !self.engines.contains_key(&message.to)
+                {
+                    *result = self.check_engine(*message, self.timeout, e.clone()).await;
+                }
+            }
+            if let Err(e) = result {
+                debug!("Batch message to {} failed: {}", message.to, e);
+            }
+        }
+        results
+    }
+
+    /// Sends a [Message] like [Modem::send_message], returning a
+    /// [CancelHandle] along with the future doing the work. Cancelling
+    /// stops any remaining retries right away, and the future resolves to
+    /// [Error::Cancelled].
+    pub fn send_message_cancellable(
+        &mut self,
+        message: Message,
+    ) -> (
+        CancelHandle,
+        impl Future<Output = Result<Message, Error>> + '_,
+    ) {
+        let (sending, handle) = future::abortable(self.send_message(message));
+        (
+            CancelHandle(handle),
+            sending.map(|result| result.unwrap_or(Err(Error::Cancelled))),
+        )
+    }
+
+    /// Sends a [Message] with the specified timeout duration.
+    ///
+    /// Extended messages include the checksum required by i2cs devices,
+    /// which older devices ignore. If the device rejects the checksum or
+    /// doesn't respond, its engine version is queried and remembered. i2
+    /// devices are tried once more, and i1 devices, which can't take
+    /// extended messages at all, fail with [Error::UnsupportedByDevice].
+    ///
+    /// Returns an acknowledged [Message] or an error.
+    pub async fn send_message_with_timeout(
+        &mut self,
+        message: Message,
+        duration: Duration,
+    ) -> Result<Message, Error> {
+        let engine = self.engines.get(&message.to).copied();
+        if !message.flags.contains(MessageFlags::EXTENDED) {
+            return self.send_message_once(message, duration).await;
+        }
+
+        match engine {
+            Some(InsteonEngine::I1) => Err(Error::UnsupportedByDevice),
+            Some(_) => self.send_message_once(message, duration).await,
+            None => match self.send_message_once(message, duration).await {
+                Err(e) => self.check_engine(message, duration, e).await,
+                result => result,
+            },
+        }
+    }
+
+    /// Works out why an extended message to a device of unknown engine
+    /// failed with `e`, sending it once more to an i2 device.
+    async fn check_engine(
+        &mut self,
+        message: Message,
+        duration: Duration,
+        e: Error,
+    ) -> Result<Message, Error> {
+        match e {
+            Error::Timeout | Error::DeviceNotAcknowledged(NAK_CHECKSUM) => {
+                warn!("Extended message to {} failed, checking engine", message.to);
+                match self.get_engine(message.to).await? {
+                    InsteonEngine::I1 => Err(Error::UnsupportedByDevice),
+                    InsteonEngine::I2 => self.send_message_once(message, duration).await,
+                    _ => Err(e),
+                }
+            }
+            e => Err(e),
+        }
+    }
+
+    async fn send_message_once(
+        &mut self,
+        message: Message,
+        duration: Duration,
+    ) -> Result<Message, Error> {
+        let mut delay = Delay::new(duration).fuse();
+        let mut sending = Box::pin(self.send_message_direct(message).fuse());
+
+        select_biased! {
+            e = delay => Err(Error::Timeout),
+            r = sending => r
+        }
+    }
+
+    /// Returns the [InsteonEngine] of the device with the given [Address].
+    /// The result is cached, so the device is only queried once.
+    pub async fn get_engine(&mut self, address: Address) -> Result<InsteonEngine, Error> {
+        if let Some(engine) = self.engines.get(&address) {
+            return Ok(*engine);
+        }
+
+        let response = self
+            .send_message_once((address, Command::VersionQuery).into(), self.timeout)
+            .await?;
+        let engine = InsteonEngine::from(u8::from(response.cmd2));
+        debug!("Device {} has engine {:?}", address, engine);
+
+        self.engines.insert(address, engine);
+        Ok(engine)
+    }
+
+    /// Asks the device with the given [Address] what kind of device it is.
+    /// This works even with devices that refuse product data requests.
+    pub async fn identify(&mut self, address: Address) -> Result<DeviceId, Error> {
+        // Listen first, since the broadcast follows right on the heels of
+        // the acknowledgement.
+        let mut stream = self.listen().await?;
+        self.send_message((address, Command::IdRequest).into())
+            .await?;
+
+        let id = wait_for(&mut stream, self.timeout, |message| {
+            if message.from == address {
+                message.device_id()
+            } else {
+                None
+            }
+        })
+        .await?;
+        debug!("Device {} is {:02x?}", address, id);
+        Ok(id)
+    }
+
+    /// Asks the device with the given [Address] for the status selected by
+    /// `kind`.
+    pub async fn get_status(
+        &mut self,
+        address: Address,
+        kind: StatusKind,
+    ) -> Result<Status, Error> {
+        let response = self
+            .send_message((address, Command::StatusRequest(kind)).into())
+            .await?;
+        Ok(Status::from_response(kind, &response))
+    }
+
+    /// Returns the current level of the device with the given [Address],
+    /// from 0 (off) to 0xff.
+    pub async fn get_level(&mut self, address: Address) -> Result<u8, Error> {
+        match self.get_status(address, StatusKind::OnLevel).await? {
+            Status::Level(level) => Ok(level),
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    /// Sets the level of the device with the given [Address] and confirms,
+    /// via [Modem::get_level], that the device actually reached it.
+    /// A `level` of 0 turns the device off. Non-dimmable devices always
+    /// report `0xff` when on, so use that level for them.
+    ///
+    /// The command is resent up to [NUM_VERIFY_ATTEMPTS] times before giving
+    /// up with [Error::VerificationFailed].
+    pub async fn set_and_verify(&mut self, address: Address, level: u8) -> Result<(), Error> {
+        let message: Message = if level == 0 {
+            (address, Command::Off).into()
+        } else {
+            (address, Command::On, Command::Other(level)).into()
+        };
+
+        let mut actual = 0u8;
+        for attempt in 1..=NUM_VERIFY_ATTEMPTS {
+            self.send_message(message).await?;
+
+            actual = self.get_level(address).await?;
+            if actual == level {
+                return Ok(());
+            }
+
+            warn!(
+                "Device {} reported level {:02x} instead of {:02x} (attempt {})",
+                address, actual, level, attempt
             );
+            Delay::new(RETRY_DELAY).await;
+        }
+
+        Err(Error::VerificationFailed {
+            expected: level,
+            actual,
+        })
+    }
+
+    /// Starts brightening or dimming the device with the given [Address],
+    /// as if its paddle were being held. The change continues until
+    /// [Modem::end_dim] is called or the device reaches its limit.
+    pub async fn begin_dim(
+        &mut self,
+        address: Address,
+        direction: DimDirection,
+    ) -> Result<(), Error> {
+        self.send_message(
+            (
+                address,
+                Command::StartManualChange,
+                Command::from(direction),
+            )
+                .into(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Stops a change started with [Modem::begin_dim].
+    pub async fn end_dim(&mut self, address: Address) -> Result<(), Error> {
+        self.send_message((address, Command::StopManualChange).into())
+            .await?;
+        Ok(())
+    }
+
+    /// Moves the device with the given [Address] towards `level` using a
+    /// manual change, stopping it after the time estimated from
+    /// [MANUAL_CHANGE_DURATION]. The result is approximate, so the level the
+    /// device reports afterwards is returned.
+    pub async fn dim_to(&mut self, address: Address, level: u8) -> Result<u8, Error> {
+        let current = self.get_level(address).await?;
+        if current == level {
+            return Ok(current);
+        }
+
+        let (direction, distance) = if level > current {
+            (DimDirection::Up, level - current)
+        } else {
+            (DimDirection::Down, current - level)
+        };
+        let duration = MANUAL_CHANGE_DURATION.mul_f32(f32::from(distance) / 255f32);
+
+        self.begin_dim(address, direction).await?;
+        Delay::new(duration).await;
+        self.end_dim(address).await?;
+
+        self.get_level(address).await
+    }
+
+    /// Makes the device with the given [Address] easy to find physically by
+    /// beeping or blinking it once a second for `duration`.
+    pub async fn locate_device(
+        &mut self,
+        address: Address,
+        pattern: LocatePattern,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        let level: u8 = match pattern {
+            LocatePattern::Blink => self.get_level(address).await?,
+            LocatePattern::Beep => 0,
+        };
+
+        let mut elapsed = Duration::from_secs(0);
+        let mut on = level == 0;
+        while elapsed < duration {
+            let command = match pattern {
+                LocatePattern::Beep => Command::Beep,
+                LocatePattern::Blink if on => Command::OnFast,
+                LocatePattern::Blink => Command::OffFast,
+            };
+            self.send_message((address, command).into()).await?;
+            on = !on;
+
+            Delay::new(LOCATE_INTERVAL).await;
+            elapsed += LOCATE_INTERVAL;
+        }
+
+        if pattern == LocatePattern::Blink {
+            let message: Message = if level == 0 {
+                (address, Command::Off).into()
+            } else {
+                (address, Command::On, Command::Other(level)).into()
+            };
+            self.send_message(message).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Retrieve information about the attached modem.
+    pub async fn get_info(&mut self) -> Result<ModemInfo, Error> {
+        match self.send_frame(Frame::GetModemInfo).await? {
+            Frame::ModemInfo(info) => {
+                self.info = Some(info.clone());
+                Ok(info)
+            }
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    /// Checks that the modem is still answering by asking for its
+    /// [ModemInfo], returning how long the answer took. Unlike
+    /// [Modem::get_info] a frame the modem doesn't take isn't retried, so a
+    /// daemon polling this notices a dead link right away.
+    pub async fn healthcheck(&mut self) -> Result<Duration, Error> {
+        let start = Instant::now();
+        match self
+            .send_frame_with_retry_policy(Frame::GetModemInfo, &RetryPolicy::never())
+            .await?
+        {
+            Frame::ModemInfo(info) => {
+                self.info = Some(info);
+                Ok(start.elapsed())
+            }
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    /// Returns the modem's configuration.
+    pub async fn get_config(&mut self) -> Result<ModemConfig, Error> {
+        match self.send_frame(Frame::GetModemConfig).await? {
+            Frame::ModemConfig(config) => Ok(config),
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    /// Changes the modem's configuration, e.g. to turn on
+    /// [ModemConfig::MONITOR_MODE]. It is set again if the modem is
+    /// factory reset while this `Modem` is open.
+    pub async fn set_config(&mut self, config: ModemConfig) -> Result<(), Error> {
+        self.send_frame(Frame::SetModemConfig(config)).await?;
+        self.config = Some(config);
+        Ok(())
+    }
+
+    /// Turns the modem's LED on or off, e.g. to tell which of several
+    /// modems this is. The modem needs [ModemConfig::MANUAL_LED] set,
+    /// otherwise it keeps using the LED to show activity.
+    pub async fn set_led(&mut self, on: bool) -> Result<(), Error> {
+        self.send_frame(if on { Frame::LedOn } else { Frame::LedOff })
+            .await?;
+        Ok(())
+    }
 
-            match self.broker.send(frame.clone()).await {
-                Ok(response) => {
-                    debug!("Received Response: {:02x?}", response);
-                    return Ok(response);
+    /// Erases the modem's link database and puts its configuration back to
+    /// the defaults, as if its SET button were held at power up. Returns
+    /// once the modem reports the reset and answers again.
+    ///
+    /// This can't be undone, so consider saving the links with
+    /// [Modem::get_links] first.
+    pub async fn factory_reset(&mut self) -> Result<(), Error> {
+        let mut listener = self.listen_raw().await?;
+        // The modem only answers once it has erased everything.
+        self.wake().await?;
+        self.broker
+            .send_with_timeout(Frame::Reset, RESET_DURATION)
+            .await?;
+        wait_for(&mut listener, RESET_DURATION, |frame| match frame {
+            Frame::UserResetDetected => Some(()),
+            _ => None,
+        })
+        .await?;
+
+        // The reset was asked for, so don't put the old configuration back.
+        self.config = None;
+        self.check_reset().await?;
+        self.get_info().await?;
+        Ok(())
+    }
+
+    /// Puts the modem's radio to sleep to save power, e.g. on battery
+    /// backed installs. Nothing is received from devices while it sleeps.
+    /// The modem is woken with [Modem::wake] or by sending it anything.
+    pub async fn sleep(&mut self) -> Result<(), Error> {
+        self.send_frame(Frame::RfSleep).await?;
+        self.asleep = true;
+        Ok(())
+    }
+
+    /// Wakes the modem after [Modem::sleep]. Does nothing if it's awake.
+    pub async fn wake(&mut self) -> Result<(), Error> {
+        if self.asleep {
+            self.broker.wake().await?;
+            self.asleep = false;
+        }
+        Ok(())
+    }
+
+    /// Return the link database stored in the modem.
+    pub async fn get_links(&mut self) -> Result<impl Iterator<Item = AllLinkRecord>, Error> {
+        let mut records = Vec::new();
+        let mut stream = Box::pin(self.stream_links().await?);
+        while let Some(record) = stream.next().await {
+            records.push(record?);
+        }
+
+        Ok(records.into_iter())
+    }
+
+    /// Reads the link database stored in the modem, yielding each
+    /// [AllLinkRecord] as it arrives. Each record is only requested once
+    /// the previous one has been taken from the stream, so dropping the
+    /// stream stops the read.
+    ///
+    /// The modem NAKs the first request when its database is empty, but a
+    /// busy modem NAKs too, so that request is retried as the [RetryPolicy]
+    /// allows and the database is only taken to be empty once the NAKs
+    /// outlast it.
+    pub async fn stream_links(
+        &mut self,
+    ) -> Result<impl Stream<Item = Result<AllLinkRecord, Error>> + '_, Error> {
+        let listener = self.listen_raw().await?;
+
+        // The modem NAKs when the database is empty, e.g. right after a
+        // factory reset.
+        if self.asleep {
+            self.wake().await?;
+        }
+        self.check_reset().await?;
+        let policy = self.retry_policy;
+        let state =
+            match send_with_retries(&mut self.broker, &Frame::GetFirstAllLinkRecord, &policy).await
+            {
+                Ok(_) => Some((self, listener, true)),
+                Err(Error::NotAcknowledged) => None,
+                Err(e) => return Err(e),
+            };
+
+        Ok(stream::unfold(state, |state| async move {
+            let (modem, mut listener, first) = state?;
+            if !first {
+                match modem.broker.send(Frame::GetNextAllLinkRecord).await {
+                    Ok(_) => {}
+                    // There's no more
+                    Err(Error::NotAcknowledged) => return None,
+                    Err(e) => return Some((Err(e), None)),
                 }
-                Err(Error::NotAcknowledged) if retries > 0 => {
-                    warn!("Frame not acknowledged, retrying after {:?}", RETRY_DELAY);
-                    Delay::new(RETRY_DELAY).await;
-                    continue;
+            }
+
+            let timeout = modem.timeout;
+            match wait_for(&mut listener, timeout, |frame| match frame {
+                Frame::AllLinkRecord(record) => Some(record),
+                _ => None,
+            })
+            .await
+            {
+                Ok(record) => {
+                    debug!("Got All Link {:?}", record);
+                    Some((Ok(record), Some((modem, listener, false))))
                 }
-                e => {
-                    error!("Failed to send frame, {:02x?}", e);
-                    return e;
+                Err(e) => Some((Err(e), None)),
+            }
+        }))
+    }
+
+    /// Returns the first record in the modem's link database for `address`
+    /// in `group`, or `None` if there isn't one.
+    pub async fn find_link(
+        &mut self,
+        address: Address,
+        group: u8,
+    ) -> Result<Option<AllLinkRecord>, Error> {
+        let frame = Frame::ManageAllLinkRecord {
+            action: AllLinkAction::FindFirst,
+            record: AllLinkRecord {
+                flags: AllLinkFlags::NONE,
+                group,
+                to: address,
+                data: [0u8; 3],
+            },
+        };
+        self.check_firmware(&frame).await?;
+
+        let mut listener = self.listen_raw().await?;
+
+        // The modem NAKs when nothing matches, so don't retry.
+        match self.broker.send(frame).await {
+            Ok(_) => {}
+            Err(Error::NotAcknowledged) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let timeout = self.timeout;
+        wait_for(&mut listener, timeout, |frame| match frame {
+            Frame::AllLinkRecord(record) if record.to == address && record.group == group => {
+                Some(record)
+            }
+            _ => None,
+        })
+        .await
+        .map(Some)
+    }
+
+    /// Returns the modem's link record for the device that sent the last
+    /// message the modem received, e.g. to find out which of the modem's
+    /// groups an all-link broadcast applies to. Returns `None` if there's
+    /// no such record.
+    pub async fn get_link_for_last_sender(&mut self) -> Result<Option<AllLinkRecord>, Error> {
+        let mut listener = self.listen_raw().await?;
+
+        // The modem NAKs when there's no record, so don't retry.
+        match self.broker.send(Frame::GetAllLinkRecordForSender).await {
+            Ok(_) => {}
+            Err(Error::NotAcknowledged) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let timeout = self.timeout;
+        wait_for(&mut listener, timeout, |frame| match frame {
+            Frame::AllLinkRecord(record) => Some(record),
+            _ => None,
+        })
+        .await
+        .map(Some)
+    }
+
+    /// Adds `record` to the modem's link database, replacing the record
+    /// for the same device, group and role if there is one. Only the
+    /// modem's side of the link is written; see [Modem::link_programmatically]
+    /// to write the device's too.
+    pub async fn write_link(&mut self, record: AllLinkRecord) -> Result<(), Error> {
+        let action = if record.flags.contains(AllLinkFlags::IS_CONTROLLER) {
+            AllLinkAction::AddController
+        } else {
+            AllLinkAction::AddResponder
+        };
+
+        self.send_frame(Frame::ManageAllLinkRecord { action, record })
+            .await?;
+        Ok(())
+    }
+
+    /// Removes the first record for `address` in `group` from the modem's
+    /// link database, returning it, or `None` if there wasn't one. The
+    /// device's side of the link is left alone.
+    pub async fn delete_link(
+        &mut self,
+        address: Address,
+        group: u8,
+    ) -> Result<Option<AllLinkRecord>, Error> {
+        let record = match self.find_link(address, group).await? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+
+        self.send_frame(Frame::ManageAllLinkRecord {
+            action: AllLinkAction::Delete,
+            record: record.clone(),
+        })
+        .await?;
+        Ok(Some(record))
+    }
+
+    /// Reads the modem's memory in `range` 8 bytes at a time, e.g. to
+    /// inspect a link database that won't back up or restore properly.
+    ///
+    /// Modems that can't read their memory get the link database area
+    /// rebuilt from [Modem::get_links] instead, with
+    /// [MemoryDump::reconstructed] set. Anything else in `range` is left
+    /// out.
+    pub async fn dump_memory(&mut self, range: Range<u16>) -> Result<MemoryDump, Error> {
+        let mut records = Vec::new();
+        let mut listener = self.listen_raw().await?;
+
+        let mut address = Some(range.start - range.start % MEMORY_RECORD_SIZE);
+        while let Some(current) = address.filter(|address| *address < range.end) {
+            let record = match self
+                .broker
+                .send(Frame::ReadMemory { address: current })
+                .await
+            {
+                Ok(_) => {
+                    wait_for(&mut listener, MEMORY_READ_DURATION, |frame| match frame {
+                        Frame::MemoryRecord(record) if record.address == current => Some(record),
+                        _ => None,
+                    })
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+
+            match record {
+                Ok(record) => records.push(record),
+                Err(Error::NotAcknowledged) | Err(Error::Timeout) | Err(Error::Parse { .. })
+                    if records.is_empty() =>
+                {
+                    warn!("The modem can't read its memory, rebuilding it from the link database");
+                    return self.rebuild_memory(range).await;
                 }
+                Err(e) => return Err(e),
             }
+
+            address = current.checked_add(MEMORY_RECORD_SIZE);
         }
+
+        Ok(MemoryDump {
+            records,
+            reconstructed: false,
+        })
     }
 
-    async fn send_message_direct(&mut self, message: Message) -> Result<Message, Error> {
-        debug!("Sending Message {:02x?}", message);
+    async fn rebuild_memory(&mut self, range: Range<u16>) -> Result<MemoryDump, Error> {
+        let mut records = Vec::new();
+        let mut address = Some(MODEM_ALDB_START);
+        for link in self.get_links().await? {
+            let current = match address {
+                Some(current) => current,
+                None => break,
+            };
+
+            if current + MEMORY_RECORD_SIZE > range.start && current < range.end {
+                let mut data = [link.flags.bits(), link.group, 0, 0, 0, 0, 0, 0];
+                data[2..5].copy_from_slice(&<[u8; 3]>::from(link.to));
+                data[5..].copy_from_slice(&link.data);
+                records.push(MemoryRecord {
+                    address: current,
+                    data,
+                });
+            }
+            address = current.checked_sub(MEMORY_RECORD_SIZE);
+        }
+
+        records.reverse();
+        Ok(MemoryDump {
+            records,
+            reconstructed: true,
+        })
+    }
+
+    /// Reads the whole link database of the device at `address` with
+    /// extended [Command::ReadWriteAldb] messages, returning its records
+    /// in memory order. The record marking the end of the database is
+    /// not included. See [Modem::stream_device_links] to handle records
+    /// as they arrive.
+    pub async fn read_device_links(
+        &mut self,
+        address: Address,
+    ) -> Result<impl Iterator<Item = DeviceLinkRecord>, Error> {
+        let mut records = Vec::new();
+        let mut stream = Box::pin(self.stream_device_links(address).await?);
+        while let Some(record) = stream.next().await {
+            records.push(record?);
+        }
+
+        Ok(records.into_iter())
+    }
+
+    /// Reads the link database of the device at `address`, yielding each
+    /// [DeviceLinkRecord] in order as it arrives.
+    ///
+    /// The whole database is requested at once. Duplicate records are
+    /// dropped, and if the device goes quiet before the last record, the
+    /// missing one is requested again, up to [NUM_ALDB_RETRIES] times.
+    ///
+    /// i1 devices can't take extended messages, so their database is read
+    /// a byte at a time with [Command::Peek] instead. This takes a message
+    /// per byte, and the records only arrive once all have been read.
+    pub async fn stream_device_links(
+        &mut self,
+        address: Address,
+    ) -> Result<impl Stream<Item = Result<DeviceLinkRecord, Error>> + '_, Error> {
+        let listener = self.listen().await?;
+        let request = DeviceLinkRecord::read_request(address, ALDB_START, 0);
+        match self.send_message(request).await {
+            Err(Error::UnsupportedByDevice) => {
+                let records = self.peek_device_links(address).await?;
+                return Ok(stream::iter(records.into_iter().map(Ok)).left_stream());
+            }
+            result => result?,
+        };
+
+        let state = (self, listener, AldbReassembler::new(), 0u8);
+        Ok(stream::unfold(Some(state), move |state| async move {
+            let (modem, mut listener, mut reassembler, mut retries) = state?;
+            loop {
+                if let Some(record) = reassembler.pop() {
+                    debug!("Got Device Link {:?}", record);
+                    return Some((Ok(record), Some((modem, listener, reassembler, 0))));
+                }
+
+                let offset = reassembler.next_offset()?;
+                let received = wait_for(&mut listener, ALDB_GAP_DURATION, |message| {
+                    DeviceLinkRecord::from_response(&message).filter(|_| message.from == address)
+                })
+                .await;
+
+                match received {
+                    Ok(record) => reassembler.push(record),
+                    Err(Error::Timeout) if retries < NUM_ALDB_RETRIES => {
+                        retries += 1;
+                        warn!("Missing device link at {:04x}, requesting again", offset);
+
+                        let request = DeviceLinkRecord::read_request(address, offset, 1);
+                        if let Err(e) = modem.send_message(request).await {
+                            return Some((Err(e), None));
+                        }
+                    }
+                    Err(e) => return Some((Err(e), None)),
+                }
+            }
+        })
+        .right_stream())
+    }
+
+    /// Reads the link database of an i1 device a byte at a time, moving
+    /// down from [ALDB_START] until the record marking the end.
+    async fn peek_device_links(
+        &mut self,
+        address: Address,
+    ) -> Result<Vec<DeviceLinkRecord>, Error> {
+        let mut records = Vec::new();
+        let mut msb = None;
+        let mut offset = ALDB_START;
+        loop {
+            let mut data = [0u8; ALDB_RECORD_SIZE as usize];
+            let first = offset + 1 - ALDB_RECORD_SIZE;
+            for (location, byte) in (first..=offset).zip(data.iter_mut()) {
+                let [high, low] = location.to_be_bytes();
+                if msb != Some(high) {
+                    let message = (address, Command::SetAddressMsb, Command::from(high));
+                    self.send_message(message.into()).await?;
+                    msb = Some(high);
+                }
+                let message = (address, Command::Peek, Command::from(low));
+                *byte = self.send_message(message.into()).await?.cmd2.into();
+            }
+
+            let record = DeviceLinkRecord {
+                offset,
+                flags: AllLinkFlags::from_bits_truncate(data[0]),
+                group: data[1],
+                address: Address::from(&data[2..5]),
+                data: [data[5], data[6], data[7]],
+            };
+            debug!("Peeked Device Link {:?}", record);
+            if record.is_last() {
+                return Ok(records);
+            }
+            records.push(record);
+
+            offset = match first.checked_sub(1) {
+                Some(next) if next >= ALDB_RECORD_SIZE => next,
+                _ => return Ok(records),
+            };
+        }
+    }
+
+    /// Links the device at `address` to the modem by writing the records
+    /// directly into both link databases, skipping linking mode entirely.
+    /// This only works with devices that allow remote link database
+    /// writes, i.e. i2 and i2cs devices.
+    ///
+    /// # Arguments
+    /// * `address` - The device to link.
+    /// * `group` - The group number of the link.
+    /// * `mode` - The role of the modem, either [AllLinkMode::Controller] or [AllLinkMode::Responder].
+    /// * `data` - The data for the responder's record, usually the on-level, ramp rate and button.
+    pub async fn link_programmatically(
+        &mut self,
+        address: Address,
+        group: u8,
+        mode: AllLinkMode,
+        data: [u8; 3],
+    ) -> Result<(), Error> {
+        let used = AllLinkFlags::IN_USE | AllLinkFlags::HAS_BEEN_USED;
+        let controller = used | AllLinkFlags::IS_CONTROLLER;
+
+        let (modem_record, device_flags, device_data) = match mode {
+            AllLinkMode::Controller => (
+                AllLinkRecord {
+                    flags: controller,
+                    group,
+                    to: address,
+                    data: [0u8; 3],
+                },
+                used,
+                data,
+            ),
+            AllLinkMode::Responder => (
+                AllLinkRecord {
+                    flags: used,
+                    group,
+                    to: address,
+                    data,
+                },
+                controller,
+                [0, 0, group],
+            ),
+            _ => return Err(Error::UnsupportedLinkMode(mode)),
+        };
+
+        let modem_address = self.get_info().await?.address;
+        self.add_device_link(address, device_flags, group, modem_address, device_data)
+            .await?;
+
+        self.write_link(modem_record).await
+    }
+
+    /// Links two devices directly, so the `controller` controls the
+    /// `responder` on `group` without the modem being involved once the
+    /// link is made. Like [Modem::link_programmatically], both devices must
+    /// allow remote link database writes.
+    ///
+    /// # Arguments
+    /// * `controller` - The device that sends the group commands, e.g. a switch.
+    /// * `responder` - The device that follows them, e.g. a plug-in module.
+    /// * `group` - The controller's group, usually its button number.
+    /// * `responder_data` - The data for the responder's record, usually the on-level, ramp rate and button.
+    pub async fn cross_link(
+        &mut self,
+        controller: Address,
+        responder: Address,
+        group: u8,
+        responder_data: [u8; 3],
+    ) -> Result<(), Error> {
+        let used = AllLinkFlags::IN_USE | AllLinkFlags::HAS_BEEN_USED;
+
+        // Write the responder first, so the controller never has a link
+        // to a device that ignores it.
+        self.add_device_link(responder, used, group, controller, responder_data)
+            .await?;
+        self.add_device_link(
+            controller,
+            used | AllLinkFlags::IS_CONTROLLER,
+            group,
+            responder,
+            [0, 0, group],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Writes `record` into the link database of the device at `address`,
+    /// at the record's offset, then reads it back to make sure the device
+    /// took it. The write carries the checksum i2cs devices require, as
+    /// with any extended message sent by [Modem::send_message].
+    ///
+    /// Returns [Error::DeviceLinkMismatch] if the record reads back
+    /// differently.
+    pub async fn write_device_link(
+        &mut self,
+        address: Address,
+        record: DeviceLinkRecord,
+    ) -> Result<(), Error> {
+        debug!("Writing Device Link {:?} to {}", record, address);
+        self.send_message(record.write_request(address)).await?;
 
         let mut listener = self.listen().await?;
+        self.send_message(DeviceLinkRecord::read_request(address, record.offset, 1))
+            .await?;
+        let written = wait_for(&mut listener, ALDB_GAP_DURATION, |message| {
+            DeviceLinkRecord::from_response(&message)
+                .filter(|read| message.from == address && read.offset == record.offset)
+        })
+        .await?;
+
+        if written != record {
+            warn!("Device Link {:?} read back as {:?}", record, written);
+            return Err(Error::DeviceLinkMismatch {
+                address,
+                offset: record.offset,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Removes the first record for a link with `other` in `group` from
+    /// the link database of the device at `address`, returning it, or
+    /// `None` if there wasn't one. The record is marked unused rather than
+    /// erased, so later records stay reachable, and the write is verified
+    /// like [Modem::write_device_link].
+    pub async fn delete_device_link(
+        &mut self,
+        address: Address,
+        other: Address,
+        group: u8,
+    ) -> Result<Option<DeviceLinkRecord>, Error> {
+        let record =
+            match self.read_device_links(address).await?.find(|record| {
+                record.is_in_use() && record.address == other && record.group == group
+            }) {
+                Some(record) => record,
+                None => return Ok(None),
+            };
+
+        let mut unused = record;
+        unused.flags.remove(AllLinkFlags::IN_USE);
+        self.write_device_link(address, unused).await?;
+        Ok(Some(record))
+    }
 
-        if message.flags.contains(MessageFlags::EXTENDED) {
-            self.send_frame(Frame::ExtendedInsteonSend {
-                to: message.to,
-                flags: message.flags,
-                max_hops: message.max_hops,
-                cmd1: message.cmd1.into(),
-                cmd2: message.cmd2.into(),
-                data: message.data,
+    /// Adds a record for a link with `other` to the link database of
+    /// `device`. A record for the same link is reused if there is one,
+    /// otherwise the first free slot is taken, or the record is appended
+    /// after the last one.
+    async fn add_device_link(
+        &mut self,
+        device: Address,
+        flags: AllLinkFlags,
+        group: u8,
+        other: Address,
+        data: [u8; 3],
+    ) -> Result<DeviceLinkRecord, Error> {
+        let records: Vec<DeviceLinkRecord> = self.read_device_links(device).await?.collect();
+
+        let offset = records
+            .iter()
+            .find(|record| {
+                record.is_in_use()
+                    && record.group == group
+                    && record.address == other
+                    && record.is_controller() == flags.contains(AllLinkFlags::IS_CONTROLLER)
             })
-            .await?;
-        } else {
-            self.send_frame(Frame::StandardInsteonSend {
-                to: message.to,
-                flags: message.flags,
-                max_hops: message.max_hops,
-                cmd1: message.cmd1.into(),
-                cmd2: message.cmd2.into(),
+            .or_else(|| records.iter().find(|record| !record.is_in_use()))
+            .map(|record| record.offset)
+            .unwrap_or(ALDB_START - ALDB_RECORD_SIZE * records.len() as u16);
+
+        let record = DeviceLinkRecord {
+            offset,
+            flags,
+            group,
+            address: other,
+            data,
+        };
+        self.write_device_link(device, record).await?;
+        Ok(record)
+    }
+
+    /// Returns the lowest group number not used by any controller record in
+    /// the modem's link database, suitable for a new [Scene](crate::Scene).
+    pub async fn allocate_group(&mut self) -> Result<u8, Error> {
+        let used: Vec<u8> = self
+            .get_links()
+            .await?
+            .filter(|record| record.flags.contains(AllLinkFlags::IS_CONTROLLER))
+            .map(|record| record.group)
+            .collect();
+
+        (1..=0xfeu8)
+            .find(|group| !used.contains(group))
+            .ok_or(Error::NoFreeGroup)
+    }
+
+    /// Sends `command` to every responder of the modem's `group`, e.g. a
+    /// scene created with [Modem::create_scene].
+    ///
+    /// Like a real controller, the group broadcast is followed by a cleanup
+    /// message sent directly to each responder, so devices that missed the
+    /// broadcast still react. Returns the responders that did not
+    /// acknowledge their cleanup.
+    pub async fn trigger_scene(
+        &mut self,
+        group: u8,
+        command: Command,
+    ) -> Result<Vec<Address>, Error> {
+        let responders: Vec<Address> = self
+            .get_links()
+            .await?
+            .filter(|record| {
+                record.group == group && record.flags.contains(AllLinkFlags::IS_CONTROLLER)
+            })
+            .map(|record| record.to)
+            .collect();
+
+        self.send_frame(Frame::AllLinkCommand {
+            group,
+            cmd1: command.into(),
+            cmd2: 0,
+        })
+        .await?;
+
+        let mut missed = Vec::new();
+        for address in responders {
+            let cleanup = (address, command, Command::Other(group), MessageFlags::GROUP).into();
+            if let Err(e) = self.send_message(cleanup).await {
+                warn!("Cleanup for group {} to {} failed: {}", group, address, e);
+                missed.push(address);
+            }
+        }
+
+        Ok(missed)
+    }
+
+    /// Sends `command` to the modem's `group` and lets the modem send the
+    /// cleanup messages to each responder itself, unlike
+    /// [Modem::trigger_scene]. Waits until the modem reports that the
+    /// cleanups are done.
+    pub async fn send_all_link_command(
+        &mut self,
+        group: u8,
+        command: Command,
+    ) -> Result<AllLinkCommandResult, Error> {
+        let mut listener = self.listen_raw().await?;
+
+        self.send_frame(Frame::AllLinkCommand {
+            group,
+            cmd1: command.into(),
+            cmd2: 0,
+        })
+        .await?;
+
+        let mut result = AllLinkCommandResult::default();
+        loop {
+            let timeout = self.timeout;
+            let frame = wait_for(&mut listener, timeout, |frame| match frame {
+                Frame::StandardInsteonReceive { .. } => Message::try_from(frame.clone())
+                    .ok()
+                    .filter(|message| message.is_cleanup_ack() && message.group() == Some(group))
+                    .map(|_| frame),
+                Frame::AllLinkCleanupFailure { group: failed, .. } if failed == group => {
+                    Some(frame)
+                }
+                Frame::AllLinkCleanupStatus { .. } => Some(frame),
+                _ => None,
             })
             .await?;
-        }
 
-        while let Some(response) = listener.next().await {
-            debug!("Received Message: {:02x?}", response);
-            if message.is_ack(&response) {
-                return Ok(response);
+            match frame {
+                Frame::StandardInsteonReceive { from, .. } => result.acknowledged.push(from),
+                Frame::AllLinkCleanupFailure { address, .. } => result.failed.push(address),
+                Frame::AllLinkCleanupStatus { completed } => {
+                    result.completed = completed;
+                    return Ok(result);
+                }
+                _ => unreachable!(),
             }
         }
-
-        Ok(message)
     }
 
-    /// Sends a [Message]. This uses the default timeout
-    /// duration defined by [DEFAULT_TIMEOUT_DURATION].
-    ///
-    /// Returns an acknowledged [Message] or an error.
-    pub async fn send_message(&mut self, message: Message) -> Result<Message, Error> {
-        self.send_message_with_timeout(message, DEFAULT_TIMEOUT_DURATION)
-            .await
+    /// Listens for every [Frame] the modem sends that isn't the response
+    /// to a frame sent to it, e.g. to debug the protocol. Most callers want
+    /// [Modem::listen] instead.
+    pub async fn listen_raw(
+        &mut self,
+    ) -> Result<impl Stream<Item = Frame> + Sync + Send + Unpin, Error> {
+        self.broker.listen(Replay::None).await
     }
 
-    /// Sends a [Message] with the specified timeout duration.
-    ///
-    /// Returns an acknowledged [Message] or an error.
-    pub async fn send_message_with_timeout(
+    /// Like [Modem::listen_raw], along with when each [Frame] was received.
+    pub(crate) async fn listen_raw_timestamped(
         &mut self,
-        message: Message,
-        duration: Duration,
-    ) -> Result<Message, Error> {
-        let mut delay = Delay::new(duration).fuse();
-        let mut sending = Box::pin(self.send_message_direct(message).fuse());
-
-        select_biased! {
-            e = delay => Err(Error::Timeout),
-            r = sending => r
-        }
+    ) -> Result<impl Stream<Item = (Timestamp, Frame)> + Sync + Send + Unpin, Error> {
+        self.broker.listen_timestamped(Replay::None).await
     }
 
-    /// Retrieve information about the attached modem.
-    pub async fn get_info(&mut self) -> Result<ModemInfo, Error> {
-        match self.send_frame(Frame::GetModemInfo).await? {
-            Frame::ModemInfo(info) => Ok(info),
-            _ => Err(Error::UnexpectedResponse),
-        }
+    /// Delivers a copy of every byte read from and written to the modem
+    /// from now on, however it's decoded. This is meant for debugging, so
+    /// the bytes pile up until the stream is read or dropped.
+    pub fn tap(&self) -> impl Stream<Item = Tapped> + Send + Unpin {
+        self.broker.tap()
     }
 
-    /// Return the link database stored in the modem.
-    pub async fn get_links(&mut self) -> Result<impl Iterator<Item = AllLinkRecord>, Error> {
-        let mut records = Vec::new();
-        let mut listener = self.listen_frames().await?;
-
-        self.send_frame(Frame::GetFirstAllLinkRecord).await?;
-
-        while let Some(frame) = listener.next().await {
-            match frame {
-                Frame::AllLinkRecord(record) => {
-                    debug!("Got All Link {:?}", record);
-                    records.push(record);
-                    if let Err(Error::NotAcknowledged) =
-                        self.broker.send(Frame::GetNextAllLinkRecord).await
-                    {
-                        // There's no more
-                        break;
-                    }
-                }
-                _ => return Err(Error::UnexpectedResponse),
-            }
-        }
+    /// Delivers a [LinkingEvent] whenever the modem enters or leaves
+    /// linking mode, or a device is linked or unlinked, whoever started it.
+    /// This is meant for showing progress while e.g. [Modem::link_device]
+    /// runs elsewhere.
+    pub async fn subscribe_linking(
+        &mut self,
+    ) -> Result<impl Stream<Item = LinkingEvent> + Sync + Send + Unpin, Error> {
+        let completed = self
+            .listen_raw()
+            .await?
+            .filter_map(|frame| future::ready(LinkingEvent::from_frame(frame)));
 
-        Ok(records.into_iter())
+        let (sender, receiver) = unbounded();
+        self.linking_listeners.push(sender);
+        Ok(stream::select(completed, receiver))
     }
 
-    async fn listen_frames(
+    /// Delivers a [ModemEvent] for each change to the modem itself, e.g. a
+    /// factory reset, so applications can re-sync their state. Cached
+    /// modem info and device engines are forgotten automatically.
+    pub async fn modem_events(
         &mut self,
-    ) -> Result<impl Stream<Item = Frame> + Sync + Send + Unpin, Error> {
-        self.broker.listen().await
+    ) -> Result<impl Stream<Item = ModemEvent> + Sync + Send + Unpin, Error> {
+        Ok(self.listen_raw().await?.filter_map(|frame| {
+            future::ready(match frame {
+                Frame::UserResetDetected => Some(ModemEvent::ModemReset),
+                Frame::ButtonEvent(event) => Some(ModemEvent::Button(event)),
+                _ => None,
+            })
+        }))
     }
 
     /// Listens for incoming [Message]s and delivers them on the returned [Stream].
     pub async fn listen(
         &mut self,
     ) -> Result<impl Stream<Item = Message> + Sync + Send + Unpin, Error> {
-        Ok(Box::pin(self.broker.listen().await?.filter_map(
-            |frame| async {
-                if let Ok(message) = Message::try_from(frame) {
-                    Some(message)
-                } else {
-                    None
-                }
-            },
-        )))
+        self.listen_with_replay(Replay::None).await
+    }
+
+    /// Like [Modem::listen], but first delivers the recently received
+    /// [Message]s selected by `replay`. This avoids missing a fast response
+    /// when listening only after sending.
+    pub async fn listen_with_replay(
+        &mut self,
+        replay: Replay,
+    ) -> Result<impl Stream<Item = Message> + Sync + Send + Unpin, Error> {
+        Ok(self
+            .listen_timestamped(replay)
+            .await?
+            .map(|(_, message)| message))
+    }
+
+    /// Like [Modem::listen_with_replay], but delivers each [Message] along
+    /// with the [Timestamp] of when it was received, e.g. for logging or
+    /// measuring latency.
+    pub async fn listen_timestamped(
+        &mut self,
+        replay: Replay,
+    ) -> Result<impl Stream<Item = (Timestamp, Message)> + Sync + Send + Unpin, Error> {
+        Ok(Box::pin(
+            self.broker.listen_timestamped(replay).await?.filter_map(
+                |(received, frame)| async move {
+                    Message::try_from(frame)
+                        .ok()
+                        .map(|message| (received, message))
+                },
+            ),
+        ))
+    }
+
+    /// Sends `command` to `unit` (1 to 16) of the X10 `house`, by selecting
+    /// the unit and then sending the command.
+    pub async fn send_x10(
+        &mut self,
+        house: X10House,
+        unit: u8,
+        command: X10Command,
+    ) -> Result<(), Error> {
+        self.send_frame(Frame::X10Send(X10Message::unit(house, unit)?))
+            .await?;
+        Delay::new(X10_SEND_INTERVAL).await;
+        self.send_frame(Frame::X10Send(X10Message::Command { house, command }))
+            .await?;
+        Ok(())
+    }
+
+    /// Listens for X10 messages received from the powerline.
+    pub async fn listen_x10(
+        &mut self,
+    ) -> Result<impl Stream<Item = X10Message> + Sync + Send + Unpin, Error> {
+        Ok(self.listen_raw().await?.filter_map(|frame| {
+            future::ready(match frame {
+                Frame::X10Receive(message) => Some(message),
+                _ => None,
+            })
+        }))
     }
 
     /// Link a new device to the modem.
@@ -212,14 +1786,18 @@ impl Modem {
         self.send_frame(Frame::CancelAllLink).await?;
 
         // We need to listen for some frames
-        let mut listener = self.listen_frames().await?;
+        let mut listener = self.listen_raw().await?;
 
         // If we have an address, ask the device to enter linking mode
         if let Some(address) = address {
             self.send_message(
                 (
                     address,
-                    Command::StartLinking,
+                    if mode == AllLinkMode::Delete {
+                        Command::StartUnlinking
+                    } else {
+                        Command::StartLinking
+                    },
                     Command::from(group),
                     MessageFlags::EXTENDED,
                 )
@@ -265,32 +1843,114 @@ impl Modem {
         let _ = self.send_frame(Frame::CancelAllLink).await;
         result
     }
+
+    /// Keeps the modem in linking mode, yielding an [AllLinkComplete] for
+    /// each device whose set button is pressed, so a whole house can be
+    /// linked in one walk around it.
+    ///
+    /// Linking mode is re-armed after each link and before it times out.
+    /// Pressing a device's button again doesn't yield it twice. The modem
+    /// stays in linking mode after the stream is dropped, so finish with
+    /// [Modem::cancel_linking].
+    pub async fn enroll_session(
+        &mut self,
+        mode: AllLinkMode,
+        group: u8,
+    ) -> Result<impl Stream<Item = Result<AllLinkComplete, Error>> + '_, Error> {
+        self.send_frame(Frame::CancelAllLink).await?;
+        let listener = self.listen_raw().await?;
+        self.send_frame(Frame::StartAllLink { mode, group }).await?;
+
+        let rearm_at = Instant::now() + ENROLL_REARM_INTERVAL;
+        let state = (self, listener, Vec::<Address>::new(), rearm_at);
+        Ok(stream::unfold(Some(state), move |state| async move {
+            let (modem, mut listener, mut linked, mut rearm_at) = state?;
+            loop {
+                let remaining = rearm_at.saturating_duration_since(Instant::now());
+                let complete = match wait_for(&mut listener, remaining, |frame| match frame {
+                    Frame::AllLinkComplete(complete) => Some(complete),
+                    _ => None,
+                })
+                .await
+                {
+                    Ok(complete) => Some(complete),
+                    Err(Error::Timeout) => None,
+                    Err(e) => return Some((Err(e), None)),
+                };
+
+                debug!("Re-arming linking mode for group {}", group);
+                if let Err(e) = modem.send_frame(Frame::StartAllLink { mode, group }).await {
+                    return Some((Err(e), None));
+                }
+                rearm_at = Instant::now() + ENROLL_REARM_INTERVAL;
+
+                if let Some(complete) = complete {
+                    if !linked.contains(&complete.address) {
+                        linked.push(complete.address);
+                        let state = (modem, listener, linked, rearm_at);
+                        return Some((Ok(complete), Some(state)));
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Takes the modem out of linking mode, e.g. after
+    /// [Modem::enroll_session].
+    pub async fn cancel_linking(&mut self) -> Result<(), Error> {
+        self.send_frame(Frame::CancelAllLink).await?;
+        self.notify_linking(LinkingEvent::Cancelled);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(unix)]
+    use crate::emulator::*;
+    use async_std::sync::Mutex;
     use lazy_static::lazy_static;
-    use std::{
-        env,
-        sync::{Arc, Mutex},
-    };
+    use std::{env, sync::Arc};
 
+    /// Set to the path of a real modem to run these tests against it
+    /// instead of the emulator.
     const MODEM_ENV_VAR: &str = "MODEM_PATH";
 
     lazy_static! {
         static ref MODEM: Arc<Mutex<Modem>> = {
-            pretty_env_logger::init();
+            let _ = pretty_env_logger::try_init();
 
-            Arc::new(Mutex::new(
-                Modem::from_path(env::var(MODEM_ENV_VAR).unwrap()).unwrap(),
-            ))
+            let modem = match env::var(MODEM_ENV_VAR) {
+                Ok(path) => Modem::from_path(path).unwrap(),
+                #[cfg(unix)]
+                Err(_) => {
+                    let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+                    let modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+                    // The emulator has to outlive every test.
+                    std::mem::forget(emulator);
+                    modem
+                }
+                #[cfg(not(unix))]
+                Err(_) => panic!("{} must be set", MODEM_ENV_VAR),
+            };
+
+            Arc::new(Mutex::new(modem))
         };
     }
 
     macro_rules! assume_modem {
         () => {
-            if env::var(MODEM_ENV_VAR).is_err() {
+            if cfg!(not(unix)) && env::var(MODEM_ENV_VAR).is_err() {
+                return ();
+            }
+        };
+    }
+
+    /// For tests that need someone to press a button on a real device.
+    macro_rules! assume_emulator {
+        () => {
+            if env::var(MODEM_ENV_VAR).is_ok() {
                 return ();
             }
         };
@@ -300,7 +1960,7 @@ mod tests {
     async fn get_info() {
         assume_modem!();
 
-        let info = MODEM.lock().unwrap().get_info().await.unwrap();
+        let info = MODEM.lock().await.get_info().await.unwrap();
         assert_eq!(info.category, 3);
     }
 
@@ -308,10 +1968,593 @@ mod tests {
     async fn get_links() {
         assume_modem!();
 
-        let links: Vec<AllLinkRecord> = MODEM.lock().unwrap().get_links().await.unwrap().collect();
+        let links: Vec<AllLinkRecord> = MODEM.lock().await.get_links().await.unwrap().collect();
         assert!(!links.is_empty());
     }
 
+    #[async_std::test]
+    async fn identify() {
+        assume_modem!();
+        assume_emulator!();
+
+        let id = MODEM
+            .lock()
+            .await
+            .identify(Address::from([0x11, 0x22, 0x33]))
+            .await
+            .unwrap();
+        assert_eq!(id.category, 0x01);
+    }
+
+    #[async_std::test]
+    async fn send_batch() {
+        assume_modem!();
+        assume_emulator!();
+
+        let address = Address::from([0x11, 0x22, 0x33]);
+        let results = MODEM
+            .lock()
+            .await
+            .send_batch(vec![
+                (address, Command::On, Command::Other(0xff)).into(),
+                (address, Command::Off).into(),
+            ])
+            .await;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().cmd1, Command::On);
+        assert_eq!(results[1].as_ref().unwrap().cmd1, Command::Off);
+    }
+
+    #[async_std::test]
+    async fn send_batch_pipelined() {
+        let mock = crate::testing::MockModem::new();
+        mock.set_latency(Duration::from_millis(300));
+        let mut modem = Modem::new(mock.clone());
+
+        // Each device takes 300ms to answer, so one at a time would take
+        // well over a second.
+        let start = Instant::now();
+        let messages = (1..=5)
+            .map(|n| (Address::from([0x22, 0x33, n]), Command::On).into())
+            .collect();
+        let results = modem.send_batch(messages).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert!(start.elapsed() < Duration::from_millis(1000));
+        assert_eq!(mock.sent().len(), 5);
+    }
+
+    #[async_std::test]
+    async fn send_batch_same_device() {
+        let mock = crate::testing::MockModem::new();
+        mock.set_latency(Duration::from_millis(300));
+        let mut modem = Modem::new(mock.clone());
+
+        // The second message waits for the device to answer the first.
+        let address = Address::from([0x22, 0x33, 0x44]);
+        let start = Instant::now();
+        let results = modem
+            .send_batch(vec![
+                (address, Command::On).into(),
+                (address, Command::Off).into(),
+            ])
+            .await;
+        assert!(start.elapsed() >= Duration::from_millis(600));
+        assert_eq!(results[0].as_ref().unwrap().cmd1, Command::On);
+        assert_eq!(results[1].as_ref().unwrap().cmd1, Command::Off);
+
+        let sent: Vec<u8> = mock
+            .sent()
+            .into_iter()
+            .filter_map(|frame| match frame {
+                Frame::StandardInsteonSend { cmd1, .. } => Some(cmd1),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sent, vec![u8::from(Command::On), u8::from(Command::Off)]);
+    }
+
+    #[async_std::test]
+    async fn link_device() {
+        assume_modem!();
+        assume_emulator!();
+
+        let address = Address::from([0x55, 0x66, 0x77]);
+        let mut modem = MODEM.lock().await;
+        let complete = modem
+            .link_device(Some(address), AllLinkMode::Controller, 2)
+            .await
+            .unwrap();
+        assert_eq!(complete.address, address);
+        assert_eq!(complete.group, 2);
+
+        let links: Vec<AllLinkRecord> = modem.get_links().await.unwrap().collect();
+        assert!(links
+            .iter()
+            .any(|link| link.to == address && link.group == 2));
+    }
+
+    #[async_std::test]
+    async fn enroll_session() {
+        assume_modem!();
+        assume_emulator!();
+
+        let mut modem = MODEM.lock().await;
+        let complete = {
+            let mut session = Box::pin(
+                modem
+                    .enroll_session(AllLinkMode::Controller, 3)
+                    .await
+                    .unwrap(),
+            );
+            session.next().await.unwrap().unwrap()
+        };
+        assert_eq!(complete.group, 3);
+        assert_eq!(complete.mode, AllLinkMode::Controller);
+        modem.cancel_linking().await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn subscribe_linking() {
+        assume_modem!();
+        assume_emulator!();
+
+        let address = Address::from([0x55, 0x66, 0x88]);
+        let mut modem = MODEM.lock().await;
+        let events = modem.subscribe_linking().await.unwrap();
+        modem
+            .link_device(Some(address), AllLinkMode::Controller, 4)
+            .await
+            .unwrap();
+
+        modem.cancel_linking().await.unwrap();
+
+        // The cancels around link_device itself aren't reported, only the
+        // one asked for.
+        let events: Vec<LinkingEvent> = events.take(3).collect().await;
+        assert!(events.contains(&LinkingEvent::Started {
+            mode: AllLinkMode::Controller,
+            group: 4
+        }));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            LinkingEvent::Linked(complete) if complete.address == address
+        )));
+        assert_eq!(events[2], LinkingEvent::Cancelled);
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn modem_reset() {
+        // Resetting clears the links, so use an emulator of our own.
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        modem.get_info().await.unwrap();
+
+        let mut events = modem.modem_events().await.unwrap();
+        modem.send_frame(Frame::Reset).await.unwrap();
+        assert_eq!(events.next().await, Some(ModemEvent::ModemReset));
+        assert_eq!(modem.health().resets, 1);
+
+        assert!(modem.info.is_some());
+        modem.check_reset().await.unwrap();
+        assert!(modem.info.is_none());
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn stream_links() {
+        let config = EmulatorConfig::default();
+        let emulator = Emulator::spawn(config.clone()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+
+        let links: Vec<AllLinkRecord> = modem
+            .stream_links()
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(links, config.links);
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn factory_reset() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        modem.set_config(ModemConfig::MONITOR_MODE).await.unwrap();
+
+        modem.factory_reset().await.unwrap();
+        // Don't wait out the retries on the empty database's NAK.
+        modem.set_retry_policy(RetryPolicy::never());
+        assert_eq!(modem.get_links().await.unwrap().count(), 0);
+        assert_eq!(modem.get_config().await.unwrap(), ModemConfig::NONE);
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn healthcheck() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        let mut state = modem.connection_state();
+        assert_eq!(state.recv().await, Some(ConnectionState::Connected));
+        modem.healthcheck().await.unwrap();
+
+        emulator.unplug().unwrap();
+        assert_eq!(state.recv().await, Some(ConnectionState::Disconnected));
+        assert!(modem.healthcheck().await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn sleep() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+
+        modem.sleep().await.unwrap();
+        assert!(modem.asleep);
+
+        // Sending anything wakes the modem first.
+        modem.get_info().await.unwrap();
+        assert!(!modem.asleep);
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn modem_config() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        assert_eq!(modem.get_config().await.unwrap(), ModemConfig::NONE);
+
+        let config = ModemConfig::MONITOR_MODE | ModemConfig::MANUAL_LED;
+        modem.set_config(config).await.unwrap();
+        assert_eq!(modem.get_config().await.unwrap(), config);
+
+        // A reset clears the config, but it is put back before the next send.
+        let mut events = modem.modem_events().await.unwrap();
+        modem.send_frame(Frame::Reset).await.unwrap();
+        assert_eq!(events.next().await, Some(ModemEvent::ModemReset));
+        assert_eq!(modem.get_config().await.unwrap(), config);
+        modem.set_led(true).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn flush() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+
+        // Queue a frame, then give up on its response.
+        let send = Box::pin(modem.broker.send(Frame::GetModemInfo));
+        assert!(futures::poll!(send).is_pending());
+
+        modem.flush().await.unwrap();
+        let health = modem.health();
+        assert_eq!(health.queue_depth, 0);
+        assert!(health.last_exchange.is_some());
+
+        modem.close(true).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn close() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        let mut listener = modem.listen().await.unwrap();
+        let mut handle = modem.handle();
+        let mut state = modem.connection_state();
+        assert_eq!(state.recv().await, Some(ConnectionState::Connected));
+
+        modem.close(true).await.unwrap();
+        assert_eq!(state.recv().await, Some(ConnectionState::Disconnected));
+        assert_eq!(listener.next().await, None);
+
+        let message = (Address::from([0x11, 0x22, 0x33]), Command::On).into();
+        assert!(matches!(
+            handle.send_message(message).await,
+            Err(Error::Disconnected)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn write_link() {
+        // This edits the links, so use an emulator of our own.
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        let address = Address::from([0x33, 0x44, 0x55]);
+
+        assert_eq!(modem.find_link(address, 7).await.unwrap(), None);
+
+        let record = AllLinkRecord {
+            flags: AllLinkFlags::IN_USE | AllLinkFlags::IS_CONTROLLER,
+            group: 7,
+            to: address,
+            data: [0x01, 0x20, 0x41],
+        };
+        modem.write_link(record.clone()).await.unwrap();
+        assert_eq!(
+            modem.find_link(address, 7).await.unwrap(),
+            Some(record.clone())
+        );
+
+        assert_eq!(modem.delete_link(address, 7).await.unwrap(), Some(record));
+        assert_eq!(modem.delete_link(address, 7).await.unwrap(), None);
+        assert!(modem
+            .get_links()
+            .await
+            .unwrap()
+            .all(|link| link.to != address));
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn read_device_links() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        let modem_address = modem.get_info().await.unwrap().address;
+
+        let links: Vec<DeviceLinkRecord> = modem
+            .read_device_links(Address::from([0x11, 0x22, 0x33]))
+            .await
+            .unwrap()
+            .collect();
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].offset, ALDB_START);
+        assert!(!links[0].is_controller());
+        assert!(links[1].is_controller());
+        assert!(links.iter().all(|link| link.address == modem_address));
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn write_device_link() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        let device = Address::from([0x22, 0x33, 0x44]);
+        let other = Address::from([0x11, 0x22, 0x33]);
+
+        let record = DeviceLinkRecord {
+            offset: ALDB_START - ALDB_RECORD_SIZE * 2,
+            flags: AllLinkFlags::IN_USE | AllLinkFlags::HAS_BEEN_USED,
+            group: 3,
+            address: other,
+            data: [0xff, DEFAULT_RAMP_RATE, 0x03],
+        };
+        modem.write_device_link(device, record).await.unwrap();
+        assert_eq!(
+            modem.read_device_links(device).await.unwrap().last(),
+            Some(record)
+        );
+
+        assert_eq!(
+            modem.delete_device_link(device, other, 3).await.unwrap(),
+            Some(record)
+        );
+        assert_eq!(
+            modem.delete_device_link(device, other, 3).await.unwrap(),
+            None
+        );
+        assert!(modem
+            .read_device_links(device)
+            .await
+            .unwrap()
+            .all(|link| link.address != other || !link.is_in_use()));
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn send_all_link_command() {
+        let mut config = EmulatorConfig::demo();
+        config.links.push(AllLinkRecord {
+            flags: AllLinkFlags::IN_USE | AllLinkFlags::IS_CONTROLLER,
+            group: 1,
+            to: Address::from([0x55, 0x66, 0x77]),
+            data: [0u8; 3],
+        });
+        let emulator = Emulator::spawn(config).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+
+        let result = modem.send_all_link_command(1, Command::On).await.unwrap();
+        assert!(result.completed);
+        assert_eq!(result.acknowledged.len(), 4);
+        assert_eq!(result.failed, vec![Address::from([0x55, 0x66, 0x77])]);
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn trigger_scene() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+
+        let missed = modem.trigger_scene(1, Command::On).await.unwrap();
+        assert!(missed.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn get_link_for_last_sender() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        assert_eq!(modem.get_link_for_last_sender().await.unwrap(), None);
+
+        let address = Address::from([0x22, 0x33, 0x44]);
+        modem.get_level(address).await.unwrap();
+        let record = modem.get_link_for_last_sender().await.unwrap().unwrap();
+        assert_eq!(record.to, address);
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn send_raw_extended_frame() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        let device = Address::from([0x11, 0x22, 0x33]);
+        let mut listener = modem.listen().await.unwrap();
+
+        // The checksum is left for the encoder to fill in.
+        modem
+            .send_frame(Frame::ExtendedInsteonSend {
+                to: device,
+                flags: MessageFlags::EXTENDED,
+                max_hops: 3,
+                cmd1: 0x2e,
+                cmd2: 0x00,
+                data: [0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            })
+            .await
+            .unwrap();
+        let ack = wait_for(&mut listener, Duration::from_secs(2), |message| {
+            if message.from == device && message.flags.contains(MessageFlags::ACK) {
+                Some(message)
+            } else {
+                None
+            }
+        })
+        .await
+        .unwrap();
+        assert!(!ack.is_nak());
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn read_i1_device_links() {
+        let mut config = EmulatorConfig::demo();
+        let device = Address::from([0x11, 0x22, 0x33]);
+        config.devices[0].engine = InsteonEngine::I1;
+        let expected = config.devices[0].links.clone();
+        let emulator = Emulator::spawn(config).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        modem.set_timeout(Duration::from_millis(500));
+
+        // i1 devices ignore extended messages, so their links are peeked.
+        let links: Vec<DeviceLinkRecord> = modem.read_device_links(device).await.unwrap().collect();
+        assert_eq!(links, expected);
+    }
+
+    #[async_std::test]
+    async fn send_x10() {
+        assume_modem!();
+
+        MODEM
+            .lock()
+            .await
+            .send_x10(X10House::new('A').unwrap(), 1, X10Command::On)
+            .await
+            .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn send_x10_invalid_unit() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        let house = X10House::new('A').unwrap();
+
+        assert_eq!(
+            modem.send_x10(house, 17, X10Command::On).await,
+            Err(Error::InvalidX10Unit(17))
+        );
+        // The modem is still usable afterwards.
+        modem.get_info().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn emulated_devices() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        let dimmer = Address::from([0x11, 0x22, 0x33]);
+
+        modem
+            .send_message((dimmer, Command::On, Command::from(0x80)).into())
+            .await
+            .unwrap();
+        assert_eq!(modem.get_level(dimmer).await.unwrap(), 0x80);
+
+        modem
+            .send_message((dimmer, Command::Off).into())
+            .await
+            .unwrap();
+        assert_eq!(modem.get_level(dimmer).await.unwrap(), 0);
+    }
+
+    #[async_std::test]
+    async fn dump_memory() {
+        assume_modem!();
+        assume_emulator!();
+
+        let dump = MODEM
+            .lock()
+            .await
+            .dump_memory(MODEM_ALDB_START..0x4000)
+            .await
+            .unwrap();
+        assert!(!dump.reconstructed);
+        assert_eq!(
+            dump.records,
+            vec![MemoryRecord {
+                address: MODEM_ALDB_START,
+                data: [0xc2, 0x01, 0x11, 0x22, 0x33, 0x01, 0x20, 0x41],
+            }]
+        );
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn dump_memory_rebuilt() {
+        // A modem that can't read its memory has it rebuilt from its links.
+        let emulator = Emulator::spawn(EmulatorConfig {
+            reads_memory: false,
+            ..EmulatorConfig::default()
+        })
+        .unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        let dump = modem
+            .dump_memory(MODEM_ALDB_START - MEMORY_RECORD_SIZE..0x4000)
+            .await
+            .unwrap();
+        assert!(dump.reconstructed);
+        assert_eq!(
+            dump.records,
+            vec![MemoryRecord {
+                address: MODEM_ALDB_START,
+                data: [0xc2, 0x01, 0x11, 0x22, 0x33, 0x01, 0x20, 0x41],
+            }]
+        );
+    }
+
+    #[async_std::test]
+    async fn max_hops() {
+        let mock = crate::testing::MockModem::new();
+        let mut modem = Modem::new(mock.clone());
+        let switch = Address::from([0x22, 0x33, 0x44]);
+
+        assert_eq!(modem.set_max_hops(4), Err(Error::InvalidMaxHops(4)));
+        assert_eq!(
+            modem.set_device_max_hops(switch, Some(7)),
+            Err(Error::InvalidMaxHops(7))
+        );
+        modem.set_max_hops(2).unwrap();
+        modem.set_device_max_hops(switch, Some(1)).unwrap();
+        assert_eq!(modem.max_hops(switch), 1);
+
+        // Only messages without their own hop count follow the settings.
+        let message: Message = (switch, Command::On).into();
+        modem.send_message(message).await.unwrap();
+        modem.send_message(message.with_max_hops(3)).await.unwrap();
+        let hops: Vec<u8> = mock
+            .sent()
+            .into_iter()
+            .filter_map(|frame| match frame {
+                Frame::StandardInsteonSend { max_hops, .. } => Some(max_hops),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(hops, vec![1, 3]);
+    }
+
     #[test]
     fn bad_path() {
         assert!(Modem::from_path("/this/does/not/exist").is_err());
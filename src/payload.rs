@@ -0,0 +1,157 @@
+use crate::aldb::*;
+use crate::frame::*;
+use crate::message::*;
+
+const PRODUCT_DATA_RESPONSE: u8 = 0x03;
+const EXTENDED_SET_GET: u8 = 0x2e;
+const THERMOSTAT_STATUS: u8 = 0x02;
+const SET_GET_RESPONSE: u8 = 0x01;
+
+/// The data carried by a received extended [Message], classified by its
+/// commands and first data bytes. See [Message::payload].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExtendedPayload {
+    /// A record from the device's link database, in reply to a read.
+    AldbRecordResponse(DeviceLinkRecord),
+    /// The device's product key and category, in reply to a product data
+    /// request.
+    ProductData {
+        product_key: [u8; 3],
+        category: u8,
+        sub_category: u8,
+    },
+    /// The state of a thermostat, in reply to a status request.
+    ThermostatStatus {
+        /// Day of the week, 0 is Sunday.
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+        /// The system mode in the high nibble and fan mode in the low one.
+        mode: u8,
+        cool_set_point: u8,
+        humidity: u8,
+        /// The current temperature, in tenths of a degree Celsius.
+        temperature: u16,
+        status: u8,
+        heat_set_point: u8,
+    },
+    /// A reply to an extended get, e.g. a keypad's button settings.
+    SetGetResponse {
+        /// The button or group the settings are for.
+        button: u8,
+        /// Data bytes 3 through 14.
+        data: [u8; 12],
+    },
+    /// Anything else, as the 14 data bytes.
+    Raw([u8; 14]),
+}
+
+impl ExtendedPayload {
+    fn classify(message: &Message) -> ExtendedPayload {
+        if let Some(record) = DeviceLinkRecord::from_response(message) {
+            return ExtendedPayload::AldbRecordResponse(record);
+        }
+
+        let data = &message.data;
+        match (
+            u8::from(message.cmd1),
+            u8::from(message.cmd2),
+            data[0],
+            data[1],
+        ) {
+            (PRODUCT_DATA_RESPONSE, 0x00, _, _) => ExtendedPayload::ProductData {
+                product_key: [data[1], data[2], data[3]],
+                category: data[4],
+                sub_category: data[5],
+            },
+            (EXTENDED_SET_GET, THERMOSTAT_STATUS, 0x01, _) => ExtendedPayload::ThermostatStatus {
+                day: data[1],
+                hour: data[2],
+                minute: data[3],
+                second: data[4],
+                mode: data[5],
+                cool_set_point: data[6],
+                humidity: data[7],
+                temperature: u16::from_be_bytes([data[8], data[9]]),
+                status: data[10],
+                heat_set_point: data[11],
+            },
+            (EXTENDED_SET_GET, 0x00, button, SET_GET_RESPONSE) => {
+                let mut response = [0u8; 12];
+                response.copy_from_slice(&data[2..]);
+                ExtendedPayload::SetGetResponse {
+                    button,
+                    data: response,
+                }
+            }
+            _ => ExtendedPayload::Raw(*data),
+        }
+    }
+}
+
+impl Message {
+    /// Returns the structured contents of an extended `Message`, or `None`
+    /// for a standard one.
+    pub fn payload(&self) -> Option<ExtendedPayload> {
+        if self.flags.contains(MessageFlags::EXTENDED) {
+            Some(ExtendedPayload::classify(self))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        let standard = Message::from_hex("0250112233445566270300").unwrap();
+        assert_eq!(standard.payload(), None);
+
+        let product =
+            Message::from_hex("025111223344556617030000000100440eff00000000000000").unwrap();
+        assert_eq!(
+            product.payload(),
+            Some(ExtendedPayload::ProductData {
+                product_key: [0x00, 0x01, 0x00],
+                category: 0x44,
+                sub_category: 0x0e,
+            })
+        );
+
+        let record =
+            Message::from_hex("02511122334455661f2f0000010fff00e201445566ff1c0100").unwrap();
+        assert!(matches!(
+            record.payload(),
+            Some(ExtendedPayload::AldbRecordResponse(DeviceLinkRecord {
+                offset: 0x0fff,
+                group: 1,
+                ..
+            }))
+        ));
+
+        let thermostat =
+            Message::from_hex("02511122334455661f2e0201030c1e00114c2800d201520000").unwrap();
+        assert!(matches!(
+            thermostat.payload(),
+            Some(ExtendedPayload::ThermostatStatus {
+                temperature: 0xd2,
+                heat_set_point: 0x52,
+                ..
+            })
+        ));
+
+        let get = Message::from_hex("02511122334455661f2e000101000000001c7f000000000000").unwrap();
+        assert!(matches!(
+            get.payload(),
+            Some(ExtendedPayload::SetGetResponse { button: 1, .. })
+        ));
+
+        let other =
+            Message::from_hex("02511122334455661f2e030000000000000000000000000000").unwrap();
+        assert!(matches!(other.payload(), Some(ExtendedPayload::Raw(_))));
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+#[cfg(feature = "persist")]
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+use crate::error::*;
+use crate::frame::*;
+use crate::message::*;
+use crate::modem::*;
+
+/// Holds [Message]s for battery powered devices, which sleep most of the
+/// time and only listen briefly after they send something. Queued messages
+/// are delivered with [Modem::deliver_pending] once the device wakes up.
+///
+/// With the `persist` feature, the queue can be kept in a JSON file with
+/// [PendingCommands::open], so it survives a restart even if the device
+/// doesn't wake up for days.
+#[derive(Debug, Default)]
+pub struct PendingCommands {
+    queues: HashMap<Address, Vec<Message>>,
+    #[cfg(feature = "persist")]
+    path: Option<PathBuf>,
+}
+
+impl PendingCommands {
+    /// Constructs an empty, in-memory `PendingCommands`.
+    pub fn new() -> Self {
+        PendingCommands::default()
+    }
+
+    /// Loads the queue stored at `path`, if there is one. Every change is
+    /// written back to the same file.
+    #[cfg(feature = "persist")]
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut pending = PendingCommands {
+            queues: HashMap::new(),
+            path: None,
+        };
+
+        if path.exists() {
+            let raw: HashMap<String, Vec<serde_json::Value>> =
+                serde_json::from_str(&std::fs::read_to_string(&path)?)
+                    .map_err(|e| Error::InvalidStore(e.to_string()))?;
+
+            for (address, messages) in raw {
+                let address: Address = address.parse()?;
+                let queue = pending.queues.entry(address).or_default();
+                for message in messages {
+                    queue.push(load_message(&message)?);
+                }
+            }
+        }
+
+        pending.path = Some(path);
+        Ok(pending)
+    }
+
+    /// Queues `message` for delivery the next time its target wakes up.
+    pub fn push(&mut self, message: Message) -> Result<(), Error> {
+        self.queues.entry(message.to).or_default().push(message);
+        self.save()
+    }
+
+    /// Returns the messages waiting for the device at `address`.
+    pub fn get(&self, address: Address) -> &[Message] {
+        self.queues
+            .get(&address)
+            .map(|queue| &queue[..])
+            .unwrap_or(&[])
+    }
+
+    /// Returns true if any messages are waiting for the device at `address`.
+    pub fn contains(&self, address: Address) -> bool {
+        !self.get(address).is_empty()
+    }
+
+    /// Returns the total number of messages waiting.
+    pub fn len(&self) -> usize {
+        self.queues.values().map(Vec::len).sum()
+    }
+
+    /// Returns true if no messages are waiting.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes and returns every message waiting for the device at `address`.
+    pub fn take(&mut self, address: Address) -> Result<Vec<Message>, Error> {
+        let messages = self.queues.remove(&address).unwrap_or_default();
+        if !messages.is_empty() {
+            self.save()?;
+        }
+        Ok(messages)
+    }
+
+    #[cfg(feature = "persist")]
+    fn save(&self) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let raw: HashMap<String, Vec<serde_json::Value>> = self
+            .queues
+            .iter()
+            .filter(|(_, queue)| !queue.is_empty())
+            .map(|(address, queue)| {
+                (
+                    address.to_string(),
+                    queue.iter().map(save_message).collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        let json =
+            serde_json::to_string_pretty(&raw).map_err(|e| Error::InvalidStore(e.to_string()))?;
+
+        // Write a new file and move it into place, so a crash can't leave
+        // a half written queue behind.
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, json)?;
+        std::fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "persist"))]
+    fn save(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Stores a message as the hex of its frame. The frame always carries a hop
+/// count, so a message with its own is stored along with the
+/// [hops_explicit](Message::hops_explicit) flag.
+#[cfg(feature = "persist")]
+fn save_message(message: &Message) -> serde_json::Value {
+    if message.hops_explicit {
+        serde_json::json!({ "frame": message.to_hex(), "hops_explicit": true })
+    } else {
+        serde_json::Value::String(message.to_hex())
+    }
+}
+
+#[cfg(feature = "persist")]
+fn load_message(value: &serde_json::Value) -> Result<Message, Error> {
+    let (hex, hops_explicit) = match value {
+        serde_json::Value::String(hex) => (hex.as_str(), false),
+        value => (
+            value["frame"]
+                .as_str()
+                .ok_or_else(|| Error::InvalidStore(format!("Bad message {}", value)))?,
+            value["hops_explicit"].as_bool().unwrap_or(false),
+        ),
+    };
+
+    let mut message = Message::from_hex(hex)?;
+    message.hops_explicit = hops_explicit;
+    Ok(message)
+}
+
+impl Modem {
+    /// Delivers the messages queued in `pending` for the device at
+    /// `address`. Call this as soon as a message from a sleeping device is
+    /// seen on [Modem::listen], while it is still awake.
+    ///
+    /// Messages that can't be delivered are put back in the queue. Returns
+    /// the number of messages delivered.
+    pub async fn deliver_pending(
+        &mut self,
+        pending: &mut PendingCommands,
+        address: Address,
+    ) -> Result<usize, Error> {
+        let mut messages = pending.take(address)?.into_iter();
+        let mut delivered = 0;
+
+        while let Some(message) = messages.next() {
+            if let Err(e) = self.send_message(message).await {
+                warn!("Failed to deliver pending message to {}: {}", address, e);
+                pending.push(message)?;
+                for message in messages {
+                    pending.push(message)?;
+                }
+                return Err(e);
+            }
+            delivered += 1;
+        }
+
+        debug!("Delivered {} pending messages to {}", delivered, address);
+        Ok(delivered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn queue() {
+        let address = Address::from_str("11.22.33").unwrap();
+        let mut pending = PendingCommands::new();
+        pending.push((address, Command::Beep).into()).unwrap();
+        pending.push((address, Command::Off).into()).unwrap();
+
+        assert!(pending.contains(address));
+        assert_eq!(pending.len(), 2);
+
+        let messages = pending.take(address).unwrap();
+        assert_eq!(messages[1].cmd1, Command::Off);
+        assert!(pending.is_empty());
+    }
+
+    #[cfg(feature = "persist")]
+    #[test]
+    fn persist() {
+        let path = std::env::temp_dir().join(format!("plm-pending-{}.json", std::process::id()));
+        let address = Address::from_str("11.22.33").unwrap();
+
+        let mut message: Message =
+            (address, Command::ExtendedSetGet, MessageFlags::EXTENDED).into();
+        message.data[1] = 0x05;
+
+        let mut pending = PendingCommands::open(&path).unwrap();
+        pending.push(message).unwrap();
+        pending.push(message.with_max_hops(1)).unwrap();
+
+        let pending = PendingCommands::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            pending.get(address),
+            &[message, message.with_max_hops(1)][..]
+        );
+    }
+}
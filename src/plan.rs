@@ -0,0 +1,531 @@
+//! Declarative management of the links stored in the modem.
+//!
+//! A [Plan] describes the desired network in TOML: the devices it owns,
+//! optional aliases for them, plain links between the modem and a device,
+//! and modem-controlled scenes where each member has its own level, and
+//! optionally its own ramp time and button.
+//!
+//! ```toml
+//! [[device]]
+//! address = "11.22.33"
+//! alias = "porch"
+//!
+//! [[device]]
+//! address = "44.55.66"
+//!
+//! [[link]]
+//! device = "porch"
+//! group = 1
+//! mode = "responder"
+//!
+//! [[scene]]
+//! group = 20
+//! members = [
+//!     { device = "porch", level = 255 },
+//!     { device = "44.55.66", level = 128, ramp = "2s" },
+//! ]
+//! ```
+//!
+//! [Plan::diff] compares the plan against the modem's link database and
+//! those of the linked devices, read with [Plan::read_state], and
+//! [Plan::apply] makes the changes. Only links involving a device listed in
+//! the plan are ever removed.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use log::debug;
+
+use serde::Deserialize;
+
+use crate::aldb::*;
+use crate::error::*;
+use crate::frame::*;
+use crate::modem::*;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPlan {
+    #[serde(default, rename = "device")]
+    devices: Vec<RawDevice>,
+    #[serde(default, rename = "link")]
+    links: Vec<RawLink>,
+    #[serde(default, rename = "scene")]
+    scenes: Vec<RawScene>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawDevice {
+    address: String,
+    alias: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawLink {
+    device: String,
+    group: u8,
+    mode: RawMode,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RawMode {
+    Controller,
+    Responder,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawScene {
+    group: u8,
+    members: Vec<RawMember>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawMember {
+    device: String,
+    level: u8,
+    ramp: Option<String>,
+    button: Option<u8>,
+}
+
+/// A device owned by a [Plan].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanDevice {
+    pub address: Address,
+    pub alias: Option<String>,
+}
+
+/// A single link the [Plan] wants present in the modem.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanLink {
+    /// The device on the other end of the link.
+    pub address: Address,
+    /// The group number of the link.
+    pub group: u8,
+    /// How the modem is linked, either [AllLinkMode::Controller] or
+    /// [AllLinkMode::Responder].
+    pub mode: AllLinkMode,
+    /// For scene members, how the device responds to the scene.
+    pub responder: Option<ResponderData>,
+}
+
+impl PlanLink {
+    fn matches(&self, record: &AllLinkRecord) -> bool {
+        self.address == record.to
+            && self.group == record.group
+            && record.flags.contains(AllLinkFlags::IS_CONTROLLER)
+                == (self.mode == AllLinkMode::Controller)
+    }
+
+    /// Whether `record`, in the device's link database, is the device's
+    /// side of this link with the modem at `modem`.
+    fn matches_device(&self, modem: Address, record: &DeviceLinkRecord) -> bool {
+        record.is_in_use()
+            && record.address == modem
+            && record.group == self.group
+            && record.is_controller() == (self.mode == AllLinkMode::Responder)
+    }
+}
+
+/// A change needed to bring the modem in line with a [Plan].
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlanChange {
+    /// The link is missing and will be created.
+    Add(PlanLink),
+    /// The link is not in the plan and will be deleted.
+    Remove(AllLinkRecord),
+    /// The link exists, but the device responds differently than planned,
+    /// e.g. at another level or ramp rate. `old` is what the device has.
+    Modify { link: PlanLink, old: ResponderData },
+}
+
+impl fmt::Display for PlanChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanChange::Add(link) => {
+                write!(
+                    f,
+                    "+ {} group {} as {}",
+                    link.address, link.group, link.mode
+                )?;
+                if let Some(responder) = link.responder {
+                    write!(f, " with {}", responder)?;
+                }
+                Ok(())
+            }
+            PlanChange::Remove(record) => {
+                let mode = if record.flags.contains(AllLinkFlags::IS_CONTROLLER) {
+                    AllLinkMode::Controller
+                } else {
+                    AllLinkMode::Responder
+                };
+                write!(f, "- {} group {} as {}", record.to, record.group, mode)
+            }
+            PlanChange::Modify { link, old } => {
+                write!(
+                    f,
+                    "~ {} group {} as {} from {}",
+                    link.address, link.group, link.mode, old
+                )?;
+                if let Some(responder) = link.responder {
+                    write!(f, " to {}", responder)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The links a [Plan] is compared against: the modem's link database and
+/// those of the devices the plan links to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlanState {
+    /// The modem's own address, which the devices' records point at.
+    pub modem: Address,
+    pub links: Vec<AllLinkRecord>,
+    /// Each device's link database. Devices that aren't here are only
+    /// checked against the modem's side of their links.
+    pub device_links: HashMap<Address, Vec<DeviceLinkRecord>>,
+}
+
+/// A declarative description of the desired modem links.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub devices: Vec<PlanDevice>,
+    pub links: Vec<PlanLink>,
+}
+
+impl Plan {
+    /// Loads a [Plan] from a TOML file.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Plan, Error> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Looks up a device by alias or address string.
+    pub fn resolve(&self, name: &str) -> Result<Address, Error> {
+        self.devices
+            .iter()
+            .find(|device| device.alias.as_deref() == Some(name))
+            .map(|device| Ok(device.address))
+            .unwrap_or_else(|| Address::from_str(name))
+    }
+
+    /// Reads the modem's link database and that of every device the plan
+    /// links to, for [Plan::diff].
+    pub async fn read_state(&self, modem: &mut Modem) -> Result<PlanState, Error> {
+        let mut state = PlanState {
+            modem: modem.get_info().await?.address,
+            links: modem.get_links().await?.collect(),
+            device_links: HashMap::new(),
+        };
+
+        for link in &self.links {
+            if let Entry::Vacant(entry) = state.device_links.entry(link.address) {
+                entry.insert(modem.read_device_links(link.address).await?.collect());
+            }
+        }
+
+        Ok(state)
+    }
+
+    /// Computes the changes needed to turn `actual` into the links
+    /// described by this plan. A link is added again when the device's
+    /// side of it is missing, and modified when the device responds with
+    /// other data than planned.
+    pub fn diff(&self, actual: &PlanState) -> Vec<PlanChange> {
+        let mut changes: Vec<PlanChange> = actual
+            .links
+            .iter()
+            .filter(|record| self.devices.iter().any(|d| d.address == record.to))
+            .filter(|record| !self.links.iter().any(|link| link.matches(record)))
+            .cloned()
+            .map(PlanChange::Remove)
+            .collect();
+
+        for link in &self.links {
+            if !actual.links.iter().any(|record| link.matches(record)) {
+                changes.push(PlanChange::Add(link.clone()));
+                continue;
+            }
+
+            let device_links = match actual.device_links.get(&link.address) {
+                Some(device_links) => device_links,
+                None => continue,
+            };
+            match device_links
+                .iter()
+                .find(|record| link.matches_device(actual.modem, record))
+            {
+                None => changes.push(PlanChange::Add(link.clone())),
+                Some(record) => match link.responder {
+                    Some(responder) if <[u8; 3]>::from(responder) != record.data => {
+                        changes.push(PlanChange::Modify {
+                            link: link.clone(),
+                            old: record.data.into(),
+                        })
+                    }
+                    _ => {}
+                },
+            }
+        }
+
+        changes
+    }
+
+    /// Reads the link databases with [Plan::read_state] and applies every
+    /// change from [Plan::diff], writing both sides of each link directly
+    /// like [Modem::link_programmatically]. Plain links respond at full
+    /// brightness and the default ramp rate. Returns the changes that
+    /// were made.
+    pub async fn apply(&self, modem: &mut Modem) -> Result<Vec<PlanChange>, Error> {
+        let actual = self.read_state(modem).await?;
+        let changes = self.diff(&actual);
+
+        for change in &changes {
+            debug!("Applying {}", change);
+            match change {
+                PlanChange::Add(link) | PlanChange::Modify { link, .. } => {
+                    let responder = link.responder.unwrap_or_else(|| ResponderData::new(0xff));
+                    modem
+                        .link_programmatically(
+                            link.address,
+                            link.group,
+                            link.mode,
+                            responder.into(),
+                        )
+                        .await?
+                }
+                PlanChange::Remove(record) => {
+                    modem
+                        .delete_device_link(record.to, actual.modem, record.group)
+                        .await?;
+                    modem.delete_link(record.to, record.group).await?;
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+impl FromStr for Plan {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw: RawPlan = toml::from_str(s).map_err(|e| Error::InvalidPlan(e.to_string()))?;
+
+        let mut plan = Plan {
+            devices: Vec::with_capacity(raw.devices.len()),
+            links: Vec::new(),
+        };
+
+        for device in raw.devices {
+            plan.devices.push(PlanDevice {
+                address: Address::from_str(&device.address)?,
+                alias: device.alias,
+            });
+        }
+
+        for link in raw.links {
+            plan.links.push(PlanLink {
+                address: plan.resolve(&link.device)?,
+                group: link.group,
+                mode: match link.mode {
+                    RawMode::Controller => AllLinkMode::Controller,
+                    RawMode::Responder => AllLinkMode::Responder,
+                },
+                responder: None,
+            });
+        }
+
+        for scene in raw.scenes {
+            for member in scene.members {
+                let mut responder = ResponderData::new(member.level);
+                if let Some(ramp) = member.ramp {
+                    let ramp = humantime::parse_duration(&ramp)
+                        .map_err(|e| Error::InvalidPlan(format!("ramp \"{}\": {}", ramp, e)))?;
+                    responder.ramp = ramp_rate(ramp);
+                }
+                if let Some(button) = member.button {
+                    responder.button = button;
+                }
+
+                plan.links.push(PlanLink {
+                    address: plan.resolve(&member.device)?,
+                    group: scene.group,
+                    mode: AllLinkMode::Controller,
+                    responder: Some(responder),
+                });
+            }
+        }
+
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAN: &str = r#"
+        [[device]]
+        address = "11.22.33"
+        alias = "porch"
+
+        [[device]]
+        address = "44.55.66"
+
+        [[link]]
+        device = "porch"
+        group = 1
+        mode = "responder"
+
+        [[scene]]
+        group = 20
+        members = [
+            { device = "porch", level = 255 },
+            { device = "44.55.66", level = 128, ramp = "2s" },
+        ]
+    "#;
+
+    fn record(to: &str, group: u8, controller: bool) -> AllLinkRecord {
+        let mut flags = AllLinkFlags::IN_USE;
+        if controller {
+            flags |= AllLinkFlags::IS_CONTROLLER;
+        }
+
+        AllLinkRecord {
+            flags,
+            group,
+            to: Address::from_str(to).unwrap(),
+            data: [0u8; 3],
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let plan = Plan::from_str(PLAN).unwrap();
+        let porch = Address::from_str("11.22.33").unwrap();
+
+        assert_eq!(plan.devices.len(), 2);
+        assert_eq!(plan.resolve("porch"), Ok(porch));
+        assert_eq!(
+            plan.links[0],
+            PlanLink {
+                address: porch,
+                group: 1,
+                mode: AllLinkMode::Responder,
+                responder: None,
+            }
+        );
+        assert_eq!(plan.links.len(), 3);
+    }
+
+    #[test]
+    fn parse_unknown_alias() {
+        let plan = "[[link]]\ndevice = \"garage\"\ngroup = 1\nmode = \"controller\"";
+        assert_eq!(Plan::from_str(plan), Err(Error::InvalidAddress));
+    }
+
+    fn device_record(group: u8, controller: bool, data: [u8; 3]) -> DeviceLinkRecord {
+        let mut flags = AllLinkFlags::IN_USE;
+        if controller {
+            flags |= AllLinkFlags::IS_CONTROLLER;
+        }
+
+        DeviceLinkRecord {
+            offset: ALDB_START,
+            flags,
+            group,
+            address: Address::from_str("aa.bb.cc").unwrap(),
+            data,
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let plan = Plan::from_str(PLAN).unwrap();
+        let porch = Address::from_str("11.22.33").unwrap();
+        let mut actual = PlanState {
+            modem: Address::from_str("aa.bb.cc").unwrap(),
+            links: vec![
+                record("11.22.33", 1, false),
+                record("11.22.33", 20, true),
+                record("44.55.66", 2, true),
+                record("44.55.66", 20, true),
+                record("77.88.99", 3, true),
+            ],
+            device_links: HashMap::new(),
+        };
+        actual.device_links.insert(
+            porch,
+            vec![
+                device_record(1, true, [0, 0, 1]),
+                device_record(20, false, [0x80, DEFAULT_RAMP_RATE, 1]),
+            ],
+        );
+        actual
+            .device_links
+            .insert(Address::from_str("44.55.66").unwrap(), Vec::new());
+
+        assert_eq!(
+            plan.diff(&actual),
+            vec![
+                PlanChange::Remove(record("44.55.66", 2, true)),
+                PlanChange::Modify {
+                    link: plan.links[1].clone(),
+                    old: ResponderData::new(0x80),
+                },
+                PlanChange::Add(PlanLink {
+                    address: Address::from_str("44.55.66").unwrap(),
+                    group: 20,
+                    mode: AllLinkMode::Controller,
+                    responder: Some(ResponderData {
+                        level: 128,
+                        ramp: 0x1b,
+                        button: 1,
+                    }),
+                }),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn apply() {
+        use crate::emulator::*;
+
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        let plan = Plan::from_str(
+            r#"
+            [[device]]
+            address = "11.22.33"
+
+            [[link]]
+            device = "11.22.33"
+            group = 1
+            mode = "responder"
+
+            [[scene]]
+            group = 1
+            members = [{ device = "11.22.33", level = 128 }]
+        "#,
+        )
+        .unwrap();
+
+        let changes = plan.apply(&mut modem).await.unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(changes[0], PlanChange::Modify { .. }));
+
+        let state = plan.read_state(&mut modem).await.unwrap();
+        assert_eq!(plan.diff(&state), Vec::new());
+    }
+}
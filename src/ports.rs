@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+
+use tokio_serial::SerialPortType;
+
+use crate::error::*;
+
+// The FTDI USB serial converter used by the 2413U PowerLinc Modem.
+const FTDI_VENDOR_ID: u16 = 0x0403;
+const FTDI_FT232R_PRODUCT_ID: u16 = 0x6001;
+
+/// A serial port found by [available_ports].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortInfo {
+    /// What to pass to [Modem::from_path](super::Modem::from_path), e.g.
+    /// `/dev/ttyUSB0` or `COM3`.
+    pub path: PathBuf,
+    /// The USB vendor and product IDs, if this is a USB port.
+    pub usb_id: Option<(u16, u16)>,
+    /// The product name reported by a USB port.
+    pub product: Option<String>,
+}
+
+impl PortInfo {
+    /// Returns true if the port looks like a USB PowerLinc Modem. Serial
+    /// modems attached through other adapters can't be told apart from
+    /// any other port.
+    pub fn is_likely_modem(&self) -> bool {
+        self.usb_id == Some((FTDI_VENDOR_ID, FTDI_FT232R_PRODUCT_ID))
+    }
+}
+
+/// Lists the serial ports on this machine that might have a modem
+/// attached, with the most likely ones first.
+pub fn available_ports() -> Result<Vec<PortInfo>, Error> {
+    let mut ports: Vec<PortInfo> = tokio_serial::available_ports()
+        .map_err(std::io::Error::from)?
+        .into_iter()
+        .map(|port| {
+            let (usb_id, product) = match port.port_type {
+                SerialPortType::UsbPort(usb) => (Some((usb.vid, usb.pid)), usb.product),
+                _ => (None, None),
+            };
+
+            PortInfo {
+                path: PathBuf::from(port.port_name),
+                usb_id,
+                product,
+            }
+        })
+        .collect();
+
+    ports.sort_by_key(|port| !port.is_likely_modem());
+    Ok(ports)
+}
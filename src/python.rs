@@ -0,0 +1,270 @@
+//! Python bindings, built as an extension module named `plm`.
+//!
+//! Build them with [maturin](https://github.com/PyO3/maturin), which picks
+//! up the settings in `pyproject.toml`:
+//!
+//! ```text
+//! $ maturin develop --release
+//! >>> import plm
+//! >>> modem = plm.Modem("/dev/ttyUSB0")
+//! >>> modem.on("11.22.33")
+//! >>> for message in modem.listen():
+//! ...     print(message)
+//! ```
+//!
+//! Every call blocks until the modem responds, with the GIL released.
+
+use std::convert::TryFrom;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use futures::{executor::block_on, Stream, StreamExt};
+use pyo3::{
+    basic::CompareOp, create_exception, exceptions::PyException, prelude::*, types::PyBytes,
+};
+
+use crate::error::*;
+use crate::frame::*;
+use crate::message::*;
+use crate::modem::*;
+
+create_exception!(plm, PlmError, PyException);
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        PlmError::new_err(e.to_string())
+    }
+}
+
+/// An INSTEON device address, e.g. `Address("11.22.33")`.
+#[pyclass(name = "Address")]
+#[derive(Clone, Copy)]
+struct PyAddress(Address);
+
+#[pymethods]
+impl PyAddress {
+    #[new]
+    fn new(address: &str) -> PyResult<Self> {
+        Ok(PyAddress(Address::from_str(address)?))
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Address('{}')", self.0)
+    }
+
+    fn __richcmp__(&self, other: &Self, op: CompareOp, py: Python<'_>) -> PyObject {
+        match op {
+            CompareOp::Eq => (self.0 == other.0).into_py(py),
+            CompareOp::Ne => (self.0 != other.0).into_py(py),
+            _ => py.NotImplemented(),
+        }
+    }
+
+    fn __hash__(&self) -> u64 {
+        let [a, b, c] = <[u8; 3]>::from(self.0);
+        u64::from_be_bytes([0, 0, 0, 0, 0, a, b, c])
+    }
+}
+
+/// Addresses can be passed either as an `Address` or a string.
+#[derive(FromPyObject)]
+enum AddressArg {
+    Address(PyAddress),
+    String(String),
+}
+
+impl TryFrom<AddressArg> for Address {
+    type Error = Error;
+
+    fn try_from(address: AddressArg) -> Result<Self, Error> {
+        match address {
+            AddressArg::Address(address) => Ok(address.0),
+            AddressArg::String(address) => Address::from_str(&address),
+        }
+    }
+}
+
+/// A message to or from an INSTEON device.
+#[pyclass(name = "Message")]
+#[derive(Clone)]
+struct PyMessage(Message);
+
+#[pymethods]
+impl PyMessage {
+    /// Builds a message for the device at `to`. Passing `data` makes it an
+    /// extended message.
+    #[new]
+    #[pyo3(signature = (to, cmd1, cmd2 = 0, data = None))]
+    fn new(to: AddressArg, cmd1: u8, cmd2: u8, data: Option<[u8; 14]>) -> PyResult<Self> {
+        let mut message: Message = (
+            Address::try_from(to)?,
+            Command::from(cmd1),
+            Command::from(cmd2),
+        )
+            .into();
+        if let Some(data) = data {
+            message.flags |= MessageFlags::EXTENDED;
+            message.data = data;
+        }
+        Ok(PyMessage(message))
+    }
+
+    #[getter]
+    fn sender(&self) -> PyAddress {
+        PyAddress(self.0.from)
+    }
+
+    #[getter]
+    fn to(&self) -> PyAddress {
+        PyAddress(self.0.to)
+    }
+
+    #[getter]
+    fn flags(&self) -> u8 {
+        self.0.flags.bits()
+    }
+
+    #[getter]
+    fn cmd1(&self) -> u8 {
+        self.0.cmd1.into()
+    }
+
+    #[getter]
+    fn cmd2(&self) -> u8 {
+        self.0.cmd2.into()
+    }
+
+    #[getter]
+    fn data<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.0.data)
+    }
+
+    #[getter]
+    fn group(&self) -> Option<u8> {
+        self.0.group()
+    }
+
+    #[getter]
+    fn hops_remaining(&self) -> u8 {
+        self.0.hops_remaining
+    }
+
+    #[getter]
+    fn max_hops(&self) -> u8 {
+        self.0.max_hops
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Message({} -> {}, {}, {:02x})",
+            self.0.from,
+            self.0.to,
+            self.0.cmd1,
+            u8::from(self.0.cmd2)
+        )
+    }
+}
+
+/// The [Message]s received by a [Modem], as a blocking iterator.
+#[pyclass(name = "MessageStream")]
+struct PyMessageStream(Mutex<Pin<Box<dyn Stream<Item = Message> + Send>>>);
+
+#[pymethods]
+impl PyMessageStream {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> Option<PyMessage> {
+        py.allow_threads(|| block_on(self.0.lock().unwrap().next()))
+            .map(PyMessage)
+    }
+}
+
+/// A PowerLinc Modem attached to a serial port, e.g. `Modem("/dev/ttyUSB0")`.
+#[pyclass(name = "Modem")]
+struct PyModem(Mutex<Modem>);
+
+impl PyModem {
+    fn run<T: Send>(
+        &self,
+        py: Python<'_>,
+        f: impl FnOnce(&mut Modem) -> Result<T, Error> + Send,
+    ) -> PyResult<T> {
+        Ok(py.allow_threads(|| f(&mut self.0.lock().unwrap()))?)
+    }
+}
+
+#[pymethods]
+impl PyModem {
+    #[new]
+    fn new(path: String) -> PyResult<Self> {
+        let modem = Modem::from_path(path).map_err(|e| PlmError::new_err(e.to_string()))?;
+        Ok(PyModem(Mutex::new(modem)))
+    }
+
+    /// Returns the modem's address, category, sub-category and firmware
+    /// version.
+    fn info(&self, py: Python<'_>) -> PyResult<(PyAddress, u8, u8, u8)> {
+        let info = self.run(py, |modem| block_on(modem.get_info()))?;
+        Ok((
+            PyAddress(info.address),
+            info.category,
+            info.sub_category,
+            info.firmware_version,
+        ))
+    }
+
+    /// Sends `message` and returns the device's acknowledgement.
+    fn send(&self, py: Python<'_>, message: PyMessage) -> PyResult<PyMessage> {
+        self.run(py, |modem| block_on(modem.send_message(message.0)))
+            .map(PyMessage)
+    }
+
+    /// Turns the device at `address` on, to `level` from 0 to 255.
+    #[pyo3(signature = (address, level = 0xff))]
+    fn on(&self, py: Python<'_>, address: AddressArg, level: u8) -> PyResult<()> {
+        let message = (
+            Address::try_from(address)?,
+            Command::On,
+            Command::from(level),
+        )
+            .into();
+        self.run(py, |modem| block_on(modem.send_message(message)))?;
+        Ok(())
+    }
+
+    /// Turns the device at `address` off.
+    fn off(&self, py: Python<'_>, address: AddressArg) -> PyResult<()> {
+        let message = (Address::try_from(address)?, Command::Off).into();
+        self.run(py, |modem| block_on(modem.send_message(message)))?;
+        Ok(())
+    }
+
+    /// Returns the level of the device at `address`, from 0 to 255.
+    fn level(&self, py: Python<'_>, address: AddressArg) -> PyResult<u8> {
+        let address = Address::try_from(address)?;
+        self.run(py, |modem| block_on(modem.get_level(address)))
+    }
+
+    /// Returns an iterator over every message received from now on.
+    fn listen(&self, py: Python<'_>) -> PyResult<PyMessageStream> {
+        let stream = self.run(py, |modem| block_on(modem.listen()))?;
+        Ok(PyMessageStream(Mutex::new(Box::pin(stream))))
+    }
+}
+
+#[pymodule]
+fn plm(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyAddress>()?;
+    m.add_class::<PyMessage>()?;
+    m.add_class::<PyMessageStream>()?;
+    m.add_class::<PyModem>()?;
+    m.add("PlmError", py.get_type::<PlmError>())?;
+    Ok(())
+}
@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures::{future::poll_fn, Stream};
+
+/// The number of frames that can wait for a listener, unless set with
+/// [ModemBuilder::listener_capacity](crate::ModemBuilder::listener_capacity).
+pub const DEFAULT_LISTENER_CAPACITY: usize = 256;
+
+/// What happens when a listener falls so far behind that its queue of
+/// frames is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Backpressure {
+    /// Discard the listener's oldest frame to make room.
+    #[default]
+    DropOldest,
+    /// Stop delivering to the listener, ending its stream.
+    DropListener,
+    /// Wait for the listener to catch up. Nothing else is received or sent
+    /// meanwhile, so one stalled listener holds up the whole modem.
+    Block,
+}
+
+/// What became of a frame handed to a [QueueSender].
+#[derive(Debug, PartialEq)]
+pub(crate) enum Delivery {
+    Queued,
+    /// Queued, but the oldest frame was discarded to make room.
+    DroppedOldest,
+    /// The listener fell behind and was dropped.
+    DroppedListener,
+    /// The listener's owner dropped it.
+    Closed,
+}
+
+struct Inner<T> {
+    frames: VecDeque<T>,
+    /// Waiting for a frame.
+    receiver: Option<Waker>,
+    /// Waiting for room.
+    sender: Option<Waker>,
+    sender_gone: bool,
+    receiver_gone: bool,
+}
+
+/// Returns the two ends of a queue holding up to `capacity` frames for a
+/// listener.
+pub(crate) fn queue<T>(capacity: usize) -> (QueueSender<T>, QueueReceiver<T>) {
+    let inner = Arc::new(Mutex::new(Inner {
+        frames: VecDeque::with_capacity(capacity.min(DEFAULT_LISTENER_CAPACITY)),
+        receiver: None,
+        sender: None,
+        sender_gone: false,
+        receiver_gone: false,
+    }));
+    (
+        QueueSender {
+            inner: inner.clone(),
+            capacity: capacity.max(1),
+        },
+        QueueReceiver { inner },
+    )
+}
+
+pub(crate) struct QueueSender<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+    capacity: usize,
+}
+
+impl<T> QueueSender<T> {
+    /// Queues `frame`, handling a full queue as `policy` says.
+    pub(crate) async fn send(&mut self, frame: T, policy: Backpressure) -> Delivery {
+        if policy == Backpressure::Block {
+            let capacity = self.capacity;
+            let inner = &self.inner;
+            poll_fn(|cx| {
+                let mut inner = inner.lock().unwrap();
+                if inner.receiver_gone || inner.frames.len() < capacity {
+                    Poll::Ready(())
+                } else {
+                    inner.sender = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            })
+            .await;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.receiver_gone {
+            return Delivery::Closed;
+        }
+
+        let mut delivery = Delivery::Queued;
+        if inner.frames.len() >= self.capacity {
+            match policy {
+                Backpressure::DropListener => {
+                    // The listener still gets what's already queued.
+                    inner.sender_gone = true;
+                    if let Some(waker) = inner.receiver.take() {
+                        waker.wake();
+                    }
+                    return Delivery::DroppedListener;
+                }
+                _ => {
+                    inner.frames.pop_front();
+                    delivery = Delivery::DroppedOldest;
+                }
+            }
+        }
+
+        inner.frames.push_back(frame);
+        if let Some(waker) = inner.receiver.take() {
+            waker.wake();
+        }
+        delivery
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.sender_gone = true;
+        if let Some(waker) = inner.receiver.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The listener's end of a [queue], delivering frames until the broker
+/// stops sending them.
+pub(crate) struct QueueReceiver<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+}
+
+impl<T> Stream for QueueReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(frame) = inner.frames.pop_front() {
+            if let Some(waker) = inner.sender.take() {
+                waker.wake();
+            }
+            Poll::Ready(Some(frame))
+        } else if inner.sender_gone {
+            Poll::Ready(None)
+        } else {
+            inner.receiver = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.receiver_gone = true;
+        inner.frames.clear();
+        if let Some(waker) = inner.sender.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::*;
+    use futures::StreamExt;
+
+    #[async_std::test]
+    async fn policies() {
+        let (mut sender, mut receiver) = queue(2);
+        assert_eq!(
+            sender
+                .send(Frame::GetModemInfo, Backpressure::DropOldest)
+                .await,
+            Delivery::Queued
+        );
+        sender.send(Frame::Reset, Backpressure::DropOldest).await;
+        assert_eq!(
+            sender
+                .send(Frame::CancelAllLink, Backpressure::DropOldest)
+                .await,
+            Delivery::DroppedOldest
+        );
+        assert_eq!(receiver.next().await, Some(Frame::Reset));
+        assert_eq!(receiver.next().await, Some(Frame::CancelAllLink));
+
+        sender
+            .send(Frame::GetModemInfo, Backpressure::DropListener)
+            .await;
+        sender
+            .send(Frame::GetModemInfo, Backpressure::DropListener)
+            .await;
+        assert_eq!(
+            sender
+                .send(Frame::GetModemInfo, Backpressure::DropListener)
+                .await,
+            Delivery::DroppedListener
+        );
+        assert_eq!(receiver.count().await, 2);
+    }
+
+    #[async_std::test]
+    async fn block() {
+        let (mut sender, mut receiver) = queue(1);
+        sender.send(Frame::GetModemInfo, Backpressure::Block).await;
+
+        {
+            let blocked = sender.send(Frame::Reset, Backpressure::Block);
+            futures::pin_mut!(blocked);
+            assert!(futures::poll!(blocked.as_mut()).is_pending());
+
+            assert_eq!(receiver.next().await, Some(Frame::GetModemInfo));
+            assert_eq!(blocked.await, Delivery::Queued);
+        }
+        drop(sender);
+        assert_eq!(receiver.next().await, Some(Frame::Reset));
+        assert_eq!(receiver.next().await, None);
+    }
+}
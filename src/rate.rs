@@ -0,0 +1,97 @@
+use std::time::{Duration, Instant};
+
+use crate::error::*;
+
+/// Limits how quickly commands are sent out over the powerline. Up to
+/// `burst` commands go out back to back, after which they are spaced to
+/// average `commands_per_second`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub commands_per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Returns [Error::InvalidRateLimit] unless `commands_per_second` is
+    /// above zero.
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        if self.commands_per_second > 0.0 {
+            Ok(())
+        } else {
+            Err(Error::InvalidRateLimit(self.commands_per_second))
+        }
+    }
+}
+
+/// A token bucket enforcing a [RateLimit].
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Returns a full bucket for `limit`, which must have passed
+    /// [RateLimit::check].
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            limit,
+            tokens: limit.burst.max(1) as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes a token at `now`. Returns how long to wait before sending
+    /// if none was available yet.
+    pub(crate) fn take(&mut self, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.updated = now;
+        self.tokens = (self.tokens + elapsed * self.limit.commands_per_second)
+            .min(self.limit.burst.max(1) as f64);
+
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                -self.tokens / self.limit.commands_per_second,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_wait() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            commands_per_second: 2.0,
+            burst: 2,
+        });
+        let now = bucket.updated;
+
+        assert_eq!(bucket.take(now), None);
+        assert_eq!(bucket.take(now), None);
+        assert_eq!(bucket.take(now), Some(Duration::from_millis(500)));
+        assert_eq!(bucket.take(now), Some(Duration::from_millis(1000)));
+
+        // Both waits have passed, and no more tokens were earned.
+        let later = now + Duration::from_millis(1000);
+        assert_eq!(bucket.take(later), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn check() {
+        let limit = |commands_per_second| RateLimit {
+            commands_per_second,
+            burst: 1,
+        };
+        assert!(limit(0.5).check().is_ok());
+        assert_eq!(limit(0.0).check(), Err(Error::InvalidRateLimit(0.0)));
+        assert_eq!(limit(-1.0).check(), Err(Error::InvalidRateLimit(-1.0)));
+        assert!(limit(f64::NAN).check().is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use log::debug;
+
+use crate::error::*;
+use crate::frame::*;
+use crate::message::*;
+use crate::modem::*;
+use crate::pending::*;
+
+/// What a button on a [MiniRemote] did.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RemoteAction {
+    On,
+    Off,
+    /// The button was double-tapped on.
+    OnFast,
+    /// The button was double-tapped off.
+    OffFast,
+    /// The button is being held, brightening or dimming.
+    StartChange(DimDirection),
+    /// The held button was released.
+    StopChange,
+}
+
+/// A button press on a [MiniRemote] or [Keypad](super::Keypad), as
+/// returned by [MiniRemote::decode].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RemoteEvent {
+    /// The button, numbered from 1.
+    pub button: u8,
+    pub action: RemoteAction,
+}
+
+/// A RemoteLinc or Mini Remote. Each button controls its own group, so
+/// button `n` broadcasts on group `n`.
+///
+/// Remotes sleep to save their battery, and only listen for a few seconds
+/// after a button is pressed. Messages for a remote are kept in a
+/// [PendingCommands] queue until then, see [Modem::handle_remote_message].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MiniRemote {
+    pub address: Address,
+    buttons: u8,
+}
+
+impl MiniRemote {
+    /// Constructs a `MiniRemote` with the given number of buttons: 1 for
+    /// the single button remote, 4 or 8 for the scene remotes. Any other
+    /// count is an [Error::InvalidButtonCount].
+    pub fn new(address: Address, buttons: u8) -> Result<Self, Error> {
+        match buttons {
+            1 | 4 | 8 => Ok(MiniRemote { address, buttons }),
+            _ => Err(Error::InvalidButtonCount(buttons)),
+        }
+    }
+
+    /// Returns the number of buttons on the remote.
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    /// Decodes a button press from `message`, if it's a group broadcast
+    /// from this remote. The cleanups that follow a broadcast repeat it,
+    /// so they are ignored to report each press only once.
+    pub fn decode(&self, message: &Message) -> Option<RemoteEvent> {
+        decode_button(self.address, self.buttons, message)
+    }
+}
+
+/// Decodes a press of one of the first `buttons` buttons of the device at
+/// `address` from its group broadcast `message`.
+pub(crate) fn decode_button(
+    address: Address,
+    buttons: u8,
+    message: &Message,
+) -> Option<RemoteEvent> {
+    if message.from != address || !message.is_group_broadcast() {
+        return None;
+    }
+
+    let button = message.group()?;
+    if button == 0 || button > buttons {
+        return None;
+    }
+
+    let action = RemoteAction::from_message(message)?;
+    Some(RemoteEvent { button, action })
+}
+
+impl RemoteAction {
+    /// Returns the action carried by the commands of a button's group
+    /// broadcast or cleanup.
+    pub(crate) fn from_message(message: &Message) -> Option<RemoteAction> {
+        match message.cmd1 {
+            Command::On => Some(RemoteAction::On),
+            Command::Off => Some(RemoteAction::Off),
+            Command::OnFast => Some(RemoteAction::OnFast),
+            Command::OffFast => Some(RemoteAction::OffFast),
+            Command::StartManualChange if message.cmd2 == Command::None => {
+                Some(RemoteAction::StartChange(DimDirection::Down))
+            }
+            Command::StartManualChange => Some(RemoteAction::StartChange(DimDirection::Up)),
+            Command::StopManualChange => Some(RemoteAction::StopChange),
+            _ => None,
+        }
+    }
+}
+
+impl Modem {
+    /// Handles a [Message] received from `remote`, returning the button
+    /// press it carries. Since the remote is awake right after sending
+    /// anything, this is also when the messages queued for it in `pending`
+    /// are delivered.
+    pub async fn handle_remote_message(
+        &mut self,
+        remote: &MiniRemote,
+        pending: &mut PendingCommands,
+        message: &Message,
+    ) -> Result<Option<RemoteEvent>, Error> {
+        if message.from != remote.address {
+            return Ok(None);
+        }
+
+        let event = remote.decode(message);
+        if let Some(event) = event {
+            debug!("Remote {} sent {:?}", remote.address, event);
+        }
+
+        if pending.contains(remote.address) {
+            self.deliver_pending(pending, remote.address).await?;
+        }
+
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn decode() {
+        let address = Address::from_str("11.22.33").unwrap();
+        let remote = MiniRemote::new(address, 4).unwrap();
+        assert_eq!(
+            MiniRemote::new(address, 3),
+            Err(Error::InvalidButtonCount(3))
+        );
+
+        let mut message: Message = (
+            Address::from_str("00.00.03").unwrap(),
+            Command::StartManualChange,
+            Command::Other(0x01),
+            MessageFlags::GROUP | MessageFlags::BROADCAST_OR_NAK,
+        )
+            .into();
+        message.from = address;
+        assert_eq!(
+            remote.decode(&message),
+            Some(RemoteEvent {
+                button: 3,
+                action: RemoteAction::StartChange(DimDirection::Up)
+            })
+        );
+
+        // The cleanup that follows is a duplicate
+        message.flags = MessageFlags::GROUP;
+        assert_eq!(remote.decode(&message), None);
+
+        // There's no button 5
+        message.flags |= MessageFlags::BROADCAST_OR_NAK;
+        message.to = Address::from_str("00.00.05").unwrap();
+        assert_eq!(remote.decode(&message), None);
+    }
+}
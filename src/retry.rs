@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+/// How long a [RetryPolicy] waits before each new attempt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backoff {
+    /// The same delay before every attempt.
+    Fixed(Duration),
+    /// Starts at `initial` and grows by `factor` after each attempt, up to
+    /// `max`. A `factor` below 1, or NaN, is taken as 1.
+    Exponential {
+        initial: Duration,
+        factor: f64,
+        max: Duration,
+    },
+}
+
+/// How often a frame is sent again when the modem is too busy to take it,
+/// and how long to wait in between. The default makes up to 20 attempts
+/// 250ms apart.
+///
+/// Set it for every frame with [Modem::set_retry_policy](crate::Modem::set_retry_policy)
+/// or [ModemBuilder::retry_policy](crate::ModemBuilder::retry_policy), or for
+/// one frame with
+/// [Modem::send_frame_with_retry_policy](crate::Modem::send_frame_with_retry_policy).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// The most times a frame is sent, including the first. Zero is
+    /// treated as one.
+    pub max_attempts: u32,
+    pub backoff: Backoff,
+}
+
+impl RetryPolicy {
+    /// Returns a policy that sends each frame once and gives up if the
+    /// modem doesn't take it.
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            backoff: Backoff::Fixed(Duration::from_millis(0)),
+        }
+    }
+
+    /// Returns a policy making up to `max_attempts` attempts, doubling the
+    /// delay from `initial` after each one up to `max`.
+    pub fn exponential(max_attempts: u32, initial: Duration, max: Duration) -> Self {
+        RetryPolicy {
+            max_attempts,
+            backoff: Backoff::Exponential {
+                initial,
+                factor: 2.0,
+                max,
+            },
+        }
+    }
+
+    /// Returns how long to wait after `attempt` fails, counting from 1, or
+    /// `None` if that was the last.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+
+        Some(match self.backoff {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential {
+                initial,
+                factor,
+                max,
+            } => {
+                // f64::max also turns a NaN factor into 1.
+                let delay = initial.as_secs_f64() * factor.max(1.0).powi(attempt as i32 - 1);
+                if delay < max.as_secs_f64() {
+                    Duration::from_secs_f64(delay)
+                } else {
+                    max
+                }
+            }
+        })
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 20,
+            backoff: Backoff::Fixed(Duration::from_millis(250)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(1), Some(Duration::from_millis(250)));
+        assert_eq!(policy.delay(19), Some(Duration::from_millis(250)));
+        assert_eq!(policy.delay(20), None);
+
+        let policy =
+            RetryPolicy::exponential(5, Duration::from_millis(100), Duration::from_millis(500));
+        let delays: Vec<_> = (1..=5).map(|attempt| policy.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(400)),
+                Some(Duration::from_millis(500)),
+                None
+            ]
+        );
+
+        assert_eq!(RetryPolicy::never().delay(1), None);
+    }
+
+    #[test]
+    fn bad_factor() {
+        for factor in &[-2.0, 0.5, f64::NAN] {
+            let policy = RetryPolicy {
+                max_attempts: 3,
+                backoff: Backoff::Exponential {
+                    initial: Duration::from_millis(100),
+                    factor: *factor,
+                    max: Duration::from_secs(1),
+                },
+            };
+            assert_eq!(policy.delay(2), Some(Duration::from_millis(100)));
+        }
+
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            backoff: Backoff::Exponential {
+                initial: Duration::from_millis(100),
+                factor: f64::INFINITY,
+                max: Duration::from_secs(1),
+            },
+        };
+        assert_eq!(policy.delay(2), Some(Duration::from_secs(1)));
+    }
+}
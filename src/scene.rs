@@ -0,0 +1,135 @@
+use log::warn;
+
+use crate::aldb::*;
+use crate::error::*;
+use crate::frame::*;
+use crate::modem::*;
+
+/// A scene controlled by the modem: a group number and the devices that
+/// respond to it, each with its own [ResponderData].
+///
+/// A `Scene` is only a description. [Modem::create_scene] writes it into
+/// the link databases of the modem and every member, and
+/// [Modem::remove_scene] takes it out again. To turn it on or off, use
+/// [Modem::send_all_link_command] or [Modem::trigger_scene] with its group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scene {
+    /// The modem group the scene uses, e.g. from [Modem::allocate_group].
+    pub group: u8,
+    /// Each responder in the scene and how it responds.
+    pub members: Vec<(Address, ResponderData)>,
+}
+
+impl Scene {
+    /// Returns a scene using `group` with no members yet.
+    pub fn new(group: u8) -> Self {
+        Scene {
+            group,
+            members: Vec::new(),
+        }
+    }
+
+    /// Adds the device at `address` to the scene, responding with `data`.
+    pub fn with_member(mut self, address: Address, data: ResponderData) -> Self {
+        self.members.push((address, data));
+        self
+    }
+}
+
+impl Modem {
+    /// Writes `scene` into the link databases of the modem and each
+    /// member, replacing any links already there for the same group. Each
+    /// member is linked as a responder with its own [ResponderData], so
+    /// members can come on at different levels and speeds. The links are
+    /// written directly with [Modem::link_programmatically].
+    pub async fn create_scene(&mut self, scene: &Scene) -> Result<(), Error> {
+        for &(address, data) in &scene.members {
+            self.link_programmatically(address, scene.group, AllLinkMode::Controller, data.into())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Reads the scene using `group` back from the link databases of the
+    /// modem and each responder. Members whose own record is missing are
+    /// left out.
+    pub async fn get_scene(&mut self, group: u8) -> Result<Scene, Error> {
+        let modem_address = self.get_info().await?.address;
+        let members: Vec<Address> = self
+            .get_links()
+            .await?
+            .filter(|record| {
+                record.group == group && record.flags.contains(AllLinkFlags::IS_CONTROLLER)
+            })
+            .map(|record| record.to)
+            .collect();
+
+        let mut scene = Scene::new(group);
+        for address in members {
+            let data = self.read_device_links(address).await?.find(|record| {
+                record.is_in_use()
+                    && !record.is_controller()
+                    && record.group == group
+                    && record.address == modem_address
+            });
+            match data {
+                Some(record) => scene.members.push((address, record.data.into())),
+                None => warn!("{} has no link for scene {}", address, group),
+            }
+        }
+        Ok(scene)
+    }
+
+    /// Removes the links for `scene` from the modem and each member. Links
+    /// that are already gone are skipped.
+    pub async fn remove_scene(&mut self, scene: &Scene) -> Result<(), Error> {
+        let modem_address = self.get_info().await?.address;
+        for &(address, _) in &scene.members {
+            self.delete_device_link(address, modem_address, scene.group)
+                .await?;
+            self.delete_link(address, scene.group).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use super::*;
+    #[cfg(unix)]
+    use crate::emulator::*;
+    #[cfg(unix)]
+    use crate::message::*;
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn scene() {
+        let emulator = Emulator::spawn(EmulatorConfig::demo()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+
+        let group = modem.allocate_group().await.unwrap();
+        let dimmer = Address::from([0x11, 0x22, 0x33]);
+        let switch = Address::from([0x22, 0x33, 0x44]);
+        let scene = Scene::new(group)
+            .with_member(dimmer, ResponderData::new(0x80))
+            .with_member(switch, ResponderData::new(0xff));
+
+        modem.create_scene(&scene).await.unwrap();
+        assert_eq!(modem.get_scene(group).await.unwrap(), scene);
+
+        let result = modem
+            .send_all_link_command(group, Command::On)
+            .await
+            .unwrap();
+        assert_eq!(result.acknowledged, vec![dimmer, switch]);
+
+        modem.remove_scene(&scene).await.unwrap();
+        assert_eq!(modem.get_scene(group).await.unwrap(), Scene::new(group));
+        assert!(modem
+            .read_device_links(dimmer)
+            .await
+            .unwrap()
+            .all(|link| link.group != group || !link.is_in_use()));
+    }
+}
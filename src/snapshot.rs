@@ -0,0 +1,123 @@
+use std::fmt;
+use std::str::FromStr;
+
+use log::warn;
+
+use crate::error::*;
+use crate::frame::*;
+use crate::message::*;
+use crate::modem::*;
+
+/// The levels of a set of devices at one moment, taken with
+/// [Modem::snapshot] and put back with [Modem::restore].
+///
+/// A `Snapshot` can be saved as text, one `address level` line per
+/// device with the level in hex, and parsed back with [str::parse].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    levels: Vec<(Address, u8)>,
+}
+
+impl Snapshot {
+    /// Returns each device in the snapshot along with its level.
+    pub fn levels(&self) -> &[(Address, u8)] {
+        &self.levels
+    }
+
+    /// Returns the level recorded for the device at `address`.
+    pub fn get(&self, address: Address) -> Option<u8> {
+        self.levels
+            .iter()
+            .find(|(known, _)| *known == address)
+            .map(|(_, level)| *level)
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (address, level) in &self.levels {
+            writeln!(f, "{} {:02x}", address, level)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut levels = Vec::new();
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let invalid = || Error::parse("snapshot", line.as_bytes());
+            let mut parts = line.split_whitespace();
+            let address = parts.next().ok_or_else(invalid)?.parse()?;
+            let level = parts
+                .next()
+                .and_then(|level| u8::from_str_radix(level, 16).ok())
+                .ok_or_else(invalid)?;
+            if parts.next().is_some() {
+                return Err(invalid());
+            }
+            levels.push((address, level));
+        }
+        Ok(Snapshot { levels })
+    }
+}
+
+impl Modem {
+    /// Records the current level of each device in `addresses`. Devices
+    /// that don't answer are left out of the snapshot, so the rest can
+    /// still be restored.
+    pub async fn snapshot(
+        &mut self,
+        addresses: impl IntoIterator<Item = Address>,
+    ) -> Result<Snapshot, Error> {
+        let mut levels = Vec::new();
+        for address in addresses {
+            match self.get_level(address).await {
+                Ok(level) => levels.push((address, level)),
+                Err(e) => warn!("Leaving {} out of the snapshot: {}", address, e),
+            }
+        }
+        Ok(Snapshot { levels })
+    }
+
+    /// Puts every device in `snapshot` back to its recorded level. Every
+    /// device is attempted even if one fails, and the first error is
+    /// returned.
+    pub async fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Error> {
+        let messages = snapshot
+            .levels
+            .iter()
+            .map(|(address, level)| match level {
+                0 => (*address, Command::Off).into(),
+                level => (*address, Command::On, Command::Other(*level)).into(),
+            })
+            .collect();
+
+        for result in self.send_batch(messages).await {
+            result?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text() {
+        let snapshot = Snapshot {
+            levels: vec![
+                (Address::from_str("11.22.33").unwrap(), 0xff),
+                (Address::from_str("44.55.66").unwrap(), 0x00),
+            ],
+        };
+
+        let text = snapshot.to_string();
+        assert_eq!(text, "11.22.33 ff\n44.55.66 00\n");
+        assert_eq!(text.parse::<Snapshot>(), Ok(snapshot));
+        assert!("11.22.33".parse::<Snapshot>().is_err());
+    }
+}
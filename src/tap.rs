@@ -0,0 +1,120 @@
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::channel::mpsc::UnboundedSender;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Bytes that passed between the host and the modem, as delivered by
+/// [Modem::tap](crate::Modem::tap). Bytes arrive in whatever chunks the
+/// connection hands them over, which need not line up with frames.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tapped {
+    /// Bytes read from the modem.
+    Read(Vec<u8>),
+    /// Bytes written to the modem.
+    Written(Vec<u8>),
+}
+
+/// Whoever is tapping a connection.
+pub(crate) type Taps = Arc<Mutex<Vec<UnboundedSender<Tapped>>>>;
+
+/// Wraps the connection to the modem, handing a copy of everything read
+/// and written to each of `taps`.
+pub(crate) struct Tap<T> {
+    io: T,
+    taps: Taps,
+}
+
+impl<T> Tap<T> {
+    pub(crate) fn new(io: T, taps: Taps) -> Self {
+        Tap { io, taps }
+    }
+
+    fn deliver(&self, tapped: impl FnOnce() -> Tapped) {
+        let mut taps = self.taps.lock().unwrap();
+        if taps.is_empty() {
+            return;
+        }
+        let tapped = tapped();
+        taps.retain(|tap| tap.unbounded_send(tapped.clone()).is_ok());
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Tap<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.io).poll_read(cx, buf);
+        if let Poll::Ready(Ok(len)) = result {
+            if len > 0 {
+                self.deliver(|| Tapped::Read(buf[..len].to_vec()));
+            }
+        }
+        result
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Tap<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.io).poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = result {
+            if len > 0 {
+                self.deliver(|| Tapped::Written(buf[..len].to_vec()));
+            }
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use super::*;
+    #[cfg(unix)]
+    use crate::emulator::*;
+    #[cfg(unix)]
+    use crate::frame::*;
+    #[cfg(unix)]
+    use crate::modem::*;
+    #[cfg(unix)]
+    use bytes::BytesMut;
+    #[cfg(unix)]
+    use futures::StreamExt;
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn tap() {
+        let emulator = Emulator::spawn(EmulatorConfig::default()).unwrap();
+        let mut modem = Modem::from_path(emulator.path().to_path_buf()).unwrap();
+        let mut tap = modem.tap();
+
+        let info = modem.send_frame(Frame::GetModemInfo).await.unwrap();
+        assert_eq!(tap.next().await, Some(Tapped::Written(vec![0x02, 0x60])));
+
+        let mut expected = BytesMut::new();
+        info.to_bytes(&mut expected);
+        let mut read = Vec::new();
+        while read.len() < expected.len() {
+            match tap.next().await {
+                Some(Tapped::Read(bytes)) => read.extend(bytes),
+                other => panic!("Unexpected {:?}", other),
+            }
+        }
+        assert_eq!(read, expected.to_vec());
+    }
+}
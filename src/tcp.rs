@@ -0,0 +1,116 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::timeout;
+
+/// The port a network-attached modem listens on unless another is given,
+/// as used by Insteon Hubs.
+pub const DEFAULT_TCP_PORT: u16 = 9761;
+
+/// How often an idle TCP connection to the modem is probed, so a modem
+/// that went away is noticed, unless set with
+/// [ModemBuilder::keepalive](crate::ModemBuilder::keepalive).
+pub const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// How long to try each of the host's addresses before giving up on it.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Adds `port` to `addr` unless it already has one. A bare IPv6 address
+/// is put in brackets.
+pub(crate) fn with_default_port(addr: &str, port: u16) -> String {
+    if let Ok(ip) = addr.parse::<IpAddr>() {
+        return SocketAddr::from((ip, port)).to_string();
+    }
+
+    let host = addr.rsplit(']').next().unwrap_or(addr);
+    if host.contains(':') {
+        addr.to_string()
+    } else {
+        format!("{}:{}", addr, port)
+    }
+}
+
+/// Connects to `addr`, trying each address it resolves to in turn. This
+/// blocks, so it's only used to open the connection in the first place.
+pub(crate) fn connect(addr: &str, keepalive: Option<Duration>) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        match std::net::TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_nodelay(true)?;
+                stream.set_nonblocking(true)?;
+                let stream = TcpStream::from_std(stream)?;
+                stream.set_keepalive(keepalive)?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("{} has no addresses", addr),
+        )
+    }))
+}
+
+/// Connects to `addr` like [connect] without blocking, for reconnecting
+/// from within the event loop.
+pub(crate) async fn connect_async(
+    addr: String,
+    keepalive: Option<Duration>,
+) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in lookup_host(addr.as_str()).await? {
+        match timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => {
+                stream.set_nodelay(true)?;
+                stream.set_keepalive(keepalive)?;
+                return Ok(stream);
+            }
+            Ok(Err(e)) => last_error = Some(e),
+            Err(_) => {
+                last_error = Some(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("connecting to {} timed out", addr),
+                ))
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("{} has no addresses", addr),
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_port() {
+        let port = DEFAULT_TCP_PORT;
+        assert_eq!(with_default_port("hub.local", port), "hub.local:9761");
+        assert_eq!(with_default_port("hub.local:2000", port), "hub.local:2000");
+        assert_eq!(with_default_port("10.0.0.5", port), "10.0.0.5:9761");
+        assert_eq!(with_default_port("::1", port), "[::1]:9761");
+        assert_eq!(with_default_port("[::1]:2000", port), "[::1]:2000");
+        assert_eq!(with_default_port("[::1]", port), "[::1]:9761");
+    }
+
+    #[tokio::test]
+    async fn connect_without_blocking() {
+        let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        assert!(connect_async(addr.clone(), None).await.is_ok());
+
+        drop(server);
+        assert!(connect_async(addr, None).await.is_err());
+    }
+}
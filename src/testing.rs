@@ -0,0 +1,542 @@
+//! Helpers for testing code that uses a [Modem](crate::Modem) without
+//! modem hardware.
+//!
+//! ```
+//! # use plm::{testing::MockModem, Address, Command, Frame, Modem};
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), plm::Error> {
+//! let mock = MockModem::new();
+//! let mut modem = Modem::new(mock.clone());
+//!
+//! let switch = Address::from([0x11, 0x22, 0x33]);
+//! modem.send_message((switch, Command::On).into()).await?;
+//! assert!(matches!(
+//!     mock.sent().last(),
+//!     Some(Frame::StandardInsteonSend { to, .. }) if *to == switch
+//! ));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A [MockModem] acknowledges whatever is sent to any address. To test
+//! against devices that keep state and link to the modem, use a
+//! [Simulator] instead.
+
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use bytes::{BufMut, BytesMut};
+use futures::FutureExt;
+use futures_timer::Delay;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::constants::*;
+use crate::emulator::*;
+use crate::frame::*;
+
+/// The reason given when a mock device refuses a message.
+const NAK_REFUSED: u8 = 0xff;
+
+struct Inner {
+    /// The modem and devices, shared with the [emulator](crate::emulator).
+    state: State,
+    latency: Duration,
+    nak_rate: f64,
+    /// How far along the next NAK is, so exactly `nak_rate` of messages
+    /// are refused and tests are repeatable.
+    naks_owed: f64,
+    sent: Vec<Frame>,
+    /// Bytes written that don't yet make up a whole command.
+    written: Vec<u8>,
+    /// Bytes to be read, each batch once its time comes.
+    replies: VecDeque<(Instant, Vec<u8>)>,
+    /// Waiting for a reply to be due.
+    reader: Option<Waker>,
+    /// Wakes the reader when the next reply is due.
+    timer: Option<Delay>,
+}
+
+impl Inner {
+    /// Returns a modem at 44.85.11 with no links, whose devices answer
+    /// right away and never refuse a message.
+    fn new() -> Arc<Mutex<Inner>> {
+        let config = EmulatorConfig {
+            links: Vec::new(),
+            ..EmulatorConfig::default()
+        };
+        Arc::new(Mutex::new(Inner {
+            state: State::new(config),
+            latency: Duration::from_millis(0),
+            nak_rate: 0.0,
+            naks_owed: 0.0,
+            sent: Vec::new(),
+            written: Vec::new(),
+            replies: VecDeque::new(),
+            reader: None,
+            timer: None,
+        }))
+    }
+
+    fn reply(&mut self, frame: Frame, after: Duration) {
+        let mut bytes = BytesMut::new();
+        frame.to_bytes(&mut bytes);
+        self.reply_bytes(bytes.to_vec(), after);
+    }
+
+    fn reply_bytes(&mut self, bytes: Vec<u8>, after: Duration) {
+        if bytes.is_empty() {
+            return;
+        }
+        let at = Instant::now() + after;
+        let index = self
+            .replies
+            .iter()
+            .position(|(queued, _)| *queued > at)
+            .unwrap_or(self.replies.len());
+        self.replies.insert(index, (at, bytes));
+        if let Some(reader) = self.reader.take() {
+            reader.wake();
+        }
+    }
+
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let at = match self.replies.front() {
+                Some((at, _)) => *at,
+                None => {
+                    self.reader = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            };
+
+            let now = Instant::now();
+            if at > now {
+                // A reply that's due sooner may still be queued meanwhile.
+                self.reader = Some(cx.waker().clone());
+                let mut timer = Delay::new(at - now);
+                if timer.poll_unpin(cx).is_pending() {
+                    self.timer = Some(timer);
+                    return Poll::Pending;
+                }
+                continue;
+            }
+
+            let (_, bytes) = self.replies.front_mut().unwrap();
+            let len = buf.len().min(bytes.len());
+            buf[..len].copy_from_slice(&bytes[..len]);
+            bytes.drain(..len);
+            if bytes.is_empty() {
+                self.replies.pop_front();
+            }
+            return Poll::Ready(Ok(len));
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        self.written.extend_from_slice(buf);
+        while let Some(len) = command_len(&self.written) {
+            let command: Vec<u8> = self.written.drain(..len).collect();
+            self.respond(&command);
+        }
+    }
+
+    /// Answers a command from the host, as `bytes`, the way a modem would.
+    fn respond(&mut self, bytes: &[u8]) {
+        let frame = command(bytes);
+        if let Some(frame) = &frame {
+            self.sent.push(frame.clone());
+        }
+
+        let direct = match frame {
+            Some(Frame::StandardInsteonSend {
+                to, flags, cmd1, ..
+            })
+            | Some(Frame::ExtendedInsteonSend {
+                to, flags, cmd1, ..
+            }) if !flags.contains(MessageFlags::GROUP) => Some((to, cmd1)),
+            _ => None,
+        };
+        let (to, cmd1) = match direct {
+            Some(direct) => direct,
+            None => {
+                let reply = self.state.respond(bytes);
+                self.reply_bytes(reply, Duration::from_millis(0));
+                return;
+            }
+        };
+
+        let mut echo = bytes.to_vec();
+        echo.push(ACK);
+        if self.state.answers(to) {
+            self.naks_owed += self.nak_rate;
+            if self.naks_owed >= 1.0 {
+                self.naks_owed -= 1.0;
+                self.reply_bytes(echo, Duration::from_millis(0));
+                let nak = Frame::StandardInsteonReceive {
+                    from: to,
+                    to: self.state.config.info.address,
+                    flags: MessageFlags::ACK | MessageFlags::BROADCAST_OR_NAK,
+                    hops_remaining: 3,
+                    max_hops: 3,
+                    cmd1,
+                    cmd2: NAK_REFUSED,
+                };
+                self.reply(nak, self.latency);
+                return;
+            }
+        }
+
+        // The modem echoes the message right away, and the device answers
+        // after the latency.
+        let mut reply = self.state.respond(bytes);
+        let answer = reply.split_off(echo.len().min(reply.len()));
+        self.reply_bytes(reply, Duration::from_millis(0));
+        self.reply_bytes(answer, self.latency);
+    }
+}
+
+/// Decodes a command the host sent, which reads like the modem's echo of
+/// it without the ACK. Returns `None` for a stray byte, e.g. one sent to
+/// wake the modem.
+fn command(bytes: &[u8]) -> Option<Frame> {
+    if bytes.len() < 2 {
+        return None;
+    }
+    let frame = match bytes[1] {
+        GETIMINFO => Frame::GetModemInfo,
+        GET_IM_CONFIGURATION => Frame::GetModemConfig,
+        _ => {
+            let mut echo = BytesMut::from(bytes);
+            echo.put_u8(ACK);
+            match Frame::from_bytes(&mut echo) {
+                Ok(Some(frame)) => frame,
+                _ => Frame::Unknown {
+                    buf: bytes.to_vec(),
+                },
+            }
+        }
+    };
+    Some(frame)
+}
+
+/// A modem that lives in memory, for testing code that uses a
+/// [Modem](crate::Modem). Pass a clone to [Modem::new](crate::Modem::new)
+/// and keep the original to check what was sent; every clone shares the
+/// same state.
+///
+/// It answers like the [emulator](crate::emulator) with no devices: [Frame::GetModemInfo]
+/// with [MockModem::set_info], link queries with [MockModem::set_links],
+/// and every address acknowledges direct messages after the
+/// [latency](MockModem::set_latency), unless it refuses them as the
+/// [NAK rate](MockModem::set_nak_rate) says.
+#[derive(Clone)]
+pub struct MockModem {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MockModem {
+    /// Returns a mock modem at 44.85.11 with no links, whose devices answer
+    /// right away and never refuse a message.
+    pub fn new() -> Self {
+        MockModem {
+            inner: Inner::new(),
+        }
+    }
+
+    /// Sets the info the modem reports.
+    pub fn set_info(&self, info: ModemInfo) {
+        self.inner.lock().unwrap().state.config.info = info;
+    }
+
+    /// Sets the modem's link database.
+    pub fn set_links(&self, links: Vec<AllLinkRecord>) {
+        self.inner.lock().unwrap().state.config.links = links;
+    }
+
+    /// Sets how long devices take to acknowledge a direct message.
+    pub fn set_latency(&self, latency: Duration) {
+        self.inner.lock().unwrap().latency = latency;
+    }
+
+    /// Sets the share of direct messages that devices refuse, between 0
+    /// and 1. Refusals are spread evenly rather than at random, e.g. every
+    /// fourth message at 0.25.
+    pub fn set_nak_rate(&self, rate: f64) {
+        self.inner.lock().unwrap().nak_rate = rate.clamp(0.0, 1.0);
+    }
+
+    /// Has the modem send `frame` as if it had just received it, e.g. a
+    /// [Frame::StandardInsteonReceive] for a button press on a device.
+    pub fn receive(&self, frame: Frame) {
+        self.inner
+            .lock()
+            .unwrap()
+            .reply(frame, Duration::from_millis(0));
+    }
+
+    /// Returns every frame sent to the modem so far, in order.
+    pub fn sent(&self) -> Vec<Frame> {
+        self.inner.lock().unwrap().sent.clone()
+    }
+}
+
+impl Default for MockModem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A modem in memory with a network of [EmulatedDevice]s behind it, for
+/// testing applications end to end. Pass a clone to
+/// [Modem::new](crate::Modem::new) and keep the original to add devices,
+/// press their buttons and check their state; every clone shares the same
+/// network.
+///
+/// The devices behave as they do behind the [emulator](crate::emulator): they turn on and
+/// off, report their level when asked for their status, identify
+/// themselves, serve their link databases and link to the modem, e.g.
+/// with [Modem::link_device](crate::Modem::link_device). Once a device is
+/// added, messages to any other address go unanswered. Like a
+/// [MockModem], it can be made slow or made to refuse messages.
+#[derive(Clone)]
+pub struct Simulator {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Simulator {
+    /// Returns a simulator with no devices, behind a modem like
+    /// [MockModem::new]'s.
+    pub fn new() -> Self {
+        Simulator {
+            inner: Inner::new(),
+        }
+    }
+
+    /// Adds `device` to the network, replacing any other at its address.
+    pub fn add_device(&self, device: EmulatedDevice) {
+        let mut inner = self.inner.lock().unwrap();
+        let devices = &mut inner.state.config.devices;
+        devices.retain(|d| d.address != device.address);
+        devices.push(device);
+    }
+
+    /// Returns the current state of the device at `address`.
+    pub fn device(&self, address: Address) -> Option<EmulatedDevice> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .state
+            .config
+            .devices
+            .iter()
+            .find(|d| d.address == address)
+            .cloned()
+    }
+
+    /// Returns the modem's link database.
+    pub fn links(&self) -> Vec<AllLinkRecord> {
+        self.inner.lock().unwrap().state.config.links.clone()
+    }
+
+    /// Presses the set button of the device at `address`. This links it if
+    /// the modem is in linking mode, and otherwise puts the device in
+    /// linking mode until the modem is.
+    pub fn press_set_button(&self, address: Address) {
+        let mut inner = self.inner.lock().unwrap();
+        let reply = inner.state.press_set_button(address);
+        inner.reply_bytes(reply, Duration::from_millis(0));
+    }
+
+    /// Sets how long devices take to answer, as with
+    /// [MockModem::set_latency].
+    pub fn set_latency(&self, latency: Duration) {
+        self.inner.lock().unwrap().latency = latency;
+    }
+
+    /// Sets the share of direct messages that devices refuse, as with
+    /// [MockModem::set_nak_rate].
+    pub fn set_nak_rate(&self, rate: f64) {
+        self.inner.lock().unwrap().nak_rate = rate.clamp(0.0, 1.0);
+    }
+
+    /// Returns every frame sent to the modem so far, in order.
+    pub fn sent(&self) -> Vec<Frame> {
+        self.inner.lock().unwrap().sent.clone()
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Implements the IO traits for a type whose `inner` is the shared
+/// [Inner].
+macro_rules! impl_io {
+    ($t:ty) => {
+        impl AsyncRead for $t {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                self.inner.lock().unwrap().poll_read(cx, buf)
+            }
+        }
+
+        impl AsyncWrite for $t {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                _: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                self.inner.lock().unwrap().write(buf);
+                Poll::Ready(Ok(buf.len()))
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+    };
+}
+
+impl_io!(MockModem);
+impl_io!(Simulator);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::*;
+    use crate::message::*;
+    use crate::modem::*;
+    use crate::retry::*;
+
+    #[async_std::test]
+    async fn empty_links() {
+        let mock = MockModem::new();
+        let mut modem = Modem::new(mock.clone());
+        modem.set_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            backoff: Backoff::Fixed(Duration::from_millis(0)),
+        });
+
+        // The empty database is only believed once the NAKs outlast the
+        // retry policy.
+        assert_eq!(modem.get_links().await.unwrap().count(), 0);
+        let requests = mock
+            .sent()
+            .iter()
+            .filter(|frame| **frame == Frame::GetFirstAllLinkRecord)
+            .count();
+        assert_eq!(requests, 3);
+    }
+
+    #[async_std::test]
+    async fn mock() {
+        let mock = MockModem::new();
+        let link = AllLinkRecord {
+            flags: AllLinkFlags::IN_USE | AllLinkFlags::IS_CONTROLLER,
+            group: 1,
+            to: Address::from([0x11, 0x22, 0x33]),
+            data: [0x01, 0x20, 0x41],
+        };
+        mock.set_links(vec![link.clone()]);
+        mock.set_latency(Duration::from_millis(50));
+        mock.set_nak_rate(0.5);
+        let mut modem = Modem::new(mock.clone());
+
+        assert_eq!(
+            modem.get_info().await.unwrap().address,
+            Address::from([0x44, 0x85, 0x11])
+        );
+        assert_eq!(
+            modem.get_links().await.unwrap().collect::<Vec<_>>(),
+            vec![link]
+        );
+        modem.set_config(ModemConfig::MONITOR_MODE).await.unwrap();
+        assert_eq!(modem.get_config().await.unwrap(), ModemConfig::MONITOR_MODE);
+
+        let switch = Address::from([0x22, 0x33, 0x44]);
+        let start = Instant::now();
+        assert!(modem
+            .send_message((switch, Command::On).into())
+            .await
+            .is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(
+            modem.send_message((switch, Command::Off).into()).await,
+            Err(Error::DeviceNotAcknowledged(NAK_REFUSED))
+        );
+
+        let sent = mock.sent();
+        assert_eq!(sent[0], Frame::GetModemInfo);
+        assert!(matches!(
+            sent.last(),
+            Some(Frame::StandardInsteonSend { to, cmd1, .. }) if *to == switch && *cmd1 == u8::from(Command::Off)
+        ));
+    }
+
+    #[async_std::test]
+    async fn simulator() {
+        let simulator = Simulator::new();
+        let dimmer = Address::from([0x11, 0x22, 0x33]);
+        simulator.add_device(EmulatedDevice::dimmer(dimmer));
+        let mut modem = Modem::new(simulator.clone());
+
+        let on = (dimmer, Command::On, Command::from(0x80)).into();
+        assert!(modem.send_message(on).await.is_ok());
+        assert_eq!(simulator.device(dimmer).unwrap().level, 0x80);
+        let status = (dimmer, Command::StatusRequest(StatusKind::OnLevel)).into();
+        assert_eq!(
+            u8::from(modem.send_message(status).await.unwrap().cmd2),
+            0x80
+        );
+
+        // Nothing answers at an address without a device.
+        modem.set_timeout(Duration::from_millis(200));
+        let missing = Address::from([0x99, 0x99, 0x99]);
+        assert_eq!(
+            modem.send_message((missing, Command::On).into()).await,
+            Err(Error::Timeout)
+        );
+
+        let complete = modem
+            .link_device(Some(dimmer), AllLinkMode::Controller, 1)
+            .await
+            .unwrap();
+        assert_eq!(complete.address, dimmer);
+        assert_eq!(complete.category, 0x01);
+        assert_eq!(simulator.links()[0].to, dimmer);
+        let device = simulator.device(dimmer).unwrap();
+        assert_eq!(device.links[0].address, Address::from([0x44, 0x85, 0x11]));
+        assert!(!device.linking);
+        assert_eq!(
+            modem
+                .read_device_links(dimmer)
+                .await
+                .unwrap()
+                .collect::<Vec<_>>(),
+            device.links
+        );
+
+        // Pressing the set button first links once the modem is ready.
+        let switch = Address::from([0x22, 0x33, 0x44]);
+        simulator.add_device(EmulatedDevice::switch(switch));
+        simulator.press_set_button(switch);
+        let complete = modem
+            .link_device(None, AllLinkMode::Responder, 2)
+            .await
+            .unwrap();
+        assert_eq!(complete.address, switch);
+        assert!(simulator.device(switch).unwrap().links[0].is_controller());
+    }
+}